@@ -0,0 +1,521 @@
+//! HTTP authentication middleware.
+//!
+//! `BasicAuth` checks `Authorization: Basic` credentials against a
+//! `CredentialVerifier`, such as an `Htpasswd` file. `BearerAuth` hands
+//! `Authorization: Bearer` tokens to a `TokenValidator`, such as
+//! `JwtVerifier`. Both answer unauthenticated requests with `401` and a
+//! `WWW-Authenticate` challenge, and otherwise pass the request on with the
+//! authenticated `Principal` attached.
+use crate::error::ServerError;
+use crate::extract::{FromRequest, Rejection};
+use crate::message::{Request, ResponseWriter};
+use crate::server::Handler;
+use crate::status_code::StatusCode;
+use base64::engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD};
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use serde_json::Value;
+use sha1::{Digest, Sha1};
+use sha2::{Sha256, Sha384, Sha512};
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use subtle::ConstantTimeEq;
+
+pub const AUTHORIZATION: &str = "Authorization";
+pub const WWW_AUTHENTICATE: &str = "WWW-Authenticate";
+
+/// The authenticated client, attached to the request by the middleware.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Principal {
+    /// The user name for Basic auth, the `sub` claim of a JWT.
+    pub name: String,
+    /// Token claims; `Value::Null` for Basic auth.
+    pub claims: Value,
+}
+
+impl Principal {
+    pub fn new(name: &str) -> Self {
+        Principal {
+            name: name.to_string(),
+            claims: Value::Null,
+        }
+    }
+}
+
+impl FromRequest for Principal {
+    fn from_request(req: &Request) -> Result<Self, Rejection> {
+        req.extension::<Principal>().cloned().ok_or_else(|| {
+            Rejection::new(
+                StatusCode::InternalServerError,
+                "no principal, the handler is not wrapped in an auth middleware",
+            )
+        })
+    }
+}
+
+/// The `token68` or credentials following `scheme` in the `Authorization`
+/// header; the scheme is matched case-insensitively.
+fn credentials<'a>(req: &'a Request, scheme: &str) -> Option<&'a str> {
+    let value = req.header(AUTHORIZATION)?.trim();
+    let (s, rest) = value.split_at(value.find(' ')?);
+    if s.eq_ignore_ascii_case(scheme) {
+        Some(rest.trim())
+    } else {
+        None
+    }
+}
+
+/// `"value"` with `"` and `\` escaped.
+fn quote(value: &str) -> String {
+    let mut out = String::with_capacity(value.len() + 2);
+    out.push('"');
+    for c in value.chars() {
+        if c == '"' || c == '\\' {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+    out.push('"');
+    out
+}
+
+fn challenge(writer: &mut dyn ResponseWriter, value: &str) {
+    writer.set_header(WWW_AUTHENTICATE, value);
+    writer.text("Unauthorized");
+    writer.status(StatusCode::Unauthorized);
+}
+
+/// Checks a user name and password.
+pub trait CredentialVerifier {
+    fn verify(&self, user: &str, password: &str) -> bool;
+}
+
+impl<F> CredentialVerifier for F
+where
+    F: Fn(&str, &str) -> bool,
+{
+    fn verify(&self, user: &str, password: &str) -> bool {
+        self(user, password)
+    }
+}
+
+enum PasswordHash {
+    Bcrypt(String),
+    Sha1([u8; 20]),
+}
+
+impl PasswordHash {
+    fn verify(&self, password: &str) -> bool {
+        match self {
+            PasswordHash::Bcrypt(hash) => bcrypt::verify(password, hash).unwrap_or(false),
+            PasswordHash::Sha1(digest) => {
+                let actual = Sha1::digest(password.as_bytes());
+                actual.as_slice().ct_eq(digest).into()
+            }
+        }
+    }
+}
+
+/// Users from an Apache htpasswd file.
+///
+/// Supports bcrypt (`$2y$`, `$2a$`, `$2b$`, as written by `htpasswd -B`)
+/// and SHA-1 (`{SHA}`, `htpasswd -s`) entries. Blank lines and lines
+/// starting with `#` are ignored.
+pub struct Htpasswd {
+    users: HashMap<String, PasswordHash>,
+}
+
+impl Htpasswd {
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Htpasswd::parse(&fs::read_to_string(path)?)
+    }
+
+    /// Parse the contents of an htpasswd file. Entries in an unsupported
+    /// format are an error rather than users that can never log in.
+    pub fn parse(contents: &str) -> io::Result<Self> {
+        let mut users = HashMap::new();
+        for (n, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let invalid = |msg: &str| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("htpasswd line {}: {}", n + 1, msg),
+                )
+            };
+            let (user, hash) = line
+                .split_once(':')
+                .ok_or_else(|| invalid("expected user:hash"))?;
+            let hash = if ["$2y$", "$2a$", "$2b$"].iter().any(|p| hash.starts_with(p)) {
+                PasswordHash::Bcrypt(hash.to_string())
+            } else if let Some(b64) = hash.strip_prefix("{SHA}") {
+                let bytes = STANDARD
+                    .decode(b64)
+                    .map_err(|_| invalid("bad {SHA} digest"))?;
+                let mut digest = [0; 20];
+                if bytes.len() != digest.len() {
+                    return Err(invalid("bad {SHA} digest"));
+                }
+                digest.copy_from_slice(&bytes);
+                PasswordHash::Sha1(digest)
+            } else {
+                return Err(invalid("unsupported hash, use bcrypt or {SHA}"));
+            };
+            users.insert(user.to_string(), hash);
+        }
+        Ok(Htpasswd { users })
+    }
+
+    pub fn len(&self) -> usize {
+        self.users.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.users.is_empty()
+    }
+}
+
+impl CredentialVerifier for Htpasswd {
+    fn verify(&self, user: &str, password: &str) -> bool {
+        self.users.get(user).is_some_and(|h| h.verify(password))
+    }
+}
+
+/// Middleware requiring HTTP Basic authentication (RFC 7617).
+pub struct BasicAuth {
+    realm: String,
+    verifier: Arc<dyn CredentialVerifier + Send + Sync>,
+    inner: Arc<dyn Handler + Send + Sync>,
+}
+
+impl BasicAuth {
+    pub fn new<V, H>(realm: &str, verifier: V, inner: H) -> Self
+    where
+        V: CredentialVerifier + Send + Sync + 'static,
+        H: Handler + Send + Sync + 'static,
+    {
+        BasicAuth {
+            realm: realm.to_string(),
+            verifier: Arc::new(verifier),
+            inner: Arc::new(inner),
+        }
+    }
+
+    fn authenticate(&self, req: &Request) -> Option<Principal> {
+        let encoded = credentials(req, "Basic")?;
+        let decoded = String::from_utf8(STANDARD.decode(encoded).ok()?).ok()?;
+        let (user, password) = decoded.split_once(':')?;
+        if self.verifier.verify(user, password) {
+            Some(Principal::new(user))
+        } else {
+            None
+        }
+    }
+}
+
+impl Handler for BasicAuth {
+    fn serve_http(
+        &self,
+        writer: &mut dyn ResponseWriter,
+        req: &Request,
+    ) -> Result<(), ServerError> {
+        match self.authenticate(req) {
+            Some(principal) => {
+                let mut req = req.clone();
+                req.extensions.insert(principal);
+                self.inner.serve_http(writer, &req)
+            }
+            None => {
+                let value = format!("Basic realm={}, charset=\"UTF-8\"", quote(&self.realm));
+                challenge(writer, &value);
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Why a bearer token was refused; sent to the client as the
+/// `error_description` of the challenge.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct TokenError {
+    message: String,
+}
+
+impl TokenError {
+    pub fn new(message: &str) -> Self {
+        TokenError {
+            message: message.to_string(),
+        }
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+}
+
+impl fmt::Display for TokenError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for TokenError {}
+
+/// Turns a bearer token into the `Principal` it stands for.
+pub trait TokenValidator {
+    fn validate(&self, token: &str) -> Result<Principal, TokenError>;
+}
+
+impl<F> TokenValidator for F
+where
+    F: Fn(&str) -> Result<Principal, TokenError>,
+{
+    fn validate(&self, token: &str) -> Result<Principal, TokenError> {
+        self(token)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum JwtAlgorithm {
+    HS256,
+    HS384,
+    HS512,
+}
+
+impl JwtAlgorithm {
+    fn name(self) -> &'static str {
+        match self {
+            JwtAlgorithm::HS256 => "HS256",
+            JwtAlgorithm::HS384 => "HS384",
+            JwtAlgorithm::HS512 => "HS512",
+        }
+    }
+}
+
+fn verify_mac<M: Mac + hmac::digest::KeyInit>(key: &[u8], input: &[u8], signature: &[u8]) -> bool {
+    match <M as Mac>::new_from_slice(key) {
+        Ok(mut mac) => {
+            mac.update(input);
+            mac.verify_slice(signature).is_ok()
+        }
+        Err(_) => false,
+    }
+}
+
+/// Verifies HMAC-signed JSON Web Tokens (RFC 7519).
+///
+/// The token must be signed with the configured algorithm; `alg: none` and
+/// other algorithms are refused. `exp` and `nbf` are checked when present,
+/// `iss` and `aud` when configured. The principal's name is the `sub` claim.
+pub struct JwtVerifier {
+    algorithm: JwtAlgorithm,
+    secret: Vec<u8>,
+    issuer: Option<String>,
+    audience: Option<String>,
+    leeway: Duration,
+}
+
+impl JwtVerifier {
+    pub fn new(algorithm: JwtAlgorithm, secret: &[u8]) -> Self {
+        JwtVerifier {
+            algorithm,
+            secret: secret.to_vec(),
+            issuer: None,
+            audience: None,
+            leeway: Duration::from_secs(0),
+        }
+    }
+
+    pub fn hs256(secret: &[u8]) -> Self {
+        JwtVerifier::new(JwtAlgorithm::HS256, secret)
+    }
+
+    /// Require the `iss` claim to be `issuer`.
+    pub fn issuer(mut self, issuer: &str) -> Self {
+        self.issuer = Some(issuer.to_string());
+        self
+    }
+
+    /// Require `audience` in the `aud` claim.
+    pub fn audience(mut self, audience: &str) -> Self {
+        self.audience = Some(audience.to_string());
+        self
+    }
+
+    /// Clock skew tolerated when checking `exp` and `nbf`.
+    pub fn leeway(mut self, leeway: Duration) -> Self {
+        self.leeway = leeway;
+        self
+    }
+
+    /// Sign `claims`, which must be a JSON object. Mostly useful for tests
+    /// and for services issuing their own tokens.
+    pub fn sign(&self, claims: &Value) -> String {
+        let header = serde_json::json!({"alg": self.algorithm.name(), "typ": "JWT"});
+        let input = format!(
+            "{}.{}",
+            URL_SAFE_NO_PAD.encode(header.to_string()),
+            URL_SAFE_NO_PAD.encode(claims.to_string())
+        );
+        let signature = match self.algorithm {
+            JwtAlgorithm::HS256 => self.mac::<Hmac<Sha256>>(input.as_bytes()),
+            JwtAlgorithm::HS384 => self.mac::<Hmac<Sha384>>(input.as_bytes()),
+            JwtAlgorithm::HS512 => self.mac::<Hmac<Sha512>>(input.as_bytes()),
+        };
+        format!("{}.{}", input, URL_SAFE_NO_PAD.encode(signature))
+    }
+
+    fn mac<M: Mac + hmac::digest::KeyInit>(&self, input: &[u8]) -> Vec<u8> {
+        let mut mac = <M as Mac>::new_from_slice(&self.secret).expect("any key length");
+        mac.update(input);
+        mac.finalize().into_bytes().to_vec()
+    }
+
+    fn check_claims(&self, claims: &Value) -> Result<(), TokenError> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let leeway = self.leeway.as_secs_f64();
+        let time = |name: &str| match claims.get(name) {
+            None => Ok(None),
+            Some(v) => v
+                .as_f64()
+                .map(Some)
+                .ok_or_else(|| TokenError::new(&format!("{} is not a number", name))),
+        };
+        if let Some(exp) = time("exp")? {
+            if now.as_secs_f64() >= exp + leeway {
+                return Err(TokenError::new("token expired"));
+            }
+        }
+        if let Some(nbf) = time("nbf")? {
+            if now.as_secs_f64() < nbf - leeway {
+                return Err(TokenError::new("token not yet valid"));
+            }
+        }
+        if let Some(issuer) = &self.issuer {
+            if claims.get("iss").and_then(Value::as_str) != Some(issuer) {
+                return Err(TokenError::new("wrong issuer"));
+            }
+        }
+        if let Some(audience) = &self.audience {
+            let ok = match claims.get("aud") {
+                Some(Value::String(aud)) => aud == audience,
+                Some(Value::Array(auds)) => auds.iter().any(|a| a.as_str() == Some(audience)),
+                _ => false,
+            };
+            if !ok {
+                return Err(TokenError::new("wrong audience"));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl TokenValidator for JwtVerifier {
+    fn validate(&self, token: &str) -> Result<Principal, TokenError> {
+        let malformed = || TokenError::new("malformed token");
+        let mut parts = token.split('.');
+        let (header, payload, signature) = match (parts.next(), parts.next(), parts.next()) {
+            (Some(h), Some(p), Some(s)) if parts.next().is_none() => (h, p, s),
+            _ => return Err(malformed()),
+        };
+        let decode = |part: &str| URL_SAFE_NO_PAD.decode(part).map_err(|_| malformed());
+        let header: Value = serde_json::from_slice(&decode(header)?).map_err(|_| malformed())?;
+        if header.get("alg").and_then(Value::as_str) != Some(self.algorithm.name()) {
+            return Err(TokenError::new("unexpected algorithm"));
+        }
+
+        let input = &token[..token.len() - signature.len() - 1];
+        let signature = decode(signature)?;
+        let valid = match self.algorithm {
+            JwtAlgorithm::HS256 => {
+                verify_mac::<Hmac<Sha256>>(&self.secret, input.as_bytes(), &signature)
+            }
+            JwtAlgorithm::HS384 => {
+                verify_mac::<Hmac<Sha384>>(&self.secret, input.as_bytes(), &signature)
+            }
+            JwtAlgorithm::HS512 => {
+                verify_mac::<Hmac<Sha512>>(&self.secret, input.as_bytes(), &signature)
+            }
+        };
+        if !valid {
+            return Err(TokenError::new("bad signature"));
+        }
+
+        let claims: Value = serde_json::from_slice(&decode(payload)?).map_err(|_| malformed())?;
+        if !claims.is_object() {
+            return Err(malformed());
+        }
+        self.check_claims(&claims)?;
+        Ok(Principal {
+            name: claims
+                .get("sub")
+                .and_then(Value::as_str)
+                .unwrap_or_default()
+                .to_string(),
+            claims,
+        })
+    }
+}
+
+/// Middleware requiring a bearer token (RFC 6750).
+pub struct BearerAuth {
+    realm: String,
+    validator: Arc<dyn TokenValidator + Send + Sync>,
+    inner: Arc<dyn Handler + Send + Sync>,
+}
+
+impl BearerAuth {
+    pub fn new<V, H>(realm: &str, validator: V, inner: H) -> Self
+    where
+        V: TokenValidator + Send + Sync + 'static,
+        H: Handler + Send + Sync + 'static,
+    {
+        BearerAuth {
+            realm: realm.to_string(),
+            validator: Arc::new(validator),
+            inner: Arc::new(inner),
+        }
+    }
+}
+
+impl Handler for BearerAuth {
+    fn serve_http(
+        &self,
+        writer: &mut dyn ResponseWriter,
+        req: &Request,
+    ) -> Result<(), ServerError> {
+        let realm = format!("Bearer realm={}", quote(&self.realm));
+        let token = match credentials(req, "Bearer") {
+            Some(token) => token,
+            None => {
+                challenge(writer, &realm);
+                return Ok(());
+            }
+        };
+        match self.validator.validate(token) {
+            Ok(principal) => {
+                let mut req = req.clone();
+                req.extensions.insert(principal);
+                self.inner.serve_http(writer, &req)
+            }
+            Err(e) => {
+                let value = format!(
+                    "{}, error=\"invalid_token\", error_description={}",
+                    realm,
+                    quote(e.message())
+                );
+                challenge(writer, &value);
+                Ok(())
+            }
+        }
+    }
+}
@@ -1,22 +1,219 @@
+use clap::{Args, Parser, Subcommand};
+use rust_server::cache::ResponseCache;
+use rust_server::config::{Config, ConfigError, StaticMount, Tls, ENV_CONFIG};
 use rust_server::error::ServerError;
-// use rust_server::header::Header;
-use rust_server::header::{ContentType, HttpHeader};
-use rust_server::message::{Header, Request, ResponseBody, ResponseWriter};
-use rust_server::method::Method;
-use rust_server::server::{DefaultServeMux, Handler, ServeMux, Server};
-use std::collections::HashMap;
+use rust_server::error_pages::ErrorPages;
+use rust_server::message::{Request, ResponseBody, ResponseWriter};
+use rust_server::server::{DefaultServeMux, Handler, HandlerFunc};
+use rust_server::status_code::StatusCode;
+use std::env;
 use std::fs::File;
 use std::io::prelude::*;
-use std::sync::Arc;
+use std::num::NonZeroUsize;
+use std::path::PathBuf;
+use std::process;
 use std::thread;
 use std::time::Duration;
 
-fn main() -> Result<(), ServerError> {
+/// The configuration is invalid.
+const EXIT_CONFIG: i32 = 1;
+/// The server could not start, e.g. an address is in use.
+const EXIT_SERVE: i32 = 3;
+
+const EXIT_CODES: &str = "\
+Settings are read from the config file, then RUST_SERVER_<KEY> variables
+such as RUST_SERVER_THREADS=16 or RUST_SERVER_TIMEOUTS__READ=30s, then the
+options of `serve`.
+
+Exit status: 0 on success, 1 if the configuration is invalid, 2 on a usage
+error, 3 if the server cannot start.";
+
+/// An HTTP server.
+#[derive(Parser)]
+#[command(name = "rust_server", version, after_help = EXIT_CODES)]
+struct Cli {
+    /// Read settings from a TOML file
+    #[arg(short, long, global = true, env = ENV_CONFIG, value_name = "FILE")]
+    config: Option<PathBuf>,
+    /// Add the demo routes /hello, /sleep and /ping and the page 404.html
+    #[arg(long, global = true)]
+    example: bool,
+    /// Same as the check-config command
+    #[arg(long, hide = true)]
+    check_config: bool,
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Run the server, the default
+    Serve(ServeArgs),
+    /// Validate the configuration and exit
+    CheckConfig,
+    /// Print the route table
+    Routes,
+}
+
+#[derive(Args, Default)]
+struct ServeArgs {
+    /// Listen on ADDR instead of the configured addresses; repeatable
+    #[arg(long, value_name = "ADDR")]
+    addr: Vec<String>,
+    /// Number of worker threads
+    #[arg(long, value_name = "N")]
+    threads: Option<NonZeroUsize>,
+    /// Serve the files in DIR at /
+    #[arg(long, value_name = "DIR")]
+    root: Option<PathBuf>,
+    /// PEM certificate chain to serve TLS with
+    #[arg(long, value_name = "FILE", requires = "tls_key")]
+    tls_cert: Option<PathBuf>,
+    /// PEM private key of the certificate
+    #[arg(long, value_name = "FILE", requires = "tls_cert")]
+    tls_key: Option<PathBuf>,
+}
+
+impl ServeArgs {
+    /// Override the settings of `config` given on the command line.
+    fn apply(self, config: &mut Config) {
+        let cwd = env::current_dir().unwrap_or_default();
+        if !self.addr.is_empty() {
+            config.listen = self.addr;
+        }
+        if let Some(threads) = self.threads {
+            config.threads = threads.get();
+        }
+        if let Some(root) = self.root {
+            config.static_files.push(StaticMount {
+                mount: "/".to_string(),
+                root: cwd.join(root),
+            });
+        }
+        if let (Some(cert), Some(key)) = (self.tls_cert, self.tls_key) {
+            config.tls = Some(Tls {
+                cert: cwd.join(cert),
+                key: cwd.join(key),
+            });
+        }
+    }
+}
+
+fn main() {
+    let cli = Cli::parse();
+    let command = match cli.command {
+        Some(command) => command,
+        None if cli.check_config => Command::CheckConfig,
+        None => Command::Serve(ServeArgs::default()),
+    };
+    let source = cli
+        .config
+        .as_ref()
+        .map_or("config".to_string(), |p| p.display().to_string());
+    let invalid = |e: ConfigError| -> ! {
+        eprintln!("{}: {}", source, e);
+        process::exit(EXIT_CONFIG);
+    };
+    let mut config = match &cli.config {
+        Some(path) => Config::load(path),
+        None => Config::from_env(),
+    }
+    .unwrap_or_else(|e| invalid(e));
+
+    match command {
+        Command::CheckConfig => {
+            config.validate().unwrap_or_else(|e| invalid(e));
+            println!("{}: ok", source);
+        }
+        Command::Routes => {
+            config.validate().unwrap_or_else(|e| invalid(e));
+            let mut mux = routes(cli.example);
+            config.mount(&mut mux).unwrap_or_else(|e| invalid(e));
+            print_routes(&config, &mux);
+        }
+        Command::Serve(args) => {
+            args.apply(&mut config);
+            let server = config
+                .server(routes(cli.example))
+                .unwrap_or_else(|e| invalid(e));
+            let server = server.start().unwrap_or_else(|e| {
+                eprintln!("cannot listen on {}: {}", config.listen.join(", "), e);
+                process::exit(EXIT_SERVE);
+            });
+            for addr in server.local_addrs() {
+                println!("listening on {}", addr);
+            }
+            server.join();
+        }
+    }
+}
+
+/// The routes served besides those of the config.
+fn routes(example: bool) -> DefaultServeMux {
     let mut m = DefaultServeMux::new();
-    m.handle(Method::Get, "/hello".to_string(), Arc::new(Index::new()));
-    m.handle(Method::Get, "/sleep".to_string(), Arc::new(Sleep::new()));
-    let s = Server::new(8, "127.0.0.1:7878".to_string(), Arc::new(m));
-    s.listen_and_serve()
+    if !example {
+        return m;
+    }
+    m.get("/hello", Index::new());
+    m.get(
+        "/sleep",
+        ResponseCache::new(Sleep::new()).default_ttl(Duration::from_secs(60)),
+    );
+    m.get(
+        "/ping",
+        HandlerFunc::new(|_req, writer| {
+            let mut file = File::open("pong.html").unwrap();
+            let mut pong_html = String::new();
+            file.read_to_string(&mut pong_html).unwrap();
+
+            writer.html(&pong_html);
+            Ok(())
+        }),
+    );
+    m.set_error_pages(ErrorPages::new().file(StatusCode::NotFound, "404.html"));
+    m
+}
+
+/// One line per route with what serves it, when the config says.
+fn print_routes(config: &Config, mux: &DefaultServeMux) {
+    let mounted = |mount: &str, pattern: &str| {
+        let base = mount.trim_end_matches('/');
+        pattern == format!("{}/*path", base) || pattern == if base.is_empty() { "/" } else { base }
+    };
+    let mut rows: Vec<(&str, &str, String)> = Vec::new();
+    if let Some(path) = &config.metrics_path {
+        rows.push(("GET", path, "metrics".to_string()));
+    }
+    for (method, pattern) in mux.routes() {
+        let method = method.map_or("*", |m| m.as_str());
+        let static_files = config
+            .static_files
+            .iter()
+            .rev()
+            .find(|s| mounted(&s.mount, pattern));
+        let proxy = config
+            .proxy
+            .iter()
+            .rev()
+            .find(|p| mounted(&p.mount, pattern));
+        let target = match (static_files, proxy) {
+            (Some(s), _) => format!("files in {}", config.resolve(&s.root).display()),
+            (None, Some(p)) => format!("proxy to {}", p.upstreams.join(", ")),
+            (None, None) => String::new(),
+        };
+        rows.push((method, pattern, target));
+    }
+    let width = rows.iter().map(|(_, p, _)| p.len()).max().unwrap_or(0);
+    for (method, pattern, target) in rows {
+        let line = format!(
+            "{:<7} {:<width$}  {}",
+            method,
+            pattern,
+            target,
+            width = width
+        );
+        println!("{}", line.trim_end());
+    }
 }
 
 struct Index;
@@ -26,23 +223,7 @@ impl Handler for Index {
         writer: &mut dyn ResponseWriter,
         _req: &Request,
     ) -> Result<(), ServerError> {
-        // println!("not found");
-        let mut headers: Header = HashMap::new();
-        headers.insert("x-my-headers".to_string(), "hello world".to_string());
-        headers.insert(
-            HttpHeader::ContentType.as_str().to_string(),
-            ContentType::TextPlain.as_str().to_string(),
-        );
-        writer.header(headers);
-
-        // let mut file = File::open("hello.html").unwrap();
-        // let mut not_found_html = String::new();
-        // file.read_to_string(&mut not_found_html).unwrap();
-
-        // writer.write(ResponseBody::StringBody(not_found_html));
-        writer.write(ResponseBody::BytesBody("hello".as_bytes().to_vec()));
-        writer.write_header(200);
-        writer.send();
+        writer.text("hello");
         Ok(())
     }
 }
@@ -60,8 +241,6 @@ impl Handler for Sleep {
         _req: &Request,
     ) -> Result<(), ServerError> {
         let time = 5;
-        println!("sleep received");
-        println!("{} second sleeping", time);
         thread::sleep(Duration::from_secs(time));
         let mut file = File::open("hello.html").unwrap();
         let mut not_found_html = String::new();
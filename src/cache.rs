@@ -0,0 +1,454 @@
+//! In-process response cache.
+//!
+//! `ResponseCache` wraps a handler and keeps its `GET` responses in a
+//! `CacheStore` for as long as their `Cache-Control` or `Expires` headers
+//! allow, serving later requests from memory. `HEAD` requests are served
+//! from the `GET` entries.
+use crate::date::parse_http_date;
+use crate::error::ServerError;
+use crate::header::HttpHeader;
+use crate::message::{BufferedWriter, Request, Response, ResponseBody, ResponseWriter};
+use crate::method::Method;
+use crate::server::Handler;
+use crate::status_code::StatusCode;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+pub const AGE: &str = "Age";
+pub const EXPIRES: &str = "Expires";
+pub const VARY: &str = "Vary";
+/// `HIT`, `STALE` or `MISS`, telling how the cache answered.
+pub const X_CACHE: &str = "X-Cache";
+
+/// Directives of a `Cache-Control` value as lowercase `(name, argument)`.
+fn directives(value: &str) -> Vec<(String, Option<String>)> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|d| !d.is_empty())
+        .map(|d| match d.split_once('=') {
+            Some((name, arg)) => (
+                name.trim().to_ascii_lowercase(),
+                Some(arg.trim().trim_matches('"').to_string()),
+            ),
+            None => (d.to_ascii_lowercase(), None),
+        })
+        .collect()
+}
+
+fn seconds(directives: &[(String, Option<String>)], name: &str) -> Option<Duration> {
+    directives
+        .iter()
+        .find(|(n, _)| n == name)
+        .and_then(|(_, arg)| arg.as_deref()?.parse().ok())
+        .map(Duration::from_secs)
+}
+
+fn has(directives: &[(String, Option<String>)], name: &str) -> bool {
+    directives.iter().any(|(n, _)| n == name)
+}
+
+/// Statuses that may be cached given explicit freshness (RFC 7231
+/// section 6.1).
+fn cacheable_status(status: StatusCode) -> bool {
+    matches!(
+        status.as_num(),
+        200 | 203 | 204 | 300 | 301 | 404 | 405 | 410 | 414 | 501
+    )
+}
+
+struct Variant {
+    /// The `Vary` header names with the request values they had.
+    vary: Vec<(String, Option<String>)>,
+    response: Response,
+    stored: Instant,
+    fresh_for: Duration,
+    stale_for: Duration,
+    size: usize,
+    last_used: u64,
+    refreshing: bool,
+}
+
+impl Variant {
+    fn matches(&self, req: &Request) -> bool {
+        self.vary
+            .iter()
+            .all(|(name, value)| req.header(name) == value.as_deref())
+    }
+}
+
+#[derive(Default)]
+struct Entries {
+    map: HashMap<String, Vec<Variant>>,
+    size: usize,
+    clock: u64,
+}
+
+enum Lookup {
+    Fresh(Response, Duration),
+    /// Past its freshness but within stale-while-revalidate; the flag is
+    /// set for the one request that should trigger the refresh.
+    Stale(Response, Duration, bool),
+    Miss,
+}
+
+/// Shared storage of a `ResponseCache`, least recently used entries being
+/// evicted once the stored responses exceed the size limit.
+///
+/// Keys are the method and request target, e.g. `GET /news?page=2`.
+pub struct CacheStore {
+    entries: Mutex<Entries>,
+    max_bytes: usize,
+}
+
+impl CacheStore {
+    pub fn new(max_bytes: usize) -> Self {
+        CacheStore {
+            entries: Mutex::new(Entries::default()),
+            max_bytes,
+        }
+    }
+
+    /// Drop every variant stored under `key`; returns whether there were any.
+    pub fn purge(&self, key: &str) -> bool {
+        let mut entries = self.entries.lock().unwrap();
+        match entries.map.remove(key) {
+            Some(variants) => {
+                entries.size -= variants.iter().map(|v| v.size).sum::<usize>();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Drop every key starting with `prefix`; returns how many there were.
+    pub fn purge_prefix(&self, prefix: &str) -> usize {
+        let mut entries = self.entries.lock().unwrap();
+        let keys: Vec<String> = entries
+            .map
+            .keys()
+            .filter(|k| k.starts_with(prefix))
+            .cloned()
+            .collect();
+        for key in &keys {
+            if let Some(variants) = entries.map.remove(key) {
+                entries.size -= variants.iter().map(|v| v.size).sum::<usize>();
+            }
+        }
+        keys.len()
+    }
+
+    pub fn clear(&self) {
+        let mut entries = self.entries.lock().unwrap();
+        entries.map.clear();
+        entries.size = 0;
+    }
+
+    /// Number of stored responses, counting every variant.
+    pub fn len(&self) -> usize {
+        let entries = self.entries.lock().unwrap();
+        entries.map.values().map(Vec::len).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Approximate bytes held by the stored responses.
+    pub fn size(&self) -> usize {
+        self.entries.lock().unwrap().size
+    }
+
+    fn lookup(&self, key: &str, req: &Request) -> Lookup {
+        let mut entries = self.entries.lock().unwrap();
+        entries.clock += 1;
+        let clock = entries.clock;
+        let variants = match entries.map.get_mut(key) {
+            Some(variants) => variants,
+            None => return Lookup::Miss,
+        };
+        let variant = match variants.iter_mut().find(|v| v.matches(req)) {
+            Some(variant) => variant,
+            None => return Lookup::Miss,
+        };
+        let age = variant.stored.elapsed();
+        if age < variant.fresh_for {
+            variant.last_used = clock;
+            Lookup::Fresh(variant.response.clone(), age)
+        } else if age < variant.fresh_for + variant.stale_for {
+            variant.last_used = clock;
+            let refresh = !variant.refreshing;
+            variant.refreshing = true;
+            Lookup::Stale(variant.response.clone(), age, refresh)
+        } else {
+            Lookup::Miss
+        }
+    }
+
+    fn insert(&self, key: String, variant: Variant) {
+        if variant.size > self.max_bytes {
+            return;
+        }
+        let mut entries = self.entries.lock().unwrap();
+        entries.clock += 1;
+        let variant = Variant {
+            last_used: entries.clock,
+            ..variant
+        };
+        let variants = entries.map.entry(key).or_default();
+        let mut freed = 0;
+        variants.retain(|v| {
+            let same = v.vary == variant.vary;
+            if same {
+                freed += v.size;
+            }
+            !same
+        });
+        let added = variant.size;
+        variants.push(variant);
+        entries.size = entries.size - freed + added;
+
+        while entries.size > self.max_bytes {
+            let oldest = entries
+                .map
+                .iter()
+                .flat_map(|(k, vs)| vs.iter().enumerate().map(move |(i, v)| (k, i, v.last_used)))
+                .min_by_key(|(_, _, used)| *used)
+                .map(|(k, i, _)| (k.clone(), i));
+            let (key, i) = match oldest {
+                Some(oldest) => oldest,
+                None => break,
+            };
+            let variants = entries.map.get_mut(&key).unwrap();
+            let removed = variants.remove(i);
+            if variants.is_empty() {
+                entries.map.remove(&key);
+            }
+            entries.size -= removed.size;
+        }
+    }
+}
+
+impl Default for CacheStore {
+    /// A store holding up to 64 MiB.
+    fn default() -> Self {
+        CacheStore::new(64 << 20)
+    }
+}
+
+/// How a cacheable response may be stored.
+struct Policy {
+    fresh_for: Duration,
+    stale_for: Duration,
+}
+
+/// Decide from the response headers whether and how long `res` may be
+/// cached for a request made at `now`.
+fn policy(res: &Response, default_ttl: Option<Duration>, now: SystemTime) -> Option<Policy> {
+    if !cacheable_status(res.status_code) || res.headers.contains("Set-Cookie") {
+        return None;
+    }
+    // a streamed body is not held in the response
+    if res.body.is_none()
+        && (res.headers.contains("Content-Length") || res.headers.contains("Transfer-Encoding"))
+    {
+        return None;
+    }
+    if res.headers.get_all(VARY).any(|v| v.trim() == "*") {
+        return None;
+    }
+    let cc: Vec<(String, Option<String>)> = res
+        .headers
+        .get_all(HttpHeader::CacheControl.as_str())
+        .flat_map(directives)
+        .collect();
+    if has(&cc, "no-store") || has(&cc, "no-cache") || has(&cc, "private") {
+        return None;
+    }
+    let fresh_for = seconds(&cc, "s-maxage")
+        .or_else(|| seconds(&cc, "max-age"))
+        .or_else(|| {
+            let expires = parse_http_date(res.headers.get(EXPIRES)?)?;
+            Some(expires.duration_since(now).unwrap_or_default())
+        })
+        .or(default_ttl)?;
+    Some(Policy {
+        fresh_for,
+        stale_for: seconds(&cc, "stale-while-revalidate").unwrap_or_default(),
+    })
+}
+
+/// The `Vary` header names of `res` paired with the values `req` sent.
+fn vary_values(res: &Response, req: &Request) -> Vec<(String, Option<String>)> {
+    let mut vary: Vec<(String, Option<String>)> = res
+        .headers
+        .get_all(VARY)
+        .flat_map(|v| v.split(','))
+        .map(|name| name.trim().to_ascii_lowercase())
+        .filter(|name| !name.is_empty())
+        .map(|name| {
+            let value = req.header(&name).map(String::from);
+            (name, value)
+        })
+        .collect();
+    vary.sort();
+    vary.dedup();
+    vary
+}
+
+fn response_size(res: &Response) -> usize {
+    let headers: usize = res.headers.iter().map(|(k, v)| k.len() + v.len() + 4).sum();
+    headers + res.body_len()
+}
+
+fn store(
+    store: &CacheStore,
+    key: String,
+    req: &Request,
+    res: &Response,
+    default_ttl: Option<Duration>,
+) {
+    if let Some(policy) = policy(res, default_ttl, SystemTime::now()) {
+        let variant = Variant {
+            vary: vary_values(res, req),
+            response: res.clone(),
+            stored: Instant::now(),
+            fresh_for: policy.fresh_for,
+            stale_for: policy.stale_for,
+            size: response_size(res),
+            last_used: 0,
+            refreshing: false,
+        };
+        store.insert(key, variant);
+    }
+}
+
+fn replay(writer: &mut dyn ResponseWriter, res: Response, age: Duration, state: &str) {
+    writer.status(res.status_code);
+    // replace what the writer already holds, keeping repeated headers
+    let mut seen = HashSet::new();
+    for (name, value) in res.headers.iter() {
+        if seen.insert(name.to_ascii_lowercase()) {
+            writer.set_header(name, value);
+        } else {
+            writer.append_header(name, value);
+        }
+    }
+    writer.set_header(AGE, &age.as_secs().to_string());
+    writer.set_header(X_CACHE, state);
+    if let Some(ResponseBody::BytesBody(body)) = res.body {
+        writer.write(ResponseBody::BytesBody(body));
+    }
+}
+
+/// Middleware caching the responses of the handler it wraps.
+///
+/// Only responses with explicit freshness (`Cache-Control: max-age`,
+/// `s-maxage` or `Expires`) are stored unless a `default_ttl` is set, and
+/// never ones marked `no-store`, `no-cache` or `private` or setting cookies.
+/// Requests carrying `Authorization` bypass the cache, as do requests with
+/// `Cache-Control: no-store`; `no-cache` or `max-age=0` ones refresh it.
+pub struct ResponseCache {
+    inner: Arc<dyn Handler + Send + Sync>,
+    store: Arc<CacheStore>,
+    default_ttl: Option<Duration>,
+}
+
+impl ResponseCache {
+    pub fn new<H: Handler + Send + Sync + 'static>(inner: H) -> Self {
+        ResponseCache {
+            inner: Arc::new(inner),
+            store: Arc::new(CacheStore::default()),
+            default_ttl: None,
+        }
+    }
+
+    /// Keep responses in `store`, e.g. one shared with other caches or kept
+    /// for purging.
+    pub fn store(mut self, store: Arc<CacheStore>) -> Self {
+        self.store = store;
+        self
+    }
+
+    /// Cache responses without freshness headers for `ttl`.
+    pub fn default_ttl(mut self, ttl: Duration) -> Self {
+        self.default_ttl = Some(ttl);
+        self
+    }
+
+    /// Rerun the handler for `req` in the background and store the result.
+    fn revalidate(&self, key: String, req: &Request) {
+        let inner = self.inner.clone();
+        let store = self.store.clone();
+        let default_ttl = self.default_ttl;
+        let mut req = req.clone();
+        req.method = Method::Get;
+        thread::spawn(move || {
+            let mut writer = BufferedWriter::new();
+            let ok = inner.serve_http(&mut writer, &req).is_ok();
+            let res = writer.finish();
+            if ok && policy(&res, default_ttl, SystemTime::now()).is_some() {
+                self::store(&store, key, &req, &res, default_ttl);
+            } else {
+                // keep serving the stale response until it runs out
+                let mut entries = store.entries.lock().unwrap();
+                if let Some(variants) = entries.map.get_mut(&key) {
+                    for v in variants.iter_mut().filter(|v| v.matches(&req)) {
+                        v.refreshing = false;
+                    }
+                }
+            }
+        });
+    }
+}
+
+impl Handler for ResponseCache {
+    fn serve_http(
+        &self,
+        writer: &mut dyn ResponseWriter,
+        req: &Request,
+    ) -> Result<(), ServerError> {
+        if (req.method != Method::Get && req.method != Method::Head)
+            || req.header("Authorization").is_some()
+        {
+            return self.inner.serve_http(writer, req);
+        }
+        let request_cc = directives(req.header(HttpHeader::CacheControl.as_str()).unwrap_or(""));
+        if has(&request_cc, "no-store") {
+            return self.inner.serve_http(writer, req);
+        }
+        let key = format!("{} {}", Method::Get.as_str(), req.path);
+        let refresh = has(&request_cc, "no-cache")
+            || seconds(&request_cc, "max-age") == Some(Duration::from_secs(0));
+
+        if !refresh {
+            match self.store.lookup(&key, req) {
+                Lookup::Fresh(res, age) => {
+                    replay(writer, res, age, "HIT");
+                    return Ok(());
+                }
+                Lookup::Stale(res, age, revalidate) => {
+                    if revalidate {
+                        self.revalidate(key, req);
+                    }
+                    replay(writer, res, age, "STALE");
+                    return Ok(());
+                }
+                Lookup::Miss => {}
+            }
+        }
+
+        if req.method == Method::Get {
+            let store = self.store.clone();
+            let default_ttl = self.default_ttl;
+            let cached_req = req.clone();
+            writer.on_send(Box::new(move |res| {
+                self::store(&store, key, &cached_req, res, default_ttl);
+                res.headers.set(X_CACHE, "MISS");
+            }));
+        }
+        self.inner.serve_http(writer, req)
+    }
+}
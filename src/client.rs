@@ -0,0 +1,646 @@
+//! Blocking HTTP/1.1 client.
+//!
+//! `Client` sends a `Request` and reads back a `Response`, the same types
+//! the server uses. Connections are kept alive and reused per host,
+//! response bodies may be delimited by `Content-Length`, chunked or run
+//! until the connection closes, and redirects are followed up to a
+//! limit. `send_streamed` hands out the response body as a reader
+//! instead of buffering it.
+use crate::framing::{ChunkedWriter, Framing};
+use crate::header::{ContentType, HttpHeader};
+use crate::message::{Request, RequestBody, Response, ResponseBody};
+use crate::method::Method;
+use crate::status_code::StatusCode;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+#[derive(Debug)]
+pub enum ClientError {
+    InvalidUrl(String),
+    /// No connection could be made; the request was not sent.
+    Connect(io::Error),
+    Io(io::Error),
+    InvalidResponse(&'static str),
+    TooManyRedirects,
+}
+
+impl ClientError {
+    pub fn is_timeout(&self) -> bool {
+        match self {
+            ClientError::Connect(e) | ClientError::Io(e) => matches!(
+                e.kind(),
+                io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock
+            ),
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClientError::InvalidUrl(url) => write!(f, "invalid url: {}", url),
+            ClientError::Connect(e) => write!(f, "connect: {}", e),
+            ClientError::Io(e) => write!(f, "{}", e),
+            ClientError::InvalidResponse(msg) => write!(f, "invalid response: {}", msg),
+            ClientError::TooManyRedirects => f.write_str("too many redirects"),
+        }
+    }
+}
+
+impl Error for ClientError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ClientError::Connect(e) | ClientError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for ClientError {
+    fn from(e: io::Error) -> Self {
+        ClientError::Io(e)
+    }
+}
+
+/// An `http://` URL split into what the client needs.
+#[derive(Clone, PartialEq, Eq, Debug)]
+struct Url {
+    /// `host:port` to connect to.
+    addr: String,
+    /// The authority as written, for the `Host` header.
+    authority: String,
+    /// Path and query, starting with `/`.
+    target: String,
+}
+
+impl Url {
+    fn parse(url: &str) -> Result<Url, ClientError> {
+        let invalid = || ClientError::InvalidUrl(url.to_string());
+        let rest = url.strip_prefix("http://").ok_or_else(invalid)?;
+        let (authority, target) = match rest.find(['/', '?']) {
+            Some(i) if rest[i..].starts_with('?') => (&rest[..i], format!("/{}", &rest[i..])),
+            Some(i) => (&rest[..i], rest[i..].to_string()),
+            None => (rest, "/".to_string()),
+        };
+        if authority.is_empty() {
+            return Err(invalid());
+        }
+        let has_port = authority
+            .rfind(':')
+            .is_some_and(|i| !authority[i..].contains(']'));
+        let addr = if has_port {
+            authority.to_string()
+        } else {
+            format!("{}:80", authority)
+        };
+        // drop any fragment, it is never sent
+        let target = match target.find('#') {
+            Some(i) => target[..i].to_string(),
+            None => target,
+        };
+        Ok(Url {
+            addr,
+            authority: authority.to_string(),
+            target,
+        })
+    }
+
+    /// Resolve a `Location` header against this URL.
+    fn join(&self, location: &str) -> Result<Url, ClientError> {
+        if location.starts_with("http://") {
+            return Url::parse(location);
+        }
+        let target = if location.starts_with('/') {
+            location.to_string()
+        } else {
+            let path = self.target.split('?').next().unwrap_or("/");
+            let dir = &path[..path.rfind('/').map_or(0, |i| i + 1)];
+            format!("{}{}", dir, location)
+        };
+        Url::parse(&format!("http://{}{}", self.authority, target))
+    }
+}
+
+fn invalid(msg: &'static str) -> ClientError {
+    ClientError::InvalidResponse(msg)
+}
+
+/// Read a status line and headers, skipping interim `1xx` responses.
+/// Returns the response without a body and whether the connection may be
+/// reused.
+fn read_head<R: BufRead>(reader: &mut R) -> Result<(Response, bool), ClientError> {
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 {
+            return Err(ClientError::Io(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "connection closed before a response",
+            )));
+        }
+        let mut parts = line.trim_end().splitn(3, ' ');
+        let version = parts.next().unwrap_or("").to_string();
+        let status: u16 = parts
+            .next()
+            .and_then(|s| s.parse().ok())
+            .ok_or_else(|| invalid("bad status line"))?;
+        if !version.starts_with("HTTP/1.") {
+            return Err(invalid("bad status line"));
+        }
+
+        let mut res = Response::new();
+        res.version = version;
+        res.status_code =
+            StatusCode::from_num(status as usize).unwrap_or(StatusCode::Other(status));
+        let mut line = String::new();
+        loop {
+            line.clear();
+            if reader.read_line(&mut line)? == 0 {
+                return Err(invalid("truncated response head"));
+            }
+            let line = line.trim_end();
+            if line.is_empty() {
+                break;
+            }
+            let (name, value) = line.split_once(':').ok_or_else(|| invalid("bad header"))?;
+            res.headers.append(name.trim(), value.trim());
+        }
+        if (100..200).contains(&status) && status != 101 {
+            continue;
+        }
+
+        if let Some(ct) = res.headers.get(HttpHeader::ContentType.as_str()) {
+            let media_type = ct.split(';').next().unwrap_or("").trim();
+            if let Ok(ct) = ContentType::from_str(media_type) {
+                res.content_type = ct;
+            }
+        }
+        let connection = res
+            .headers
+            .get("Connection")
+            .unwrap_or("")
+            .to_ascii_lowercase();
+        let keep_alive = if res.version == "HTTP/1.0" {
+            connection.contains("keep-alive")
+        } else {
+            !connection.contains("close")
+        };
+        return Ok((res, keep_alive));
+    }
+}
+
+fn framing(res: &Response, method: Method) -> Result<Framing, ClientError> {
+    let status = res.status_code.as_num();
+    if method == Method::Head || status < 200 || status == 204 || status == 304 {
+        return Ok(Framing::Empty);
+    }
+    let chunked = res
+        .headers
+        .get("Transfer-Encoding")
+        .is_some_and(|te| te.to_ascii_lowercase().contains("chunked"));
+    if chunked {
+        return Ok(Framing::chunked());
+    }
+    match res.headers.get(HttpHeader::ContentLength.as_str()) {
+        Some(len) => len
+            .trim()
+            .parse()
+            .map(Framing::Length)
+            .map_err(|_| invalid("bad Content-Length")),
+        None => Ok(Framing::UntilClose),
+    }
+}
+
+/// Whether a pooled connection is still open: the server has neither
+/// closed it nor sent anything on it while it was idle.
+fn still_open(stream: &TcpStream) -> bool {
+    if stream.set_nonblocking(true).is_err() {
+        return false;
+    }
+    let open = matches!(
+        stream.peek(&mut [0]),
+        Err(ref e) if e.kind() == io::ErrorKind::WouldBlock
+    );
+    stream.set_nonblocking(false).is_ok() && open
+}
+
+/// An exchange that failed, and how far it got.
+struct Failed {
+    error: ClientError,
+    /// The request was written whole, so the server may have acted on it.
+    sent: bool,
+    /// Nothing of a streamed body had been taken.
+    replayable: bool,
+}
+
+type Pool = Mutex<HashMap<String, Vec<TcpStream>>>;
+
+/// A response body being read from the connection. Once read to the end
+/// the connection goes back to the client's pool.
+pub struct Body {
+    reader: Option<BufReader<TcpStream>>,
+    framing: Framing,
+    reusable: bool,
+    pool: Arc<Pool>,
+    addr: String,
+    max_idle: usize,
+}
+
+impl Body {
+    /// The length announced by `Content-Length`, if any.
+    pub fn content_length(&self) -> Option<u64> {
+        match self.framing {
+            Framing::Length(n) => Some(n),
+            _ => None,
+        }
+    }
+
+    /// Whether the response has no body at all, e.g. answering `HEAD`.
+    pub fn is_empty(&self) -> bool {
+        matches!(self.framing, Framing::Empty)
+    }
+
+    /// Whether the whole body has been read.
+    pub(crate) fn finished(&self) -> bool {
+        self.framing.finished()
+    }
+
+    /// Put the connection back into the pool if the body was read whole.
+    fn release(&mut self) {
+        if !self.reusable || !self.finished() {
+            return;
+        }
+        if let Some(reader) = self.reader.take() {
+            if reader.buffer().is_empty() {
+                let mut pool = self.pool.lock().unwrap();
+                let idle = pool.entry(self.addr.clone()).or_default();
+                if idle.len() < self.max_idle {
+                    idle.push(reader.into_inner());
+                }
+            }
+        }
+    }
+}
+
+impl Read for Body {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = match &mut self.reader {
+            Some(reader) => self.framing.read(reader, buf)?,
+            None => return Ok(0),
+        };
+        // pooled as soon as it is free, not only once the body is dropped
+        if self.finished() {
+            self.release();
+        }
+        Ok(n)
+    }
+}
+
+impl Drop for Body {
+    fn drop(&mut self) {
+        self.release();
+    }
+}
+
+impl fmt::Debug for Body {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Body")
+            .field("content_length", &self.content_length())
+            .finish()
+    }
+}
+
+/// The request line and headers of `req` for `url`, followed by the body
+/// unless it is streamed. A streamed body of unknown length is sent
+/// chunked.
+fn serialize(req: &Request, url: &Url) -> Vec<u8> {
+    let mut head = format!("{} {} HTTP/1.1\r\n", req.method, url.target);
+    if req.header("Host").is_none() {
+        head.push_str(&format!("Host: {}\r\n", url.authority));
+    }
+    for (name, value) in &req.headers {
+        if name.eq_ignore_ascii_case("Content-Length")
+            || name.eq_ignore_ascii_case("Transfer-Encoding")
+        {
+            continue;
+        }
+        head.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    match req.body_length() {
+        Some(len)
+            if len > 0 || matches!(req.method, Method::Post | Method::Put | Method::Patch) =>
+        {
+            head.push_str(&format!("Content-Length: {}\r\n", len))
+        }
+        Some(_) => {}
+        None => head.push_str("Transfer-Encoding: chunked\r\n"),
+    }
+    head.push_str("\r\n");
+    let mut bytes = head.into_bytes();
+    if !req.is_streamed() {
+        bytes.extend_from_slice(req.body_bytes());
+    }
+    bytes
+}
+
+/// Send the streamed body of `req` after its head.
+fn write_streamed(mut stream: &TcpStream, req: &Request) -> io::Result<()> {
+    let mut body = req.body_reader();
+    match req.body_length() {
+        Some(len) => {
+            let copied = io::copy(&mut (&mut body).take(len), &mut stream)?;
+            if copied < len {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "request body shorter than its length",
+                ));
+            }
+        }
+        None => {
+            io::copy(&mut body, &mut ChunkedWriter(stream))?;
+            stream.write_all(b"0\r\n\r\n")?;
+        }
+    }
+    Ok(())
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Config {
+    connect_timeout: Duration,
+    timeout: Option<Duration>,
+    max_redirects: usize,
+    max_idle_per_host: usize,
+}
+
+/// A blocking HTTP/1.1 client. Clones share the connection pool.
+///
+/// Requests are addressed by an absolute `http://` URL in `Request::path`;
+/// the builder methods (`get`, `post`, ...) set it up. A `Host` header in
+/// the request is kept, otherwise it comes from the URL.
+#[derive(Clone)]
+pub struct Client {
+    config: Config,
+    pool: Arc<Pool>,
+}
+
+impl Default for Client {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Client {
+    /// A client with a 10 second connect timeout, no read timeout, up to
+    /// 10 redirects and 8 idle connections per host.
+    pub fn new() -> Self {
+        Client {
+            config: Config {
+                connect_timeout: Duration::from_secs(10),
+                timeout: None,
+                max_redirects: 10,
+                max_idle_per_host: 8,
+            },
+            pool: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.config.connect_timeout = timeout;
+        self
+    }
+
+    /// Limit on each read and write once connected.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.config.timeout = Some(timeout);
+        self
+    }
+
+    /// Redirects followed before giving up; 0 returns redirects as they are.
+    pub fn max_redirects(mut self, max: usize) -> Self {
+        self.config.max_redirects = max;
+        self
+    }
+
+    pub fn max_idle_per_host(mut self, max: usize) -> Self {
+        self.config.max_idle_per_host = max;
+        self
+    }
+
+    pub fn request(&self, method: Method, url: &str) -> RequestBuilder<'_> {
+        let mut req = Request::new();
+        req.method = method;
+        req.path = url.to_string();
+        RequestBuilder { client: self, req }
+    }
+
+    pub fn get(&self, url: &str) -> RequestBuilder<'_> {
+        self.request(Method::Get, url)
+    }
+
+    pub fn head(&self, url: &str) -> RequestBuilder<'_> {
+        self.request(Method::Head, url)
+    }
+
+    pub fn post(&self, url: &str) -> RequestBuilder<'_> {
+        self.request(Method::Post, url)
+    }
+
+    pub fn put(&self, url: &str) -> RequestBuilder<'_> {
+        self.request(Method::Put, url)
+    }
+
+    pub fn patch(&self, url: &str) -> RequestBuilder<'_> {
+        self.request(Method::Patch, url)
+    }
+
+    pub fn delete(&self, url: &str) -> RequestBuilder<'_> {
+        self.request(Method::Delete, url)
+    }
+
+    /// Send `req` and read the whole response.
+    pub fn send(&self, req: &Request) -> Result<Response, ClientError> {
+        let (mut res, mut body) = self.send_streamed(req)?;
+        let mut buf = Vec::new();
+        body.read_to_end(&mut buf)?;
+        if !body.is_empty() {
+            res.body = Some(ResponseBody::BytesBody(buf));
+        }
+        Ok(res)
+    }
+
+    /// Send `req`, returning once the response head has arrived.
+    pub fn send_streamed(&self, req: &Request) -> Result<(Response, Body), ClientError> {
+        let mut url = Url::parse(&req.path)?;
+        let mut req = req.clone();
+        let mut redirects = 0;
+        loop {
+            let (res, mut body) = self.exchange(&url, &req)?;
+            let status = res.status_code.as_num();
+            let location = res.headers.get(HttpHeader::Location.as_str());
+            let location = match location {
+                Some(l) if matches!(status, 301 | 302 | 303 | 307 | 308) => l,
+                _ => return Ok((res, body)),
+            };
+            if self.config.max_redirects == 0 {
+                return Ok((res, body));
+            }
+            redirects += 1;
+            if redirects > self.config.max_redirects {
+                return Err(ClientError::TooManyRedirects);
+            }
+
+            let next = url.join(location)?;
+            // drain so the connection can be reused
+            let _ = io::copy(&mut body, &mut io::sink());
+            let to_get =
+                status == 303 || (matches!(status, 301 | 302) && req.method == Method::Post);
+            if to_get && req.method != Method::Head {
+                req.method = Method::Get;
+                req.body = None;
+                req.headers
+                    .retain(|k, _| !k.eq_ignore_ascii_case(HttpHeader::ContentType.as_str()));
+            }
+            if next.authority != url.authority {
+                req.headers.retain(|k, _| {
+                    !["Host", "Authorization", "Cookie"]
+                        .iter()
+                        .any(|h| h.eq_ignore_ascii_case(k))
+                });
+            }
+            url = next;
+        }
+    }
+
+    fn connect(&self, addr: &str) -> Result<TcpStream, ClientError> {
+        let mut last = io::Error::new(io::ErrorKind::NotFound, "host did not resolve");
+        for a in addr.to_socket_addrs().map_err(ClientError::Connect)? {
+            match TcpStream::connect_timeout(&a, self.config.connect_timeout) {
+                Ok(stream) => {
+                    stream.set_read_timeout(self.config.timeout)?;
+                    stream.set_write_timeout(self.config.timeout)?;
+                    stream.set_nodelay(true)?;
+                    return Ok(stream);
+                }
+                Err(e) => last = e,
+            }
+        }
+        Err(ClientError::Connect(last))
+    }
+
+    /// One request and response on a pooled or new connection. A failure
+    /// on a pooled connection is retried on a new one for idempotent
+    /// requests, and for others only if the request could not be written:
+    /// once it was, the server may have acted on it. A streamed body is
+    /// sent once: the request is not retried after any of it was taken.
+    fn exchange(&self, url: &Url, req: &Request) -> Result<(Response, Body), ClientError> {
+        let bytes = serialize(req, url);
+        let idempotent = !matches!(req.method, Method::Post | Method::Patch);
+        let pooled = {
+            let mut pool = self.pool.lock().unwrap();
+            let idle = pool.get_mut(&url.addr);
+            idle.and_then(|idle| std::iter::from_fn(|| idle.pop()).find(still_open))
+        };
+        if let Some(stream) = pooled {
+            match self.exchange_on(stream, url, req, &bytes) {
+                Err(Failed {
+                    error: ClientError::Io(_),
+                    sent,
+                    replayable: true,
+                }) if idempotent || !sent => {}
+                result => return result.map_err(|f| f.error),
+            }
+        }
+        let stream = self.connect(&url.addr)?;
+        self.exchange_on(stream, url, req, &bytes)
+            .map_err(|f| f.error)
+    }
+
+    fn exchange_on(
+        &self,
+        stream: TcpStream,
+        url: &Url,
+        req: &Request,
+        bytes: &[u8],
+    ) -> Result<(Response, Body), Failed> {
+        let streamed = req.is_streamed();
+        (&stream).write_all(bytes).map_err(|e| Failed {
+            error: e.into(),
+            sent: false,
+            replayable: true,
+        })?;
+        if streamed {
+            write_streamed(&stream, req).map_err(|e| Failed {
+                error: e.into(),
+                sent: false,
+                replayable: false,
+            })?;
+        }
+        let failed = |e: ClientError| Failed {
+            error: e,
+            sent: true,
+            replayable: !streamed,
+        };
+        let mut reader = BufReader::new(stream);
+        let (res, keep_alive) = read_head(&mut reader).map_err(failed)?;
+        let framing = framing(&res, req.method).map_err(failed)?;
+        let body = Body {
+            reader: Some(reader),
+            framing,
+            reusable: keep_alive,
+            pool: self.pool.clone(),
+            addr: url.addr.clone(),
+            max_idle: self.config.max_idle_per_host,
+        };
+        Ok((res, body))
+    }
+}
+
+/// A request being put together; see `Client::request`.
+pub struct RequestBuilder<'a> {
+    client: &'a Client,
+    req: Request,
+}
+
+impl RequestBuilder<'_> {
+    /// Set a header, replacing an earlier value.
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.req
+            .headers
+            .retain(|k, _| !k.eq_ignore_ascii_case(name));
+        self.req.headers.insert(name.to_string(), value.to_string());
+        self
+    }
+
+    pub fn body<B: Into<Vec<u8>>>(mut self, body: B) -> Self {
+        self.req.body = Some(RequestBody::BytesBody(body.into()));
+        self
+    }
+
+    pub fn bearer_auth(self, token: &str) -> Self {
+        self.header("Authorization", &format!("Bearer {}", token))
+    }
+
+    /// The request as built so far.
+    pub fn build(self) -> Request {
+        self.req
+    }
+
+    pub fn send(self) -> Result<Response, ClientError> {
+        self.client.send(&self.req)
+    }
+
+    pub fn send_streamed(self) -> Result<(Response, Body), ClientError> {
+        self.client.send_streamed(&self.req)
+    }
+}
+
+/// `GET` `url` with a one-off client.
+pub fn get(url: &str) -> Result<Response, ClientError> {
+    Client::new().get(url).send()
+}
@@ -0,0 +1,380 @@
+//! Validators, conditional requests and `Cache-Control`.
+//!
+//! `ConditionalHandler` wraps a handler and evaluates `If-Match`,
+//! `If-None-Match`, `If-Modified-Since` and `If-Unmodified-Since` against
+//! the `ETag` and `Last-Modified` of whatever response it produced, turning
+//! it into `304 Not Modified` or `412 Precondition Failed` as RFC 7232
+//! requires. Handlers changing state should call `check_preconditions`
+//! themselves before acting, since by the time the response is built the
+//! change has been made.
+use crate::date::{parse_http_date, DateTime};
+use crate::error::ServerError;
+use crate::header::HttpHeader;
+use crate::message::{Request, Response, ResponseBody, ResponseWriter};
+use crate::method::Method;
+use crate::server::Handler;
+use crate::status_code::StatusCode;
+use std::fmt;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// An entity tag (RFC 7232 section 2.3).
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub struct ETag {
+    tag: String,
+    weak: bool,
+}
+
+/// 64-bit FNV-1a, stable across runs and platforms unlike `DefaultHasher`.
+fn fnv1a(data: &[u8]) -> u64 {
+    data.iter().fold(0xcbf2_9ce4_8422_2325, |h, b| {
+        (h ^ u64::from(*b)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+impl ETag {
+    /// A strong tag; `tag` must not contain `"`.
+    pub fn strong(tag: &str) -> Self {
+        ETag {
+            tag: tag.to_string(),
+            weak: false,
+        }
+    }
+
+    pub fn weak(tag: &str) -> Self {
+        ETag {
+            tag: tag.to_string(),
+            weak: true,
+        }
+    }
+
+    /// A strong tag derived from the bytes of `body`.
+    pub fn from_body(body: &[u8]) -> Self {
+        ETag::strong(&format!("{:x}-{:016x}", body.len(), fnv1a(body)))
+    }
+
+    /// A weak tag derived from `body`, for representations that are
+    /// equivalent but not byte-identical, e.g. after compression.
+    pub fn weak_from_body(body: &[u8]) -> Self {
+        ETag {
+            weak: true,
+            ..ETag::from_body(body)
+        }
+    }
+
+    /// Parse `"tag"` or `W/"tag"`.
+    pub fn parse(s: &str) -> Option<Self> {
+        let s = s.trim();
+        let (weak, quoted) = match s.strip_prefix("W/") {
+            Some(rest) => (true, rest),
+            None => (false, s),
+        };
+        let tag = quoted.strip_prefix('"')?.strip_suffix('"')?;
+        if tag.contains('"') {
+            return None;
+        }
+        Some(ETag {
+            tag: tag.to_string(),
+            weak,
+        })
+    }
+
+    pub fn tag(&self) -> &str {
+        &self.tag
+    }
+
+    pub fn is_weak(&self) -> bool {
+        self.weak
+    }
+
+    /// Both tags are strong and identical; used by `If-Match`.
+    pub fn strong_eq(&self, other: &ETag) -> bool {
+        !self.weak && !other.weak && self.tag == other.tag
+    }
+
+    /// The tags are identical, ignoring weakness; used by `If-None-Match`.
+    pub fn weak_eq(&self, other: &ETag) -> bool {
+        self.tag == other.tag
+    }
+}
+
+impl fmt::Display for ETag {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.weak {
+            f.write_str("W/")?;
+        }
+        write!(f, "\"{}\"", self.tag)
+    }
+}
+
+/// Whether an `If-Match` / `If-None-Match` value matches `etag`.
+fn list_matches(header: &str, etag: Option<&ETag>, cmp: fn(&ETag, &ETag) -> bool) -> bool {
+    if header.trim() == "*" {
+        return etag.is_some();
+    }
+    let etag = match etag {
+        Some(etag) => etag,
+        None => return false,
+    };
+    split_tags(header)
+        .filter_map(ETag::parse)
+        .any(|t| cmp(&t, etag))
+}
+
+/// The members of a list of entity tags, split at the commas between
+/// them; a tag may contain commas of its own (RFC 9110 section 8.8.3).
+fn split_tags(header: &str) -> impl Iterator<Item = &str> {
+    let mut quoted = false;
+    header.split(move |c| {
+        if c == '"' {
+            quoted = !quoted;
+        }
+        c == ',' && !quoted
+    })
+}
+
+/// Drop sub-second precision, which HTTP dates cannot carry.
+fn truncate(t: SystemTime) -> SystemTime {
+    let secs = t.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+    UNIX_EPOCH + Duration::from_secs(secs)
+}
+
+/// Evaluate the conditional headers of `req` for a resource whose current
+/// validators are `etag` and `last_modified` (`None` when it has none or
+/// does not exist). Returns `304 Not Modified` or `412 Precondition
+/// Failed` when the request should not proceed.
+pub fn check_preconditions(
+    req: &Request,
+    etag: Option<&ETag>,
+    last_modified: Option<SystemTime>,
+) -> Option<StatusCode> {
+    let header = |h: HttpHeader| req.header(h.as_str());
+    let last_modified = last_modified.map(truncate);
+    let safe = req.method == Method::Get || req.method == Method::Head;
+
+    if let Some(if_match) = header(HttpHeader::IfMatch) {
+        if !list_matches(if_match, etag, ETag::strong_eq) {
+            return Some(StatusCode::PreconditionFailed);
+        }
+    } else if let Some(since) = header(HttpHeader::IfUnmodifiedSince).and_then(parse_http_date) {
+        if last_modified.is_some_and(|lm| lm > since) {
+            return Some(StatusCode::PreconditionFailed);
+        }
+    }
+
+    if let Some(if_none_match) = header(HttpHeader::IfNoneMatch) {
+        if list_matches(if_none_match, etag, ETag::weak_eq) {
+            return Some(if safe {
+                StatusCode::NotModified
+            } else {
+                StatusCode::PreconditionFailed
+            });
+        }
+    } else if safe {
+        if let Some(since) = header(HttpHeader::IfModifiedSince).and_then(parse_http_date) {
+            if last_modified.is_some_and(|lm| lm <= since) {
+                return Some(StatusCode::NotModified);
+            }
+        }
+    }
+    None
+}
+
+/// Builder for `Cache-Control` response directives.
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct CacheControl {
+    public: bool,
+    private: bool,
+    no_cache: bool,
+    no_store: bool,
+    no_transform: bool,
+    must_revalidate: bool,
+    proxy_revalidate: bool,
+    immutable: bool,
+    max_age: Option<Duration>,
+    s_maxage: Option<Duration>,
+    stale_while_revalidate: Option<Duration>,
+    stale_if_error: Option<Duration>,
+}
+
+impl CacheControl {
+    pub fn new() -> Self {
+        CacheControl::default()
+    }
+
+    pub fn public(mut self) -> Self {
+        self.public = true;
+        self
+    }
+
+    pub fn private(mut self) -> Self {
+        self.private = true;
+        self
+    }
+
+    /// Caches may store the response but must revalidate before each use.
+    pub fn no_cache(mut self) -> Self {
+        self.no_cache = true;
+        self
+    }
+
+    pub fn no_store(mut self) -> Self {
+        self.no_store = true;
+        self
+    }
+
+    pub fn no_transform(mut self) -> Self {
+        self.no_transform = true;
+        self
+    }
+
+    pub fn must_revalidate(mut self) -> Self {
+        self.must_revalidate = true;
+        self
+    }
+
+    pub fn proxy_revalidate(mut self) -> Self {
+        self.proxy_revalidate = true;
+        self
+    }
+
+    /// The response never changes while fresh; browsers skip revalidation
+    /// on reload.
+    pub fn immutable(mut self) -> Self {
+        self.immutable = true;
+        self
+    }
+
+    pub fn max_age(mut self, age: Duration) -> Self {
+        self.max_age = Some(age);
+        self
+    }
+
+    /// `max-age` for shared caches.
+    pub fn s_maxage(mut self, age: Duration) -> Self {
+        self.s_maxage = Some(age);
+        self
+    }
+
+    pub fn stale_while_revalidate(mut self, window: Duration) -> Self {
+        self.stale_while_revalidate = Some(window);
+        self
+    }
+
+    pub fn stale_if_error(mut self, window: Duration) -> Self {
+        self.stale_if_error = Some(window);
+        self
+    }
+}
+
+impl fmt::Display for CacheControl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let flags = [
+            (self.public, "public"),
+            (self.private, "private"),
+            (self.no_cache, "no-cache"),
+            (self.no_store, "no-store"),
+            (self.no_transform, "no-transform"),
+            (self.must_revalidate, "must-revalidate"),
+            (self.proxy_revalidate, "proxy-revalidate"),
+            (self.immutable, "immutable"),
+        ];
+        let durations = [
+            (self.max_age, "max-age"),
+            (self.s_maxage, "s-maxage"),
+            (self.stale_while_revalidate, "stale-while-revalidate"),
+            (self.stale_if_error, "stale-if-error"),
+        ];
+        let directives: Vec<String> = flags
+            .iter()
+            .filter(|(set, _)| *set)
+            .map(|(_, name)| name.to_string())
+            .chain(
+                durations
+                    .iter()
+                    .filter_map(|(d, name)| d.map(|d| format!("{}={}", name, d.as_secs()))),
+            )
+            .collect();
+        f.write_str(&directives.join(", "))
+    }
+}
+
+/// `t` as an HTTP date, for `Last-Modified` and `Expires`.
+pub fn http_date(t: SystemTime) -> String {
+    DateTime::from_system_time(t).to_http_date()
+}
+
+/// Turn `res` into the `304` or `412` response for `status`.
+fn apply(res: &mut Response, status: StatusCode) {
+    res.status_code = status;
+    if status == StatusCode::NotModified {
+        res.body = None;
+        res.headers.remove(HttpHeader::ContentType.as_str());
+    } else {
+        res.headers.remove(HttpHeader::ETag.as_str());
+        res.headers.remove(HttpHeader::LastModified.as_str());
+        res.headers.set(
+            HttpHeader::ContentType.as_str(),
+            "text/plain; charset=utf-8",
+        );
+        res.body = Some(ResponseBody::BytesBody(b"Precondition Failed".to_vec()));
+    }
+}
+
+/// Middleware answering conditional `GET` and `HEAD` requests.
+///
+/// Successful responses without an `ETag` get a strong one computed from
+/// their body unless `auto_etag(false)` is set.
+pub struct ConditionalHandler {
+    inner: Arc<dyn Handler + Send + Sync>,
+    auto_etag: bool,
+}
+
+impl ConditionalHandler {
+    pub fn new<H: Handler + Send + Sync + 'static>(inner: H) -> Self {
+        ConditionalHandler {
+            inner: Arc::new(inner),
+            auto_etag: true,
+        }
+    }
+
+    pub fn auto_etag(mut self, enabled: bool) -> Self {
+        self.auto_etag = enabled;
+        self
+    }
+}
+
+impl Handler for ConditionalHandler {
+    fn serve_http(
+        &self,
+        writer: &mut dyn ResponseWriter,
+        req: &Request,
+    ) -> Result<(), ServerError> {
+        if req.method != Method::Get && req.method != Method::Head {
+            return self.inner.serve_http(writer, req);
+        }
+        let auto_etag = self.auto_etag;
+        let conditions = req.clone();
+        writer.on_send(Box::new(move |res| {
+            if !(200..300).contains(&res.status_code.as_num()) {
+                return;
+            }
+            let etag_header = HttpHeader::ETag.as_str();
+            if auto_etag && !res.headers.contains(etag_header) {
+                if let Some(ResponseBody::BytesBody(body)) = &res.body {
+                    let etag = ETag::from_body(body);
+                    res.headers.set(etag_header, &etag.to_string());
+                }
+            }
+            let etag = res.headers.get(etag_header).and_then(ETag::parse);
+            let last_modified = res
+                .headers
+                .get(HttpHeader::LastModified.as_str())
+                .and_then(parse_http_date);
+            if let Some(status) = check_preconditions(&conditions, etag.as_ref(), last_modified) {
+                apply(res, status);
+            }
+        }));
+        self.inner.serve_http(writer, req)
+    }
+}
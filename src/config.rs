@@ -0,0 +1,750 @@
+//! Configuration files.
+//!
+//! A `Config` is read from TOML and describes a whole `Server`: listeners,
+//! the thread pool, timeouts, limits, TLS, the access log, directories
+//! served as static files, reverse proxy routes and error pages.
+//!
+//! ```toml
+//! threads = 8
+//! listen = ["0.0.0.0:8080", "unix:/run/app.sock"]
+//! metrics_path = "/metrics"
+//!
+//! [timeouts]
+//! read = "30s"
+//! shutdown = "10s"
+//!
+//! [limits]
+//! max_body_size = "10MiB"
+//!
+//! [tls]
+//! cert = "cert.pem"
+//! key = "key.pem"
+//!
+//! [log]
+//! format = "json"
+//! file = "access.log"
+//! max_size = "100MB"
+//!
+//! [[static]]
+//! mount = "/assets"
+//! root = "public"
+//!
+//! [[proxy]]
+//! mount = "/api"
+//! upstreams = ["http://127.0.0.1:9000", "http://127.0.0.1:9001"]
+//! balance = "least_connections"
+//!
+//! [error_pages]
+//! 404 = "404.html"
+//! ```
+//!
+//! Relative paths are taken from the directory of the config file.
+//! Durations are seconds or strings such as `"500ms"`, `"30s"`, `"5m"`;
+//! sizes are bytes or strings such as `"64KiB"` or `"10MB"`.
+//!
+//! Environment variables override single keys: `RUST_SERVER_THREADS=16`
+//! sets `threads` and `RUST_SERVER_TIMEOUTS__READ=10s` sets
+//! `timeouts.read`, nested keys being separated by `__`. Values are read
+//! as TOML, e.g. `true` or `["a", "b"]`, and as a string when they are not
+//! valid TOML.
+//!
+//! Errors name the offending key, e.g. `proxy[1].upstreams[0]`, and the
+//! variable that set it when it came from the environment.
+use crate::error_pages::ErrorPages;
+use crate::files::FileServer;
+use crate::log::{AccessLog, LogFormat, RotatingFile};
+use crate::message::{Request, ResponseWriter};
+use crate::proxy::{Balance, ReverseProxy, Upstream};
+use crate::server::{DefaultServeMux, Handler, Server};
+use crate::status_code::StatusCode;
+#[cfg(feature = "tls")]
+use crate::tls::TlsConfig;
+use serde::de::{Deserializer, Error as _};
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::fs;
+use std::net::ToSocketAddrs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Prefix of the environment variables overriding config keys.
+pub const ENV_PREFIX: &str = "RUST_SERVER_";
+/// The variable naming the config file, which is not a key.
+pub const ENV_CONFIG: &str = "RUST_SERVER_CONFIG";
+
+/// The settings of a server, see the module documentation.
+#[derive(Clone, PartialEq, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// Worker threads serving connections, 8 by default.
+    #[serde(default = "default_threads")]
+    pub threads: usize,
+    /// Addresses to accept connections on, `127.0.0.1:7878` by default.
+    /// A single address may be given as a string.
+    #[serde(default = "default_listen", deserialize_with = "strings")]
+    pub listen: Vec<String>,
+    #[serde(default = "enabled")]
+    pub http2: bool,
+    /// Where Prometheus metrics are served, nowhere by default.
+    pub metrics_path: Option<String>,
+    #[serde(default)]
+    pub timeouts: Timeouts,
+    #[serde(default)]
+    pub limits: Limits,
+    pub tls: Option<Tls>,
+    #[serde(default)]
+    pub log: Log,
+    #[serde(default, rename = "static")]
+    pub static_files: Vec<StaticMount>,
+    #[serde(default)]
+    pub proxy: Vec<ProxyRoute>,
+    /// Files sent as the body of error responses, by status code.
+    #[serde(default)]
+    pub error_pages: BTreeMap<String, PathBuf>,
+    /// Where relative paths are taken from.
+    #[serde(skip)]
+    pub base_dir: PathBuf,
+    /// Keys set from the environment and the variables that set them.
+    #[serde(skip)]
+    overrides: Vec<(String, String)>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Timeouts {
+    /// How long a client may send nothing while the server waits for its
+    /// request; no limit by default.
+    #[serde(default, deserialize_with = "option_duration")]
+    pub read: Option<Duration>,
+    /// How long a shutdown waits for open connections, 5 seconds by
+    /// default.
+    #[serde(default = "default_shutdown", deserialize_with = "duration")]
+    pub shutdown: Duration,
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Timeouts {
+            read: None,
+            shutdown: default_shutdown(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Limits {
+    /// Largest request body accepted.
+    #[serde(default, deserialize_with = "option_bytes")]
+    pub max_body_size: Option<u64>,
+    /// Read bodies before running handlers rather than as they ask.
+    #[serde(default)]
+    pub buffer_bodies: bool,
+}
+
+#[derive(Clone, PartialEq, Eq, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Tls {
+    /// PEM certificate chain, leaf first.
+    pub cert: PathBuf,
+    /// PEM private key.
+    pub key: PathBuf,
+}
+
+#[derive(Clone, PartialEq, Eq, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Log {
+    /// `common`, `combined` (the default), `json` or `off`.
+    #[serde(default = "default_log_format", deserialize_with = "log_format")]
+    pub format: Option<LogFormat>,
+    /// File to append to instead of standard output.
+    pub file: Option<PathBuf>,
+    /// Rotate the file once it grows past this size.
+    #[serde(default, deserialize_with = "option_bytes")]
+    pub max_size: Option<u64>,
+    /// Rotate the file once it has been open this long.
+    #[serde(default, deserialize_with = "option_duration")]
+    pub max_age: Option<Duration>,
+}
+
+impl Default for Log {
+    fn default() -> Self {
+        Log {
+            format: default_log_format(),
+            file: None,
+            max_size: None,
+            max_age: None,
+        }
+    }
+}
+
+/// A directory served below a path.
+#[derive(Clone, PartialEq, Eq, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct StaticMount {
+    pub mount: String,
+    pub root: PathBuf,
+}
+
+/// Requests below a path forwarded to upstream servers.
+#[derive(Clone, PartialEq, Eq, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ProxyRoute {
+    pub mount: String,
+    /// `http://host[:port][/base]` URLs; a single one may be given as a
+    /// string.
+    #[serde(deserialize_with = "strings")]
+    pub upstreams: Vec<String>,
+    /// `round_robin` (the default) or `least_connections`.
+    #[serde(default = "default_balance", deserialize_with = "balance")]
+    pub balance: Balance,
+    /// Remove the mount path before forwarding, on by default.
+    #[serde(default = "enabled")]
+    pub strip_prefix: bool,
+    #[serde(default)]
+    pub preserve_host: bool,
+    #[serde(default, deserialize_with = "option_duration")]
+    pub connect_timeout: Option<Duration>,
+    #[serde(default, deserialize_with = "option_duration")]
+    pub timeout: Option<Duration>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config::parse("").expect("the empty config is valid")
+    }
+}
+
+impl Config {
+    /// Read the config file at `path`, with overrides from the
+    /// environment.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Config, ConfigError> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path).map_err(|e| ConfigError {
+            key: None,
+            line: None,
+            env: None,
+            message: format!("cannot read {}: {}", path.display(), e),
+        })?;
+        let mut config = Config::parse_with_env(&text, std::env::vars())?;
+        config.base_dir = path.parent().map(Path::to_path_buf).unwrap_or_default();
+        Ok(config)
+    }
+
+    /// The defaults with overrides from the environment, for running
+    /// without a config file.
+    pub fn from_env() -> Result<Config, ConfigError> {
+        Config::parse_with_env("", std::env::vars())
+    }
+
+    /// Parse `text` as a config file.
+    pub fn parse(text: &str) -> Result<Config, ConfigError> {
+        Config::parse_with_env(text, std::iter::empty())
+    }
+
+    /// Parse `text` with overrides from the variables in `vars`, given as
+    /// `(name, value)`. Variables without `ENV_PREFIX` are ignored.
+    pub fn parse_with_env<I>(text: &str, vars: I) -> Result<Config, ConfigError>
+    where
+        I: IntoIterator<Item = (String, String)>,
+    {
+        let mut table: toml::Table = text.parse().map_err(|e: toml::de::Error| {
+            let line = e
+                .span()
+                .map(|span| text[..span.start].matches('\n').count() + 1);
+            ConfigError {
+                key: None,
+                line,
+                env: None,
+                message: e.message().trim().replace('\n', "; "),
+            }
+        })?;
+        let overrides = apply_env(&mut table, vars)?;
+        let mut config: Config = serde_path_to_error::deserialize(toml::Value::Table(table))
+            .map_err(|e| {
+                let key = e.path().to_string();
+                let err = ConfigError::new(&key, e.into_inner().message().trim());
+                blame(&overrides, err)
+            })?;
+        config.overrides = overrides;
+        Ok(config)
+    }
+
+    /// Check everything a server built from this config needs: that
+    /// addresses resolve and that files and directories can be read.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.threads == 0 {
+            return Err(self.error("threads", "must be at least 1"));
+        }
+        if self.listen.is_empty() {
+            return Err(self.error("listen", "needs at least one address"));
+        }
+        for (i, addr) in self.listen.iter().enumerate() {
+            check_listen(addr).map_err(|msg| self.error(&format!("listen[{}]", i), msg))?;
+        }
+        if let Some(path) = &self.metrics_path {
+            if !path.starts_with('/') {
+                return Err(self.error("metrics_path", "must start with `/`"));
+            }
+        }
+        if let Some(tls) = &self.tls {
+            self.tls_config(tls)?;
+        }
+        if let Some(file) = &self.log.file {
+            let file = self.resolve(file);
+            let dir = file.parent().filter(|d| !d.as_os_str().is_empty());
+            if dir.is_some_and(|d| !d.is_dir()) {
+                return Err(self.error("log.file", "is not in an existing directory"));
+            }
+        }
+
+        let mut mounts: HashMap<String, String> = HashMap::new();
+        let mut mount = |key: String, path: &str| -> Result<(), ConfigError> {
+            check_mount(path).map_err(|msg| self.error(&key, msg))?;
+            let path = path.trim_end_matches('/').to_string();
+            match mounts.get(&path) {
+                Some(other) => Err(self.error(&key, format!("is already mounted by {}", other))),
+                None => {
+                    mounts.insert(path, key.trim_end_matches(".mount").to_string());
+                    Ok(())
+                }
+            }
+        };
+        for (i, s) in self.static_files.iter().enumerate() {
+            mount(format!("static[{}].mount", i), &s.mount)?;
+            if !self.resolve(&s.root).is_dir() {
+                return Err(self.error(&format!("static[{}].root", i), "is not a directory"));
+            }
+        }
+        for (i, p) in self.proxy.iter().enumerate() {
+            mount(format!("proxy[{}].mount", i), &p.mount)?;
+            if p.upstreams.is_empty() {
+                let key = format!("proxy[{}].upstreams", i);
+                return Err(self.error(&key, "needs at least one upstream"));
+            }
+            for (j, url) in p.upstreams.iter().enumerate() {
+                Upstream::parse(url).map_err(|e| {
+                    self.error(&format!("proxy[{}].upstreams[{}]", i, j), e.to_string())
+                })?;
+            }
+        }
+
+        for (code, path) in &self.error_pages {
+            let key = format!("error_pages.{}", code);
+            error_status(code).map_err(|msg| self.error(&key, msg))?;
+            fs::read(self.resolve(path)).map_err(|e| self.error(&key, e.to_string()))?;
+        }
+        Ok(())
+    }
+
+    /// A server as configured, serving `mux` with the static and proxy
+    /// routes added to it.
+    pub fn server(&self, mut mux: DefaultServeMux) -> Result<Server, ConfigError> {
+        self.validate()?;
+        self.mount(&mut mux)?;
+        if !self.error_pages.is_empty() {
+            let mut pages = ErrorPages::new();
+            for (code, path) in &self.error_pages {
+                let key = format!("error_pages.{}", code);
+                let status = error_status(code).map_err(|msg| self.error(&key, msg))?;
+                pages = pages.file(status, self.resolve(path));
+            }
+            mux.set_error_pages(pages);
+        }
+
+        let mut server = Server::new(self.threads, self.listen[0].as_str(), Arc::new(mux))
+            .map_err(|_| self.error("threads", "must be at least 1"))?;
+        for addr in &self.listen[1..] {
+            server.add_listener(addr.as_str());
+        }
+        server.set_http2(self.http2);
+        if let Some(path) = &self.metrics_path {
+            server.set_metrics_path(path);
+        }
+        server.set_read_timeout(self.timeouts.read);
+        server.set_shutdown_timeout(self.timeouts.shutdown);
+        if let Some(bytes) = self.limits.max_body_size {
+            server.set_max_body_size(bytes);
+        }
+        server.set_buffer_bodies(self.limits.buffer_bodies);
+        #[cfg(feature = "tls")]
+        {
+            if let Some(tls) = &self.tls {
+                server.set_tls(self.tls_config(tls)?);
+            }
+        }
+        if let Some(format) = self.log.format {
+            let log = match &self.log.file {
+                Some(file) => {
+                    let mut file = RotatingFile::open(self.resolve(file))
+                        .map_err(|e| self.error("log.file", e.to_string()))?;
+                    if let Some(bytes) = self.log.max_size {
+                        file = file.max_size(bytes);
+                    }
+                    if let Some(age) = self.log.max_age {
+                        file = file.max_age(age);
+                    }
+                    AccessLog::new(format, file)
+                }
+                None => AccessLog::stdout(format),
+            };
+            server.set_logger(Arc::new(log));
+        }
+        Ok(server)
+    }
+
+    /// Add the static and proxy routes to `mux`.
+    pub fn mount(&self, mux: &mut DefaultServeMux) -> Result<(), ConfigError> {
+        for s in &self.static_files {
+            let files = Arc::new(FileServer::new(self.resolve(&s.root)).strip_prefix(&s.mount));
+            for pattern in mount_patterns(&s.mount) {
+                mux.get(&pattern, Shared(files.clone()));
+            }
+        }
+        for (i, p) in self.proxy.iter().enumerate() {
+            let upstreams: Vec<&str> = p.upstreams.iter().map(String::as_str).collect();
+            let mut proxy = ReverseProxy::with_upstreams(&upstreams)
+                .map_err(|e| self.error(&format!("proxy[{}].upstreams", i), e.to_string()))?
+                .balance(p.balance)
+                .preserve_host(p.preserve_host);
+            if p.strip_prefix {
+                proxy = proxy.strip_prefix(&p.mount);
+            }
+            if let Some(timeout) = p.connect_timeout {
+                proxy = proxy.connect_timeout(timeout);
+            }
+            if let Some(timeout) = p.timeout {
+                proxy = proxy.timeout(timeout);
+            }
+            let proxy = Arc::new(proxy);
+            for pattern in mount_patterns(&p.mount) {
+                mux.any(&pattern, Shared(proxy.clone()));
+            }
+        }
+        Ok(())
+    }
+
+    /// `path` relative to the directory of the config file.
+    pub fn resolve(&self, path: &Path) -> PathBuf {
+        self.base_dir.join(path)
+    }
+
+    #[cfg(feature = "tls")]
+    fn tls_config(&self, tls: &Tls) -> Result<TlsConfig, ConfigError> {
+        let cert =
+            fs::read(self.resolve(&tls.cert)).map_err(|e| self.error("tls.cert", e.to_string()))?;
+        let key =
+            fs::read(self.resolve(&tls.key)).map_err(|e| self.error("tls.key", e.to_string()))?;
+        TlsConfig::from_pem(&cert, &key).map_err(|e| self.error("tls", e.to_string()))
+    }
+
+    #[cfg(not(feature = "tls"))]
+    fn tls_config(&self, _tls: &Tls) -> Result<(), ConfigError> {
+        Err(self.error("tls", "this build has no TLS support"))
+    }
+
+    fn error<M: Into<String>>(&self, key: &str, message: M) -> ConfigError {
+        blame(&self.overrides, ConfigError::new(key, message))
+    }
+}
+
+/// A problem with a config, and where it is.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct ConfigError {
+    key: Option<String>,
+    line: Option<usize>,
+    env: Option<String>,
+    message: String,
+}
+
+impl ConfigError {
+    fn new<M: Into<String>>(key: &str, message: M) -> Self {
+        ConfigError {
+            key: Some(key.to_string()).filter(|k| !k.is_empty() && k != "."),
+            line: None,
+            env: None,
+            message: message.into(),
+        }
+    }
+
+    /// The key at fault, e.g. `static[0].root`.
+    pub fn key(&self) -> Option<&str> {
+        self.key.as_deref()
+    }
+
+    /// The line of a syntax error.
+    pub fn line(&self) -> Option<usize> {
+        self.line
+    }
+
+    /// The environment variable that set the key.
+    pub fn env(&self) -> Option<&str> {
+        self.env.as_deref()
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (&self.key, self.line) {
+            (Some(key), _) => write!(f, "{}: {}", key, self.message)?,
+            (None, Some(line)) => write!(f, "line {}: {}", line, self.message)?,
+            (None, None) => f.write_str(&self.message)?,
+        }
+        if let Some(env) = &self.env {
+            write!(f, " (set by {})", env)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// Set the keys named by the variables in `vars`, returning which
+/// variable set which key.
+fn apply_env<I>(table: &mut toml::Table, vars: I) -> Result<Vec<(String, String)>, ConfigError>
+where
+    I: IntoIterator<Item = (String, String)>,
+{
+    let mut applied = Vec::new();
+    for (name, raw) in vars {
+        let keys: Vec<String> = match name.strip_prefix(ENV_PREFIX) {
+            Some(_) if name == ENV_CONFIG => continue,
+            Some(rest) => rest.split("__").map(str::to_ascii_lowercase).collect(),
+            None => continue,
+        };
+        let unnamed = |key: Option<String>, message: &str| ConfigError {
+            key,
+            line: None,
+            env: Some(name.clone()),
+            message: message.to_string(),
+        };
+        if keys.iter().any(String::is_empty) {
+            return Err(unnamed(None, "does not name a key"));
+        }
+        let value = format!("v = {}", raw)
+            .parse::<toml::Table>()
+            .ok()
+            .and_then(|mut t| t.remove("v"))
+            .unwrap_or_else(|| toml::Value::String(raw.clone()));
+        let (last, parents) = keys.split_last().expect("split yields a key");
+        let mut t = &mut *table;
+        for (i, key) in parents.iter().enumerate() {
+            let entry = t
+                .entry(key.clone())
+                .or_insert_with(|| toml::Value::Table(toml::Table::new()));
+            t = match entry {
+                toml::Value::Table(t) => t,
+                _ => return Err(unnamed(Some(keys[..=i].join(".")), "is not a table")),
+            };
+        }
+        t.insert(last.clone(), value);
+        applied.push((keys.join("."), name));
+    }
+    Ok(applied)
+}
+
+/// Note the variable that set the key of `err`, if one did.
+fn blame(overrides: &[(String, String)], mut err: ConfigError) -> ConfigError {
+    if let Some(key) = &err.key {
+        err.env = overrides
+            .iter()
+            .find(|(k, _)| {
+                key == k
+                    || key
+                        .strip_prefix(k.as_str())
+                        .is_some_and(|rest| rest.starts_with(['.', '[']))
+            })
+            .map(|(_, var)| var.clone());
+    }
+    err
+}
+
+fn check_listen(addr: &str) -> Result<(), String> {
+    if let Some(path) = addr.strip_prefix("unix:") {
+        if cfg!(not(unix)) {
+            return Err("Unix sockets are not supported on this platform".to_string());
+        }
+        if path.is_empty() {
+            return Err("needs a socket path after `unix:`".to_string());
+        }
+        return Ok(());
+    }
+    match addr.to_socket_addrs() {
+        Ok(addrs) if addrs.len() > 0 => Ok(()),
+        Ok(_) => Err(format!("`{}` resolves to no address", addr)),
+        Err(e) => Err(format!("`{}` is not an address: {}", addr, e)),
+    }
+}
+
+fn check_mount(path: &str) -> Result<(), String> {
+    if !path.starts_with('/') {
+        return Err("must start with `/`".to_string());
+    }
+    if path
+        .split('/')
+        .any(|seg| seg.starts_with(':') || seg.starts_with('*'))
+    {
+        return Err("cannot contain route parameters".to_string());
+    }
+    Ok(())
+}
+
+fn error_status(code: &str) -> Result<StatusCode, String> {
+    code.parse::<usize>()
+        .ok()
+        .filter(|c| (400..600).contains(c))
+        .and_then(|c| StatusCode::from_num(c).ok())
+        .ok_or_else(|| "is not an error status code".to_string())
+}
+
+/// The route patterns covering `mount` and everything below it.
+fn mount_patterns(mount: &str) -> Vec<String> {
+    let base = mount.trim_end_matches('/');
+    if base.is_empty() {
+        vec!["/".to_string(), "/*path".to_string()]
+    } else {
+        vec![base.to_string(), format!("{}/*path", base)]
+    }
+}
+
+/// One handler registered under several patterns.
+struct Shared<H>(Arc<H>);
+
+impl<H: Handler> Handler for Shared<H> {
+    fn serve_http(
+        &self,
+        writer: &mut dyn ResponseWriter,
+        req: &Request,
+    ) -> Result<(), crate::error::ServerError> {
+        self.0.serve_http(writer, req)
+    }
+}
+
+fn enabled() -> bool {
+    true
+}
+
+fn default_threads() -> usize {
+    8
+}
+
+fn default_listen() -> Vec<String> {
+    vec!["127.0.0.1:7878".to_string()]
+}
+
+fn default_shutdown() -> Duration {
+    Duration::from_secs(5)
+}
+
+fn default_log_format() -> Option<LogFormat> {
+    Some(LogFormat::Combined)
+}
+
+fn default_balance() -> Balance {
+    Balance::RoundRobin
+}
+
+/// A string or an array of strings.
+fn strings<'de, D: Deserializer<'de>>(d: D) -> Result<Vec<String>, D::Error> {
+    match toml::Value::deserialize(d)? {
+        toml::Value::String(s) => Ok(vec![s]),
+        toml::Value::Array(values) => values
+            .into_iter()
+            .map(|v| match v {
+                toml::Value::String(s) => Ok(s),
+                _ => Err(D::Error::custom("expected an array of strings")),
+            })
+            .collect(),
+        _ => Err(D::Error::custom("expected a string or an array of strings")),
+    }
+}
+
+fn duration<'de, D: Deserializer<'de>>(d: D) -> Result<Duration, D::Error> {
+    match toml::Value::deserialize(d)? {
+        toml::Value::Integer(secs) if secs >= 0 => Ok(Duration::from_secs(secs as u64)),
+        toml::Value::String(s) => parse_duration(&s).ok_or_else(|| {
+            D::Error::custom(format!(
+                "invalid duration `{}`, expected e.g. \"500ms\", \"30s\", \"5m\"",
+                s
+            ))
+        }),
+        _ => Err(D::Error::custom("expected a duration such as \"30s\"")),
+    }
+}
+
+fn option_duration<'de, D: Deserializer<'de>>(d: D) -> Result<Option<Duration>, D::Error> {
+    duration(d).map(Some)
+}
+
+fn parse_duration(s: &str) -> Option<Duration> {
+    let s = s.trim();
+    let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let n: u64 = s[..split].parse().ok()?;
+    let secs = |mult: u64| n.checked_mul(mult).map(Duration::from_secs);
+    match s[split..].trim() {
+        "ms" => Some(Duration::from_millis(n)),
+        "s" => secs(1),
+        "m" => secs(60),
+        "h" => secs(60 * 60),
+        "d" => secs(24 * 60 * 60),
+        _ => None,
+    }
+}
+
+fn option_bytes<'de, D: Deserializer<'de>>(d: D) -> Result<Option<u64>, D::Error> {
+    match toml::Value::deserialize(d)? {
+        toml::Value::Integer(n) if n >= 0 => Ok(Some(n as u64)),
+        toml::Value::String(s) => parse_bytes(&s).map(Some).ok_or_else(|| {
+            D::Error::custom(format!(
+                "invalid size `{}`, expected e.g. \"64KiB\", \"10MB\"",
+                s
+            ))
+        }),
+        _ => Err(D::Error::custom("expected a size such as \"10MiB\"")),
+    }
+}
+
+fn parse_bytes(s: &str) -> Option<u64> {
+    let s = s.trim();
+    let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let n: u64 = s[..split].parse().ok()?;
+    let mult: u64 = match s[split..].trim().to_ascii_lowercase().as_str() {
+        "" | "b" => 1,
+        "kb" => 1000,
+        "mb" => 1000 * 1000,
+        "gb" => 1000 * 1000 * 1000,
+        "kib" => 1 << 10,
+        "mib" => 1 << 20,
+        "gib" => 1 << 30,
+        _ => return None,
+    };
+    n.checked_mul(mult)
+}
+
+fn log_format<'de, D: Deserializer<'de>>(d: D) -> Result<Option<LogFormat>, D::Error> {
+    const FORMATS: &[&str] = &["common", "combined", "json", "off"];
+    let s = String::deserialize(d)?;
+    match s.as_str() {
+        "common" => Ok(Some(LogFormat::Common)),
+        "combined" => Ok(Some(LogFormat::Combined)),
+        "json" => Ok(Some(LogFormat::Json)),
+        "off" => Ok(None),
+        _ => Err(D::Error::unknown_variant(&s, FORMATS)),
+    }
+}
+
+fn balance<'de, D: Deserializer<'de>>(d: D) -> Result<Balance, D::Error> {
+    const BALANCES: &[&str] = &["round_robin", "least_connections"];
+    let s = String::deserialize(d)?;
+    match s.as_str() {
+        "round_robin" => Ok(Balance::RoundRobin),
+        "least_connections" => Ok(Balance::LeastConnections),
+        _ => Err(D::Error::unknown_variant(&s, BALANCES)),
+    }
+}
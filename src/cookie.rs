@@ -0,0 +1,474 @@
+//! `Cookie` request header parsing, a `Set-Cookie` builder and cookie jars.
+//!
+//! With the `secure-cookies` feature a `CookieJar` can also sign or encrypt
+//! cookies with a `Key` derived from a server secret. The key is usually
+//! registered as application state and extracted with `State<Key>`.
+use crate::date::DateTime;
+use crate::extract::{FromRequest, Rejection};
+use crate::message::{percent_decode, Request, ResponseWriter};
+use std::fmt;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+pub const COOKIE: &str = "Cookie";
+pub const SET_COOKIE: &str = "Set-Cookie";
+
+/// Split a `Cookie` request header into `(name, value)` pairs.
+///
+/// Surrounding double quotes of a value are removed, `%XX` escapes decoded
+/// and malformed pairs skipped.
+pub fn parse_cookies(header: &str) -> Vec<(String, String)> {
+    header
+        .split(';')
+        .filter_map(|pair| pair.split_once('='))
+        .map(|(k, v)| {
+            let v = v.trim();
+            let v = if v.len() >= 2 && v.starts_with('"') && v.ends_with('"') {
+                &v[1..v.len() - 1]
+            } else {
+                v
+            };
+            (percent_decode(k.trim()), percent_decode(v))
+        })
+        .filter(|(k, _)| !k.is_empty())
+        .collect()
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum SameSite {
+    Strict,
+    Lax,
+    /// Browsers only accept this together with `Secure`.
+    None,
+}
+
+impl SameSite {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            SameSite::Strict => "Strict",
+            SameSite::Lax => "Lax",
+            SameSite::None => "None",
+        }
+    }
+}
+
+/// A cookie to be sent with `Set-Cookie`.
+///
+/// Bytes that may not appear in a cookie name or value, such as `;`, `,`,
+/// spaces and control characters, are sent as `%XX` and decoded again by
+/// `parse_cookies`, so a value cannot add attributes or headers of its own.
+/// `Domain` and `Path` get the same treatment for `;`, `,`, whitespace and
+/// control characters.
+///
+/// ```
+/// use rust_server::cookie::{Cookie, SameSite};
+/// use std::time::Duration;
+///
+/// let c = Cookie::new("id", "a3fWa")
+///     .path("/")
+///     .max_age(Duration::from_secs(3600))
+///     .secure(true)
+///     .http_only(true)
+///     .same_site(SameSite::Lax);
+/// assert_eq!(
+///     c.to_string(),
+///     "id=a3fWa; Max-Age=3600; Path=/; Secure; HttpOnly; SameSite=Lax"
+/// );
+/// ```
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Cookie {
+    name: String,
+    value: String,
+    expires: Option<SystemTime>,
+    max_age: Option<Duration>,
+    domain: Option<String>,
+    path: Option<String>,
+    secure: bool,
+    http_only: bool,
+    same_site: Option<SameSite>,
+    partitioned: bool,
+}
+
+impl Cookie {
+    pub fn new(name: &str, value: &str) -> Self {
+        Cookie {
+            name: name.to_string(),
+            value: value.to_string(),
+            expires: None,
+            max_age: None,
+            domain: None,
+            path: None,
+            secure: false,
+            http_only: false,
+            same_site: None,
+            partitioned: false,
+        }
+    }
+
+    /// A cookie telling the client to delete `name`.
+    pub fn removal(name: &str) -> Self {
+        Cookie::new(name, "")
+            .max_age(Duration::from_secs(0))
+            .expires(UNIX_EPOCH)
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn value(&self) -> &str {
+        &self.value
+    }
+
+    pub fn set_name(&mut self, name: &str) {
+        self.name = name.to_string();
+    }
+
+    pub fn set_value(&mut self, value: &str) {
+        self.value = value.to_string();
+    }
+
+    pub fn expires(mut self, at: SystemTime) -> Self {
+        self.expires = Some(at);
+        self
+    }
+
+    pub fn max_age(mut self, age: Duration) -> Self {
+        self.max_age = Some(age);
+        self
+    }
+
+    pub fn domain(mut self, domain: &str) -> Self {
+        self.domain = Some(domain.to_string());
+        self
+    }
+
+    pub fn path(mut self, path: &str) -> Self {
+        self.path = Some(path.to_string());
+        self
+    }
+
+    pub fn secure(mut self, secure: bool) -> Self {
+        self.secure = secure;
+        self
+    }
+
+    pub fn http_only(mut self, http_only: bool) -> Self {
+        self.http_only = http_only;
+        self
+    }
+
+    pub fn same_site(mut self, same_site: SameSite) -> Self {
+        self.same_site = Some(same_site);
+        self
+    }
+
+    /// Ask for partitioned storage (CHIPS); browsers also require `Secure`.
+    pub fn partitioned(mut self, partitioned: bool) -> Self {
+        self.partitioned = partitioned;
+        self
+    }
+}
+
+/// Formats the cookie as a `Set-Cookie` header value.
+impl fmt::Display for Cookie {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}={}",
+            escape(&self.name, is_token),
+            escape(&self.value, is_cookie_octet)
+        )?;
+        if let Some(t) = self.expires {
+            write!(
+                f,
+                "; Expires={}",
+                DateTime::from_system_time(t).to_http_date()
+            )?;
+        }
+        if let Some(age) = self.max_age {
+            write!(f, "; Max-Age={}", age.as_secs())?;
+        }
+        if let Some(domain) = &self.domain {
+            write!(f, "; Domain={}", escape(domain, is_attribute_octet))?;
+        }
+        if let Some(path) = &self.path {
+            write!(f, "; Path={}", escape(path, is_attribute_octet))?;
+        }
+        if self.secure {
+            write!(f, "; Secure")?;
+        }
+        if self.http_only {
+            write!(f, "; HttpOnly")?;
+        }
+        if let Some(same_site) = self.same_site {
+            write!(f, "; SameSite={}", same_site.as_str())?;
+        }
+        if self.partitioned {
+            write!(f, "; Partitioned")?;
+        }
+        Ok(())
+    }
+}
+
+/// `s` with the bytes `keep` refuses written as `%XX`.
+fn escape(s: &str, keep: fn(u8) -> bool) -> String {
+    let mut out = String::with_capacity(s.len());
+    for b in s.bytes() {
+        if keep(b) {
+            out.push(b as char);
+        } else {
+            out.push_str(&format!("%{:02X}", b));
+        }
+    }
+    out
+}
+
+/// A `token` character of RFC 9110, other than `%`.
+fn is_token(b: u8) -> bool {
+    b.is_ascii_graphic() && !b"()<>@,;:\\\"/[]?={}%".contains(&b)
+}
+
+/// A `cookie-octet` of RFC 6265, other than `%`.
+fn is_cookie_octet(b: u8) -> bool {
+    b.is_ascii_graphic() && !b"\",;\\%".contains(&b)
+}
+
+fn is_attribute_octet(b: u8) -> bool {
+    b.is_ascii_graphic() && b != b';' && b != b','
+}
+
+/// The cookies of a request plus the changes to send back.
+///
+/// Changes made with `add` and `remove` are visible to later `get` calls and
+/// are written to the response by `write`.
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct CookieJar {
+    original: Vec<(String, String)>,
+    delta: Vec<Cookie>,
+}
+
+impl CookieJar {
+    pub fn new() -> Self {
+        CookieJar {
+            original: Vec::new(),
+            delta: Vec::new(),
+        }
+    }
+
+    pub fn from_request(req: &Request) -> Self {
+        CookieJar {
+            original: req.header(COOKIE).map(parse_cookies).unwrap_or_default(),
+            delta: Vec::new(),
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&str> {
+        match self.delta.iter().rev().find(|c| c.name == name) {
+            Some(c) if c.max_age == Some(Duration::from_secs(0)) => None,
+            Some(c) => Some(&c.value),
+            None => self
+                .original
+                .iter()
+                .find(|(k, _)| k == name)
+                .map(|(_, v)| v.as_str()),
+        }
+    }
+
+    pub fn add(&mut self, cookie: Cookie) {
+        self.delta.retain(|c| c.name != cookie.name);
+        self.delta.push(cookie);
+    }
+
+    /// Expire `name` on the client.
+    pub fn remove(&mut self, name: &str) {
+        self.add(Cookie::removal(name));
+    }
+
+    /// The cookies added or removed since the jar was created.
+    pub fn delta(&self) -> &[Cookie] {
+        &self.delta
+    }
+
+    /// Append a `Set-Cookie` header for every change.
+    pub fn write(&self, writer: &mut dyn ResponseWriter) {
+        for c in &self.delta {
+            writer.append_header(SET_COOKIE, &c.to_string());
+        }
+    }
+}
+
+impl FromRequest for CookieJar {
+    fn from_request(req: &Request) -> Result<Self, Rejection> {
+        Ok(CookieJar::from_request(req))
+    }
+}
+
+#[cfg(feature = "secure-cookies")]
+pub use self::secure::{Key, PrivateJar, SignedJar};
+
+#[cfg(feature = "secure-cookies")]
+mod secure {
+    use super::{Cookie, CookieJar};
+    use aes_gcm::aead::{Aead, KeyInit, Payload};
+    use aes_gcm::{Aes256Gcm, Nonce};
+    use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+    use base64::Engine;
+    use hmac::{Hmac, Mac};
+    use sha2::Sha256;
+
+    type HmacSha256 = Hmac<Sha256>;
+
+    const NONCE_LEN: usize = 12;
+    const MAC_LEN: usize = 32;
+
+    /// Secret material for signed and private cookies.
+    #[derive(Clone)]
+    pub struct Key {
+        signing: [u8; 32],
+        encryption: [u8; 32],
+    }
+
+    impl Key {
+        /// Derive separate signing and encryption keys from `secret`, which
+        /// should hold at least 32 bytes of entropy.
+        pub fn derive_from(secret: &[u8]) -> Self {
+            let derive = |label: &[u8]| {
+                let mut mac = <HmacSha256 as Mac>::new_from_slice(secret).expect("any key length");
+                mac.update(label);
+                let mut out = [0; 32];
+                out.copy_from_slice(&mac.finalize().into_bytes());
+                out
+            };
+            Key {
+                signing: derive(b"rust_server cookie signing"),
+                encryption: derive(b"rust_server cookie encryption"),
+            }
+        }
+
+        /// A random key; cookies do not survive a restart.
+        pub fn generate() -> Self {
+            let mut secret = [0; 64];
+            getrandom::getrandom(&mut secret).expect("system random source");
+            Key::derive_from(&secret)
+        }
+    }
+
+    impl std::fmt::Debug for Key {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            f.write_str("Key(..)")
+        }
+    }
+
+    fn mac(key: &Key, name: &str, value: &str) -> HmacSha256 {
+        let mut mac = <HmacSha256 as Mac>::new_from_slice(&key.signing).expect("any key length");
+        mac.update(name.as_bytes());
+        mac.update(b"=");
+        mac.update(value.as_bytes());
+        mac
+    }
+
+    /// Cookies whose values are readable by the client but carry a MAC, so
+    /// tampered values are ignored.
+    pub struct SignedJar<'a> {
+        jar: &'a mut CookieJar,
+        key: &'a Key,
+    }
+
+    impl SignedJar<'_> {
+        /// The verified value of `name`.
+        pub fn get(&self, name: &str) -> Option<String> {
+            let raw = self.jar.get(name)?;
+            let tag_len = URL_SAFE_NO_PAD.encode([0; MAC_LEN]).len();
+            if raw.len() < tag_len || !raw.is_char_boundary(tag_len) {
+                return None;
+            }
+            let (tag, value) = raw.split_at(tag_len);
+            let tag = URL_SAFE_NO_PAD.decode(tag).ok()?;
+            mac(self.key, name, value).verify_slice(&tag).ok()?;
+            Some(value.to_string())
+        }
+
+        pub fn add(&mut self, mut cookie: Cookie) {
+            let tag = mac(self.key, &cookie.name, &cookie.value).finalize();
+            let value = format!(
+                "{}{}",
+                URL_SAFE_NO_PAD.encode(tag.into_bytes()),
+                cookie.value
+            );
+            cookie.set_value(&value);
+            self.jar.add(cookie);
+        }
+
+        pub fn remove(&mut self, name: &str) {
+            self.jar.remove(name);
+        }
+    }
+
+    /// Cookies whose values are encrypted and authenticated, so the client
+    /// can neither read nor alter them.
+    pub struct PrivateJar<'a> {
+        jar: &'a mut CookieJar,
+        key: &'a Key,
+    }
+
+    impl PrivateJar<'_> {
+        fn cipher(&self) -> Aes256Gcm {
+            Aes256Gcm::new_from_slice(&self.key.encryption).expect("32 byte key")
+        }
+
+        /// The decrypted value of `name`.
+        pub fn get(&self, name: &str) -> Option<String> {
+            let data = URL_SAFE_NO_PAD.decode(self.jar.get(name)?).ok()?;
+            if data.len() < NONCE_LEN {
+                return None;
+            }
+            let (nonce, ciphertext) = data.split_at(NONCE_LEN);
+            let plain = self
+                .cipher()
+                .decrypt(
+                    Nonce::from_slice(nonce),
+                    Payload {
+                        msg: ciphertext,
+                        aad: name.as_bytes(),
+                    },
+                )
+                .ok()?;
+            String::from_utf8(plain).ok()
+        }
+
+        pub fn add(&mut self, mut cookie: Cookie) {
+            let mut nonce = [0; NONCE_LEN];
+            getrandom::getrandom(&mut nonce).expect("system random source");
+            let ciphertext = self
+                .cipher()
+                .encrypt(
+                    Nonce::from_slice(&nonce),
+                    Payload {
+                        msg: cookie.value.as_bytes(),
+                        aad: cookie.name.as_bytes(),
+                    },
+                )
+                .expect("encryption of an in-memory value");
+            let mut data = nonce.to_vec();
+            data.extend_from_slice(&ciphertext);
+            cookie.set_value(&URL_SAFE_NO_PAD.encode(data));
+            self.jar.add(cookie);
+        }
+
+        pub fn remove(&mut self, name: &str) {
+            self.jar.remove(name);
+        }
+    }
+
+    impl CookieJar {
+        /// View of this jar that signs and verifies values with `key`.
+        pub fn signed<'a>(&'a mut self, key: &'a Key) -> SignedJar<'a> {
+            SignedJar { jar: self, key }
+        }
+
+        /// View of this jar that encrypts and decrypts values with `key`.
+        pub fn private<'a>(&'a mut self, key: &'a Key) -> PrivateJar<'a> {
+            PrivateJar { jar: self, key }
+        }
+    }
+}
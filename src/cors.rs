@@ -0,0 +1,262 @@
+//! Cross-Origin Resource Sharing.
+//!
+//! `Cors` wraps a handler, answers preflight requests itself and adds the
+//! `Access-Control-*` headers to responses for allowed origins. Requests
+//! from origins that are not allowed are still served, just without those
+//! headers, so the browser withholds the response from the calling page.
+use crate::error::ServerError;
+use crate::message::{Request, ResponseWriter};
+use crate::method::Method;
+use crate::server::Handler;
+use crate::status_code::StatusCode;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+pub const ORIGIN: &str = "Origin";
+pub const VARY: &str = "Vary";
+pub const ACCESS_CONTROL_ALLOW_ORIGIN: &str = "Access-Control-Allow-Origin";
+pub const ACCESS_CONTROL_ALLOW_METHODS: &str = "Access-Control-Allow-Methods";
+pub const ACCESS_CONTROL_ALLOW_HEADERS: &str = "Access-Control-Allow-Headers";
+pub const ACCESS_CONTROL_ALLOW_CREDENTIALS: &str = "Access-Control-Allow-Credentials";
+pub const ACCESS_CONTROL_EXPOSE_HEADERS: &str = "Access-Control-Expose-Headers";
+pub const ACCESS_CONTROL_MAX_AGE: &str = "Access-Control-Max-Age";
+pub const ACCESS_CONTROL_REQUEST_METHOD: &str = "Access-Control-Request-Method";
+pub const ACCESS_CONTROL_REQUEST_HEADERS: &str = "Access-Control-Request-Headers";
+
+enum OriginRule {
+    Exact(String),
+    /// The part before and after the `*`.
+    Wildcard(String, String),
+    Predicate(Arc<dyn Fn(&str) -> bool + Send + Sync>),
+}
+
+impl OriginRule {
+    fn matches(&self, origin: &str) -> bool {
+        match self {
+            OriginRule::Exact(o) => o.eq_ignore_ascii_case(origin),
+            OriginRule::Wildcard(prefix, suffix) => {
+                let origin = origin.to_ascii_lowercase();
+                origin.len() > prefix.len() + suffix.len()
+                    && origin.starts_with(prefix.as_str())
+                    && origin.ends_with(suffix.as_str())
+                    && !origin[prefix.len()..origin.len() - suffix.len()].contains('/')
+            }
+            OriginRule::Predicate(f) => f(origin),
+        }
+    }
+}
+
+/// CORS middleware.
+///
+/// Nothing is allowed until configured: add origins with `allow_origin` or
+/// `allow_origin_fn`. The allowed methods default to `GET`, `HEAD` and
+/// `POST`; no request headers beyond the CORS-safelisted ones are allowed
+/// and no response headers are exposed.
+pub struct Cors {
+    inner: Arc<dyn Handler + Send + Sync>,
+    any_origin: bool,
+    origins: Vec<OriginRule>,
+    methods: Vec<Method>,
+    any_header: bool,
+    headers: Vec<String>,
+    expose: Vec<String>,
+    credentials: bool,
+    max_age: Option<Duration>,
+}
+
+impl Cors {
+    pub fn new<H: Handler + Send + Sync + 'static>(inner: H) -> Self {
+        Cors {
+            inner: Arc::new(inner),
+            any_origin: false,
+            origins: Vec::new(),
+            methods: vec![Method::Get, Method::Head, Method::Post],
+            any_header: false,
+            headers: Vec::new(),
+            expose: Vec::new(),
+            credentials: false,
+            max_age: None,
+        }
+    }
+
+    /// Allow `origin`, e.g. `https://example.com`. `*` allows every origin
+    /// and a single `*` inside a pattern such as `https://*.example.com`
+    /// matches one or more host labels.
+    pub fn allow_origin(mut self, origin: &str) -> Self {
+        let origin = origin.trim_end_matches('/').to_ascii_lowercase();
+        if origin == "*" {
+            self.any_origin = true;
+        } else if let Some((prefix, suffix)) = origin.split_once('*') {
+            let rule = OriginRule::Wildcard(prefix.to_string(), suffix.to_string());
+            self.origins.push(rule);
+        } else {
+            self.origins.push(OriginRule::Exact(origin));
+        }
+        self
+    }
+
+    /// Allow every origin for which `f` returns true.
+    pub fn allow_origin_fn<F>(mut self, f: F) -> Self
+    where
+        F: Fn(&str) -> bool + Send + Sync + 'static,
+    {
+        self.origins.push(OriginRule::Predicate(Arc::new(f)));
+        self
+    }
+
+    /// Replace the allowed methods.
+    pub fn allow_methods(mut self, methods: &[Method]) -> Self {
+        self.methods = methods.to_vec();
+        self
+    }
+
+    pub fn allow_headers(mut self, headers: &[&str]) -> Self {
+        self.headers
+            .extend(headers.iter().map(|h| h.to_ascii_lowercase()));
+        self
+    }
+
+    /// Allow whatever request headers a preflight asks for.
+    pub fn allow_any_header(mut self) -> Self {
+        self.any_header = true;
+        self
+    }
+
+    /// Response headers the calling page may read.
+    pub fn expose_headers(mut self, headers: &[&str]) -> Self {
+        self.expose.extend(headers.iter().map(|h| h.to_string()));
+        self
+    }
+
+    /// Let the page send cookies and HTTP authentication. The allowed
+    /// origin is then echoed even when every origin is allowed, as browsers
+    /// refuse `*` for credentialed requests.
+    pub fn allow_credentials(mut self, allow: bool) -> Self {
+        self.credentials = allow;
+        self
+    }
+
+    /// How long browsers may cache a preflight response.
+    pub fn max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    fn origin_allowed(&self, origin: &str) -> bool {
+        self.any_origin || self.origins.iter().any(|r| r.matches(origin))
+    }
+
+    /// The `Access-Control-Allow-Origin` value for an allowed `origin`.
+    fn allow_origin_value(&self, origin: &str) -> String {
+        if self.any_origin && !self.credentials {
+            "*".to_string()
+        } else {
+            origin.to_string()
+        }
+    }
+
+    /// Whether the response depends on the `Origin` request header.
+    fn varies(&self) -> bool {
+        !self.any_origin || self.credentials
+    }
+
+    fn preflight(
+        &self,
+        writer: &mut dyn ResponseWriter,
+        req: &Request,
+        origin: &str,
+        method: &str,
+    ) {
+        writer.append_header(VARY, "Origin");
+        writer.append_header(VARY, ACCESS_CONTROL_REQUEST_METHOD);
+        writer.append_header(VARY, ACCESS_CONTROL_REQUEST_HEADERS);
+
+        let requested: Vec<String> = req
+            .header(ACCESS_CONTROL_REQUEST_HEADERS)
+            .unwrap_or("")
+            .split(',')
+            .map(|h| h.trim().to_ascii_lowercase())
+            .filter(|h| !h.is_empty())
+            .collect();
+        let method_ok = Method::from_str(method).is_ok_and(|m| self.methods.contains(&m));
+        let headers_ok = self.any_header || requested.iter().all(|h| self.headers.contains(h));
+        if !self.origin_allowed(origin) || !method_ok || !headers_ok {
+            writer.text("CORS request not allowed");
+            writer.status(StatusCode::Forbidden);
+            return;
+        }
+
+        writer.set_header(
+            ACCESS_CONTROL_ALLOW_ORIGIN,
+            &self.allow_origin_value(origin),
+        );
+        let methods: Vec<&str> = self.methods.iter().map(|m| m.as_str()).collect();
+        writer.set_header(ACCESS_CONTROL_ALLOW_METHODS, &methods.join(", "));
+        let headers = if self.any_header {
+            requested.join(", ")
+        } else {
+            self.headers.join(", ")
+        };
+        if !headers.is_empty() {
+            writer.set_header(ACCESS_CONTROL_ALLOW_HEADERS, &headers);
+        }
+        if self.credentials {
+            writer.set_header(ACCESS_CONTROL_ALLOW_CREDENTIALS, "true");
+        }
+        if let Some(max_age) = self.max_age {
+            writer.set_header(ACCESS_CONTROL_MAX_AGE, &max_age.as_secs().to_string());
+        }
+        writer.no_content();
+    }
+}
+
+impl Handler for Cors {
+    fn serve_http(
+        &self,
+        writer: &mut dyn ResponseWriter,
+        req: &Request,
+    ) -> Result<(), ServerError> {
+        let varies = self.varies();
+        let origin = match req.header(ORIGIN) {
+            Some(origin) => origin.to_string(),
+            None => {
+                // a cache must not hand this response to a request with an
+                // Origin either
+                if varies {
+                    writer.on_send(Box::new(|res| res.headers.append(VARY, "Origin")));
+                }
+                return self.inner.serve_http(writer, req);
+            }
+        };
+        if req.method == Method::Options {
+            if let Some(method) = req.header(ACCESS_CONTROL_REQUEST_METHOD) {
+                self.preflight(writer, req, &origin, method);
+                return Ok(());
+            }
+        }
+
+        let mut headers = Vec::new();
+        if self.origin_allowed(&origin) {
+            headers.push((
+                ACCESS_CONTROL_ALLOW_ORIGIN,
+                self.allow_origin_value(&origin),
+            ));
+            if self.credentials {
+                headers.push((ACCESS_CONTROL_ALLOW_CREDENTIALS, "true".to_string()));
+            }
+            if !self.expose.is_empty() {
+                headers.push((ACCESS_CONTROL_EXPOSE_HEADERS, self.expose.join(", ")));
+            }
+        }
+        writer.on_send(Box::new(move |res| {
+            if varies {
+                res.headers.append(VARY, "Origin");
+            }
+            for (name, value) in headers {
+                res.headers.set(name, &value);
+            }
+        }));
+        self.inner.serve_http(writer, req)
+    }
+}
@@ -6,6 +6,11 @@ pub enum ServerError {
     ReadLineError,
     ParseError,
     ReadHeaderError,
+    BindError,
+    /// The handler panicked after sending part of the response.
+    HandlerPanic,
+    /// A server needs at least one worker thread.
+    PoolCreationError,
 }
 
 impl ServerError {
@@ -14,6 +19,9 @@ impl ServerError {
             ServerError::ReadLineError => "ReadLinError",
             ServerError::ParseError => "ParseError",
             ServerError::ReadHeaderError => "ReadHeaderError",
+            ServerError::BindError => "BindError",
+            ServerError::HandlerPanic => "HandlerPanic",
+            ServerError::PoolCreationError => "PoolCreationError",
         }
     }
 }
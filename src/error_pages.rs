@@ -0,0 +1,223 @@
+//! Error pages.
+//!
+//! `ErrorPages` maps status codes to the page answering them: a handler, a
+//! template or a file, which is read on first use and then served from
+//! memory. Set on a `DefaultServeMux`, a page replaces the body of every
+//! response with its status, whichever handler produced it.
+//!
+//! Templates and files are HTML for browsers; clients preferring JSON get
+//! `{"error":{"message":"Not Found","status":404}}` instead, the shape of
+//! every JSON error from `json`. Handler pages answer both themselves, with
+//! `prefers_json` to tell them apart.
+use crate::header::{ContentType, HttpHeader};
+use crate::message::{BufferedWriter, Request, Response, ResponseBody, ResponseWriter};
+use crate::server::Handler;
+use crate::status_code::StatusCode;
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Arc, OnceLock};
+
+enum Page {
+    Handler(Arc<dyn Handler + Send + Sync>),
+    Template(String),
+    /// The file, and its content type and contents once read.
+    File(PathBuf, OnceLock<Option<(&'static str, Vec<u8>)>>),
+}
+
+/// The pages answering error statuses.
+#[derive(Default)]
+pub struct ErrorPages {
+    pages: HashMap<StatusCode, Page>,
+}
+
+impl ErrorPages {
+    pub fn new() -> Self {
+        ErrorPages {
+            pages: HashMap::new(),
+        }
+    }
+
+    /// Answer `status` with `handler`, called with the status already set
+    /// and the request without its body.
+    pub fn handler<H: Handler + Send + Sync + 'static>(
+        mut self,
+        status: StatusCode,
+        handler: H,
+    ) -> Self {
+        self.pages.insert(status, Page::Handler(Arc::new(handler)));
+        self
+    }
+
+    /// Answer `status` with an HTML template in which `{status}`,
+    /// `{reason}`, `{method}` and `{path}` are replaced by those of the
+    /// response and request.
+    pub fn template(mut self, status: StatusCode, html: &str) -> Self {
+        self.pages.insert(status, Page::Template(html.to_string()));
+        self
+    }
+
+    /// Answer `status` with the file at `path`, with a `Content-Type` from
+    /// its extension. A file that cannot be read leaves the response as
+    /// the handler wrote it.
+    pub fn file<P: Into<PathBuf>>(mut self, status: StatusCode, path: P) -> Self {
+        self.pages
+            .insert(status, Page::File(path.into(), OnceLock::new()));
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pages.is_empty()
+    }
+
+    /// Replace the body of the response to `req` if a page answers its
+    /// status.
+    pub(crate) fn install(pages: &Arc<ErrorPages>, writer: &mut dyn ResponseWriter, req: &Request) {
+        if pages.is_empty() {
+            return;
+        }
+        let pages = pages.clone();
+        let req = without_body(req);
+        writer.on_send(Box::new(move |res| {
+            if let Some(page) = pages.pages.get(&res.status_code) {
+                page.render(res, &req);
+            }
+        }));
+    }
+}
+
+impl Page {
+    fn render(&self, res: &mut Response, req: &Request) {
+        let status = res.status_code;
+        let (content_type, body) = match self {
+            Page::Handler(handler) => {
+                let mut writer = BufferedWriter::new();
+                writer.status(status);
+                if handler.serve_http(&mut writer, req).is_err() {
+                    return;
+                }
+                let page = writer.finish();
+                let content_type = page.header(HttpHeader::ContentType.as_str());
+                (content_type.map(String::from), page.body_bytes().to_vec())
+            }
+            _ if prefers_json(req) => (
+                Some(ContentType::ApplicationJson.as_str().to_string()),
+                json_error(status).into_bytes(),
+            ),
+            Page::Template(html) => {
+                let body = html
+                    .replace("{status}", &status.as_num().to_string())
+                    .replace("{reason}", status.as_str())
+                    .replace("{method}", req.method.as_str())
+                    .replace("{path}", &escape_html(req.target_path()));
+                (Some(html_type()), body.into_bytes())
+            }
+            Page::File(path, cached) => {
+                let cached = cached.get_or_init(|| {
+                    let body = fs::read(path).ok()?;
+                    Some((crate::files::content_type(path), body))
+                });
+                match cached {
+                    Some((content_type, body)) => (Some(content_type.to_string()), body.clone()),
+                    None => return,
+                }
+            }
+        };
+        if !matches!(self, Page::Handler(_)) {
+            res.headers.append("Vary", HttpHeader::Accept.as_str());
+        }
+        match content_type {
+            Some(content_type) => res
+                .headers
+                .set(HttpHeader::ContentType.as_str(), &content_type),
+            None => {
+                res.headers.remove(HttpHeader::ContentType.as_str());
+            }
+        }
+        res.body = Some(ResponseBody::BytesBody(body));
+    }
+}
+
+/// Whether the client asks for JSON rather than HTML in its `Accept`
+/// header. `*/*` counts as HTML.
+pub fn prefers_json(req: &Request) -> bool {
+    let accept = match req.header(HttpHeader::Accept.as_str()) {
+        Some(accept) => accept,
+        None => return false,
+    };
+    let (mut json, mut html) = (0.0f32, 0.0f32);
+    for range in accept.split(',') {
+        let mut params = range.split(';');
+        let media = params.next().unwrap_or("").trim().to_ascii_lowercase();
+        let q = params
+            .find_map(|p| p.trim().strip_prefix("q="))
+            .and_then(|q| q.trim().parse().ok())
+            .unwrap_or(1.0);
+        match media.as_str() {
+            "application/json" => json = json.max(q),
+            m if m.starts_with("application/") && m.ends_with("+json") => json = json.max(q),
+            "text/html" | "application/xhtml+xml" | "text/*" | "*/*" => html = html.max(q),
+            _ => {}
+        }
+    }
+    json > html
+}
+
+/// Answer `req` with `status` and a short page saying so, or the JSON error
+/// object if the client prefers JSON.
+pub fn write_error(writer: &mut dyn ResponseWriter, req: &Request, status: StatusCode) {
+    writer.status(status);
+    if prefers_json(req) {
+        writer.json(&json_error(status));
+    } else {
+        let title = format!("{} {}", status.as_num(), status.as_str());
+        writer.html(&format!(
+            "<!DOCTYPE html>\n<title>{0}</title>\n<h1>{0}</h1>\n",
+            title
+        ));
+    }
+}
+
+fn html_type() -> String {
+    format!("{}; charset=utf-8", ContentType::TextHtml.as_str())
+}
+
+/// The body `json::write_error` would write for `status`, without needing
+/// the `json` feature.
+fn json_error(status: StatusCode) -> String {
+    format!(
+        "{{\"error\":{{\"message\":\"{}\",\"status\":{}}}}}",
+        status.as_str(),
+        status.as_num()
+    )
+}
+
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// A copy of `req` for rendering a page after the handler is done with it.
+fn without_body(req: &Request) -> Request {
+    let mut copy = Request::new();
+    copy.method = req.method;
+    copy.path = req.path.clone();
+    copy.version = req.version.clone();
+    copy.headers = req.headers.clone();
+    copy.remote_addr = req.remote_addr;
+    copy.secure = req.secure;
+    copy.params = req.params.clone();
+    copy.extensions = req.extensions.clone();
+    copy.app_state = req.app_state.clone();
+    copy
+}
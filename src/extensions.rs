@@ -0,0 +1,84 @@
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+
+/// A map holding at most one value per type.
+///
+/// Used both for application state shared by every request and for values
+/// attached to a single request by middleware. Cloning is cheap, values are
+/// shared behind `Arc`.
+#[derive(Clone, Default)]
+pub struct Extensions {
+    map: HashMap<TypeId, Arc<dyn Any + Send + Sync>>,
+}
+
+impl Extensions {
+    pub fn new() -> Self {
+        Extensions {
+            map: HashMap::new(),
+        }
+    }
+
+    /// Store `value`, replacing a previous value of the same type.
+    pub fn insert<T: Send + Sync + 'static>(&mut self, value: T) {
+        self.map.insert(TypeId::of::<T>(), Arc::new(value));
+    }
+
+    pub fn get<T: Send + Sync + 'static>(&self) -> Option<&T> {
+        self.map
+            .get(&TypeId::of::<T>())
+            .and_then(|v| v.downcast_ref())
+    }
+
+    /// A shared handle to the stored `T`.
+    pub fn get_arc<T: Send + Sync + 'static>(&self) -> Option<Arc<T>> {
+        self.map
+            .get(&TypeId::of::<T>())
+            .and_then(|v| v.clone().downcast().ok())
+    }
+
+    pub fn remove<T: Send + Sync + 'static>(&mut self) {
+        self.map.remove(&TypeId::of::<T>());
+    }
+
+    pub fn contains<T: Send + Sync + 'static>(&self) -> bool {
+        self.map.contains_key(&TypeId::of::<T>())
+    }
+
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    /// Copy every value of `other` into `self`, `other` winning on conflicts.
+    pub fn extend(&mut self, other: &Extensions) {
+        for (k, v) in &other.map {
+            self.map.insert(*k, v.clone());
+        }
+    }
+}
+
+/// Two maps are equal when they share the very same values.
+impl PartialEq for Extensions {
+    fn eq(&self, other: &Self) -> bool {
+        self.map.len() == other.map.len()
+            && self
+                .map
+                .iter()
+                .all(|(k, v)| other.map.get(k).is_some_and(|o| Arc::ptr_eq(v, o)))
+    }
+}
+
+impl Eq for Extensions {}
+
+impl fmt::Debug for Extensions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Extensions")
+            .field("len", &self.map.len())
+            .finish()
+    }
+}
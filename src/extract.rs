@@ -0,0 +1,428 @@
+//! Typed handler inputs.
+//!
+//! A type implementing `FromRequest` can be pulled out of a `Request`, and an
+//! `ExtractHandler` runs a function taking such types (or a tuple of them),
+//! answering with the extractor's `Rejection` when one of them fails:
+//!
+//! ```no_run
+//! use rust_server::extract::{ExtractHandler, Path, TypedHeader, UserAgent};
+//! use rust_server::server::DefaultServeMux;
+//!
+//! let mut m = DefaultServeMux::new();
+//! m.get(
+//!     "/users/:id",
+//!     ExtractHandler::new(|(Path(id), TypedHeader(UserAgent(ua))): (Path<u32>, TypedHeader<UserAgent>), w| {
+//!         w.text(&format!("user {} from {}", id, ua));
+//!         Ok(())
+//!     }),
+//! );
+//! ```
+use crate::cookie;
+use crate::error::ServerError;
+use crate::header::HttpHeader;
+use crate::message::{Params, Request, ResponseWriter};
+use crate::server::Handler;
+use crate::status_code::StatusCode;
+use std::collections::HashMap;
+use std::fmt;
+use std::marker::PhantomData;
+use std::str::FromStr;
+use std::sync::Arc;
+
+/// Why an extractor could not produce its value.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Rejection {
+    status: StatusCode,
+    message: String,
+    json: bool,
+}
+
+impl Rejection {
+    pub fn new(status: StatusCode, message: &str) -> Self {
+        Rejection {
+            status,
+            message: message.to_string(),
+            json: false,
+        }
+    }
+
+    pub fn status(&self) -> StatusCode {
+        self.status
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    /// Answer the request with this rejection, as a JSON error like
+    /// `JsonHandler`'s when it comes from the `Json` extractor.
+    pub fn respond(&self, writer: &mut dyn ResponseWriter) {
+        #[cfg(feature = "json")]
+        if self.json {
+            crate::json::write_error(writer, self.status, &self.message);
+            return;
+        }
+        writer.text(&self.message);
+        writer.status(self.status);
+    }
+}
+
+#[cfg(feature = "json")]
+impl From<crate::json::JsonRejection> for Rejection {
+    fn from(rejection: crate::json::JsonRejection) -> Self {
+        Rejection {
+            status: rejection.status(),
+            message: rejection.to_string(),
+            json: true,
+        }
+    }
+}
+
+impl fmt::Display for Rejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {}: {}",
+            self.status.as_num(),
+            self.status,
+            self.message
+        )
+    }
+}
+
+/// A value that can be extracted from a request.
+pub trait FromRequest: Sized {
+    fn from_request(req: &Request) -> Result<Self, Rejection>;
+}
+
+impl FromRequest for () {
+    fn from_request(_req: &Request) -> Result<Self, Rejection> {
+        Ok(())
+    }
+}
+
+impl FromRequest for Request {
+    fn from_request(req: &Request) -> Result<Self, Rejection> {
+        Ok(req.clone())
+    }
+}
+
+/// Never rejects; `None` when `T` would have.
+impl<T: FromRequest> FromRequest for Option<T> {
+    fn from_request(req: &Request) -> Result<Self, Rejection> {
+        Ok(T::from_request(req).ok())
+    }
+}
+
+macro_rules! tuple_from_request {
+    ($($t:ident),+) => {
+        impl<$($t: FromRequest),+> FromRequest for ($($t,)+) {
+            fn from_request(req: &Request) -> Result<Self, Rejection> {
+                Ok(($($t::from_request(req)?,)+))
+            }
+        }
+    };
+}
+
+tuple_from_request!(A);
+tuple_from_request!(A, B);
+tuple_from_request!(A, B, C);
+tuple_from_request!(A, B, C, D);
+tuple_from_request!(A, B, C, D, E);
+tuple_from_request!(A, B, C, D, E, F);
+
+/// Values that can be built from the route parameters of a request.
+pub trait FromParams: Sized {
+    fn from_params(params: &Params) -> Result<Self, Rejection>;
+}
+
+fn parse_param<T>(params: &Params, i: usize) -> Result<T, Rejection>
+where
+    T: FromStr,
+    T::Err: fmt::Display,
+{
+    let (name, value) = params.get(i).ok_or_else(|| {
+        Rejection::new(
+            StatusCode::InternalServerError,
+            "route has fewer parameters than the handler expects",
+        )
+    })?;
+    value.parse().map_err(|e: T::Err| {
+        Rejection::new(
+            StatusCode::BadRequest,
+            &format!("invalid path parameter `{}`: {}", name, e),
+        )
+    })
+}
+
+macro_rules! single_from_params {
+    ($($t:ty),+) => {
+        $(
+            impl FromParams for $t {
+                fn from_params(params: &Params) -> Result<Self, Rejection> {
+                    parse_param(params, 0)
+                }
+            }
+        )+
+    };
+}
+
+single_from_params!(String, bool, char, f32, f64);
+single_from_params!(u8, u16, u32, u64, u128, usize, i8, i16, i32, i64, i128, isize);
+
+macro_rules! tuple_from_params {
+    ($($t:ident => $i:tt),+) => {
+        impl<$($t),+> FromParams for ($($t,)+)
+        where
+            $($t: FromStr, $t::Err: fmt::Display),+
+        {
+            fn from_params(params: &Params) -> Result<Self, Rejection> {
+                Ok(($(parse_param::<$t>(params, $i)?,)+))
+            }
+        }
+    };
+}
+
+tuple_from_params!(A => 0);
+tuple_from_params!(A => 0, B => 1);
+tuple_from_params!(A => 0, B => 1, C => 2);
+tuple_from_params!(A => 0, B => 1, C => 2, D => 3);
+
+/// Route parameters captured by `DefaultServeMux`, in pattern order.
+///
+/// A single value is taken from the first parameter, a tuple from the first
+/// parameters in order. A value that does not parse is rejected with 400.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Path<T>(pub T);
+
+impl<T: FromParams> FromRequest for Path<T> {
+    fn from_request(req: &Request) -> Result<Self, Rejection> {
+        T::from_params(&req.params).map(Path)
+    }
+}
+
+/// The query string deserialized into `T`; 400 when it does not fit.
+#[cfg(feature = "form")]
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Query<T>(pub T);
+
+#[cfg(feature = "form")]
+impl<T: serde::de::DeserializeOwned> FromRequest for Query<T> {
+    fn from_request(req: &Request) -> Result<Self, Rejection> {
+        serde_urlencoded::from_str(req.query().unwrap_or(""))
+            .map(Query)
+            .map_err(|e| {
+                Rejection::new(
+                    StatusCode::BadRequest,
+                    &format!("invalid query string: {}", e),
+                )
+            })
+    }
+}
+
+#[cfg(feature = "form")]
+fn has_media_type(req: &Request, expected: &str) -> bool {
+    req.header(HttpHeader::ContentType.as_str())
+        .and_then(|v| v.split(';').next())
+        .is_some_and(|v| uncased::eq(v.trim(), expected))
+}
+
+/// An `application/x-www-form-urlencoded` body deserialized into `T`.
+///
+/// Rejected with 415 for other content types, 400 when the fields do not
+/// fit `T`, and 413 for a body over the server's limit.
+#[cfg(feature = "form")]
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Form<T>(pub T);
+
+#[cfg(feature = "form")]
+impl<T: serde::de::DeserializeOwned> FromRequest for Form<T> {
+    fn from_request(req: &Request) -> Result<Self, Rejection> {
+        if !has_media_type(req, "application/x-www-form-urlencoded") {
+            return Err(Rejection::new(
+                StatusCode::UnsupportedMediaType,
+                "expected Content-Type: application/x-www-form-urlencoded",
+            ));
+        }
+        let body = req.try_body_bytes().map_err(|e| {
+            let status = crate::message::body_error_status(&e);
+            Rejection::new(status, &format!("cannot read body: {}", e))
+        })?;
+        serde_urlencoded::from_bytes(body).map(Form).map_err(|e| {
+            Rejection::new(StatusCode::BadRequest, &format!("invalid form body: {}", e))
+        })
+    }
+}
+
+/// A JSON body deserialized into `T` by `json::read_json`.
+///
+/// Rejected like `JsonHandler` rejects: 415 for non-JSON content types, 400
+/// for a body that is not JSON fitting `T` and 413 for one over the
+/// server's limit, with a JSON error body.
+#[cfg(feature = "json")]
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Json<T>(pub T);
+
+#[cfg(feature = "json")]
+impl<T: serde::de::DeserializeOwned> FromRequest for Json<T> {
+    fn from_request(req: &Request) -> Result<Self, Rejection> {
+        crate::json::read_json(req)
+            .map(Json)
+            .map_err(Rejection::from)
+    }
+}
+
+/// A header that can be extracted with `TypedHeader`.
+pub trait NamedHeader: Sized {
+    fn name() -> &'static str;
+    fn parse(value: &str) -> Option<Self>;
+}
+
+/// A single header parsed into `H`; 400 when missing or malformed.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct TypedHeader<H>(pub H);
+
+impl<H: NamedHeader> FromRequest for TypedHeader<H> {
+    fn from_request(req: &Request) -> Result<Self, Rejection> {
+        let value = req.header(H::name()).ok_or_else(|| {
+            Rejection::new(
+                StatusCode::BadRequest,
+                &format!("missing header `{}`", H::name()),
+            )
+        })?;
+        H::parse(value).map(TypedHeader).ok_or_else(|| {
+            Rejection::new(
+                StatusCode::BadRequest,
+                &format!("invalid header `{}`", H::name()),
+            )
+        })
+    }
+}
+
+macro_rules! string_header {
+    ($name:ident, $header:expr) => {
+        #[derive(Clone, PartialEq, Eq, Debug)]
+        pub struct $name(pub String);
+
+        impl NamedHeader for $name {
+            fn name() -> &'static str {
+                $header
+            }
+            fn parse(value: &str) -> Option<Self> {
+                Some($name(value.to_string()))
+            }
+        }
+    };
+}
+
+string_header!(UserAgent, HttpHeader::UserAgent.as_str());
+string_header!(Accept, HttpHeader::Accept.as_str());
+string_header!(Referer, HttpHeader::Referer.as_str());
+string_header!(Host, "Host");
+string_header!(Authorization, "Authorization");
+
+/// The parsed `Content-Length` header.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct ContentLength(pub u64);
+
+impl NamedHeader for ContentLength {
+    fn name() -> &'static str {
+        HttpHeader::ContentLength.as_str()
+    }
+    fn parse(value: &str) -> Option<Self> {
+        value.trim().parse().ok().map(ContentLength)
+    }
+}
+
+/// Application state of type `T`, see `Server::set_state`.
+///
+/// Rejected with 500 when no such state was registered.
+#[derive(Debug)]
+pub struct State<T>(pub Arc<T>);
+
+impl<T: Send + Sync + 'static> FromRequest for State<T> {
+    fn from_request(req: &Request) -> Result<Self, Rejection> {
+        req.app_state.get_arc().map(State).ok_or_else(|| {
+            Rejection::new(
+                StatusCode::InternalServerError,
+                &format!("no state of type `{}`", std::any::type_name::<T>()),
+            )
+        })
+    }
+}
+
+/// A value of type `T` attached to the request by middleware.
+///
+/// Rejected with 500 when it is missing.
+#[derive(Debug)]
+pub struct Extension<T>(pub Arc<T>);
+
+impl<T: Send + Sync + 'static> FromRequest for Extension<T> {
+    fn from_request(req: &Request) -> Result<Self, Rejection> {
+        req.extensions.get_arc().map(Extension).ok_or_else(|| {
+            Rejection::new(
+                StatusCode::InternalServerError,
+                &format!("no extension of type `{}`", std::any::type_name::<T>()),
+            )
+        })
+    }
+}
+
+/// Cookies sent with the request, by name. Never rejects.
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct Cookies(pub HashMap<String, String>);
+
+impl Cookies {
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.0.get(name).map(String::as_str)
+    }
+}
+
+impl FromRequest for Cookies {
+    fn from_request(req: &Request) -> Result<Self, Rejection> {
+        let cookies = req
+            .header(cookie::COOKIE)
+            .map(|v| cookie::parse_cookies(v).into_iter().collect())
+            .unwrap_or_default();
+        Ok(Cookies(cookies))
+    }
+}
+
+/// A `Handler` that extracts `T` from the request before calling `f`.
+pub struct ExtractHandler<T, F> {
+    f: F,
+    _input: PhantomData<fn() -> T>,
+}
+
+impl<T, F> ExtractHandler<T, F>
+where
+    T: FromRequest,
+    F: Fn(T, &mut dyn ResponseWriter) -> Result<(), ServerError>,
+{
+    pub fn new(f: F) -> Self {
+        ExtractHandler {
+            f,
+            _input: PhantomData,
+        }
+    }
+}
+
+impl<T, F> Handler for ExtractHandler<T, F>
+where
+    T: FromRequest,
+    F: Fn(T, &mut dyn ResponseWriter) -> Result<(), ServerError>,
+{
+    fn serve_http(
+        &self,
+        writer: &mut dyn ResponseWriter,
+        req: &Request,
+    ) -> Result<(), ServerError> {
+        match T::from_request(req) {
+            Ok(input) => (self.f)(input, writer),
+            Err(rejection) => {
+                rejection.respond(writer);
+                Ok(())
+            }
+        }
+    }
+}
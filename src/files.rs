@@ -0,0 +1,143 @@
+//! Serving files from a directory.
+//!
+//! `FileServer` maps request paths onto files below a root directory and
+//! streams them with a `Content-Type` guessed from the extension, an `ETag`
+//! and `Last-Modified`, answering conditional requests itself. Paths that
+//! would leave the root are refused.
+use crate::conditional::{check_preconditions, ETag};
+use crate::error::ServerError;
+use crate::header::HttpHeader;
+use crate::message::{percent_decode, Request, ResponseWriter};
+use crate::method::Method;
+use crate::server::Handler;
+use crate::status_code::StatusCode;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+/// A `Handler` serving the files below a directory to `GET` and `HEAD`
+/// requests.
+///
+/// A directory is served by its `index.html`. Files and directories whose
+/// name starts with a dot are never served.
+pub struct FileServer {
+    root: PathBuf,
+    strip_prefix: String,
+    index: Option<String>,
+}
+
+impl FileServer {
+    pub fn new<P: Into<PathBuf>>(root: P) -> Self {
+        FileServer {
+            root: root.into(),
+            strip_prefix: String::new(),
+            index: Some("index.html".to_string()),
+        }
+    }
+
+    /// Remove `prefix` from request paths before looking them up, e.g. to
+    /// mount the directory under `/assets`.
+    pub fn strip_prefix(mut self, prefix: &str) -> Self {
+        self.strip_prefix = prefix.trim_end_matches('/').to_string();
+        self
+    }
+
+    /// The file serving a directory, `index.html` by default; `None`
+    /// answers directories with `404`.
+    pub fn index(mut self, index: Option<&str>) -> Self {
+        self.index = index.map(String::from);
+        self
+    }
+
+    /// The file `target` refers to, if it is below the root.
+    fn resolve(&self, target: &str) -> Option<PathBuf> {
+        let rest = target.strip_prefix(self.strip_prefix.as_str())?;
+        if !rest.is_empty() && !rest.starts_with('/') {
+            return None;
+        }
+        let mut path = self.root.clone();
+        for segment in rest.split('/').filter(|s| !s.is_empty()) {
+            let segment = percent_decode(segment);
+            if segment.starts_with('.') || segment.contains(['/', '\\', '\0']) {
+                return None;
+            }
+            path.push(segment);
+        }
+        if path.is_dir() {
+            path.push(self.index.as_ref()?);
+        }
+        Some(path)
+    }
+}
+
+impl Handler for FileServer {
+    fn serve_http(
+        &self,
+        writer: &mut dyn ResponseWriter,
+        req: &Request,
+    ) -> Result<(), ServerError> {
+        if req.method != Method::Get && req.method != Method::Head {
+            writer.set_header("Allow", "GET, HEAD");
+            writer.status(StatusCode::MethodNotAllowed);
+            return Ok(());
+        }
+        let found = self.resolve(req.target_path()).and_then(|path| {
+            let file = File::open(&path).ok()?;
+            let meta = file.metadata().ok().filter(|m| m.is_file())?;
+            Some((path, file, meta))
+        });
+        let (path, file, meta) = match found {
+            Some(found) => found,
+            None => {
+                writer.status(StatusCode::NotFound);
+                writer.text("Not Found");
+                return Ok(());
+            }
+        };
+
+        let modified = meta.modified().ok();
+        let stamp = modified
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .map_or(0, |d| d.as_secs());
+        let etag = ETag::weak(&format!("{:x}-{:x}", stamp, meta.len()));
+        writer.set_etag(&etag);
+        if let Some(modified) = modified {
+            writer.set_last_modified(modified);
+        }
+        if let Some(status) = check_preconditions(req, Some(&etag), modified) {
+            writer.status(status);
+            return Ok(());
+        }
+        writer.set_header(HttpHeader::ContentType.as_str(), content_type(&path));
+        writer.write_stream(Box::new(file), Some(meta.len()));
+        Ok(())
+    }
+}
+
+/// The media type of a file, from its extension.
+pub fn content_type(path: &Path) -> &'static str {
+    let ext = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(str::to_ascii_lowercase);
+    match ext.as_deref() {
+        Some("html") | Some("htm") => "text/html; charset=utf-8",
+        Some("css") => "text/css; charset=utf-8",
+        Some("js") | Some("mjs") => "text/javascript; charset=utf-8",
+        Some("json") => "application/json",
+        Some("txt") => "text/plain; charset=utf-8",
+        Some("csv") => "text/csv; charset=utf-8",
+        Some("xml") => "application/xml",
+        Some("svg") => "image/svg+xml",
+        Some("png") => "image/png",
+        Some("jpg") | Some("jpeg") => "image/jpeg",
+        Some("gif") => "image/gif",
+        Some("webp") => "image/webp",
+        Some("ico") => "image/x-icon",
+        Some("woff") => "font/woff",
+        Some("woff2") => "font/woff2",
+        Some("wasm") => "application/wasm",
+        Some("pdf") => "application/pdf",
+        _ => "application/octet-stream",
+    }
+}
@@ -0,0 +1,111 @@
+//! How an HTTP/1 message body is delimited on the wire, shared by the
+//! server and the client for the bodies each reads and writes.
+use std::io::{self, BufRead, Write};
+
+pub(crate) enum Framing {
+    Empty,
+    Length(u64),
+    Chunked { left: u64, done: bool },
+    UntilClose,
+}
+
+impl Framing {
+    /// Framing for a body sent with `Transfer-Encoding: chunked`.
+    pub(crate) fn chunked() -> Framing {
+        Framing::Chunked {
+            left: 0,
+            done: false,
+        }
+    }
+
+    /// Whether the whole body has been read.
+    pub(crate) fn finished(&self) -> bool {
+        match *self {
+            Framing::Empty => true,
+            Framing::Length(left) => left == 0,
+            Framing::Chunked { done, .. } => done,
+            Framing::UntilClose => false,
+        }
+    }
+
+    /// Read the next part of the body from `reader`, decoding chunks.
+    /// `Ok(0)` once the body ends; an error if the connection closes
+    /// before that.
+    pub(crate) fn read<R: BufRead>(&mut self, reader: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Framing::Empty => Ok(0),
+            Framing::Length(left) => {
+                if *left == 0 {
+                    return Ok(0);
+                }
+                let max = std::cmp::min(buf.len() as u64, *left) as usize;
+                let n = reader.read(&mut buf[..max])?;
+                if n == 0 {
+                    return Err(truncated());
+                }
+                *left -= n as u64;
+                Ok(n)
+            }
+            Framing::Chunked { left, done } => {
+                if *done {
+                    return Ok(0);
+                }
+                if *left == 0 {
+                    let mut line = String::new();
+                    if reader.read_line(&mut line)? == 0 {
+                        return Err(truncated());
+                    }
+                    let size = line.trim().split(';').next().unwrap_or("").trim();
+                    *left = u64::from_str_radix(size, 16).map_err(|_| {
+                        io::Error::new(io::ErrorKind::InvalidData, "bad chunk size")
+                    })?;
+                    if *left == 0 {
+                        // trailers, then the final empty line
+                        loop {
+                            line.clear();
+                            if reader.read_line(&mut line)? == 0 || line.trim().is_empty() {
+                                break;
+                            }
+                        }
+                        *done = true;
+                        return Ok(0);
+                    }
+                }
+                let max = std::cmp::min(buf.len() as u64, *left) as usize;
+                let n = reader.read(&mut buf[..max])?;
+                if n == 0 {
+                    return Err(truncated());
+                }
+                *left -= n as u64;
+                if *left == 0 {
+                    let mut crlf = [0; 2];
+                    reader.read_exact(&mut crlf)?;
+                }
+                Ok(n)
+            }
+            Framing::UntilClose => reader.read(buf),
+        }
+    }
+}
+
+/// Writes everything as one chunk of the chunked transfer coding.
+pub(crate) struct ChunkedWriter<W>(pub(crate) W);
+
+impl<W: Write> Write for ChunkedWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        write!(self.0, "{:x}\r\n", buf.len())?;
+        self.0.write_all(buf)?;
+        self.0.write_all(b"\r\n")?;
+        Ok(buf.len())
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+fn truncated() -> io::Error {
+    io::Error::new(io::ErrorKind::UnexpectedEof, "body truncated")
+}
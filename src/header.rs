@@ -1,14 +1,106 @@
 use self::ContentType::*;
 use self::HttpHeader::*;
+use std::collections::HashMap;
 use std::str::FromStr;
 use uncased;
 
+/// Ordered, case-insensitive header collection that can hold a name more
+/// than once (e.g. several `Set-Cookie` lines).
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Default)]
+pub struct HeaderMap {
+    entries: Vec<(String, String)>,
+}
+
+impl HeaderMap {
+    pub fn new() -> Self {
+        HeaderMap {
+            entries: Vec::new(),
+        }
+    }
+
+    /// First value stored under `name`.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.entries
+            .iter()
+            .find(|(k, _)| uncased::eq(k, name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    pub fn get_all<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.entries
+            .iter()
+            .filter(move |(k, _)| uncased::eq(k, name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.get(name).is_some()
+    }
+
+    /// Replace every value of `name` with `value`.
+    pub fn set(&mut self, name: &str, value: &str) {
+        self.remove(name);
+        self.append(name, value);
+    }
+
+    /// Add `value` without touching existing values of `name`.
+    ///
+    /// CR, LF and NUL are dropped from both, so that a value taken from
+    /// the request cannot end the header line and start another.
+    pub fn append(&mut self, name: &str, value: &str) {
+        self.entries.push((strip_breaks(name), strip_breaks(value)));
+    }
+
+    pub fn remove(&mut self, name: &str) {
+        self.entries.retain(|(k, _)| !uncased::eq(k, name));
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.entries.iter().map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+fn strip_breaks(s: &str) -> String {
+    s.chars()
+        .filter(|c| !matches!(c, '\r' | '\n' | '\0'))
+        .collect()
+}
+
+impl From<HashMap<String, String>> for HeaderMap {
+    fn from(headers: HashMap<String, String>) -> Self {
+        HeaderMap {
+            entries: headers
+                .iter()
+                .map(|(k, v)| (strip_breaks(k), strip_breaks(v)))
+                .collect(),
+        }
+    }
+}
+
 #[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
 pub enum HttpHeader {
     UserAgent,
     Accept,
     ContentLength,
     ContentType,
+    Referer,
+    Location,
+    Expect,
+    CacheControl,
+    ETag,
+    LastModified,
+    IfMatch,
+    IfNoneMatch,
+    IfModifiedSince,
+    IfUnmodifiedSince,
 }
 
 impl HttpHeader {
@@ -18,6 +110,16 @@ impl HttpHeader {
             Accept => "Accept",
             ContentLength => "Content-Length",
             ContentType => "Content-Type",
+            Referer => "Referer",
+            Location => "Location",
+            Expect => "Expect",
+            CacheControl => "Cache-Control",
+            ETag => "ETag",
+            LastModified => "Last-Modified",
+            IfMatch => "If-Match",
+            IfNoneMatch => "If-None-Match",
+            IfModifiedSince => "If-Modified-Since",
+            IfUnmodifiedSince => "If-Unmodified-Since",
         }
     }
 }
@@ -0,0 +1,1062 @@
+//! One HTTP/2 connection.
+//!
+//! The worker serving the connection reads frames; each request runs its
+//! handler on a thread of its own, so a slow response does not hold up the
+//! others. Request bodies reach the handlers through a pipe as they
+//! arrive. Responses share the writing half, and wait for flow-control
+//! window updates the reader applies.
+use super::frame::{
+    header_frames, ErrorCode, Frame, FrameError, DEFAULT_MAX_FRAME_SIZE, DEFAULT_WINDOW_SIZE,
+    MAX_WINDOW_SIZE, PREFACE, SETTINGS_ENABLE_PUSH, SETTINGS_HEADER_TABLE_SIZE,
+    SETTINGS_INITIAL_WINDOW_SIZE, SETTINGS_MAX_CONCURRENT_STREAMS, SETTINGS_MAX_FRAME_SIZE,
+};
+use super::hpack::{Decoder, Encoder};
+use crate::error::ServerError;
+use crate::message::{
+    body_error_status, early_rejection, record_request, BodyStream, CountingReader, Header,
+    Request, Response, ResponseBody, ResponseWriter, SendHook, BODY_TOO_LARGE,
+};
+use crate::method::Method;
+use crate::server::{ServeHandler, Server};
+use crate::status_code::StatusCode;
+use crate::transport::Transport;
+use std::collections::{HashMap, VecDeque};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::SocketAddr;
+use std::panic::{self, AssertUnwindSafe};
+use std::str::FromStr;
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, Scope};
+use std::time::{Duration, Instant, SystemTime};
+
+const MAX_CONCURRENT_STREAMS: usize = 100;
+/// Window offered to the client for each stream, opened again as its
+/// handler reads the body.
+const RECV_WINDOW: u32 = 1 << 20;
+/// Window offered for the whole connection, opened again as handlers read
+/// or bodies are dropped. Room for several streams, so one handler slow
+/// to read does not hold up the others.
+const CONN_RECV_WINDOW: u32 = 4 * RECV_WINDOW;
+/// Largest header block accepted, including its `CONTINUATION` frames.
+const MAX_HEADER_BLOCK: usize = 64 * 1024;
+/// How long a connection without open streams may stay silent.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(30);
+/// Largest frame size a peer may ask for.
+const MAX_FRAME_SIZE_LIMIT: u32 = (1 << 24) - 1;
+
+/// Header fields that only make sense for a single HTTP/1 connection.
+const CONNECTION_SPECIFIC: [&str; 5] = [
+    "connection",
+    "keep-alive",
+    "proxy-connection",
+    "transfer-encoding",
+    "upgrade",
+];
+
+fn protocol_error() -> FrameError {
+    FrameError::Connection(ErrorCode::ProtocolError)
+}
+
+fn stream_gone() -> io::Error {
+    io::Error::new(io::ErrorKind::BrokenPipe, "stream reset")
+}
+
+struct Output {
+    writer: Box<dyn Write + Send>,
+    encoder: Encoder,
+}
+
+/// Send-side flow control.
+struct Flow {
+    window: i64,
+    /// Windows of the streams whose response is not finished.
+    streams: HashMap<u32, i64>,
+    /// The client's `SETTINGS_INITIAL_WINDOW_SIZE`.
+    initial_window: i64,
+    max_frame_size: u32,
+    closed: bool,
+}
+
+/// Receive-side flow control for the connection.
+struct Recv {
+    /// What the client may still send.
+    window: i64,
+    /// Bytes consumed but not yet given back to the window.
+    unacked: u32,
+}
+
+/// What the reader and the stream threads share.
+struct Shared {
+    server: Arc<Server>,
+    remote_addr: Option<SocketAddr>,
+    secure: bool,
+    out: Mutex<Output>,
+    flow: Mutex<Flow>,
+    flow_changed: Condvar,
+    recv: Mutex<Recv>,
+}
+
+impl Shared {
+    fn write_locked(&self, out: &mut Output, frames: &[Frame]) -> io::Result<()> {
+        let mut buf = Vec::new();
+        for frame in frames {
+            frame.encode(&mut buf);
+        }
+        out.writer.write_all(&buf)?;
+        out.writer.flush()?;
+        self.server.stats.add_bytes_out(buf.len() as u64);
+        Ok(())
+    }
+
+    fn write_frames(&self, frames: &[Frame]) -> io::Result<()> {
+        let mut out = self.out.lock().unwrap();
+        self.write_locked(&mut out, frames)
+    }
+
+    fn write_headers(
+        &self,
+        stream_id: u32,
+        fields: &[(String, String)],
+        end_stream: bool,
+    ) -> io::Result<()> {
+        let max_frame_size = self.flow.lock().unwrap().max_frame_size;
+        // blocks must reach the client in the order they were encoded
+        let mut out = self.out.lock().unwrap();
+        let mut block = Vec::new();
+        let fields = fields.iter().map(|(n, v)| (n.as_str(), v.as_str()));
+        out.encoder.encode(fields, &mut block);
+        let frames = header_frames(stream_id, &block, end_stream, max_frame_size);
+        self.write_locked(&mut out, &frames)
+    }
+
+    /// Send `data` as the window allows, waiting for the client to open it.
+    fn write_data(&self, stream_id: u32, mut data: &[u8], end_stream: bool) -> io::Result<()> {
+        loop {
+            let mut n = 0;
+            if !data.is_empty() {
+                let mut flow = self.flow.lock().unwrap();
+                loop {
+                    if flow.closed {
+                        return Err(stream_gone());
+                    }
+                    let stream_window = *flow.streams.get(&stream_id).ok_or_else(stream_gone)?;
+                    let available = flow
+                        .window
+                        .min(stream_window)
+                        .min(i64::from(flow.max_frame_size));
+                    if available > 0 {
+                        n = data.len().min(available as usize);
+                        break;
+                    }
+                    flow = self.flow_changed.wait(flow).unwrap();
+                }
+                flow.window -= n as i64;
+                *flow.streams.get_mut(&stream_id).unwrap() -= n as i64;
+            }
+            let (chunk, rest) = data.split_at(n);
+            data = rest;
+            self.write_frames(&[Frame::Data {
+                stream_id,
+                end_stream: end_stream && rest.is_empty(),
+                data: chunk.to_vec(),
+                flow_len: n as u32,
+            }])?;
+            if rest.is_empty() {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Take `len` bytes of DATA off the connection window; false if the
+    /// client sent more than it allows.
+    fn receive(&self, len: u32) -> bool {
+        let mut recv = self.recv.lock().unwrap();
+        recv.window -= i64::from(len);
+        recv.window >= 0
+    }
+
+    /// Give `len` bytes read or dropped back to the connection window, in
+    /// updates large enough to be worth a frame.
+    fn release(&self, len: u32) {
+        let mut recv = self.recv.lock().unwrap();
+        recv.unacked += len;
+        if recv.unacked < CONN_RECV_WINDOW / 4 {
+            return;
+        }
+        let increment = std::mem::take(&mut recv.unacked);
+        recv.window += i64::from(increment);
+        drop(recv);
+        let _ = self.write_frames(&[Frame::WindowUpdate {
+            stream_id: 0,
+            increment,
+        }]);
+    }
+
+    /// The response of `stream_id` is complete, or abandoned.
+    fn finish(&self, stream_id: u32) {
+        self.flow.lock().unwrap().streams.remove(&stream_id);
+        self.flow_changed.notify_all();
+    }
+
+    fn reset(&self, stream_id: u32, code: ErrorCode) {
+        self.finish(stream_id);
+        let _ = self.write_frames(&[Frame::RstStream { stream_id, code }]);
+    }
+
+    /// Answer `req` with `status` without running the handler, and tell
+    /// the client to stop sending the body.
+    fn refuse(&self, stream_id: u32, req: &Request, status: StatusCode) {
+        let time = SystemTime::now();
+        let mut w = StreamWriter::new(self, stream_id, req.method == Method::Head);
+        w.status(status);
+        w.send();
+        record_request(&self.server, req, &w.res, time, Duration::ZERO);
+        self.reset(stream_id, ErrorCode::NoError);
+    }
+
+    fn serve_stream(&self, stream_id: u32, mut req: Request, body: Option<Arc<Pipe>>) {
+        if self.server.buffer_bodies {
+            if let Err(e) = req.buffer_body() {
+                if let Some(body) = body {
+                    self.release(body.close().1);
+                }
+                self.refuse(stream_id, &req, body_error_status(&e));
+                return;
+            }
+        }
+        let time = SystemTime::now();
+        let start = Instant::now();
+        let mut w = StreamWriter::new(self, stream_id, req.method == Method::Head);
+        let served = panic::catch_unwind(AssertUnwindSafe(|| {
+            ServeHandler::new(self.server.clone()).serve_http(&mut w, &req)
+        }));
+        if let Ok(Ok(())) = served {
+            w.send();
+        }
+        // the client may still be sending a body nobody is going to read
+        let unread = body.is_some_and(|body| {
+            let (arriving, dropped) = body.close();
+            self.release(dropped);
+            arriving
+        });
+        if w.sent {
+            record_request(&self.server, &req, &w.res, time, start.elapsed());
+            if unread {
+                self.reset(stream_id, ErrorCode::NoError);
+            } else {
+                self.finish(stream_id);
+            }
+        } else {
+            self.reset(stream_id, ErrorCode::InternalError);
+        }
+    }
+}
+
+/// The body of a stream on its way from the reader to the handler.
+struct Pipe {
+    state: Mutex<PipeState>,
+    changed: Condvar,
+}
+
+/// How a body ended when it was not received whole.
+type BodyError = (io::ErrorKind, &'static str);
+
+struct PipeState {
+    data: VecDeque<u8>,
+    /// What the client may still send on the stream.
+    window: i64,
+    end: Option<Result<(), BodyError>>,
+    /// The handler is done and reads no more.
+    closed: bool,
+}
+
+impl Pipe {
+    fn new() -> Self {
+        Pipe {
+            state: Mutex::new(PipeState {
+                data: VecDeque::new(),
+                window: i64::from(RECV_WINDOW),
+                end: None,
+                closed: false,
+            }),
+            changed: Condvar::new(),
+        }
+    }
+
+    /// Take `len` bytes of DATA off the stream window; false if the client
+    /// sent more than it allows.
+    fn receive(&self, len: u32) -> bool {
+        let mut state = self.state.lock().unwrap();
+        state.window -= i64::from(len);
+        state.window >= 0
+    }
+
+    /// Pass `data` on to the handler; false if it reads no more.
+    fn push(&self, data: &[u8]) -> bool {
+        let mut state = self.state.lock().unwrap();
+        if state.closed {
+            return false;
+        }
+        state.data.extend(data);
+        self.changed.notify_all();
+        true
+    }
+
+    fn end(&self, end: Result<(), BodyError>) {
+        let mut state = self.state.lock().unwrap();
+        state.end.get_or_insert(end);
+        self.changed.notify_all();
+    }
+
+    /// Drop what the handler did not read; whether the body was still
+    /// arriving, and how many bytes were dropped.
+    fn close(&self) -> (bool, u32) {
+        let mut state = self.state.lock().unwrap();
+        state.closed = true;
+        let dropped = std::mem::take(&mut state.data).len() as u32;
+        (state.end.is_none(), dropped)
+    }
+
+    fn is_closed(&self) -> bool {
+        self.state.lock().unwrap().closed
+    }
+}
+
+/// The handler's end of a `Pipe`. Reading opens the stream and connection
+/// windows again.
+struct StreamBody {
+    shared: Arc<Shared>,
+    stream_id: u32,
+    pipe: Arc<Pipe>,
+    send_continue: bool,
+    /// Bytes read but not yet given back to the client's window.
+    unacked: u32,
+}
+
+impl Read for StreamBody {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.send_continue {
+            self.send_continue = false;
+            let fields = [(":status".to_string(), "100".to_string())];
+            self.shared.write_headers(self.stream_id, &fields, false)?;
+        }
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let mut state = self.pipe.state.lock().unwrap();
+        loop {
+            if !state.data.is_empty() {
+                let n = state.data.read(buf)?;
+                self.unacked += n as u32;
+                // the client still has most of the window while this is small
+                let update = state.end.is_none() && self.unacked >= RECV_WINDOW / 4;
+                let increment = if update {
+                    let increment = std::mem::take(&mut self.unacked);
+                    state.window += i64::from(increment);
+                    increment
+                } else {
+                    0
+                };
+                drop(state);
+                if update {
+                    let _ = self.shared.write_frames(&[Frame::WindowUpdate {
+                        stream_id: self.stream_id,
+                        increment,
+                    }]);
+                }
+                self.shared.release(n as u32);
+                return Ok(n);
+            }
+            match state.end {
+                Some(Ok(())) => return Ok(0),
+                Some(Err((kind, msg))) => return Err(io::Error::new(kind, msg)),
+                None => state = self.pipe.changed.wait(state).unwrap(),
+            }
+        }
+    }
+}
+
+/// A stream still receiving its body.
+struct Incoming {
+    body: Arc<Pipe>,
+    received: u64,
+    content_length: Option<u64>,
+}
+
+impl Incoming {
+    /// The client ended the stream; false if the body did not have the
+    /// announced length.
+    fn complete(self) -> bool {
+        let whole = self.content_length.is_none_or(|len| len == self.received);
+        self.body.end(if whole {
+            Ok(())
+        } else {
+            Err((
+                io::ErrorKind::InvalidData,
+                "body does not match Content-Length",
+            ))
+        });
+        whole
+    }
+
+    fn abort(self, kind: io::ErrorKind, msg: &'static str) {
+        self.body.end(Err((kind, msg)));
+    }
+}
+
+/// A header block waiting for its `CONTINUATION` frames.
+struct Continuing {
+    stream_id: u32,
+    end_stream: bool,
+    block: Vec<u8>,
+}
+
+/// The reading side of a connection.
+struct Connection<'scope, 'env> {
+    shared: Arc<Shared>,
+    scope: &'scope Scope<'scope, 'env>,
+    reader: BufReader<CountingReader<Box<dyn Read + Send>>>,
+    counted: u64,
+    decoder: Decoder,
+    streams: HashMap<u32, Incoming>,
+    continuing: Option<Continuing>,
+    last_stream_id: u32,
+    going_away: bool,
+}
+
+/// Serve an HTTP/2 connection, after the `101` response for `upgrade`:
+/// the client's `HTTP2-Settings` and the request that becomes stream 1.
+pub(crate) fn serve(
+    server: Arc<Server>,
+    transport: Transport,
+    upgrade: Option<(Vec<(u16, u32)>, Request)>,
+) -> Result<(), ServerError> {
+    let remote_addr = transport.peer_addr().ok();
+    let secure = transport.is_secure();
+    let (reader, writer) = transport
+        .set_read_timeout(Some(IDLE_TIMEOUT))
+        .and_then(|()| transport.split())
+        .map_err(|_| ServerError::ReadLineError)?;
+    let shared = Arc::new(Shared {
+        server,
+        remote_addr,
+        secure,
+        out: Mutex::new(Output {
+            writer,
+            encoder: Encoder::new(),
+        }),
+        flow: Mutex::new(Flow {
+            window: i64::from(DEFAULT_WINDOW_SIZE),
+            streams: HashMap::new(),
+            initial_window: i64::from(DEFAULT_WINDOW_SIZE),
+            max_frame_size: DEFAULT_MAX_FRAME_SIZE,
+            closed: false,
+        }),
+        flow_changed: Condvar::new(),
+        recv: Mutex::new(Recv {
+            window: i64::from(CONN_RECV_WINDOW),
+            unacked: 0,
+        }),
+    });
+    let result = thread::scope(|scope| {
+        let mut conn = Connection {
+            shared: shared.clone(),
+            scope,
+            reader: BufReader::new(CountingReader::new(reader)),
+            counted: 0,
+            decoder: Decoder::new(),
+            streams: HashMap::new(),
+            continuing: None,
+            last_stream_id: 0,
+            going_away: false,
+        };
+        let result = conn.run(upgrade);
+        for (_, incoming) in conn.streams.drain() {
+            incoming.abort(io::ErrorKind::UnexpectedEof, "connection closed");
+        }
+        if let Err(FrameError::Connection(code)) = &result {
+            let _ = shared.write_frames(&[Frame::GoAway {
+                last_stream_id: conn.last_stream_id,
+                code: *code,
+                debug: Vec::new(),
+            }]);
+        }
+        // responses still waiting for a window will never get one
+        shared.flow.lock().unwrap().closed = true;
+        shared.flow_changed.notify_all();
+        result
+    });
+    match result {
+        Err(FrameError::Connection(_)) => Err(ServerError::ParseError),
+        _ => Ok(()),
+    }
+}
+
+impl<'scope, 'env> Connection<'scope, 'env> {
+    fn run(&mut self, upgrade: Option<(Vec<(u16, u32)>, Request)>) -> Result<(), FrameError> {
+        self.shared.write_frames(&[
+            Frame::Settings {
+                ack: false,
+                params: vec![
+                    (
+                        SETTINGS_MAX_CONCURRENT_STREAMS,
+                        MAX_CONCURRENT_STREAMS as u32,
+                    ),
+                    (SETTINGS_INITIAL_WINDOW_SIZE, RECV_WINDOW),
+                ],
+            },
+            Frame::WindowUpdate {
+                stream_id: 0,
+                increment: CONN_RECV_WINDOW - DEFAULT_WINDOW_SIZE,
+            },
+        ])?;
+        if let Some((settings, req)) = upgrade {
+            self.apply_settings(&settings)?;
+            self.open(1, req, true);
+        }
+
+        let mut preface = [0; 24];
+        self.reader.read_exact(&mut preface)?;
+        if preface[..] != *PREFACE {
+            return Err(protocol_error());
+        }
+        let mut first = true;
+        while self.wait_for_frame()? {
+            let frame = Frame::read(&mut self.reader, DEFAULT_MAX_FRAME_SIZE)?;
+            let count = self.reader.get_ref().count;
+            self.shared.server.stats.add_bytes_in(count - self.counted);
+            self.counted = count;
+            if first && !matches!(frame, Frame::Settings { ack: false, .. }) {
+                return Err(protocol_error());
+            }
+            first = false;
+            self.handle(frame)?;
+        }
+        Ok(())
+    }
+
+    /// Wait for the next frame; false once the client closed the connection
+    /// or it was idle for too long.
+    fn wait_for_frame(&mut self) -> Result<bool, FrameError> {
+        loop {
+            match self.reader.fill_buf() {
+                Ok(buf) => return Ok(!buf.is_empty()),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e)
+                    if e.kind() == io::ErrorKind::WouldBlock
+                        || e.kind() == io::ErrorKind::TimedOut =>
+                {
+                    // bodies of finished responses the client stopped sending
+                    self.streams
+                        .retain(|_, incoming| !incoming.body.is_closed());
+                    if self.streams.is_empty()
+                        && self.shared.flow.lock().unwrap().streams.is_empty()
+                    {
+                        self.shared.write_frames(&[Frame::GoAway {
+                            last_stream_id: self.last_stream_id,
+                            code: ErrorCode::NoError,
+                            debug: Vec::new(),
+                        }])?;
+                        return Ok(false);
+                    }
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+
+    fn handle(&mut self, frame: Frame) -> Result<(), FrameError> {
+        if let Some(c) = &self.continuing {
+            match &frame {
+                Frame::Continuation { stream_id, .. } if *stream_id == c.stream_id => {}
+                _ => return Err(protocol_error()),
+            }
+        }
+        match frame {
+            Frame::Headers {
+                stream_id,
+                end_stream,
+                end_headers,
+                block,
+            } => {
+                self.continuing = Some(Continuing {
+                    stream_id,
+                    end_stream,
+                    block,
+                });
+                if end_headers {
+                    self.end_headers()?;
+                }
+            }
+            Frame::Continuation {
+                end_headers, block, ..
+            } => {
+                let c = self.continuing.as_mut().ok_or_else(protocol_error)?;
+                c.block.extend_from_slice(&block);
+                if c.block.len() > MAX_HEADER_BLOCK {
+                    return Err(FrameError::Connection(ErrorCode::EnhanceYourCalm));
+                }
+                if end_headers {
+                    self.end_headers()?;
+                }
+            }
+            Frame::Data {
+                stream_id,
+                end_stream,
+                data,
+                flow_len,
+            } => self.data(stream_id, end_stream, data, flow_len)?,
+            Frame::Settings { ack: true, .. } => {}
+            Frame::Settings { ack: false, params } => {
+                self.apply_settings(&params)?;
+                self.shared.write_frames(&[Frame::Settings {
+                    ack: true,
+                    params: Vec::new(),
+                }])?;
+            }
+            Frame::Ping { ack: false, data } => {
+                self.shared
+                    .write_frames(&[Frame::Ping { ack: true, data }])?;
+            }
+            Frame::Ping { ack: true, .. } => {}
+            Frame::WindowUpdate {
+                stream_id,
+                increment,
+            } => self.window_update(stream_id, increment)?,
+            Frame::RstStream { stream_id, .. } => {
+                if stream_id > self.last_stream_id {
+                    return Err(protocol_error());
+                }
+                if let Some(incoming) = self.streams.remove(&stream_id) {
+                    incoming.abort(io::ErrorKind::ConnectionReset, "stream reset");
+                }
+                self.shared.finish(stream_id);
+            }
+            Frame::GoAway { .. } => self.going_away = true,
+            Frame::PushPromise { .. } => return Err(protocol_error()),
+            Frame::Priority { .. } | Frame::Unknown { .. } => {}
+        }
+        Ok(())
+    }
+
+    fn end_headers(&mut self) -> Result<(), FrameError> {
+        let Continuing {
+            stream_id,
+            end_stream,
+            block,
+        } = self.continuing.take().unwrap();
+        let fields = self
+            .decoder
+            .decode(&block)
+            .map_err(|_| FrameError::Connection(ErrorCode::CompressionError))?;
+
+        if let Some(incoming) = self.streams.remove(&stream_id) {
+            // trailers, which end the stream and are not passed on
+            if !end_stream || fields.iter().any(|(n, _)| n.starts_with(':')) {
+                incoming.abort(io::ErrorKind::InvalidData, "malformed trailers");
+                self.shared.reset(stream_id, ErrorCode::ProtocolError);
+            } else if !incoming.complete() {
+                self.shared.reset(stream_id, ErrorCode::ProtocolError);
+            }
+            return Ok(());
+        }
+        if stream_id <= self.last_stream_id {
+            return Err(FrameError::Connection(ErrorCode::StreamClosed));
+        }
+        if stream_id.is_multiple_of(2) {
+            return Err(protocol_error());
+        }
+        self.last_stream_id = stream_id;
+        if self.going_away {
+            return Ok(());
+        }
+        if self.shared.flow.lock().unwrap().streams.len() >= MAX_CONCURRENT_STREAMS {
+            self.shared.reset(stream_id, ErrorCode::RefusedStream);
+            return Ok(());
+        }
+        match request(fields) {
+            Some(req) => self.open(stream_id, req, end_stream),
+            None => self.shared.reset(stream_id, ErrorCode::ProtocolError),
+        }
+        Ok(())
+    }
+
+    fn open(&mut self, stream_id: u32, mut req: Request, end_stream: bool) {
+        req.remote_addr = self.shared.remote_addr;
+        req.secure = self.shared.secure;
+        req.app_state = self.shared.server.state.clone();
+        self.last_stream_id = stream_id;
+        {
+            let mut flow = self.shared.flow.lock().unwrap();
+            let window = flow.initial_window;
+            flow.streams.insert(stream_id, window);
+        }
+        if let Some(status) = early_rejection(&self.shared.server, &req) {
+            self.shared.refuse(stream_id, &req, status);
+            return;
+        }
+        // an upgraded request arrives with its body
+        if end_stream
+            && req.header("content-length").is_some()
+            && req.content_length != req.body_bytes().len() as u64
+        {
+            self.shared.reset(stream_id, ErrorCode::ProtocolError);
+            return;
+        }
+        let body = if end_stream {
+            None
+        } else {
+            let body = Arc::new(Pipe::new());
+            req.set_body_reader(Box::new(StreamBody {
+                shared: self.shared.clone(),
+                stream_id,
+                pipe: body.clone(),
+                send_continue: req.expects_continue(),
+                unacked: 0,
+            }));
+            let content_length = req.header("content-length").map(|_| req.content_length);
+            self.streams.insert(
+                stream_id,
+                Incoming {
+                    body: body.clone(),
+                    received: 0,
+                    content_length,
+                },
+            );
+            Some(body)
+        };
+        let shared = self.shared.clone();
+        self.scope
+            .spawn(move || shared.serve_stream(stream_id, req, body));
+    }
+
+    fn data(
+        &mut self,
+        stream_id: u32,
+        end_stream: bool,
+        data: Vec<u8>,
+        flow_len: u32,
+    ) -> Result<(), FrameError> {
+        if stream_id > self.last_stream_id {
+            return Err(protocol_error());
+        }
+        if !self.shared.receive(flow_len) {
+            return Err(FrameError::Connection(ErrorCode::FlowControlError));
+        }
+        // padding is given back at once, and so is data for a stream
+        // already closed or reset, which only counts for the connection
+        let padding = flow_len - data.len() as u32;
+        let incoming = match self.streams.get_mut(&stream_id) {
+            Some(incoming) => incoming,
+            None => {
+                self.shared.release(flow_len);
+                return Ok(());
+            }
+        };
+        self.shared.release(padding);
+        if !incoming.body.receive(flow_len) {
+            let incoming = self.streams.remove(&stream_id).unwrap();
+            incoming.abort(io::ErrorKind::InvalidData, "stream window exceeded");
+            self.shared.release(data.len() as u32);
+            self.shared.reset(stream_id, ErrorCode::FlowControlError);
+            return Ok(());
+        }
+        incoming.received += data.len() as u64;
+        let max = self.shared.server.max_body_size;
+        if max.is_some_and(|max| incoming.received > max) {
+            let incoming = self.streams.remove(&stream_id).unwrap();
+            incoming.abort(io::ErrorKind::InvalidData, BODY_TOO_LARGE);
+            self.shared.release(data.len() as u32);
+            return Ok(());
+        }
+        if !incoming.body.push(&data) {
+            self.shared.release(data.len() as u32);
+        }
+        if end_stream {
+            let incoming = self.streams.remove(&stream_id).unwrap();
+            if !incoming.complete() {
+                self.shared.reset(stream_id, ErrorCode::ProtocolError);
+            }
+        }
+        Ok(())
+    }
+
+    fn window_update(&mut self, stream_id: u32, increment: u32) -> Result<(), FrameError> {
+        let mut flow = self.shared.flow.lock().unwrap();
+        let increment = i64::from(increment);
+        if stream_id == 0 {
+            if increment == 0 {
+                return Err(protocol_error());
+            }
+            flow.window += increment;
+            if flow.window > i64::from(MAX_WINDOW_SIZE) {
+                return Err(FrameError::Connection(ErrorCode::FlowControlError));
+            }
+        } else if let Some(window) = flow.streams.get_mut(&stream_id) {
+            *window += increment;
+            if increment == 0 || *window > i64::from(MAX_WINDOW_SIZE) {
+                let code = if increment == 0 {
+                    ErrorCode::ProtocolError
+                } else {
+                    ErrorCode::FlowControlError
+                };
+                drop(flow);
+                if let Some(incoming) = self.streams.remove(&stream_id) {
+                    incoming.abort(io::ErrorKind::ConnectionReset, "stream reset");
+                }
+                self.shared.reset(stream_id, code);
+                return Ok(());
+            }
+        } else if stream_id > self.last_stream_id {
+            return Err(protocol_error());
+        }
+        drop(flow);
+        self.shared.flow_changed.notify_all();
+        Ok(())
+    }
+
+    fn apply_settings(&mut self, params: &[(u16, u32)]) -> Result<(), FrameError> {
+        for &(id, value) in params {
+            match id {
+                SETTINGS_HEADER_TABLE_SIZE => {
+                    let mut out = self.shared.out.lock().unwrap();
+                    out.encoder.set_max_table_size(value as usize);
+                }
+                SETTINGS_ENABLE_PUSH if value > 1 => return Err(protocol_error()),
+                SETTINGS_INITIAL_WINDOW_SIZE => {
+                    if value > MAX_WINDOW_SIZE {
+                        return Err(FrameError::Connection(ErrorCode::FlowControlError));
+                    }
+                    let mut flow = self.shared.flow.lock().unwrap();
+                    let delta = i64::from(value) - flow.initial_window;
+                    flow.initial_window = i64::from(value);
+                    for window in flow.streams.values_mut() {
+                        *window += delta;
+                        if *window > i64::from(MAX_WINDOW_SIZE) {
+                            return Err(FrameError::Connection(ErrorCode::FlowControlError));
+                        }
+                    }
+                }
+                SETTINGS_MAX_FRAME_SIZE => {
+                    if !(DEFAULT_MAX_FRAME_SIZE..=MAX_FRAME_SIZE_LIMIT).contains(&value) {
+                        return Err(protocol_error());
+                    }
+                    self.shared.flow.lock().unwrap().max_frame_size = value;
+                }
+                _ => {}
+            }
+        }
+        self.shared.flow_changed.notify_all();
+        Ok(())
+    }
+}
+
+/// Build the request from its header fields, or `None` when they are not
+/// a valid HTTP/2 request.
+fn request(fields: Vec<(String, String)>) -> Option<Request> {
+    let mut req = Request::new();
+    req.version = "HTTP/2.0".to_string();
+    let (mut method, mut scheme, mut path, mut authority) = (None, None, None, None);
+    let mut regular = false;
+    let mut cookies = Vec::new();
+    for (name, value) in fields {
+        if let Some(pseudo) = name.strip_prefix(':') {
+            let slot = match pseudo {
+                "method" => &mut method,
+                "scheme" => &mut scheme,
+                "path" => &mut path,
+                "authority" => &mut authority,
+                _ => return None,
+            };
+            if regular || slot.replace(value).is_some() {
+                return None;
+            }
+            continue;
+        }
+        regular = true;
+        if name.bytes().any(|b| b.is_ascii_uppercase())
+            || CONNECTION_SPECIFIC.contains(&name.as_str())
+            || (name == "te" && value != "trailers")
+        {
+            return None;
+        }
+        if name == "cookie" {
+            cookies.push(value);
+            continue;
+        }
+        let value = match req.headers.get(&name) {
+            Some(prev) => format!("{}, {}", prev, value),
+            None => value,
+        };
+        req.insert_header(&name, &value);
+    }
+
+    req.method = Method::from_str(&method?).unwrap_or(Method::Other);
+    scheme?;
+    req.path = path.filter(|p| !p.is_empty())?;
+    if let Some(authority) = authority {
+        if req.header("host").is_none() {
+            req.insert_header("host", &authority);
+        }
+    }
+    if !cookies.is_empty() {
+        req.insert_header("cookie", &cookies.join("; "));
+    }
+    Some(req)
+}
+
+/// The `ResponseWriter` of a stream.
+struct StreamWriter<'a> {
+    shared: &'a Shared,
+    stream_id: u32,
+    head: bool,
+    res: Response,
+    sent: bool,
+    hooks: Vec<SendHook>,
+    stream: Option<BodyStream>,
+}
+
+impl<'a> StreamWriter<'a> {
+    fn new(shared: &'a Shared, stream_id: u32, head: bool) -> Self {
+        let mut res = Response::new();
+        res.version = "HTTP/2.0".to_string();
+        StreamWriter {
+            shared,
+            stream_id,
+            head,
+            res,
+            sent: false,
+            hooks: Vec::new(),
+            stream: None,
+        }
+    }
+
+    /// Copy a streamed body into `DATA` frames; the number of bytes sent.
+    fn copy_stream(&self, mut body: Box<dyn Read + Send>) -> io::Result<u64> {
+        let mut buf = vec![0; DEFAULT_MAX_FRAME_SIZE as usize];
+        let mut copied = 0;
+        loop {
+            let n = match body.read(&mut buf) {
+                Ok(n) => n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => {
+                    self.shared.reset(self.stream_id, ErrorCode::InternalError);
+                    return Err(e);
+                }
+            };
+            self.shared.write_data(self.stream_id, &buf[..n], n == 0)?;
+            if n == 0 {
+                return Ok(copied);
+            }
+            copied += n as u64;
+        }
+    }
+}
+
+impl ResponseWriter for StreamWriter<'_> {
+    fn write(&mut self, data: ResponseBody) {
+        if !self.sent {
+            self.res.body = Some(data);
+        }
+    }
+    fn write_header(&mut self, code: usize) {
+        self.status(StatusCode::from_num(code).unwrap_or(StatusCode::Ok));
+    }
+    fn header(&mut self, headers: Header) {
+        for (name, value) in headers {
+            self.set_header(&name, &value);
+        }
+    }
+    fn send(&mut self) {
+        if self.sent {
+            return;
+        }
+        self.sent = true;
+        if let Some((_, Some(n))) = &self.stream {
+            self.res.headers.set("Content-Length", &n.to_string());
+        }
+        while let Some(hook) = self.hooks.pop() {
+            hook(&mut self.res);
+        }
+        let status = self.res.status_code.as_num();
+        let bodiless = status < 200 || status == 204 || status == 304;
+        let mut stream = self.stream.take();
+        if stream.is_some() && (self.res.body.is_some() || bodiless) {
+            stream = None;
+            self.res.headers.remove("Content-Length");
+        }
+
+        let mut fields = vec![(":status".to_string(), status.to_string())];
+        for (name, value) in self.res.headers.iter() {
+            let name = name.to_ascii_lowercase();
+            let replaced = name == "content-length" && self.res.body.is_some();
+            if !replaced && !CONNECTION_SPECIFIC.contains(&name.as_str()) {
+                fields.push((name, value.to_string()));
+            }
+        }
+        let body = self.res.body_bytes();
+        if self.res.body.is_some() {
+            fields.push(("content-length".to_string(), body.len().to_string()));
+        }
+        let with_data = !self.head && !bodiless && (stream.is_some() || !body.is_empty());
+        if self
+            .shared
+            .write_headers(self.stream_id, &fields, !with_data)
+            .is_err()
+            || !with_data
+        {
+            return;
+        }
+        match stream {
+            Some((body, _)) => {
+                if let Ok(n) = self.copy_stream(body) {
+                    self.res.content_length = n;
+                }
+            }
+            None => {
+                let _ = self.shared.write_data(self.stream_id, body, true);
+            }
+        }
+    }
+    fn set_header(&mut self, name: &str, value: &str) {
+        if !self.sent {
+            self.res.headers.set(name, value);
+        }
+    }
+    fn append_header(&mut self, name: &str, value: &str) {
+        if !self.sent {
+            self.res.headers.append(name, value);
+        }
+    }
+    fn status(&mut self, code: StatusCode) {
+        if !self.sent {
+            self.res.status_code = code;
+        }
+    }
+    fn is_sent(&self) -> bool {
+        self.sent
+    }
+    fn response(&self) -> &Response {
+        &self.res
+    }
+    fn on_send(&mut self, hook: SendHook) {
+        if !self.sent {
+            self.hooks.push(hook);
+        }
+    }
+    fn clear(&mut self) {
+        if !self.sent {
+            self.res = Response {
+                version: self.res.version.clone(),
+                ..Response::new()
+            };
+            self.stream = None;
+        }
+    }
+    fn write_stream(&mut self, body: Box<dyn Read + Send>, length: Option<u64>) {
+        if !self.sent {
+            self.res.body = None;
+            self.stream = Some((body, length));
+        }
+    }
+}
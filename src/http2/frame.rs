@@ -0,0 +1,454 @@
+//! HTTP/2 frames (RFC 7540 section 4 and 6).
+use std::fmt;
+use std::io::{self, Read, Write};
+
+/// What a client sends before its first frame.
+pub const PREFACE: &[u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+
+/// Smallest `SETTINGS_MAX_FRAME_SIZE`, and the size every peer must accept.
+pub const DEFAULT_MAX_FRAME_SIZE: u32 = 16_384;
+pub const DEFAULT_WINDOW_SIZE: u32 = 65_535;
+/// Largest flow-control window.
+pub const MAX_WINDOW_SIZE: u32 = (1 << 31) - 1;
+
+pub const SETTINGS_HEADER_TABLE_SIZE: u16 = 0x1;
+pub const SETTINGS_ENABLE_PUSH: u16 = 0x2;
+pub const SETTINGS_MAX_CONCURRENT_STREAMS: u16 = 0x3;
+pub const SETTINGS_INITIAL_WINDOW_SIZE: u16 = 0x4;
+pub const SETTINGS_MAX_FRAME_SIZE: u16 = 0x5;
+pub const SETTINGS_MAX_HEADER_LIST_SIZE: u16 = 0x6;
+
+const DATA: u8 = 0x0;
+const HEADERS: u8 = 0x1;
+const PRIORITY: u8 = 0x2;
+const RST_STREAM: u8 = 0x3;
+const SETTINGS: u8 = 0x4;
+const PUSH_PROMISE: u8 = 0x5;
+const PING: u8 = 0x6;
+const GOAWAY: u8 = 0x7;
+const WINDOW_UPDATE: u8 = 0x8;
+const CONTINUATION: u8 = 0x9;
+
+const END_STREAM: u8 = 0x1;
+const ACK: u8 = 0x1;
+const END_HEADERS: u8 = 0x4;
+const PADDED: u8 = 0x8;
+const PRIORITY_FLAG: u8 = 0x20;
+
+/// Error codes of `RST_STREAM` and `GOAWAY` (RFC 7540 section 7).
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum ErrorCode {
+    NoError,
+    ProtocolError,
+    InternalError,
+    FlowControlError,
+    SettingsTimeout,
+    StreamClosed,
+    FrameSizeError,
+    RefusedStream,
+    Cancel,
+    CompressionError,
+    ConnectError,
+    EnhanceYourCalm,
+    InadequateSecurity,
+    Http11Required,
+    /// A code without a variant; peers must treat it as `InternalError`.
+    Other(u32),
+}
+
+impl ErrorCode {
+    const ALL: [ErrorCode; 14] = [
+        ErrorCode::NoError,
+        ErrorCode::ProtocolError,
+        ErrorCode::InternalError,
+        ErrorCode::FlowControlError,
+        ErrorCode::SettingsTimeout,
+        ErrorCode::StreamClosed,
+        ErrorCode::FrameSizeError,
+        ErrorCode::RefusedStream,
+        ErrorCode::Cancel,
+        ErrorCode::CompressionError,
+        ErrorCode::ConnectError,
+        ErrorCode::EnhanceYourCalm,
+        ErrorCode::InadequateSecurity,
+        ErrorCode::Http11Required,
+    ];
+
+    pub fn from_u32(code: u32) -> Self {
+        ErrorCode::ALL
+            .get(code as usize)
+            .copied()
+            .unwrap_or(ErrorCode::Other(code))
+    }
+
+    pub fn as_u32(self) -> u32 {
+        match self {
+            ErrorCode::Other(code) => code,
+            known => ErrorCode::ALL.iter().position(|c| *c == known).unwrap() as u32,
+        }
+    }
+}
+
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ErrorCode::Other(code) => write!(f, "error code {:#x}", code),
+            known => fmt::Debug::fmt(known, f),
+        }
+    }
+}
+
+/// A frame, with padding and priority information already removed.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Frame {
+    Data {
+        stream_id: u32,
+        end_stream: bool,
+        data: Vec<u8>,
+        /// Payload length including padding, which counts against flow
+        /// control.
+        flow_len: u32,
+    },
+    Headers {
+        stream_id: u32,
+        end_stream: bool,
+        end_headers: bool,
+        block: Vec<u8>,
+    },
+    Priority {
+        stream_id: u32,
+    },
+    RstStream {
+        stream_id: u32,
+        code: ErrorCode,
+    },
+    Settings {
+        ack: bool,
+        params: Vec<(u16, u32)>,
+    },
+    PushPromise {
+        stream_id: u32,
+        promised_id: u32,
+        end_headers: bool,
+        block: Vec<u8>,
+    },
+    Ping {
+        ack: bool,
+        data: [u8; 8],
+    },
+    GoAway {
+        last_stream_id: u32,
+        code: ErrorCode,
+        debug: Vec<u8>,
+    },
+    WindowUpdate {
+        stream_id: u32,
+        increment: u32,
+    },
+    Continuation {
+        stream_id: u32,
+        end_headers: bool,
+        block: Vec<u8>,
+    },
+    /// A frame of an unknown type, which receivers ignore.
+    Unknown {
+        kind: u8,
+        stream_id: u32,
+    },
+}
+
+/// Why a frame could not be read.
+#[derive(Debug)]
+pub enum FrameError {
+    Io(io::Error),
+    /// The peer broke the protocol; the connection must be closed with
+    /// this code.
+    Connection(ErrorCode),
+}
+
+impl From<io::Error> for FrameError {
+    fn from(e: io::Error) -> Self {
+        FrameError::Io(e)
+    }
+}
+
+impl fmt::Display for FrameError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FrameError::Io(e) => e.fmt(f),
+            FrameError::Connection(code) => code.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for FrameError {}
+
+fn u32_at(b: &[u8]) -> u32 {
+    u32::from_be_bytes([b[0], b[1], b[2], b[3]])
+}
+
+fn stream_id_at(b: &[u8]) -> u32 {
+    u32_at(b) & MAX_WINDOW_SIZE
+}
+
+/// Strip the padding of a `PADDED` payload.
+fn unpad(flags: u8, payload: &mut Vec<u8>) -> Result<(), FrameError> {
+    if flags & PADDED == 0 {
+        return Ok(());
+    }
+    let pad = *payload
+        .first()
+        .ok_or(FrameError::Connection(ErrorCode::FrameSizeError))? as usize;
+    if pad >= payload.len() {
+        return Err(FrameError::Connection(ErrorCode::ProtocolError));
+    }
+    payload.truncate(payload.len() - pad);
+    payload.remove(0);
+    Ok(())
+}
+
+impl Frame {
+    /// Read one frame, refusing payloads over `max_frame_size`.
+    pub fn read<R: Read>(r: &mut R, max_frame_size: u32) -> Result<Frame, FrameError> {
+        let mut head = [0; 9];
+        r.read_exact(&mut head)?;
+        let len = u32::from_be_bytes([0, head[0], head[1], head[2]]);
+        let (kind, flags) = (head[3], head[4]);
+        let stream_id = stream_id_at(&head[5..]);
+        if len > max_frame_size {
+            return Err(FrameError::Connection(ErrorCode::FrameSizeError));
+        }
+        let mut payload = vec![0; len as usize];
+        r.read_exact(&mut payload)?;
+
+        let protocol_error = Err(FrameError::Connection(ErrorCode::ProtocolError));
+        let size_error = Err(FrameError::Connection(ErrorCode::FrameSizeError));
+        let needs_stream = matches!(
+            kind,
+            DATA | HEADERS | PRIORITY | RST_STREAM | PUSH_PROMISE | CONTINUATION
+        );
+        let forbids_stream = matches!(kind, SETTINGS | PING | GOAWAY);
+        if (needs_stream && stream_id == 0) || (forbids_stream && stream_id != 0) {
+            return protocol_error;
+        }
+
+        let frame = match kind {
+            DATA => {
+                unpad(flags, &mut payload)?;
+                Frame::Data {
+                    stream_id,
+                    end_stream: flags & END_STREAM != 0,
+                    data: payload,
+                    flow_len: len,
+                }
+            }
+            HEADERS => {
+                unpad(flags, &mut payload)?;
+                if flags & PRIORITY_FLAG != 0 {
+                    if payload.len() < 5 {
+                        return size_error;
+                    }
+                    payload.drain(..5);
+                }
+                Frame::Headers {
+                    stream_id,
+                    end_stream: flags & END_STREAM != 0,
+                    end_headers: flags & END_HEADERS != 0,
+                    block: payload,
+                }
+            }
+            PRIORITY if len != 5 => return size_error,
+            PRIORITY => Frame::Priority { stream_id },
+            RST_STREAM if len != 4 => return size_error,
+            RST_STREAM => Frame::RstStream {
+                stream_id,
+                code: ErrorCode::from_u32(u32_at(&payload)),
+            },
+            SETTINGS if flags & ACK != 0 && len != 0 => return size_error,
+            SETTINGS if !len.is_multiple_of(6) => return size_error,
+            SETTINGS => Frame::Settings {
+                ack: flags & ACK != 0,
+                params: payload
+                    .chunks(6)
+                    .map(|p| (u16::from_be_bytes([p[0], p[1]]), u32_at(&p[2..])))
+                    .collect(),
+            },
+            PUSH_PROMISE => {
+                unpad(flags, &mut payload)?;
+                if payload.len() < 4 {
+                    return size_error;
+                }
+                let promised_id = stream_id_at(&payload);
+                payload.drain(..4);
+                Frame::PushPromise {
+                    stream_id,
+                    promised_id,
+                    end_headers: flags & END_HEADERS != 0,
+                    block: payload,
+                }
+            }
+            PING if len != 8 => return size_error,
+            PING => {
+                let mut data = [0; 8];
+                data.copy_from_slice(&payload);
+                Frame::Ping {
+                    ack: flags & ACK != 0,
+                    data,
+                }
+            }
+            GOAWAY if len < 8 => return size_error,
+            GOAWAY => Frame::GoAway {
+                last_stream_id: stream_id_at(&payload),
+                code: ErrorCode::from_u32(u32_at(&payload[4..])),
+                debug: payload[8..].to_vec(),
+            },
+            WINDOW_UPDATE if len != 4 => return size_error,
+            WINDOW_UPDATE => Frame::WindowUpdate {
+                stream_id,
+                increment: stream_id_at(&payload),
+            },
+            CONTINUATION => Frame::Continuation {
+                stream_id,
+                end_headers: flags & END_HEADERS != 0,
+                block: payload,
+            },
+            kind => Frame::Unknown { kind, stream_id },
+        };
+        Ok(frame)
+    }
+
+    /// Append the frame as sent on the wire to `dst`. Payloads are never
+    /// padded.
+    pub fn encode(&self, dst: &mut Vec<u8>) {
+        let mut head = |kind: u8, flags: u8, stream_id: u32, len: usize| {
+            dst.extend_from_slice(&(len as u32).to_be_bytes()[1..]);
+            dst.push(kind);
+            dst.push(flags);
+            dst.extend_from_slice(&stream_id.to_be_bytes());
+        };
+        let flag = |set: bool, flag: u8| if set { flag } else { 0 };
+        match self {
+            Frame::Data {
+                stream_id,
+                end_stream,
+                data,
+                ..
+            } => {
+                head(DATA, flag(*end_stream, END_STREAM), *stream_id, data.len());
+                dst.extend_from_slice(data);
+            }
+            Frame::Headers {
+                stream_id,
+                end_stream,
+                end_headers,
+                block,
+            } => {
+                let flags = flag(*end_stream, END_STREAM) | flag(*end_headers, END_HEADERS);
+                head(HEADERS, flags, *stream_id, block.len());
+                dst.extend_from_slice(block);
+            }
+            Frame::Priority { stream_id } => {
+                head(PRIORITY, 0, *stream_id, 5);
+                dst.extend_from_slice(&[0, 0, 0, 0, 15]);
+            }
+            Frame::RstStream { stream_id, code } => {
+                head(RST_STREAM, 0, *stream_id, 4);
+                dst.extend_from_slice(&code.as_u32().to_be_bytes());
+            }
+            Frame::Settings { ack, params } => {
+                head(SETTINGS, flag(*ack, ACK), 0, params.len() * 6);
+                for (id, value) in params {
+                    dst.extend_from_slice(&id.to_be_bytes());
+                    dst.extend_from_slice(&value.to_be_bytes());
+                }
+            }
+            Frame::PushPromise {
+                stream_id,
+                promised_id,
+                end_headers,
+                block,
+            } => {
+                let flags = flag(*end_headers, END_HEADERS);
+                head(PUSH_PROMISE, flags, *stream_id, block.len() + 4);
+                dst.extend_from_slice(&promised_id.to_be_bytes());
+                dst.extend_from_slice(block);
+            }
+            Frame::Ping { ack, data } => {
+                head(PING, flag(*ack, ACK), 0, 8);
+                dst.extend_from_slice(data);
+            }
+            Frame::GoAway {
+                last_stream_id,
+                code,
+                debug,
+            } => {
+                head(GOAWAY, 0, 0, 8 + debug.len());
+                dst.extend_from_slice(&last_stream_id.to_be_bytes());
+                dst.extend_from_slice(&code.as_u32().to_be_bytes());
+                dst.extend_from_slice(debug);
+            }
+            Frame::WindowUpdate {
+                stream_id,
+                increment,
+            } => {
+                head(WINDOW_UPDATE, 0, *stream_id, 4);
+                dst.extend_from_slice(&increment.to_be_bytes());
+            }
+            Frame::Continuation {
+                stream_id,
+                end_headers,
+                block,
+            } => {
+                head(
+                    CONTINUATION,
+                    flag(*end_headers, END_HEADERS),
+                    *stream_id,
+                    block.len(),
+                );
+                dst.extend_from_slice(block);
+            }
+            Frame::Unknown { kind, stream_id } => head(*kind, 0, *stream_id, 0),
+        }
+    }
+
+    pub fn write<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        let mut buf = Vec::new();
+        self.encode(&mut buf);
+        w.write_all(&buf)
+    }
+}
+
+/// `HEADERS` followed by as many `CONTINUATION` frames as needed to carry
+/// `block` in frames of at most `max_frame_size`.
+pub fn header_frames(
+    stream_id: u32,
+    block: &[u8],
+    end_stream: bool,
+    max_frame_size: u32,
+) -> Vec<Frame> {
+    let mut chunks: Vec<&[u8]> = block.chunks(max_frame_size as usize).collect();
+    if chunks.is_empty() {
+        chunks.push(&[]);
+    }
+    let last = chunks.len() - 1;
+    chunks
+        .into_iter()
+        .enumerate()
+        .map(|(i, chunk)| {
+            if i == 0 {
+                Frame::Headers {
+                    stream_id,
+                    end_stream,
+                    end_headers: i == last,
+                    block: chunk.to_vec(),
+                }
+            } else {
+                Frame::Continuation {
+                    stream_id,
+                    end_headers: i == last,
+                    block: chunk.to_vec(),
+                }
+            }
+        })
+        .collect()
+}
@@ -0,0 +1,648 @@
+//! HPACK header compression (RFC 7541).
+//!
+//! `Decoder` implements the whole format, dynamic table included.
+//! `Encoder` only ever emits literals and static-table references, so it
+//! keeps no state a peer's decoder could get out of step with.
+use std::collections::VecDeque;
+use std::fmt;
+use std::sync::OnceLock;
+
+/// The header block could not be decoded; the connection must be closed
+/// with `COMPRESSION_ERROR`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct HpackError(&'static str);
+
+impl fmt::Display for HpackError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "hpack: {}", self.0)
+    }
+}
+
+impl std::error::Error for HpackError {}
+
+const STATIC_TABLE: [(&str, &str); 61] = [
+    (":authority", ""),
+    (":method", "GET"),
+    (":method", "POST"),
+    (":path", "/"),
+    (":path", "/index.html"),
+    (":scheme", "http"),
+    (":scheme", "https"),
+    (":status", "200"),
+    (":status", "204"),
+    (":status", "206"),
+    (":status", "304"),
+    (":status", "400"),
+    (":status", "404"),
+    (":status", "500"),
+    ("accept-charset", ""),
+    ("accept-encoding", "gzip, deflate"),
+    ("accept-language", ""),
+    ("accept-ranges", ""),
+    ("accept", ""),
+    ("access-control-allow-origin", ""),
+    ("age", ""),
+    ("allow", ""),
+    ("authorization", ""),
+    ("cache-control", ""),
+    ("content-disposition", ""),
+    ("content-encoding", ""),
+    ("content-language", ""),
+    ("content-length", ""),
+    ("content-location", ""),
+    ("content-range", ""),
+    ("content-type", ""),
+    ("cookie", ""),
+    ("date", ""),
+    ("etag", ""),
+    ("expect", ""),
+    ("expires", ""),
+    ("from", ""),
+    ("host", ""),
+    ("if-match", ""),
+    ("if-modified-since", ""),
+    ("if-none-match", ""),
+    ("if-range", ""),
+    ("if-unmodified-since", ""),
+    ("last-modified", ""),
+    ("link", ""),
+    ("location", ""),
+    ("max-forwards", ""),
+    ("proxy-authenticate", ""),
+    ("proxy-authorization", ""),
+    ("range", ""),
+    ("referer", ""),
+    ("refresh", ""),
+    ("retry-after", ""),
+    ("server", ""),
+    ("set-cookie", ""),
+    ("strict-transport-security", ""),
+    ("transfer-encoding", ""),
+    ("user-agent", ""),
+    ("vary", ""),
+    ("via", ""),
+    ("www-authenticate", ""),
+];
+
+/// The Huffman code of each byte and of EOS (index 256) as `(code, bits)`
+/// (RFC 7541 appendix B).
+const HUFFMAN: [(u32, u8); 257] = [
+    (0x1ff8, 13),
+    (0x7fffd8, 23),
+    (0xfffffe2, 28),
+    (0xfffffe3, 28),
+    (0xfffffe4, 28),
+    (0xfffffe5, 28),
+    (0xfffffe6, 28),
+    (0xfffffe7, 28),
+    (0xfffffe8, 28),
+    (0xffffea, 24),
+    (0x3ffffffc, 30),
+    (0xfffffe9, 28),
+    (0xfffffea, 28),
+    (0x3ffffffd, 30),
+    (0xfffffeb, 28),
+    (0xfffffec, 28),
+    (0xfffffed, 28),
+    (0xfffffee, 28),
+    (0xfffffef, 28),
+    (0xffffff0, 28),
+    (0xffffff1, 28),
+    (0xffffff2, 28),
+    (0x3ffffffe, 30),
+    (0xffffff3, 28),
+    (0xffffff4, 28),
+    (0xffffff5, 28),
+    (0xffffff6, 28),
+    (0xffffff7, 28),
+    (0xffffff8, 28),
+    (0xffffff9, 28),
+    (0xffffffa, 28),
+    (0xffffffb, 28),
+    (0x14, 6),
+    (0x3f8, 10),
+    (0x3f9, 10),
+    (0xffa, 12),
+    (0x1ff9, 13),
+    (0x15, 6),
+    (0xf8, 8),
+    (0x7fa, 11),
+    (0x3fa, 10),
+    (0x3fb, 10),
+    (0xf9, 8),
+    (0x7fb, 11),
+    (0xfa, 8),
+    (0x16, 6),
+    (0x17, 6),
+    (0x18, 6),
+    (0x0, 5),
+    (0x1, 5),
+    (0x2, 5),
+    (0x19, 6),
+    (0x1a, 6),
+    (0x1b, 6),
+    (0x1c, 6),
+    (0x1d, 6),
+    (0x1e, 6),
+    (0x1f, 6),
+    (0x5c, 7),
+    (0xfb, 8),
+    (0x7ffc, 15),
+    (0x20, 6),
+    (0xffb, 12),
+    (0x3fc, 10),
+    (0x1ffa, 13),
+    (0x21, 6),
+    (0x5d, 7),
+    (0x5e, 7),
+    (0x5f, 7),
+    (0x60, 7),
+    (0x61, 7),
+    (0x62, 7),
+    (0x63, 7),
+    (0x64, 7),
+    (0x65, 7),
+    (0x66, 7),
+    (0x67, 7),
+    (0x68, 7),
+    (0x69, 7),
+    (0x6a, 7),
+    (0x6b, 7),
+    (0x6c, 7),
+    (0x6d, 7),
+    (0x6e, 7),
+    (0x6f, 7),
+    (0x70, 7),
+    (0x71, 7),
+    (0x72, 7),
+    (0xfc, 8),
+    (0x73, 7),
+    (0xfd, 8),
+    (0x1ffb, 13),
+    (0x7fff0, 19),
+    (0x1ffc, 13),
+    (0x3ffc, 14),
+    (0x22, 6),
+    (0x7ffd, 15),
+    (0x3, 5),
+    (0x23, 6),
+    (0x4, 5),
+    (0x24, 6),
+    (0x5, 5),
+    (0x25, 6),
+    (0x26, 6),
+    (0x27, 6),
+    (0x6, 5),
+    (0x74, 7),
+    (0x75, 7),
+    (0x28, 6),
+    (0x29, 6),
+    (0x2a, 6),
+    (0x7, 5),
+    (0x2b, 6),
+    (0x76, 7),
+    (0x2c, 6),
+    (0x8, 5),
+    (0x9, 5),
+    (0x2d, 6),
+    (0x77, 7),
+    (0x78, 7),
+    (0x79, 7),
+    (0x7a, 7),
+    (0x7b, 7),
+    (0x7ffe, 15),
+    (0x7fc, 11),
+    (0x3ffd, 14),
+    (0x1ffd, 13),
+    (0xffffffc, 28),
+    (0xfffe6, 20),
+    (0x3fffd2, 22),
+    (0xfffe7, 20),
+    (0xfffe8, 20),
+    (0x3fffd3, 22),
+    (0x3fffd4, 22),
+    (0x3fffd5, 22),
+    (0x7fffd9, 23),
+    (0x3fffd6, 22),
+    (0x7fffda, 23),
+    (0x7fffdb, 23),
+    (0x7fffdc, 23),
+    (0x7fffdd, 23),
+    (0x7fffde, 23),
+    (0xffffeb, 24),
+    (0x7fffdf, 23),
+    (0xffffec, 24),
+    (0xffffed, 24),
+    (0x3fffd7, 22),
+    (0x7fffe0, 23),
+    (0xffffee, 24),
+    (0x7fffe1, 23),
+    (0x7fffe2, 23),
+    (0x7fffe3, 23),
+    (0x7fffe4, 23),
+    (0x1fffdc, 21),
+    (0x3fffd8, 22),
+    (0x7fffe5, 23),
+    (0x3fffd9, 22),
+    (0x7fffe6, 23),
+    (0x7fffe7, 23),
+    (0xffffef, 24),
+    (0x3fffda, 22),
+    (0x1fffdd, 21),
+    (0xfffe9, 20),
+    (0x3fffdb, 22),
+    (0x3fffdc, 22),
+    (0x7fffe8, 23),
+    (0x7fffe9, 23),
+    (0x1fffde, 21),
+    (0x7fffea, 23),
+    (0x3fffdd, 22),
+    (0x3fffde, 22),
+    (0xfffff0, 24),
+    (0x1fffdf, 21),
+    (0x3fffdf, 22),
+    (0x7fffeb, 23),
+    (0x7fffec, 23),
+    (0x1fffe0, 21),
+    (0x1fffe1, 21),
+    (0x3fffe0, 22),
+    (0x1fffe2, 21),
+    (0x7fffed, 23),
+    (0x3fffe1, 22),
+    (0x7fffee, 23),
+    (0x7fffef, 23),
+    (0xfffea, 20),
+    (0x3fffe2, 22),
+    (0x3fffe3, 22),
+    (0x3fffe4, 22),
+    (0x7ffff0, 23),
+    (0x3fffe5, 22),
+    (0x3fffe6, 22),
+    (0x7ffff1, 23),
+    (0x3ffffe0, 26),
+    (0x3ffffe1, 26),
+    (0xfffeb, 20),
+    (0x7fff1, 19),
+    (0x3fffe7, 22),
+    (0x7ffff2, 23),
+    (0x3fffe8, 22),
+    (0x1ffffec, 25),
+    (0x3ffffe2, 26),
+    (0x3ffffe3, 26),
+    (0x3ffffe4, 26),
+    (0x7ffffde, 27),
+    (0x7ffffdf, 27),
+    (0x3ffffe5, 26),
+    (0xfffff1, 24),
+    (0x1ffffed, 25),
+    (0x7fff2, 19),
+    (0x1fffe3, 21),
+    (0x3ffffe6, 26),
+    (0x7ffffe0, 27),
+    (0x7ffffe1, 27),
+    (0x3ffffe7, 26),
+    (0x7ffffe2, 27),
+    (0xfffff2, 24),
+    (0x1fffe4, 21),
+    (0x1fffe5, 21),
+    (0x3ffffe8, 26),
+    (0x3ffffe9, 26),
+    (0xffffffd, 28),
+    (0x7ffffe3, 27),
+    (0x7ffffe4, 27),
+    (0x7ffffe5, 27),
+    (0xfffec, 20),
+    (0xfffff3, 24),
+    (0xfffed, 20),
+    (0x1fffe6, 21),
+    (0x3fffe9, 22),
+    (0x1fffe7, 21),
+    (0x1fffe8, 21),
+    (0x7ffff3, 23),
+    (0x3fffea, 22),
+    (0x3fffeb, 22),
+    (0x1ffffee, 25),
+    (0x1ffffef, 25),
+    (0xfffff4, 24),
+    (0xfffff5, 24),
+    (0x3ffffea, 26),
+    (0x7ffff4, 23),
+    (0x3ffffeb, 26),
+    (0x7ffffe6, 27),
+    (0x3ffffec, 26),
+    (0x3ffffed, 26),
+    (0x7ffffe7, 27),
+    (0x7ffffe8, 27),
+    (0x7ffffe9, 27),
+    (0x7ffffea, 27),
+    (0x7ffffeb, 27),
+    (0xffffffe, 28),
+    (0x7ffffec, 27),
+    (0x7ffffed, 27),
+    (0x7ffffee, 27),
+    (0x7ffffef, 27),
+    (0x7fffff0, 27),
+    (0x3ffffee, 26),
+    (0x3fffffff, 30),
+];
+
+/// The Huffman code is canonical: codes of one length are consecutive, and
+/// sorting symbols by `(length, code)` orders them by code. Decoding only
+/// needs the first code and the first symbol index of each length.
+struct Canonical {
+    /// Symbols ordered by `(length, code)`.
+    symbols: Vec<u16>,
+    /// Per length: the first code, the index of its symbol, and the count.
+    lengths: [(u32, usize, usize); 31],
+}
+
+fn canonical() -> &'static Canonical {
+    static TABLE: OnceLock<Canonical> = OnceLock::new();
+    TABLE.get_or_init(|| {
+        let mut symbols: Vec<u16> = (0..257).collect();
+        symbols.sort_by_key(|&s| (HUFFMAN[s as usize].1, HUFFMAN[s as usize].0));
+        let mut lengths = [(0, 0, 0); 31];
+        for (i, &s) in symbols.iter().enumerate() {
+            let (code, bits) = HUFFMAN[s as usize];
+            let entry = &mut lengths[bits as usize];
+            if entry.2 == 0 {
+                *entry = (code, i, 0);
+            }
+            entry.2 += 1;
+        }
+        Canonical { symbols, lengths }
+    })
+}
+
+fn huffman_decode(src: &[u8]) -> Result<Vec<u8>, HpackError> {
+    let table = canonical();
+    let mut out = Vec::with_capacity(src.len() * 8 / 5);
+    let (mut code, mut bits) = (0u32, 0usize);
+    for byte in src {
+        for shift in (0..8).rev() {
+            code = (code << 1) | u32::from((byte >> shift) & 1);
+            bits += 1;
+            let (first, index, count) = table.lengths[bits];
+            if count > 0 && code >= first && code - first < count as u32 {
+                let symbol = table.symbols[index + (code - first) as usize];
+                if symbol == 256 {
+                    return Err(HpackError("EOS in huffman string"));
+                }
+                out.push(symbol as u8);
+                code = 0;
+                bits = 0;
+            } else if bits == 30 {
+                return Err(HpackError("bad huffman code"));
+            }
+        }
+    }
+    // padding is a prefix of EOS: up to 7 one bits
+    if bits > 7 || code != (1 << bits) - 1 {
+        return Err(HpackError("bad huffman padding"));
+    }
+    Ok(out)
+}
+
+fn huffman_len(src: &[u8]) -> usize {
+    let bits: usize = src.iter().map(|&b| HUFFMAN[b as usize].1 as usize).sum();
+    bits.div_ceil(8)
+}
+
+fn huffman_encode(src: &[u8], dst: &mut Vec<u8>) {
+    let (mut acc, mut bits) = (0u64, 0u32);
+    for &b in src {
+        let (code, len) = HUFFMAN[b as usize];
+        acc = (acc << len) | u64::from(code);
+        bits += u32::from(len);
+        while bits >= 8 {
+            bits -= 8;
+            dst.push((acc >> bits) as u8);
+        }
+    }
+    if bits > 0 {
+        // pad with the most significant bits of EOS
+        dst.push(((acc << (8 - bits)) | ((1 << (8 - bits)) - 1)) as u8);
+    }
+}
+
+/// Append `value` as an integer with an `n`-bit prefix, the other bits of
+/// the first byte set to `flags`.
+fn encode_int(value: usize, n: u8, flags: u8, dst: &mut Vec<u8>) {
+    let max = (1usize << n) - 1;
+    if value < max {
+        dst.push(flags | value as u8);
+        return;
+    }
+    dst.push(flags | max as u8);
+    let mut rest = value - max;
+    while rest >= 128 {
+        dst.push((rest % 128 + 128) as u8);
+        rest /= 128;
+    }
+    dst.push(rest as u8);
+}
+
+fn encode_string(s: &str, dst: &mut Vec<u8>) {
+    let huffman = huffman_len(s.as_bytes());
+    if huffman < s.len() {
+        encode_int(huffman, 7, 0x80, dst);
+        huffman_encode(s.as_bytes(), dst);
+    } else {
+        encode_int(s.len(), 7, 0, dst);
+        dst.extend_from_slice(s.as_bytes());
+    }
+}
+
+/// Reads the representations of a header block.
+struct Cursor<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl Cursor<'_> {
+    fn int(&mut self, n: u8) -> Result<usize, HpackError> {
+        let truncated = HpackError("truncated integer");
+        let max = (1usize << n) - 1;
+        let first = *self.buf.get(self.pos).ok_or(truncated)? as usize & max;
+        self.pos += 1;
+        if first < max {
+            return Ok(first);
+        }
+        let mut value = max;
+        let mut shift = 0;
+        loop {
+            let b = *self.buf.get(self.pos).ok_or(truncated)? as usize;
+            self.pos += 1;
+            if shift > 28 {
+                return Err(HpackError("integer overflow"));
+            }
+            value += (b & 127) << shift;
+            shift += 7;
+            if b & 128 == 0 {
+                return Ok(value);
+            }
+        }
+    }
+
+    fn string(&mut self) -> Result<String, HpackError> {
+        let huffman = self
+            .buf
+            .get(self.pos)
+            .ok_or(HpackError("truncated string"))?
+            & 0x80
+            != 0;
+        let len = self.int(7)?;
+        let end = self.pos.checked_add(len).filter(|&e| e <= self.buf.len());
+        let end = end.ok_or(HpackError("truncated string"))?;
+        let raw = &self.buf[self.pos..end];
+        self.pos = end;
+        let bytes = if huffman {
+            huffman_decode(raw)?
+        } else {
+            raw.to_vec()
+        };
+        Ok(String::from_utf8_lossy(&bytes).into_owned())
+    }
+}
+
+/// Size of an entry in the dynamic table (RFC 7541 section 4.1).
+fn entry_size(name: &str, value: &str) -> usize {
+    name.len() + value.len() + 32
+}
+
+/// Decodes header blocks from one peer; blocks must be decoded in the order
+/// they were received.
+pub struct Decoder {
+    table: VecDeque<(String, String)>,
+    size: usize,
+    max_size: usize,
+    /// The limit announced in `SETTINGS_HEADER_TABLE_SIZE`.
+    limit: usize,
+}
+
+impl Default for Decoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Decoder {
+    /// A decoder with the default 4096 byte dynamic table.
+    pub fn new() -> Self {
+        Decoder {
+            table: VecDeque::new(),
+            size: 0,
+            max_size: 4096,
+            limit: 4096,
+        }
+    }
+
+    fn evict(&mut self) {
+        while self.size > self.max_size {
+            match self.table.pop_back() {
+                Some((name, value)) => self.size -= entry_size(&name, &value),
+                None => break,
+            }
+        }
+    }
+
+    fn get(&self, index: usize) -> Result<(String, String), HpackError> {
+        let entry = match index {
+            0 => None,
+            i if i <= STATIC_TABLE.len() => {
+                let (name, value) = STATIC_TABLE[i - 1];
+                Some((name.to_string(), value.to_string()))
+            }
+            i => self.table.get(i - STATIC_TABLE.len() - 1).cloned(),
+        };
+        entry.ok_or(HpackError("bad table index"))
+    }
+
+    /// Decode a complete header block into `(name, value)` pairs.
+    pub fn decode(&mut self, block: &[u8]) -> Result<Vec<(String, String)>, HpackError> {
+        let mut c = Cursor { buf: block, pos: 0 };
+        let mut headers = Vec::new();
+        while c.pos < block.len() {
+            let first = block[c.pos];
+            if first & 0x80 != 0 {
+                let index = c.int(7)?;
+                headers.push(self.get(index)?);
+            } else if first & 0x40 != 0 {
+                let index = c.int(6)?;
+                let name = match index {
+                    0 => c.string()?,
+                    i => self.get(i)?.0,
+                };
+                let value = c.string()?;
+                self.size += entry_size(&name, &value);
+                self.table.push_front((name.clone(), value.clone()));
+                self.evict();
+                headers.push((name, value));
+            } else if first & 0x20 != 0 {
+                if !headers.is_empty() {
+                    return Err(HpackError("table size update after a header"));
+                }
+                let size = c.int(5)?;
+                if size > self.limit {
+                    return Err(HpackError("table size update over the limit"));
+                }
+                self.max_size = size;
+                self.evict();
+            } else {
+                // literal without indexing or never indexed
+                let index = c.int(4)?;
+                let name = match index {
+                    0 => c.string()?,
+                    i => self.get(i)?.0,
+                };
+                headers.push((name, c.string()?));
+            }
+        }
+        Ok(headers)
+    }
+}
+
+/// Encodes header blocks without using the dynamic table.
+#[derive(Default)]
+pub struct Encoder {
+    /// Whether the peer changed `SETTINGS_HEADER_TABLE_SIZE` since the last
+    /// block, which must then start with a table size update.
+    size_changed: bool,
+}
+
+impl Encoder {
+    pub fn new() -> Self {
+        Encoder::default()
+    }
+
+    /// Note a new `SETTINGS_HEADER_TABLE_SIZE` from the peer.
+    pub fn set_max_table_size(&mut self, _size: usize) {
+        self.size_changed = true;
+    }
+
+    /// Append the block for `headers` to `dst`. Names must be lowercase.
+    pub fn encode<'a, I>(&mut self, headers: I, dst: &mut Vec<u8>)
+    where
+        I: IntoIterator<Item = (&'a str, &'a str)>,
+    {
+        if self.size_changed {
+            self.size_changed = false;
+            encode_int(0, 5, 0x20, dst);
+        }
+        for (name, value) in headers {
+            let exact = STATIC_TABLE.iter().position(|&e| e == (name, value));
+            if let Some(i) = exact {
+                encode_int(i + 1, 7, 0x80, dst);
+                continue;
+            }
+            match STATIC_TABLE.iter().position(|&(n, _)| n == name) {
+                Some(i) => encode_int(i + 1, 4, 0, dst),
+                None => {
+                    dst.push(0);
+                    encode_string(name, dst);
+                }
+            }
+            encode_string(value, dst);
+        }
+    }
+}
@@ -0,0 +1,70 @@
+//! HTTP/2 (RFC 7540).
+//!
+//! The server speaks HTTP/2 to cleartext clients that start with the
+//! connection preface or ask for `Upgrade: h2c`, and to TLS clients that
+//! negotiate `h2` with ALPN. Requests go to the same handler as HTTP/1.1
+//! ones, with `Request::version` set to `HTTP/2.0`.
+//!
+//! `frame` and `hpack` are the wire format, public for clients and tests.
+mod conn;
+pub mod frame;
+pub mod hpack;
+
+pub(crate) use self::conn::serve;
+
+use crate::message::Request;
+use crate::server::Server;
+
+/// The answer accepting `Upgrade: h2c`, after which the server speaks
+/// HTTP/2.
+pub(crate) const SWITCHING_PROTOCOLS: &[u8] =
+    b"HTTP/1.1 101 Switching Protocols\r\nConnection: Upgrade\r\nUpgrade: h2c\r\n\r\n";
+
+/// The settings of a request asking to switch to HTTP/2 with
+/// `Upgrade: h2c`, when the server allows it.
+pub(crate) fn upgrade_settings(server: &Server, req: &Request) -> Option<Vec<(u16, u32)>> {
+    let has_token = |header: &str, token: &str| {
+        req.header(header)
+            .is_some_and(|v| v.split(',').any(|t| t.trim().eq_ignore_ascii_case(token)))
+    };
+    if !server.http2 || !has_token("Upgrade", "h2c") || !has_token("Connection", "Upgrade") {
+        return None;
+    }
+    let payload = base64url_decode(req.header("HTTP2-Settings")?.trim())?;
+    if !payload.len().is_multiple_of(6) {
+        return None;
+    }
+    Some(
+        payload
+            .chunks(6)
+            .map(|p| {
+                let id = u16::from_be_bytes([p[0], p[1]]);
+                (id, u32::from_be_bytes([p[2], p[3], p[4], p[5]]))
+            })
+            .collect(),
+    )
+}
+
+/// Decode unpadded base64url, as used by `HTTP2-Settings`.
+fn base64url_decode(s: &str) -> Option<Vec<u8>> {
+    let mut out = Vec::with_capacity(s.len() * 3 / 4);
+    let mut acc = 0u32;
+    let mut bits = 0;
+    for c in s.bytes().take_while(|&c| c != b'=') {
+        let v = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'-' => 62,
+            b'_' => 63,
+            _ => return None,
+        };
+        acc = (acc << 6) | u32::from(v);
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((acc >> bits) as u8);
+        }
+    }
+    Some(out)
+}
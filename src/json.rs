@@ -0,0 +1,152 @@
+//! JSON request and response helpers, enabled with the `json` feature.
+//!
+//! Every error written by this module has the same shape:
+//!
+//! ```text
+//! {"error":{"message":"...","status":400}}
+//! ```
+use crate::error::ServerError;
+use crate::header::{ContentType, HttpHeader};
+use crate::message::{body_error_status, Request, ResponseBody, ResponseWriter};
+use crate::server::Handler;
+use crate::status_code::StatusCode;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::fmt;
+use std::marker::PhantomData;
+
+/// Why a request body could not be read as JSON.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum JsonRejection {
+    /// The request's `Content-Type` is not JSON.
+    UnsupportedMediaType,
+    /// The body is not valid JSON for the expected type.
+    InvalidBody(String),
+    /// The body could not be read: 413 past the server's limit, 400 when
+    /// it is malformed or cut short.
+    UnreadableBody(StatusCode, String),
+}
+
+impl JsonRejection {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            JsonRejection::UnsupportedMediaType => StatusCode::UnsupportedMediaType,
+            JsonRejection::InvalidBody(_) => StatusCode::BadRequest,
+            JsonRejection::UnreadableBody(status, _) => *status,
+        }
+    }
+
+    /// Write this rejection as a JSON error response.
+    pub fn respond(&self, writer: &mut dyn ResponseWriter) {
+        write_error(writer, self.status(), &self.to_string());
+    }
+}
+
+impl fmt::Display for JsonRejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JsonRejection::UnsupportedMediaType => {
+                write!(f, "expected Content-Type: application/json")
+            }
+            JsonRejection::InvalidBody(e) => write!(f, "invalid JSON body: {}", e),
+            JsonRejection::UnreadableBody(_, e) => write!(f, "cannot read body: {}", e),
+        }
+    }
+}
+
+/// Whether `req` declares a JSON body (`application/json` or `*/*+json`).
+pub fn is_json(req: &Request) -> bool {
+    match req.header(HttpHeader::ContentType.as_str()) {
+        Some(v) => {
+            let media_type = v.split(';').next().unwrap_or("").trim();
+            uncased::eq(media_type, ContentType::ApplicationJson.as_str())
+                || media_type.to_ascii_lowercase().ends_with("+json")
+        }
+        None => false,
+    }
+}
+
+/// Deserialize the body of `req` into `T`.
+pub fn read_json<T: DeserializeOwned>(req: &Request) -> Result<T, JsonRejection> {
+    if !is_json(req) {
+        return Err(JsonRejection::UnsupportedMediaType);
+    }
+    let body = req
+        .try_body_bytes()
+        .map_err(|e| JsonRejection::UnreadableBody(body_error_status(&e), e.to_string()))?;
+    serde_json::from_slice(body).map_err(|e| JsonRejection::InvalidBody(e.to_string()))
+}
+
+/// Serialize `value` as the `application/json` body of the response.
+pub fn write_json<T: Serialize + ?Sized>(writer: &mut dyn ResponseWriter, value: &T) {
+    match serde_json::to_vec(value) {
+        Ok(body) => {
+            writer.set_header(
+                HttpHeader::ContentType.as_str(),
+                ContentType::ApplicationJson.as_str(),
+            );
+            writer.write(ResponseBody::BytesBody(body));
+        }
+        Err(e) => write_error(
+            writer,
+            StatusCode::InternalServerError,
+            &format!("failed to serialize response: {}", e),
+        ),
+    }
+}
+
+/// Respond with `status` and a JSON error body carrying `message`.
+pub fn write_error(writer: &mut dyn ResponseWriter, status: StatusCode, message: &str) {
+    let body = serde_json::json!({
+        "error": {
+            "status": status.as_num(),
+            "message": message,
+        }
+    });
+    writer.set_header(
+        HttpHeader::ContentType.as_str(),
+        ContentType::ApplicationJson.as_str(),
+    );
+    writer.write(ResponseBody::BytesBody(body.to_string().into_bytes()));
+    writer.status(status);
+}
+
+/// A `Handler` that decodes the request body into `T` before calling `f`,
+/// answering 415 or 400 itself when the body is not usable.
+pub struct JsonHandler<T, F> {
+    f: F,
+    _body: PhantomData<fn() -> T>,
+}
+
+impl<T, F> JsonHandler<T, F>
+where
+    T: DeserializeOwned,
+    F: Fn(&Request, T, &mut dyn ResponseWriter) -> Result<(), ServerError>,
+{
+    pub fn new(f: F) -> Self {
+        JsonHandler {
+            f,
+            _body: PhantomData,
+        }
+    }
+}
+
+impl<T, F> Handler for JsonHandler<T, F>
+where
+    T: DeserializeOwned,
+    F: Fn(&Request, T, &mut dyn ResponseWriter) -> Result<(), ServerError>,
+{
+    fn serve_http(
+        &self,
+        writer: &mut dyn ResponseWriter,
+        req: &Request,
+    ) -> Result<(), ServerError> {
+        match read_json(req) {
+            Ok(body) => (self.f)(req, body, writer),
+            Err(rejection) => {
+                rejection.respond(writer);
+                Ok(())
+            }
+        }
+    }
+}
@@ -1,7 +1,35 @@
+#[cfg(feature = "auth")]
+pub mod auth;
+pub mod cache;
+pub mod client;
+pub mod conditional;
+#[cfg(feature = "config")]
+pub mod config;
+pub mod cookie;
+pub mod cors;
+mod date;
 pub mod error;
+pub mod error_pages;
+pub mod extensions;
+pub mod extract;
+pub mod files;
+mod framing;
 pub mod header;
+pub mod http2;
+#[cfg(feature = "json")]
+pub mod json;
+pub mod listener;
+pub mod log;
 pub mod message;
 pub mod method;
+pub mod proxy;
+mod recover;
 pub mod server;
+pub mod session;
+pub mod stats;
 pub mod status_code;
+pub mod testing;
+#[cfg(feature = "tls")]
+pub mod tls;
+mod transport;
 pub mod worker;
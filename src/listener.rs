@@ -0,0 +1,211 @@
+//! Endpoints a server accepts connections on.
+//!
+//! A `Server` listens on any number of them at once, all feeding the same
+//! handler: TCP addresses over IPv4 or IPv6, listeners bound beforehand,
+//! and Unix domain sockets.
+use crate::transport::Transport;
+use socket2::{Domain, Socket, Type};
+use std::fmt;
+use std::io;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+#[cfg(unix)]
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// Where a server accepts connections.
+///
+/// Strings convert into a TCP address such as `127.0.0.1:8080` or
+/// `[::]:8080`, or with a `unix:` prefix into a Unix socket path.
+pub enum Listen {
+    /// An address to bind. An unspecified IPv6 address such as `[::]`
+    /// accepts IPv4 clients as well.
+    Tcp(String),
+    /// A listener bound by the caller, e.g. inherited from a supervisor.
+    Bound(TcpListener),
+    /// A Unix domain socket at `path`. A socket file left there by a
+    /// server that is no longer running is replaced, and the file is
+    /// removed once the listener closes. `mode` sets its permissions.
+    #[cfg(unix)]
+    Unix { path: PathBuf, mode: Option<u32> },
+}
+
+impl Listen {
+    /// A Unix domain socket at `path` with default permissions.
+    #[cfg(unix)]
+    pub fn unix<P: Into<PathBuf>>(path: P) -> Self {
+        Listen::Unix {
+            path: path.into(),
+            mode: None,
+        }
+    }
+}
+
+impl From<&str> for Listen {
+    fn from(addr: &str) -> Self {
+        #[cfg(unix)]
+        {
+            if let Some(path) = addr.strip_prefix("unix:") {
+                return Listen::unix(path);
+            }
+        }
+        Listen::Tcp(addr.to_string())
+    }
+}
+
+impl From<String> for Listen {
+    fn from(addr: String) -> Self {
+        Listen::from(addr.as_str())
+    }
+}
+
+impl From<SocketAddr> for Listen {
+    fn from(addr: SocketAddr) -> Self {
+        Listen::Tcp(addr.to_string())
+    }
+}
+
+impl From<TcpListener> for Listen {
+    fn from(listener: TcpListener) -> Self {
+        Listen::Bound(listener)
+    }
+}
+
+impl fmt::Display for Listen {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Listen::Tcp(addr) => f.write_str(addr),
+            Listen::Bound(listener) => match listener.local_addr() {
+                Ok(addr) => write!(f, "{}", addr),
+                Err(_) => f.write_str("bound listener"),
+            },
+            #[cfg(unix)]
+            Listen::Unix { path, .. } => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+impl fmt::Debug for Listen {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Listen({})", self)
+    }
+}
+
+/// A bound endpoint accepting connections.
+pub(crate) enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(UnixListener, PathBuf),
+}
+
+impl Listener {
+    pub(crate) fn bind(listen: Listen) -> io::Result<Listener> {
+        match listen {
+            Listen::Tcp(addr) => bind_tcp(&addr).map(Listener::Tcp),
+            Listen::Bound(listener) => Ok(Listener::Tcp(listener)),
+            #[cfg(unix)]
+            Listen::Unix { path, mode } => {
+                remove_stale_socket(&path)?;
+                let listener = UnixListener::bind(&path)?;
+                if let Some(mode) = mode {
+                    use std::os::unix::fs::PermissionsExt;
+                    std::fs::set_permissions(&path, std::fs::Permissions::from_mode(mode))?;
+                }
+                Ok(Listener::Unix(listener, path))
+            }
+        }
+    }
+
+    /// The address a TCP listener is bound to, with the actual port when
+    /// port 0 was asked for. `None` for a Unix socket.
+    pub(crate) fn local_addr(&self) -> Option<SocketAddr> {
+        match self {
+            Listener::Tcp(l) => l.local_addr().ok(),
+            #[cfg(unix)]
+            Listener::Unix(..) => None,
+        }
+    }
+
+    /// Return a thread blocked in `accept` by connecting to the listener.
+    pub(crate) fn wake(&self) {
+        match self {
+            Listener::Tcp(_) => {
+                if let Some(mut addr) = self.local_addr() {
+                    if addr.ip().is_unspecified() {
+                        addr.set_ip(match addr {
+                            SocketAddr::V4(_) => Ipv4Addr::LOCALHOST.into(),
+                            SocketAddr::V6(_) => Ipv6Addr::LOCALHOST.into(),
+                        });
+                    }
+                    let _ = TcpStream::connect_timeout(&addr, Duration::from_secs(1));
+                }
+            }
+            #[cfg(unix)]
+            Listener::Unix(_, path) => {
+                let _ = UnixStream::connect(path);
+            }
+        }
+    }
+
+    pub(crate) fn accept(&self) -> io::Result<Transport> {
+        match self {
+            Listener::Tcp(l) => l.accept().map(|(s, _)| Transport::Tcp(s)),
+            #[cfg(unix)]
+            Listener::Unix(l, _) => l.accept().map(|(s, _)| Transport::Unix(s)),
+        }
+    }
+}
+
+#[cfg(unix)]
+impl Drop for Listener {
+    fn drop(&mut self) {
+        if let Listener::Unix(_, path) = self {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+/// Bind the first address `addr` resolves to that is free.
+fn bind_tcp(addr: &str) -> io::Result<TcpListener> {
+    let mut last_err = None;
+    for addr in addr.to_socket_addrs()? {
+        match bind_addr(addr) {
+            Ok(listener) => return Ok(listener),
+            Err(e) => last_err = Some(e),
+        }
+    }
+    Err(last_err.unwrap_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidInput, "address resolved to nothing")
+    }))
+}
+
+fn bind_addr(addr: SocketAddr) -> io::Result<TcpListener> {
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, None)?;
+    if addr.is_ipv6() {
+        socket.set_only_v6(false)?;
+    }
+    #[cfg(unix)]
+    socket.set_reuse_address(true)?;
+    socket.bind(&addr.into())?;
+    socket.listen(1024)?;
+    Ok(socket.into())
+}
+
+/// Remove a socket file at `path` nobody accepts on any more, so a server
+/// that did not shut down cleanly can be restarted. A live socket or any
+/// other kind of file is left alone, and binding fails.
+#[cfg(unix)]
+fn remove_stale_socket(path: &std::path::Path) -> io::Result<()> {
+    use std::os::unix::fs::FileTypeExt;
+    match std::fs::symlink_metadata(path) {
+        Ok(meta) if meta.file_type().is_socket() => match UnixStream::connect(path) {
+            Ok(_) => Err(io::Error::new(
+                io::ErrorKind::AddrInUse,
+                format!("{} is in use", path.display()),
+            )),
+            Err(_) => std::fs::remove_file(path),
+        },
+        _ => Ok(()),
+    }
+}
@@ -0,0 +1,244 @@
+use crate::date::DateTime;
+use crate::method::Method;
+use crate::status_code::StatusCode;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Everything known about a finished request.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct LogEntry {
+    pub time: SystemTime,
+    pub remote_addr: Option<SocketAddr>,
+    pub method: Method,
+    pub target: String,
+    pub version: String,
+    pub status: StatusCode,
+    /// Size of the response body.
+    pub bytes: u64,
+    pub duration: Duration,
+    pub user_agent: Option<String>,
+    pub referer: Option<String>,
+}
+
+/// Receives one call per served request.
+pub trait RequestLogger {
+    fn log(&self, entry: &LogEntry);
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum LogFormat {
+    /// `host - - [time] "request" status bytes`
+    Common,
+    /// Common followed by `"referer" "user-agent"`.
+    Combined,
+    /// One JSON object per line.
+    Json,
+}
+
+impl LogFormat {
+    pub fn format(self, e: &LogEntry) -> String {
+        let host = e
+            .remote_addr
+            .map(|a| a.ip().to_string())
+            .unwrap_or_else(|| "-".to_string());
+        let time = DateTime::from_system_time(e.time);
+        match self {
+            LogFormat::Common | LogFormat::Combined => {
+                let mut line = format!(
+                    "{} - - [{}] \"{} {} {}\" {} {}",
+                    host,
+                    time.to_clf(),
+                    e.method,
+                    clf_escape(&e.target),
+                    clf_escape(&e.version),
+                    e.status.as_num(),
+                    if e.bytes == 0 {
+                        "-".to_string()
+                    } else {
+                        e.bytes.to_string()
+                    }
+                );
+                if self == LogFormat::Combined {
+                    line.push_str(&format!(
+                        " \"{}\" \"{}\"",
+                        clf_escape(e.referer.as_deref().unwrap_or("-")),
+                        clf_escape(e.user_agent.as_deref().unwrap_or("-"))
+                    ));
+                }
+                line
+            }
+            LogFormat::Json => {
+                let opt = |v: &Option<String>| match v {
+                    Some(x) => json_string(x),
+                    None => "null".to_string(),
+                };
+                format!(
+                    "{{\"time\":{},\"remote_addr\":{},\"method\":{},\"target\":{},\"version\":{},\"status\":{},\"bytes\":{},\"duration_ms\":{:.3},\"user_agent\":{},\"referer\":{}}}",
+                    json_string(&time.to_rfc3339()),
+                    json_string(&host),
+                    json_string(e.method.as_str()),
+                    json_string(&e.target),
+                    json_string(&e.version),
+                    e.status.as_num(),
+                    e.bytes,
+                    e.duration.as_secs_f64() * 1000.0,
+                    opt(&e.user_agent),
+                    opt(&e.referer)
+                )
+            }
+        }
+    }
+}
+
+/// Escape a client-supplied field the way Apache does, so it cannot end
+/// its quotes or the line: `\"`, `\\` and `\xHH` for control bytes.
+fn clf_escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            c if c.is_ascii_control() => out.push_str(&format!("\\x{:02x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// A `RequestLogger` writing one formatted line per request.
+pub struct AccessLog {
+    format: LogFormat,
+    out: Mutex<Box<dyn Write + Send>>,
+}
+
+impl AccessLog {
+    pub fn new<W: Write + Send + 'static>(format: LogFormat, out: W) -> Self {
+        AccessLog {
+            format,
+            out: Mutex::new(Box::new(out)),
+        }
+    }
+
+    pub fn stdout(format: LogFormat) -> Self {
+        AccessLog::new(format, io::stdout())
+    }
+}
+
+impl RequestLogger for AccessLog {
+    fn log(&self, entry: &LogEntry) {
+        let mut line = self.format.format(entry);
+        line.push('\n');
+        if let Ok(mut out) = self.out.lock() {
+            let _ = out.write_all(line.as_bytes());
+            let _ = out.flush();
+        }
+    }
+}
+
+/// A log file that is moved aside to `<path>.<unix time>` once it grows past
+/// `max_size` bytes or has been open longer than `max_age`.
+pub struct RotatingFile {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    opened: SystemTime,
+    max_size: Option<u64>,
+    max_age: Option<Duration>,
+}
+
+impl RotatingFile {
+    pub fn open<P: Into<PathBuf>>(path: P) -> io::Result<Self> {
+        let path = path.into();
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let size = file.metadata()?.len();
+        Ok(RotatingFile {
+            path,
+            file,
+            size,
+            opened: SystemTime::now(),
+            max_size: None,
+            max_age: None,
+        })
+    }
+
+    pub fn max_size(mut self, bytes: u64) -> Self {
+        self.max_size = Some(bytes);
+        self
+    }
+
+    pub fn max_age(mut self, age: Duration) -> Self {
+        self.max_age = Some(age);
+        self
+    }
+
+    fn should_rotate(&self, incoming: usize) -> bool {
+        let too_big = self
+            .max_size
+            .is_some_and(|m| self.size > 0 && self.size + incoming as u64 > m);
+        let too_old = self
+            .max_age
+            .is_some_and(|m| self.opened.elapsed().is_ok_and(|age| age >= m));
+        too_big || too_old
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        let stamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        // `<path>.<secs>`, then `.1`, `.2`... for more rotations that second
+        let base = format!("{}.{}", self.path.display(), stamp);
+        let mut rotated = PathBuf::from(&base);
+        let mut seq = 0;
+        while rotated.exists() {
+            seq += 1;
+            rotated = PathBuf::from(format!("{}.{}", base, seq));
+        }
+        fs::rename(&self.path, rotated)?;
+        self.file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        self.size = 0;
+        self.opened = SystemTime::now();
+        Ok(())
+    }
+}
+
+impl Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.should_rotate(buf.len()) {
+            self.rotate()?;
+        }
+        let n = self.file.write(buf)?;
+        self.size += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
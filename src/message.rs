@@ -1,49 +1,253 @@
+use crate::conditional::{http_date, CacheControl, ETag};
+use crate::cookie::{self, Cookie};
 use crate::error::ServerError;
-use crate::header::{ContentType, HttpHeader};
+use crate::extensions::Extensions;
+use crate::framing::{ChunkedWriter, Framing};
+use crate::header::{ContentType, HeaderMap, HttpHeader};
+use crate::http2;
+use crate::log::LogEntry;
 use crate::method::Method;
 use crate::server::{ServeHandler, Server};
+use crate::stats::Stats;
 use crate::status_code::StatusCode;
+use crate::transport::Transport;
 use std::collections::HashMap;
+use std::fmt;
+use std::io;
 use std::io::prelude::*;
 use std::io::Read;
 use std::io::{BufRead, BufReader};
-use std::net::TcpStream;
+use std::net::SocketAddr;
 use std::str::FromStr;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant, SystemTime};
 use uncased;
 
 type Path = String;
 type Version = String;
 pub type Header = HashMap<String, String>;
+/// Values captured from the route pattern as `(name, value)` in pattern order.
+pub type Params = Vec<(String, String)>;
 
 const HTTP_11: &str = "HTTP/1.1";
+const CONTENT_LENGTH: &str = "Content-Length";
+const TRANSFER_ENCODING: &str = "Transfer-Encoding";
+const CONTINUE_EXPECTATION: &str = "100-continue";
+const CONTINUE: &[u8] = b"HTTP/1.1 100 Continue\r\n\r\n";
+/// How much of a body the handler left unread is read before closing.
+const DRAIN_LIMIT: u64 = 256 * 1024;
+
+/// Called with the final response right before it is written.
+pub type SendHook = Box<dyn FnOnce(&mut Response) + Send>;
+
+/// A body read while the response is sent, with its length when known.
+pub type BodyStream = (Box<dyn Read + Send>, Option<u64>);
 
 pub struct Message {
     pub req: Request,
     pub res: Response,
     conn: Conn,
+    sent: bool,
+    hooks: Vec<SendHook>,
+    stream: Option<BodyStream>,
 }
 
+/// Builds the response of a request.
+///
+/// The response is buffered until `send` is called; if a handler returns
+/// without calling it the server sends whatever was built. `send` only
+/// writes once, later calls and later changes to the response are ignored.
 pub trait ResponseWriter {
+    /// Set the response body.
     fn write(&mut self, data: ResponseBody);
+    /// Merge `headers` into the response headers, replacing same-named ones.
     fn header(&mut self, headers: Header);
     fn write_header(&mut self, code: usize);
     fn send(&mut self);
+
+    /// Replace every value of the header `name` with `value`.
+    fn set_header(&mut self, name: &str, value: &str);
+    /// Add a value for the header `name`, keeping the existing ones.
+    fn append_header(&mut self, name: &str, value: &str);
+    fn status(&mut self, code: StatusCode);
+    /// Whether the response has already been written to the client.
+    fn is_sent(&self) -> bool;
+    fn response(&self) -> &Response;
+    /// Run `hook` on the response just before it is sent. Hooks run in
+    /// reverse order of registration, so middleware wrapping a handler sees
+    /// the changes made by the hooks of the handlers it wraps.
+    fn on_send(&mut self, hook: SendHook);
+    /// Drop the status, headers and body set so far, keeping the send
+    /// hooks. Does nothing once the response is sent.
+    fn clear(&mut self);
+
+    /// Set a body that is copied from `body` as the response is sent rather
+    /// than held in memory. With a `length` it is sent with that
+    /// `Content-Length`, otherwise chunked. A body set later with `write`,
+    /// e.g. by a send hook, takes precedence.
+    fn write_stream(&mut self, mut body: Box<dyn Read + Send>, _length: Option<u64>) {
+        let mut buf = Vec::new();
+        if body.read_to_end(&mut buf).is_ok() {
+            self.write(ResponseBody::BytesBody(buf));
+        }
+    }
+
+    /// Respond with a `text/plain` body.
+    fn text(&mut self, body: &str) {
+        self.set_header(
+            HttpHeader::ContentType.as_str(),
+            &format!("{}; charset=utf-8", ContentType::TextPlain.as_str()),
+        );
+        self.write(ResponseBody::BytesBody(body.as_bytes().to_vec()));
+    }
+
+    /// Respond with a `text/html` body.
+    fn html(&mut self, body: &str) {
+        self.set_header(
+            HttpHeader::ContentType.as_str(),
+            &format!("{}; charset=utf-8", ContentType::TextHtml.as_str()),
+        );
+        self.write(ResponseBody::BytesBody(body.as_bytes().to_vec()));
+    }
+
+    /// Respond with an already encoded `application/json` body.
+    fn json(&mut self, body: &str) {
+        self.set_header(
+            HttpHeader::ContentType.as_str(),
+            ContentType::ApplicationJson.as_str(),
+        );
+        self.write(ResponseBody::BytesBody(body.as_bytes().to_vec()));
+    }
+
+    /// Redirect to `location` with a 3xx `code`.
+    fn redirect(&mut self, code: StatusCode, location: &str) {
+        self.status(code);
+        self.set_header(HttpHeader::Location.as_str(), location);
+    }
+
+    fn no_content(&mut self) {
+        self.status(StatusCode::NoContent);
+    }
+
+    /// Add a `Set-Cookie` header for `cookie`.
+    fn set_cookie(&mut self, cookie: &Cookie) {
+        self.append_header(cookie::SET_COOKIE, &cookie.to_string());
+    }
+
+    fn set_etag(&mut self, etag: &ETag) {
+        self.set_header(HttpHeader::ETag.as_str(), &etag.to_string());
+    }
+
+    fn set_last_modified(&mut self, t: SystemTime) {
+        self.set_header(HttpHeader::LastModified.as_str(), &http_date(t));
+    }
+
+    fn set_cache_control(&mut self, cache_control: &CacheControl) {
+        self.set_header(
+            HttpHeader::CacheControl.as_str(),
+            &cache_control.to_string(),
+        );
+    }
 }
 
 impl ResponseWriter for Message {
     fn write(&mut self, data: ResponseBody) {
-        self.res.body = Some(data);
+        if !self.sent {
+            self.res.body = Some(data);
+        }
     }
     fn write_header(&mut self, code: usize) {
-        self.res.status_code = StatusCode::from_num(code).unwrap_or_else(|_| StatusCode::Ok);
+        self.status(StatusCode::from_num(code).unwrap_or(StatusCode::Ok));
     }
     fn header(&mut self, headers: Header) {
-        self.res.headers = headers;
+        for (name, value) in headers {
+            self.set_header(&name, &value);
+        }
     }
     fn send(&mut self) {
-        self.conn.stream.write(&self.res.format()).unwrap();
-        self.conn.stream.flush();
+        if self.sent {
+            return;
+        }
+        self.sent = true;
+        if let Some((_, length)) = &self.stream {
+            match length {
+                Some(n) => self.res.headers.set(CONTENT_LENGTH, &n.to_string()),
+                None => self.res.headers.set(TRANSFER_ENCODING, "chunked"),
+            }
+        }
+        while let Some(hook) = self.hooks.pop() {
+            hook(&mut self.res);
+        }
+        let status = self.res.status_code.as_num();
+        let bodiless = status < 200 || status == 204 || status == 304;
+        let mut stream = self.stream.take();
+        if stream.is_some() && (self.res.body.is_some() || bodiless) {
+            stream = None;
+            self.res.headers.remove(CONTENT_LENGTH);
+            self.res.headers.remove(TRANSFER_ENCODING);
+        }
+
+        let with_body = self.req.method != Method::Head;
+        let buf = self.res.format(with_body);
+        let mut written = match self.conn.stream.write_all(&buf) {
+            Ok(()) => buf.len() as u64,
+            Err(_) => return,
+        };
+        if let (Some((mut body, length)), true) = (stream, with_body) {
+            let copied = if length.is_some() {
+                io::copy(&mut body, &mut self.conn.stream)
+            } else {
+                io::copy(&mut body, &mut ChunkedWriter(&mut self.conn.stream))
+                    .and_then(|n| self.conn.stream.write_all(b"0\r\n\r\n").map(|_| n))
+            };
+            if let Ok(n) = copied {
+                self.res.content_length = n;
+                written += n;
+            }
+        }
+        self.conn.server.stats.add_bytes_out(written);
+        let _ = self.conn.stream.flush();
+    }
+    fn set_header(&mut self, name: &str, value: &str) {
+        if !self.sent {
+            self.res.headers.set(name, value);
+        }
+    }
+    fn append_header(&mut self, name: &str, value: &str) {
+        if !self.sent {
+            self.res.headers.append(name, value);
+        }
+    }
+    fn status(&mut self, code: StatusCode) {
+        if !self.sent {
+            self.res.status_code = code;
+        }
+    }
+    fn is_sent(&self) -> bool {
+        self.sent
+    }
+    fn response(&self) -> &Response {
+        &self.res
+    }
+    fn on_send(&mut self, hook: SendHook) {
+        if !self.sent {
+            self.hooks.push(hook);
+        }
+    }
+    fn clear(&mut self) {
+        if !self.sent {
+            self.res = Response {
+                version: self.res.version.clone(),
+                ..Response::new()
+            };
+            self.stream = None;
+        }
+    }
+    fn write_stream(&mut self, body: Box<dyn Read + Send>, length: Option<u64>) {
+        if !self.sent {
+            self.res.body = None;
+            self.stream = Some((body, length));
+        }
     }
 }
 
@@ -53,29 +257,470 @@ impl Message {
             req: Request::new(),
             res: Response::new(),
             conn,
+            sent: false,
+            hooks: Vec::new(),
+            stream: None,
+        }
+    }
+}
+
+/// A `ResponseWriter` building the response in memory, for running
+/// handlers outside of a connection. A streamed body is read into memory.
+pub struct BufferedWriter {
+    res: Response,
+    sent: bool,
+    hooks: Vec<SendHook>,
+}
+
+impl Default for BufferedWriter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl BufferedWriter {
+    pub fn new() -> Self {
+        BufferedWriter {
+            res: Response::new(),
+            sent: false,
+            hooks: Vec::new(),
+        }
+    }
+
+    /// Send the response if the handler did not and return it.
+    pub fn finish(mut self) -> Response {
+        self.send();
+        self.res
+    }
+}
+
+impl ResponseWriter for BufferedWriter {
+    fn write(&mut self, data: ResponseBody) {
+        if !self.sent {
+            self.res.body = Some(data);
+        }
+    }
+    fn write_header(&mut self, code: usize) {
+        self.status(StatusCode::from_num(code).unwrap_or(StatusCode::Ok));
+    }
+    fn header(&mut self, headers: Header) {
+        for (name, value) in headers {
+            self.set_header(&name, &value);
+        }
+    }
+    fn send(&mut self) {
+        if self.sent {
+            return;
+        }
+        self.sent = true;
+        while let Some(hook) = self.hooks.pop() {
+            hook(&mut self.res);
+        }
+    }
+    fn set_header(&mut self, name: &str, value: &str) {
+        if !self.sent {
+            self.res.headers.set(name, value);
+        }
+    }
+    fn append_header(&mut self, name: &str, value: &str) {
+        if !self.sent {
+            self.res.headers.append(name, value);
+        }
+    }
+    fn status(&mut self, code: StatusCode) {
+        if !self.sent {
+            self.res.status_code = code;
+        }
+    }
+    fn is_sent(&self) -> bool {
+        self.sent
+    }
+    fn response(&self) -> &Response {
+        &self.res
+    }
+    fn on_send(&mut self, hook: SendHook) {
+        if !self.sent {
+            self.hooks.push(hook);
+        }
+    }
+    fn clear(&mut self) {
+        if !self.sent {
+            self.res = Response {
+                version: self.res.version.clone(),
+                ..Response::new()
+            };
         }
     }
 }
 
 pub(crate) struct Conn {
     server: Arc<Server>,
-    stream: TcpStream,
+    stream: Transport,
 }
 
 impl Conn {
-    pub fn new(server: Arc<Server>, stream: TcpStream) -> Conn {
-        Conn {
-            server: server,
-            stream: stream,
+    pub fn new(server: Arc<Server>, stream: Transport) -> Conn {
+        Conn { server, stream }
+    }
+
+    pub fn serve(mut self) -> Result<(), ServerError> {
+        let server = self.server.clone();
+        server.stats.connection_opened();
+        let result = self.handshake().and_then(|()| {
+            if server.read_timeout.is_some() {
+                self.stream
+                    .set_read_timeout(server.read_timeout)
+                    .map_err(|_| ServerError::ReadLineError)?;
+            }
+            if server.http2 && self.stream.is_http2() {
+                http2::serve(server.clone(), self.stream, None)
+            } else {
+                Conn::serve_request(server.clone(), self)
+            }
+        });
+        server.stats.connection_closed();
+        result
+    }
+
+    /// Run the TLS handshake when the server has a certificate.
+    #[cfg(feature = "tls")]
+    fn handshake(&mut self) -> Result<(), ServerError> {
+        if let (Some(tls), Transport::Tcp(tcp)) = (&self.server.tls, &self.stream) {
+            let tcp = tcp.try_clone().map_err(|_| ServerError::ReadLineError)?;
+            let tls = tls.accept(tcp).map_err(|_| ServerError::ReadLineError)?;
+            self.stream = Transport::Tls(tls);
         }
+        Ok(())
     }
 
-    pub fn serve(self) -> Result<(), ServerError> {
-        let serve_handler = ServeHandler::new(self.server.clone());
-        let msg = &mut Message::new(self);
-        msg.req.parse(&mut msg.conn.stream)?;
+    #[cfg(not(feature = "tls"))]
+    fn handshake(&mut self) -> Result<(), ServerError> {
+        Ok(())
+    }
+
+    fn serve_request(server: Arc<Server>, conn: Conn) -> Result<(), ServerError> {
+        let serve_handler = ServeHandler::new(server.clone());
+        let mut msg = Message::new(conn);
+        msg.req.remote_addr = msg.conn.stream.peer_addr().ok();
+        msg.req.secure = msg.conn.stream.is_secure();
+        msg.req.app_state = server.state.clone();
+        let stream = msg
+            .conn
+            .stream
+            .try_clone()
+            .map_err(|_| ServerError::ReadLineError)?;
+        let mut reader = BufReader::new(CountingReader::new(stream));
+        let parsed = msg.req.parse_head(&mut reader);
+        server.stats.add_bytes_in(reader.get_ref().count);
+        parsed?;
+
+        if let Some(status) = early_rejection(&server, &msg.req) {
+            let time = SystemTime::now();
+            msg.status(status);
+            msg.send();
+            record_request(&server, &msg.req, &msg.res, time, Duration::ZERO);
+            return Ok(());
+        }
+
+        // `Upgrade: h2c` is for cleartext connections only
+        let upgrade = match msg.conn.stream {
+            Transport::Tcp(_) => http2::upgrade_settings(&server, &msg.req),
+            #[cfg(unix)]
+            Transport::Unix(_) => http2::upgrade_settings(&server, &msg.req),
+            #[cfg(feature = "tls")]
+            Transport::Tls(_) => None,
+        };
+        let framing = msg.req.body_framing();
+        let interim = if msg.req.expects_continue() && !framing.finished() {
+            let interim = msg
+                .conn
+                .stream
+                .try_clone()
+                .map_err(|_| ServerError::ReadLineError)?;
+            Some(interim)
+        } else {
+            None
+        };
+        msg.req.set_body_reader(Box::new(Http1Body {
+            reader,
+            framing,
+            interim,
+            limit: server.max_body_size,
+            read: 0,
+            failed: false,
+            stats: server.stats.clone(),
+        }));
+        if upgrade.is_some() || server.buffer_bodies {
+            if let Err(e) = msg.req.buffer_body() {
+                let time = SystemTime::now();
+                msg.status(body_error_status(&e));
+                msg.send();
+                record_request(&server, &msg.req, &msg.res, time, Duration::ZERO);
+                return Ok(());
+            }
+        }
+
+        if let Some(settings) = upgrade {
+            let Message { mut conn, req, .. } = msg;
+            conn.stream
+                .write_all(http2::SWITCHING_PROTOCOLS)
+                .map_err(|_| ServerError::ReadLineError)?;
+            return http2::serve(server, conn.stream, Some((settings, req)));
+        }
+
+        let time = SystemTime::now();
+        let start = Instant::now();
         let req = msg.req.clone();
-        serve_handler.serve_http(msg, &req)
+        serve_handler.serve_http(&mut msg, &req)?;
+        msg.send();
+        record_request(&server, &req, &msg.res, time, start.elapsed());
+        Ok(())
+    }
+}
+
+/// Reads the body of an HTTP/1 request from the connection, within its
+/// framing.
+struct Http1Body {
+    reader: BufReader<CountingReader<Transport>>,
+    framing: Framing,
+    /// Where `100 Continue` goes before the first read, when the client
+    /// waits for it.
+    interim: Option<Transport>,
+    limit: Option<u64>,
+    read: u64,
+    /// Reading failed, and what follows on the connection is not body.
+    failed: bool,
+    stats: Arc<Stats>,
+}
+
+impl Read for Http1Body {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if let Some(mut interim) = self.interim.take() {
+            interim.write_all(CONTINUE)?;
+        }
+        let before = self.reader.get_ref().count;
+        let n = self.framing.read(&mut self.reader, buf);
+        self.stats
+            .add_bytes_in(self.reader.get_ref().count - before);
+        self.failed = n.is_err();
+        let n = n?;
+        self.read += n as u64;
+        if self.limit.is_some_and(|max| self.read > max) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, BODY_TOO_LARGE));
+        }
+        Ok(n)
+    }
+}
+
+impl Drop for Http1Body {
+    /// Read what the handler left of the body, as a client still sending
+    /// it when the connection closes could lose the response to a reset.
+    /// One waiting for `100 Continue` sends nothing more, and after a
+    /// malformed body there is nothing to tell where it ends.
+    fn drop(&mut self) {
+        if self.interim.is_some() || self.failed {
+            return;
+        }
+        let before = self.reader.get_ref().count;
+        let mut buf = [0; 8192];
+        let mut left = DRAIN_LIMIT;
+        while left > 0 && !self.framing.finished() {
+            match self.framing.read(&mut self.reader, &mut buf) {
+                Ok(0) | Err(_) => break,
+                Ok(n) => left = left.saturating_sub(n as u64),
+            }
+        }
+        self.stats
+            .add_bytes_in(self.reader.get_ref().count - before);
+    }
+}
+
+/// How reading a body fails once it grows past the server's limit.
+pub(crate) const BODY_TOO_LARGE: &str = "request body too large";
+
+/// The status answering a request whose body could not be read.
+pub(crate) fn body_error_status(e: &io::Error) -> StatusCode {
+    if e.kind() == io::ErrorKind::InvalidData && e.to_string() == BODY_TOO_LARGE {
+        StatusCode::PayloadTooLarge
+    } else {
+        StatusCode::BadRequest
+    }
+}
+
+/// The status refusing `req` from its headers alone: an expectation other
+/// than `100-continue`, or a body over the server's limit.
+pub(crate) fn early_rejection(server: &Server, req: &Request) -> Option<StatusCode> {
+    if req.header(HttpHeader::Expect.as_str()).is_some() && !req.expects_continue() {
+        return Some(StatusCode::ExpectationFailed);
+    }
+    match server.max_body_size {
+        Some(max) if req.content_length > max => Some(StatusCode::PayloadTooLarge),
+        _ => None,
+    }
+}
+
+/// Count a served request in the stats and log it.
+pub(crate) fn record_request(
+    server: &Server,
+    req: &Request,
+    res: &Response,
+    time: SystemTime,
+    elapsed: Duration,
+) {
+    server.stats.record_request(res.status_code, elapsed);
+    if let Some(logger) = &server.logger {
+        logger.log(&LogEntry {
+            time,
+            remote_addr: req.remote_addr,
+            method: req.method,
+            target: req.path.clone(),
+            version: req.version.clone(),
+            status: res.status_code,
+            bytes: res.body_len() as u64,
+            duration: elapsed,
+            user_agent: req.header(HttpHeader::UserAgent.as_str()).map(String::from),
+            referer: req.header(HttpHeader::Referer.as_str()).map(String::from),
+        });
+    }
+}
+
+/// Counts the bytes read through it so the server can report traffic.
+pub(crate) struct CountingReader<R> {
+    inner: R,
+    pub(crate) count: u64,
+}
+
+impl<R: Read> CountingReader<R> {
+    pub(crate) fn new(inner: R) -> Self {
+        CountingReader { inner, count: 0 }
+    }
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.count += n as u64;
+        Ok(n)
+    }
+}
+
+/// Decode `%XX` escapes; invalid escapes are kept as they are.
+pub fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' && i + 2 < bytes.len() {
+            let hex = |b: u8| (b as char).to_digit(16);
+            if let (Some(h), Some(l)) = (hex(bytes[i + 1]), hex(bytes[i + 2])) {
+                out.push((h * 16 + l) as u8);
+                i += 3;
+                continue;
+            }
+        }
+        out.push(bytes[i]);
+        i += 1;
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+/// Escape every byte except ASCII letters, digits and `-_.~` as `%XX`.
+pub fn percent_encode(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for b in s.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                out.push(b as char)
+            }
+            _ => out.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    out
+}
+
+/// Split an `application/x-www-form-urlencoded` string into decoded pairs.
+pub fn parse_urlencoded(s: &str) -> Vec<(String, String)> {
+    s.split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (k, v) = pair.split_once('=').unwrap_or((pair, ""));
+            (
+                percent_decode(&k.replace('+', " ")),
+                percent_decode(&v.replace('+', " ")),
+            )
+        })
+        .collect()
+}
+
+/// A request body streamed from the connection. The first reader takes
+/// the stream, or `get` reads all of it into memory. Clones of the request
+/// share it.
+#[derive(Clone)]
+struct DeferredBody(Arc<Deferred>);
+
+type BodyRead = Box<dyn Read + Send>;
+
+struct Deferred {
+    read: Mutex<Option<BodyRead>>,
+    body: OnceLock<Buffered>,
+}
+
+/// A streamed body read into memory, and why it is cut short if it is.
+struct Buffered {
+    bytes: Vec<u8>,
+    error: Option<io::Error>,
+}
+
+impl DeferredBody {
+    fn new(read: BodyRead) -> Self {
+        DeferredBody(Arc::new(Deferred {
+            read: Mutex::new(Some(read)),
+            body: OnceLock::new(),
+        }))
+    }
+
+    fn take(&self) -> Option<BodyRead> {
+        self.0.read.lock().unwrap().take()
+    }
+
+    fn get(&self) -> &Buffered {
+        self.0.body.get_or_init(|| {
+            let mut bytes = Vec::new();
+            let error = match self.take() {
+                Some(mut read) => read.read_to_end(&mut bytes).err(),
+                None => None,
+            };
+            Buffered { bytes, error }
+        })
+    }
+}
+
+impl PartialEq for DeferredBody {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for DeferredBody {}
+
+impl fmt::Debug for DeferredBody {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("DeferredBody")
+    }
+}
+
+/// A request body read as it is consumed, from `Request::body_reader`.
+pub struct BodyReader(BodyRead);
+
+impl Read for BodyReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+
+impl fmt::Debug for BodyReader {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("BodyReader")
     }
 }
 
@@ -102,6 +747,21 @@ pub struct Request {
     state: RequestState,
     pub content_length: u64,
     pub content_type: ContentType,
+    pub remote_addr: Option<SocketAddr>,
+    /// Whether the request arrived over TLS.
+    pub secure: bool,
+    pub params: Params,
+    /// Values attached to this request only, e.g. by middleware.
+    pub extensions: Extensions,
+    pub(crate) app_state: Arc<Extensions>,
+    /// The body still on the connection, read on first use.
+    deferred: Option<DeferredBody>,
+}
+
+impl Default for Request {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Request {
@@ -115,39 +775,237 @@ impl Request {
             state: RequestState::FirstLine,
             content_length: 0,
             content_type: ContentType::TextPlain,
+            remote_addr: None,
+            secure: false,
+            params: Vec::new(),
+            extensions: Extensions::new(),
+            app_state: Arc::new(Extensions::new()),
+            deferred: None,
         }
     }
 
-    pub fn parse(&mut self, msg: &mut TcpStream) -> Result<(), ServerError> {
-        println!("parse start");
-        let mut buf = String::new();
+    /// Application state of type `T` registered on the `Server` or on the
+    /// `DefaultServeMux` that routed this request.
+    pub fn state<T: Send + Sync + 'static>(&self) -> Option<&T> {
+        self.app_state.get()
+    }
+
+    /// The value of the cookie `name` sent with this request.
+    pub fn cookie(&self, name: &str) -> Option<String> {
+        self.header(cookie::COOKIE).and_then(|v| {
+            cookie::parse_cookies(v)
+                .into_iter()
+                .find(|(k, _)| k == name)
+                .map(|(_, v)| v)
+        })
+    }
+
+    /// A value of type `T` attached to this request.
+    pub fn extension<T: Send + Sync + 'static>(&self) -> Option<&T> {
+        self.extensions.get()
+    }
+
+    /// `https` for a request that arrived over TLS, `http` otherwise.
+    pub fn scheme(&self) -> &'static str {
+        if self.secure {
+            "https"
+        } else {
+            "http"
+        }
+    }
+
+    /// The request target without its query string.
+    pub fn target_path(&self) -> &str {
+        self.path.split('?').next().unwrap_or("")
+    }
+
+    /// The raw query string, without the leading `?`.
+    pub fn query(&self) -> Option<&str> {
+        self.path.split_once('?').map(|(_, q)| q)
+    }
+
+    /// A route parameter captured by `DefaultServeMux`.
+    pub fn param(&self, name: &str) -> Option<&str> {
+        self.params
+            .iter()
+            .find(|(k, _)| k == name)
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Look up a header value, ignoring the case of `name`.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(k, _)| uncased::eq(k, name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// The body as raw bytes, empty when there is none. Unless the server
+    /// buffers bodies it is read into memory on the first call; it is
+    /// empty or cut short if the connection fails or it is over the
+    /// server's limit, which `try_body_bytes` reports.
+    ///
+    /// A client sending `Expect: 100-continue` waits for the server before
+    /// uploading the body; the server lets it go ahead the first time the
+    /// body is read. Handlers refusing a request from its headers alone
+    /// respond without reading it, and the body is never sent.
+    pub fn body_bytes(&self) -> &[u8] {
+        match (&self.body, &self.deferred) {
+            (Some(RequestBody::StringBody(s)), _) => s.as_bytes(),
+            (Some(RequestBody::BytesBody(b)), _) => b,
+            (None, Some(deferred)) => &deferred.get().bytes,
+            (None, None) => &[],
+        }
+    }
+
+    /// The body as raw bytes like `body_bytes`, or the error that cut it
+    /// short.
+    pub fn try_body_bytes(&self) -> io::Result<&[u8]> {
+        if let (None, Some(deferred)) = (&self.body, &self.deferred) {
+            let buffered = deferred.get();
+            if let Some(e) = &buffered.error {
+                return Err(io::Error::new(e.kind(), e.to_string()));
+            }
+        }
+        Ok(self.body_bytes())
+    }
+
+    /// The body as a stream read from the connection as it is consumed,
+    /// for bodies too large to hold in memory. It is bounded by
+    /// `Content-Length` or the chunked coding, and fails to read past the
+    /// server's body limit. The stream can only be taken once: after that
+    /// `body_bytes` is empty, while after `body_bytes` this reads the
+    /// buffered copy.
+    pub fn body_reader(&self) -> BodyReader {
+        let read: BodyRead = match (&self.body, &self.deferred) {
+            (Some(_), _) => Box::new(io::Cursor::new(self.body_bytes().to_vec())),
+            (None, Some(deferred)) => match deferred.0.body.get() {
+                Some(body) => Box::new(io::Cursor::new(body.bytes.clone())),
+                None => deferred.take().unwrap_or_else(|| Box::new(io::empty())),
+            },
+            (None, None) => Box::new(io::empty()),
+        };
+        BodyReader(read)
+    }
+
+    /// Stream the body from `read` when the handler asks for it.
+    pub(crate) fn set_body_reader(&mut self, read: BodyRead) {
+        self.deferred = Some(DeferredBody::new(read));
+    }
+
+    /// Read a streamed body into `body`.
+    pub(crate) fn buffer_body(&mut self) -> io::Result<()> {
+        if self.body.is_none() {
+            let body = self.try_body_bytes()?.to_vec();
+            self.set_body(body);
+        }
+        Ok(())
+    }
+
+    /// The length of the body if known before reading it; `None` for a
+    /// chunked body, or an HTTP/2 one without `Content-Length`.
+    pub(crate) fn body_length(&self) -> Option<u64> {
+        if self.body.is_some() || self.deferred.is_none() {
+            return Some(self.body_bytes().len() as u64);
+        }
+        match self.body_framing() {
+            Framing::Length(n)
+                if self.version != "HTTP/2.0"
+                    || self.header(HttpHeader::ContentLength.as_str()).is_some() =>
+            {
+                Some(n)
+            }
+            _ => None,
+        }
+    }
+
+    /// Whether the body is still to be read from a stream.
+    pub(crate) fn is_streamed(&self) -> bool {
+        self.body.is_none() && self.deferred.is_some()
+    }
+
+    /// How the body follows the headers on an HTTP/1 connection.
+    pub(crate) fn body_framing(&self) -> Framing {
+        let chunked = self
+            .header(TRANSFER_ENCODING)
+            .is_some_and(|te| te.to_ascii_lowercase().contains("chunked"));
+        if chunked {
+            Framing::chunked()
+        } else {
+            Framing::Length(self.content_length)
+        }
+    }
+
+    /// Whether the client waits for `100 Continue` before sending the body.
+    pub fn expects_continue(&self) -> bool {
+        self.version != "HTTP/1.0"
+            && self
+                .header(HttpHeader::Expect.as_str())
+                .is_some_and(|v| v.eq_ignore_ascii_case(CONTINUE_EXPECTATION))
+    }
+
+    /// Add a header field, noting the length and type of the body.
+    pub(crate) fn insert_header(&mut self, name: &str, value: &str) {
+        match name {
+            x if uncased::eq(x, HttpHeader::ContentLength.as_str()) => {
+                self.content_length = value.parse().unwrap_or(0);
+            }
+            x if uncased::eq(x, HttpHeader::ContentType.as_str()) => {
+                let media_type = value.split(';').next().unwrap_or("").trim();
+                if let Ok(x) = ContentType::from_str(media_type) {
+                    self.content_type = x;
+                }
+            }
+            _ => {}
+        }
+        self.headers.insert(name.to_string(), value.to_string());
+    }
+
+    /// Set the body read from the client, as text unless the content type
+    /// says it is an image.
+    pub(crate) fn set_body(&mut self, body: Vec<u8>) {
+        self.body = Some(match self.content_type {
+            ContentType::ApplicationJson | ContentType::TextHtml | ContentType::TextPlain => {
+                RequestBody::StringBody(String::from_utf8_lossy(&body).to_string())
+            }
+            ContentType::ImageJpeg | ContentType::ImagePng => RequestBody::BytesBody(body),
+        });
+    }
+
+    pub fn parse<R: Read>(&mut self, msg: R) -> Result<(), ServerError> {
         let mut reader = BufReader::new(msg);
-        while reader.read_line(&mut buf).unwrap() > 0 {
+        self.parse_head(&mut reader)?;
+        read_body(self, reader)
+    }
+
+    /// Read the request line and the headers, leaving the body.
+    pub(crate) fn parse_head<R: BufRead>(&mut self, reader: &mut R) -> Result<(), ServerError> {
+        let mut buf = String::new();
+        while reader
+            .read_line(&mut buf)
+            .map_err(|_| ServerError::ReadLineError)?
+            > 0
+        {
             buf.pop();
             buf.pop();
             match &self.state {
                 RequestState::FirstLine => {
-                    let v: Vec<&str> = buf.split(" ").collect();
+                    let v: Vec<&str> = buf.split(' ').collect();
                     read_first_line(self, v)?;
                     self.state = RequestState::Header;
                 }
                 RequestState::Header => {
-                    if buf == "" {
+                    if buf.is_empty() {
                         self.state = RequestState::Body;
                         break;
                     }
-                    let v: Vec<&str> = buf.split(":").collect();
+                    let v: Vec<&str> = buf.splitn(2, ':').collect();
                     read_header(self, v)?
                 }
-                _ => {
-                    println!("{:?}", self.state);
-                    break;
-                }
+                _ => break,
             }
             buf.clear();
         }
-        read_body(self, reader)?;
-
         Ok(())
     }
 }
@@ -176,39 +1034,22 @@ fn read_header(msg: &mut Request, v: Vec<&str>) -> Result<(), ServerError> {
     if v.len() < 2 {
         return Err(ServerError::ReadHeaderError);
     }
-
-    match v[0] {
-        x if uncased::eq(x, HttpHeader::ContentLength.as_str()) => {
-            let content_length: u64 = v[1].trim().parse().unwrap_or_else(|_| 0);
-            msg.content_length = content_length;
-        }
-        x if uncased::eq(x, HttpHeader::ContentType.as_str()) => {
-            if let Ok(x) = ContentType::from_str(v[1]) {
-                msg.content_type = x;
-            }
-        }
-        _ => {
-            msg.headers.insert(v[0].to_string(), v[1].to_string());
-        }
-    }
-
+    msg.insert_header(v[0], v[1].trim());
     Ok(())
 }
 
-fn read_body(msg: &mut Request, reader: BufReader<&mut TcpStream>) -> Result<(), ServerError> {
-    let mut v = Vec::new();
-    let mut chunk = reader.take(msg.content_length);
-    let _ = chunk.read_to_end(&mut v).unwrap();
-    match msg.content_type {
-        ContentType::ApplicationJson | ContentType::TextHtml | ContentType::TextPlain => {
-            msg.body = Some(RequestBody::StringBody(
-                String::from_utf8_lossy(&v).to_string(),
-            ));
-        }
-        ContentType::ImageJpeg | ContentType::ImagePng => {
-            msg.body = Some(RequestBody::BytesBody(v))
+fn read_body<R: BufRead>(msg: &mut Request, mut reader: R) -> Result<(), ServerError> {
+    let mut framing = msg.body_framing();
+    let mut body = Vec::new();
+    let mut buf = [0; 8192];
+    loop {
+        match framing.read(&mut reader, &mut buf) {
+            Ok(0) => break,
+            Ok(n) => body.extend_from_slice(&buf[..n]),
+            Err(_) => return Err(ServerError::ReadLineError),
         }
     }
+    msg.set_body(body);
     Ok(())
 }
 
@@ -222,25 +1063,57 @@ pub enum ResponseBody {
 pub struct Response {
     pub version: Version,
     pub status_code: StatusCode,
-    pub headers: Header,
+    pub headers: HeaderMap,
     pub body: Option<ResponseBody>,
     pub content_length: u64,
     pub content_type: ContentType,
 }
 
 impl Response {
-    fn new() -> Self {
+    pub(crate) fn new() -> Self {
         Response {
             version: HTTP_11.to_string(),
             status_code: StatusCode::Ok,
-            headers: HashMap::new(),
+            headers: HeaderMap::new(),
             body: None,
             content_length: 0,
             content_type: ContentType::TextPlain,
         }
     }
 
-    fn format(&self) -> Vec<u8> {
+    /// The numeric status code.
+    pub fn status(&self) -> usize {
+        self.status_code.as_num()
+    }
+
+    /// First value of header `name`, matched case-insensitively.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name)
+    }
+
+    pub fn body_bytes(&self) -> &[u8] {
+        match &self.body {
+            Some(ResponseBody::BytesBody(b)) => b,
+            None => &[],
+        }
+    }
+
+    /// The body as text, with invalid UTF-8 replaced.
+    pub fn text(&self) -> String {
+        String::from_utf8_lossy(self.body_bytes()).into_owned()
+    }
+
+    /// Length of the body, or of the streamed body once sent.
+    pub(crate) fn body_len(&self) -> usize {
+        match &self.body {
+            Some(ResponseBody::BytesBody(b)) => b.len(),
+            None => self.content_length as usize,
+        }
+    }
+
+    /// The response on the wire; `with_body` is false when answering `HEAD`,
+    /// which still reports the `Content-Length` of the body it omits.
+    fn format(&self, with_body: bool) -> Vec<u8> {
         let status_line = format!(
             "{} {} {}\r\n",
             self.version,
@@ -249,9 +1122,14 @@ impl Response {
         );
 
         let mut headers = String::new();
-        for (key, value) in &self.headers {
-            let s = format!("{}: {}\r\n", key, value);
-            headers = headers + &s;
+        for (key, value) in self.headers.iter() {
+            headers.push_str(&format!("{}: {}\r\n", key, value));
+        }
+        // `Conn` serves one request per connection and then closes it. Say so,
+        // or a client keeping the connection for its next request races
+        // against the close and sees that request fail.
+        if !self.headers.contains("Connection") {
+            headers.push_str("Connection: close\r\n");
         }
 
         let mut body = Vec::new();
@@ -259,18 +1137,19 @@ impl Response {
             match x {
                 ResponseBody::BytesBody(y) => {
                     body = y.clone();
-                    let s = format!(
-                        "{}: {}\r\n\r\n",
+                    headers.push_str(&format!(
+                        "{}: {}\r\n",
                         HttpHeader::ContentLength.as_str(),
                         y.len()
-                    );
-                    headers = headers + &s;
+                    ));
                 } // ResponseBody::StringBody(y) => body = y,
             }
         }
-        let s = format!("{}{}", status_line, headers);
+        let s = format!("{}{}\r\n", status_line, headers);
         let mut ss = s.as_bytes().to_vec();
-        ss.append(&mut body);
+        if with_body {
+            ss.append(&mut body);
+        }
         ss
     }
 }
@@ -11,6 +11,8 @@ pub enum Method {
     Delete,
     Patch,
     Put,
+    Head,
+    Options,
     Other,
 }
 
@@ -22,6 +24,8 @@ impl Method {
             Delete => "DELETE",
             Patch => "PATCH",
             Put => "PUT",
+            Head => "HEAD",
+            Options => "OPTIONS",
             Other => "Other",
         }
     }
@@ -36,6 +40,8 @@ impl FromStr for Method {
             x if uncased::eq(x, Delete.as_str()) => Ok(Delete),
             x if uncased::eq(x, Patch.as_str()) => Ok(Patch),
             x if uncased::eq(x, Put.as_str()) => Ok(Put),
+            x if uncased::eq(x, Head.as_str()) => Ok(Head),
+            x if uncased::eq(x, Options.as_str()) => Ok(Options),
             _ => Err(()),
         }
     }
@@ -0,0 +1,541 @@
+//! Reverse proxy.
+//!
+//! `ReverseProxy` is a `Handler` forwarding requests to one or more
+//! upstream HTTP/1.1 servers through a `Client`. Connections to
+//! upstreams are kept alive and reused, bodies are streamed as they
+//! arrive, and upstreams that keep failing are taken out of rotation for
+//! a while.
+use crate::client::{Body, Client, ClientError};
+use crate::error::ServerError;
+use crate::message::{Request, RequestBody, ResponseWriter};
+use crate::method::Method;
+use crate::server::Handler;
+use crate::status_code::StatusCode;
+use std::error::Error;
+use std::fmt;
+use std::io::{self, Read};
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Headers that describe a single connection and are never forwarded
+/// (RFC 7230 section 6.1).
+const HOP_BY_HOP: [&str; 8] = [
+    "Connection",
+    "Keep-Alive",
+    "Proxy-Authenticate",
+    "Proxy-Authorization",
+    "TE",
+    "Trailer",
+    "Transfer-Encoding",
+    "Upgrade",
+];
+
+/// Why a proxy cannot be made for the upstreams given.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum UpstreamError {
+    NoUpstreams,
+    Https(String),
+    InvalidUrl(String),
+    InvalidPort(String),
+}
+
+impl fmt::Display for UpstreamError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UpstreamError::NoUpstreams => f.write_str("a proxy needs an upstream"),
+            UpstreamError::Https(url) => write!(f, "https upstreams are not supported: {}", url),
+            UpstreamError::InvalidUrl(url) => write!(f, "`{}` is not an http URL", url),
+            UpstreamError::InvalidPort(url) => write!(f, "`{}` has an invalid port", url),
+        }
+    }
+}
+
+impl Error for UpstreamError {}
+
+/// How requests are spread over several upstreams.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Balance {
+    RoundRobin,
+    /// The upstream with the fewest requests in flight.
+    LeastConnections,
+}
+
+pub(crate) struct Upstream {
+    /// `host:port` to connect to.
+    addr: String,
+    /// The `Host` header for requests to this upstream.
+    host: String,
+    /// Path prefix put in front of every forwarded target.
+    base: String,
+    active: AtomicUsize,
+    failures: AtomicU32,
+    down_until: Mutex<Option<Instant>>,
+}
+
+impl Upstream {
+    /// Parse `http://host[:port][/base]`; the scheme may be omitted.
+    pub(crate) fn parse(url: &str) -> Result<Self, UpstreamError> {
+        if url.starts_with("https://") {
+            return Err(UpstreamError::Https(url.to_string()));
+        }
+        let rest = url.strip_prefix("http://").unwrap_or(url);
+        let (authority, base) = match rest.find('/') {
+            Some(i) => (&rest[..i], rest[i..].trim_end_matches('/')),
+            None => (rest, ""),
+        };
+        let (host, port) = match authority.strip_prefix('[') {
+            Some(v6) => match v6.split_once(']') {
+                Some((host, rest)) => (host, rest.strip_prefix(':')),
+                None => ("", None),
+            },
+            None => match authority.split_once(':') {
+                Some((host, port)) => (host, Some(port)),
+                None => (authority, None),
+            },
+        };
+        if host.is_empty() || host.contains("://") {
+            return Err(UpstreamError::InvalidUrl(url.to_string()));
+        }
+        let addr = match port {
+            Some(port) if port.parse::<u16>().is_err() => {
+                return Err(UpstreamError::InvalidPort(url.to_string()))
+            }
+            Some(_) => authority.to_string(),
+            None => format!("{}:80", authority),
+        };
+        Ok(Upstream {
+            addr,
+            host: authority.to_string(),
+            base: base.to_string(),
+            active: AtomicUsize::new(0),
+            failures: AtomicU32::new(0),
+            down_until: Mutex::new(None),
+        })
+    }
+
+    fn is_up(&self, now: Instant) -> bool {
+        let mut down_until = self.down_until.lock().unwrap();
+        match *down_until {
+            Some(t) if t > now => false,
+            Some(_) => {
+                *down_until = None;
+                true
+            }
+            None => true,
+        }
+    }
+
+    fn record_failure(&self, max_fails: u32, fail_timeout: Duration) {
+        if self.failures.fetch_add(1, Ordering::SeqCst) + 1 >= max_fails {
+            self.failures.store(0, Ordering::SeqCst);
+            *self.down_until.lock().unwrap() = Some(Instant::now() + fail_timeout);
+        }
+    }
+
+    fn record_success(&self) {
+        self.failures.store(0, Ordering::SeqCst);
+    }
+}
+
+/// Decrements the in-flight count of an upstream when dropped.
+struct ActiveGuard(Arc<Upstream>);
+
+impl ActiveGuard {
+    fn new(upstream: Arc<Upstream>) -> Self {
+        upstream.active.fetch_add(1, Ordering::SeqCst);
+        ActiveGuard(upstream)
+    }
+}
+
+impl Drop for ActiveGuard {
+    fn drop(&mut self) {
+        self.0.active.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+fn is_hop_by_hop(name: &str, connection: &[String]) -> bool {
+    HOP_BY_HOP.iter().any(|h| h.eq_ignore_ascii_case(name))
+        || connection.iter().any(|c| c.eq_ignore_ascii_case(name))
+}
+
+/// Header names listed in a `Connection` header, which are hop-by-hop too.
+fn connection_tokens(value: Option<&str>) -> Vec<String> {
+    value
+        .unwrap_or("")
+        .split(',')
+        .map(|t| t.trim().to_string())
+        .filter(|t| !t.is_empty())
+        .collect()
+}
+
+/// A `Forwarded` node for `addr`, quoting IPv6 addresses.
+fn forwarded_node(addr: SocketAddr) -> String {
+    match addr {
+        SocketAddr::V4(a) => a.ip().to_string(),
+        SocketAddr::V6(a) => format!("\"[{}]\"", a.ip()),
+    }
+}
+
+/// `value` as a quoted-string (RFC 7230 section 3.2.6).
+fn quoted_string(value: &str) -> String {
+    let mut quoted = String::with_capacity(value.len() + 2);
+    quoted.push('"');
+    for c in value.chars() {
+        if c == '"' || c == '\\' {
+            quoted.push('\\');
+        }
+        quoted.push(c);
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// An upstream response body, counted as in flight until it has been read
+/// from the upstream, ahead of the last bytes reaching the client.
+struct UpstreamBody {
+    body: Body,
+    guard: Option<ActiveGuard>,
+}
+
+impl Read for UpstreamBody {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.body.read(buf)?;
+        if n == 0 || self.body.finished() {
+            self.guard = None;
+        }
+        Ok(n)
+    }
+}
+
+/// A streamed request body, shared by the attempts to send it upstream.
+/// It can only be read once, so an attempt failing after any of it was
+/// read cannot be retried.
+#[derive(Clone)]
+struct SharedBody {
+    reader: Arc<Mutex<Box<dyn Read + Send>>>,
+    taken: Arc<AtomicBool>,
+}
+
+impl SharedBody {
+    fn new(reader: Box<dyn Read + Send>) -> Self {
+        SharedBody {
+            reader: Arc::new(Mutex::new(reader)),
+            taken: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    fn taken(&self) -> bool {
+        self.taken.load(Ordering::SeqCst)
+    }
+}
+
+impl Read for SharedBody {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.reader.lock().unwrap().read(buf)?;
+        if n > 0 {
+            self.taken.store(true, Ordering::SeqCst);
+        }
+        Ok(n)
+    }
+}
+
+/// Handler forwarding requests to upstream servers.
+///
+/// The forwarded request gets the upstream's `Host` (unless
+/// `preserve_host` is set), the client address appended to
+/// `X-Forwarded-For` and `Forwarded`, and `X-Forwarded-Proto` and
+/// `X-Forwarded-Host` describing the original request. Hop-by-hop headers
+/// are dropped in both directions. Upstream failures answer `502 Bad
+/// Gateway`, timeouts `504 Gateway Timeout`; idempotent requests are
+/// retried on the next upstream first, unless some of a streamed body was
+/// already sent.
+///
+/// Bodies are streamed both ways. A request body keeps its
+/// `Content-Length` when it has one and is sent chunked otherwise.
+pub struct ReverseProxy {
+    upstreams: Vec<Arc<Upstream>>,
+    balance: Balance,
+    next: AtomicUsize,
+    strip_prefix: String,
+    preserve_host: bool,
+    max_fails: u32,
+    fail_timeout: Duration,
+    client: Client,
+}
+
+impl ReverseProxy {
+    /// A proxy to `upstream`, given as `http://host[:port][/base]`.
+    pub fn new(upstream: &str) -> Result<Self, UpstreamError> {
+        ReverseProxy::with_upstreams(&[upstream])
+    }
+
+    /// A proxy balancing between several upstreams, round-robin unless
+    /// changed with `balance`.
+    pub fn with_upstreams(upstreams: &[&str]) -> Result<Self, UpstreamError> {
+        if upstreams.is_empty() {
+            return Err(UpstreamError::NoUpstreams);
+        }
+        let upstreams = upstreams
+            .iter()
+            .map(|u| Upstream::parse(u).map(Arc::new))
+            .collect::<Result<_, _>>()?;
+        Ok(ReverseProxy {
+            upstreams,
+            balance: Balance::RoundRobin,
+            next: AtomicUsize::new(0),
+            strip_prefix: String::new(),
+            preserve_host: false,
+            max_fails: 3,
+            fail_timeout: Duration::from_secs(10),
+            client: Client::new()
+                .connect_timeout(Duration::from_secs(5))
+                .timeout(Duration::from_secs(60))
+                .max_idle_per_host(16)
+                .max_redirects(0),
+        })
+    }
+
+    pub fn balance(mut self, balance: Balance) -> Self {
+        self.balance = balance;
+        self
+    }
+
+    /// Remove `prefix` from request paths before forwarding, e.g. to mount
+    /// an upstream under `/api`.
+    pub fn strip_prefix(mut self, prefix: &str) -> Self {
+        self.strip_prefix = prefix.trim_end_matches('/').to_string();
+        self
+    }
+
+    /// Forward the client's `Host` header instead of the upstream's.
+    pub fn preserve_host(mut self, preserve: bool) -> Self {
+        self.preserve_host = preserve;
+        self
+    }
+
+    /// Take an upstream out of rotation for `fail_timeout` after
+    /// `max_fails` consecutive failures. Defaults to 3 and 10 seconds.
+    pub fn passive_health(mut self, max_fails: u32, fail_timeout: Duration) -> Self {
+        self.max_fails = max_fails.max(1);
+        self.fail_timeout = fail_timeout;
+        self
+    }
+
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.client = self.client.connect_timeout(timeout);
+        self
+    }
+
+    /// Limit on each read from and write to an upstream.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.client = self.client.timeout(timeout);
+        self
+    }
+
+    /// Idle connections kept per upstream.
+    pub fn max_idle(mut self, max_idle: usize) -> Self {
+        self.client = self.client.max_idle_per_host(max_idle);
+        self
+    }
+
+    /// The addresses of the upstreams currently in rotation.
+    pub fn healthy_upstreams(&self) -> Vec<String> {
+        let now = Instant::now();
+        self.upstreams
+            .iter()
+            .filter(|u| u.is_up(now))
+            .map(|u| u.addr.clone())
+            .collect()
+    }
+
+    /// Healthy upstreams in the order to try them.
+    fn candidates(&self) -> Vec<Arc<Upstream>> {
+        let now = Instant::now();
+        let mut order: Vec<Arc<Upstream>> = self
+            .upstreams
+            .iter()
+            .filter(|u| u.is_up(now))
+            .cloned()
+            .collect();
+        if order.is_empty() {
+            return order;
+        }
+        let start = self.next.fetch_add(1, Ordering::SeqCst) % order.len();
+        order.rotate_left(start);
+        if self.balance == Balance::LeastConnections {
+            // stable, so ties keep the round-robin order
+            order.sort_by_key(|u| u.active.load(Ordering::SeqCst));
+        }
+        order
+    }
+
+    fn target(&self, upstream: &Upstream, req: &Request) -> String {
+        let path = req.path.as_str();
+        let rest = match path.strip_prefix(self.strip_prefix.as_str()) {
+            Some(rest) if !self.strip_prefix.is_empty() => rest,
+            _ => path,
+        };
+        let rest = if rest.starts_with('/') {
+            rest.to_string()
+        } else {
+            format!("/{}", rest)
+        };
+        format!("{}{}", upstream.base, rest)
+    }
+
+    /// The request to send to `upstream`.
+    fn upstream_request(&self, upstream: &Upstream, req: &Request, body: &SharedBody) -> Request {
+        let mut out = Request::new();
+        out.method = req.method;
+        out.path = format!("http://{}{}", upstream.host, self.target(upstream, req));
+        if req.is_streamed() {
+            match req.body_length() {
+                Some(0) => {}
+                Some(len) => {
+                    out.insert_header("Content-Length", &len.to_string());
+                    out.set_body_reader(Box::new(body.clone()));
+                }
+                None => {
+                    out.insert_header("Transfer-Encoding", "chunked");
+                    out.set_body_reader(Box::new(body.clone()));
+                }
+            }
+        } else {
+            out.body = Some(RequestBody::BytesBody(req.body_bytes().to_vec()));
+        }
+        let connection = connection_tokens(req.header("Connection"));
+        let skip = [
+            "Host",
+            "Content-Length",
+            "X-Forwarded-For",
+            "X-Forwarded-Proto",
+            "X-Forwarded-Host",
+            "Forwarded",
+        ];
+        for (name, value) in &req.headers {
+            if is_hop_by_hop(name, &connection) || skip.iter().any(|s| s.eq_ignore_ascii_case(name))
+            {
+                continue;
+            }
+            out.headers.insert(name.clone(), value.clone());
+        }
+        let mut set = |name: &str, value: String| {
+            out.headers.insert(name.to_string(), value);
+        };
+
+        let original_host = req.header("Host");
+        let host = match original_host {
+            Some(h) if self.preserve_host => h,
+            _ => upstream.host.as_str(),
+        };
+        set("Host", host.to_string());
+        if let Some(addr) = req.remote_addr {
+            let ip = addr.ip().to_string();
+            let xff = match req.header("X-Forwarded-For") {
+                Some(prior) => format!("{}, {}", prior, ip),
+                None => ip,
+            };
+            set("X-Forwarded-For", xff);
+        }
+        set("X-Forwarded-Proto", req.scheme().to_string());
+        if let Some(h) = original_host {
+            set("X-Forwarded-Host", h.to_string());
+        }
+        let mut node = String::new();
+        if let Some(addr) = req.remote_addr {
+            node.push_str(&format!("for={};", forwarded_node(addr)));
+        }
+        if let Some(h) = original_host {
+            node.push_str(&format!("host={};", quoted_string(h)));
+        }
+        node.push_str(&format!("proto={}", req.scheme()));
+        let forwarded = match req.header("Forwarded") {
+            Some(prior) => format!("{}, {}", prior, node),
+            None => node,
+        };
+        set("Forwarded", forwarded);
+        out
+    }
+
+    fn respond_error(writer: &mut dyn ResponseWriter, err: &ClientError) {
+        let status = if err.is_timeout() {
+            StatusCode::GatewayTimeout
+        } else {
+            StatusCode::BadGateway
+        };
+        writer.text(status.as_str());
+        writer.status(status);
+    }
+}
+
+impl Handler for ReverseProxy {
+    fn serve_http(
+        &self,
+        writer: &mut dyn ResponseWriter,
+        req: &Request,
+    ) -> Result<(), ServerError> {
+        let idempotent = !matches!(req.method, Method::Post | Method::Patch);
+        let request_body = SharedBody::new(if req.is_streamed() {
+            Box::new(req.body_reader())
+        } else {
+            Box::new(io::empty())
+        });
+        let mut last_error = ClientError::Connect(io::Error::new(
+            io::ErrorKind::NotConnected,
+            "no healthy upstream",
+        ));
+
+        for upstream in self.candidates() {
+            let guard = ActiveGuard::new(upstream.clone());
+            let (res, body) = match self.client.send_streamed(&self.upstream_request(
+                &upstream,
+                req,
+                &request_body,
+            )) {
+                Ok(ok) => ok,
+                Err(e) => {
+                    upstream.record_failure(self.max_fails, self.fail_timeout);
+                    let retry = match e {
+                        ClientError::Connect(_) => true,
+                        ClientError::Io(_) => idempotent,
+                        _ => false,
+                    };
+                    // the next upstream would get what is left of the body
+                    if retry && !request_body.taken() {
+                        last_error = e;
+                        continue;
+                    }
+                    Self::respond_error(writer, &e);
+                    return Ok(());
+                }
+            };
+            upstream.record_success();
+
+            writer.status(res.status_code);
+            let connection = connection_tokens(res.header("Connection"));
+            for (name, value) in res.headers.iter() {
+                if is_hop_by_hop(name, &connection) {
+                    continue;
+                }
+                if name.eq_ignore_ascii_case("Content-Length") && !body.is_empty() {
+                    continue;
+                }
+                writer.append_header(name, value);
+            }
+            if !body.is_empty() {
+                let length = body.content_length();
+                let body = UpstreamBody {
+                    body,
+                    guard: Some(guard),
+                };
+                writer.write_stream(Box::new(body), length);
+            }
+            return Ok(());
+        }
+
+        Self::respond_error(writer, &last_error);
+        Ok(())
+    }
+}
@@ -0,0 +1,62 @@
+//! Catching the panics of handlers.
+//!
+//! A backtrace has to be taken while the panicking stack is still there,
+//! so the first `catch` installs a panic hook that records one for the
+//! code it runs, and leaves panics elsewhere to the hook it replaced.
+use std::any::Any;
+use std::backtrace::Backtrace;
+use std::cell::{Cell, RefCell};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::Once;
+
+thread_local! {
+    static CATCHING: Cell<bool> = const { Cell::new(false) };
+    static CAUGHT: RefCell<Option<(String, Backtrace)>> = const { RefCell::new(None) };
+}
+
+/// A panic caught by `catch`.
+pub(crate) struct Panic {
+    pub message: String,
+    /// Where the panic happened, unless another panic hook was set since.
+    pub location: Option<String>,
+    pub backtrace: Option<Backtrace>,
+}
+
+/// Run `f`, returning its panic rather than unwinding further.
+pub(crate) fn catch<R, F: FnOnce() -> R>(f: F) -> Result<R, Panic> {
+    static HOOK: Once = Once::new();
+    HOOK.call_once(|| {
+        let previous = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            if !CATCHING.with(Cell::get) {
+                return previous(info);
+            }
+            let location = info.location().map_or(String::new(), |l| l.to_string());
+            let backtrace = Backtrace::force_capture();
+            CAUGHT.with(|c| *c.borrow_mut() = Some((location, backtrace)));
+        }));
+    });
+
+    let catching = CATCHING.with(|c| c.replace(true));
+    let result = panic::catch_unwind(AssertUnwindSafe(f));
+    CATCHING.with(|c| c.set(catching));
+    result.map_err(|payload| {
+        let caught = CAUGHT.with(|c| c.borrow_mut().take());
+        let (location, backtrace) = caught.map_or((None, None), |(l, b)| (Some(l), Some(b)));
+        Panic {
+            message: message(&*payload),
+            location,
+            backtrace,
+        }
+    })
+}
+
+fn message(payload: &(dyn Any + Send)) -> String {
+    if let Some(s) = payload.downcast_ref::<&str>() {
+        s.to_string()
+    } else if let Some(s) = payload.downcast_ref::<String>() {
+        s.clone()
+    } else {
+        "Box<dyn Any>".to_string()
+    }
+}
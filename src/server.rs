@@ -1,18 +1,25 @@
 use crate::error::ServerError;
+use crate::error_pages::{write_error, ErrorPages};
+use crate::extensions::Extensions;
+use crate::listener::{Listen, Listener};
+use crate::log::RequestLogger;
 use crate::message::Conn;
-use crate::message::Header;
 use crate::message::ResponseWriter;
-use crate::message::{Request, ResponseBody};
+use crate::message::{percent_decode, Params, Request};
 use crate::method::Method;
+use crate::recover;
+use crate::stats::{MetricsHandler, Stats};
+use crate::status_code::StatusCode;
+#[cfg(feature = "tls")]
+use crate::tls::TlsConfig;
 use crate::worker::ThreadPool;
 use std::collections::HashMap;
-use std::fs::File;
-use std::io::prelude::*;
-use std::net::TcpListener;
-
+use std::io;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
-
-const NOT_FOUND_HANDLER: NotFoundHandler = NotFoundHandler::new();
+use std::thread;
+use std::time::{Duration, Instant};
 
 pub trait HandlerServeMux: Handler + ServeMux {}
 
@@ -25,29 +32,119 @@ pub trait ServeMux {
     fn handle(&mut self, method: Method, pattern: String, handler: Arc<dyn Handler + Send + Sync>);
 }
 
-struct GetEntry {
-    path: String,
-    handler: Arc<dyn Handler + Send + Sync>,
+/// Adapter turning a function or closure into a `Handler`.
+///
+/// Any `Fn(&Request, &mut dyn ResponseWriter)` already implements `Handler`;
+/// wrapping a closure in `HandlerFunc::new` lets the compiler infer its
+/// argument types.
+pub struct HandlerFunc<F>(F);
+
+impl<F> HandlerFunc<F>
+where
+    F: Fn(&Request, &mut dyn ResponseWriter) -> Result<(), ServerError>,
+{
+    pub fn new(f: F) -> Self {
+        HandlerFunc(f)
+    }
 }
-impl GetEntry {
-    fn new(path: String, handler: Arc<dyn Handler + Send + Sync>) -> Self {
-        GetEntry { path, handler }
+
+impl<F> Handler for HandlerFunc<F>
+where
+    F: Fn(&Request, &mut dyn ResponseWriter) -> Result<(), ServerError>,
+{
+    fn serve_http(
+        &self,
+        writer: &mut dyn ResponseWriter,
+        req: &Request,
+    ) -> Result<(), ServerError> {
+        (self.0)(req, writer)
     }
 }
 
-struct PostEntry {
+impl<F> Handler for F
+where
+    F: Fn(&Request, &mut dyn ResponseWriter) -> Result<(), ServerError> + Send + Sync,
+{
+    fn serve_http(
+        &self,
+        writer: &mut dyn ResponseWriter,
+        req: &Request,
+    ) -> Result<(), ServerError> {
+        self(req, writer)
+    }
+}
+
+struct Entry {
     path: String,
     handler: Arc<dyn Handler + Send + Sync>,
 }
-impl PostEntry {
+impl Entry {
     fn new(path: String, handler: Arc<dyn Handler + Send + Sync>) -> Self {
-        PostEntry { path, handler }
+        Entry { path, handler }
+    }
+
+    fn is_static(&self) -> bool {
+        !self
+            .path
+            .split('/')
+            .any(|seg| seg.starts_with(':') || seg.starts_with('*'))
+    }
+
+    /// Match `path` against this entry's pattern, collecting the segments
+    /// named by `:name` and a trailing `*name`.
+    fn matches(&self, path: &str) -> Option<Params> {
+        let mut params = Params::new();
+        let mut pattern = self.path.split('/');
+        let mut segments = path.split('/');
+        loop {
+            match (pattern.next(), segments.next()) {
+                (None, None) => return Some(params),
+                (Some(p), Some(seg)) if p.starts_with('*') => {
+                    let rest: Vec<&str> = std::iter::once(seg).chain(segments).collect();
+                    params.push((p[1..].to_string(), percent_decode(&rest.join("/"))));
+                    return Some(params);
+                }
+                (Some(p), Some(seg)) if p.starts_with(':') && !seg.is_empty() => {
+                    params.push((p[1..].to_string(), percent_decode(seg)));
+                }
+                (Some(p), Some(seg)) if p == seg => {}
+                _ => return None,
+            }
+        }
     }
 }
 
+/// Registers each entry once per pattern, replacing an earlier registration.
+fn insert_entry(entries: &mut Vec<Entry>, e: Entry) {
+    match entries.iter_mut().find(|x| x.path == e.path) {
+        Some(x) => *x = e,
+        None => entries.push(e),
+    }
+}
+
+/// Find the entry for `path`, preferring static patterns over ones with
+/// parameters and otherwise keeping registration order.
+fn find_entry<'a>(entries: &'a [Entry], path: &str) -> Option<(&'a Entry, Params)> {
+    entries
+        .iter()
+        .filter(|e| e.is_static())
+        .chain(entries.iter().filter(|e| !e.is_static()))
+        .find_map(|e| e.matches(path).map(|params| (e, params)))
+}
+
+/// Routes requests by method and path.
+///
+/// Patterns are matched segment by segment: `:name` matches any single
+/// segment and `*name` matches the rest of the path. The matched values are
+/// available to the handler in `Request::params`. `HEAD` requests are served
+/// by the `GET` route unless they have their own, and `OPTIONS` requests
+/// without a route are answered with `204` and an `Allow` header.
 pub struct DefaultServeMux {
-    get: HashMap<String, GetEntry>,
-    post: HashMap<String, PostEntry>,
+    routes: HashMap<Method, Vec<Entry>>,
+    any: Vec<Entry>,
+    state: Extensions,
+    not_found: Arc<dyn Handler + Send + Sync>,
+    error_pages: Option<Arc<ErrorPages>>,
 }
 
 impl HandlerServeMux for DefaultServeMux {}
@@ -58,89 +155,399 @@ impl Handler for DefaultServeMux {
         writer: &mut dyn ResponseWriter,
         req: &Request,
     ) -> Result<(), ServerError> {
-        self.handler(req).serve_http(writer, req)
+        if let Some(pages) = &self.error_pages {
+            ErrorPages::install(pages, writer, req);
+        }
+        match self.handler(req) {
+            Some((handler, params)) => {
+                if params.is_empty() && self.state.is_empty() {
+                    return handler.serve_http(writer, req);
+                }
+                let mut req = req.clone();
+                req.params = params;
+                if !self.state.is_empty() {
+                    let mut state = (*req.app_state).clone();
+                    state.extend(&self.state);
+                    req.app_state = Arc::new(state);
+                }
+                handler.serve_http(writer, &req)
+            }
+            None if req.method == Method::Options => {
+                let allowed = self.allowed_methods(req.target_path());
+                if allowed.is_empty() {
+                    return self.not_found.serve_http(writer, req);
+                }
+                let allowed: Vec<&str> = allowed.iter().map(|m| m.as_str()).collect();
+                writer.set_header("Allow", &allowed.join(", "));
+                writer.no_content();
+                Ok(())
+            }
+            None => self.not_found.serve_http(writer, req),
+        }
     }
 }
 impl ServeMux for DefaultServeMux {
     fn handle(&mut self, method: Method, pattern: String, handler: Arc<dyn Handler + Send + Sync>) {
-        match method {
-            Method::Get => {
-                let e = GetEntry::new(pattern, handler);
-                self.get.insert(e.path.clone(), e);
-            }
-            Method::Post => {
-                let e = PostEntry::new(pattern, handler);
-                self.post.insert(e.path.clone(), e);
-            }
-            _ => {}
-        }
+        let entries = self.routes.entry(method).or_default();
+        insert_entry(entries, Entry::new(pattern, handler));
+    }
+}
+
+impl Default for DefaultServeMux {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
 impl DefaultServeMux {
     pub fn new() -> Self {
         DefaultServeMux {
-            get: HashMap::new(),
-            post: HashMap::new(),
+            routes: HashMap::new(),
+            any: Vec::new(),
+            state: Extensions::new(),
+            not_found: Arc::new(NotFoundHandler),
+            error_pages: None,
         }
     }
 
-    // fn handler(&self, r: &Request) -> impl Handler {
-    fn handler(&self, r: &Request) -> Arc<dyn Handler> {
-        match r.method {
-            Method::Get => {
-                if let Some(x) = self.get.get(&r.path) {
-                    x.handler.clone()
-                } else {
-                    Arc::new(NOT_FOUND_HANDLER)
-                }
-                // Box::new(NotFoundHandler::new())
-            }
-            _ => Arc::new(NOT_FOUND_HANDLER),
+    /// Answer requests no route matches with `handler` rather than a plain
+    /// `404 Not Found` page.
+    pub fn set_not_found<H: Handler + Send + Sync + 'static>(&mut self, handler: H) {
+        self.not_found = Arc::new(handler);
+    }
+
+    /// Answer the error statuses of every response of this mux with
+    /// `pages`.
+    pub fn set_error_pages(&mut self, pages: ErrorPages) {
+        self.error_pages = Some(Arc::new(pages));
+    }
+
+    /// Make `value` available to the handlers of this mux through
+    /// `Request::state`, overriding server state of the same type.
+    pub fn set_state<T: Send + Sync + 'static>(&mut self, value: T) {
+        self.state.insert(value);
+    }
+
+    pub fn get<H: Handler + Send + Sync + 'static>(&mut self, pattern: &str, handler: H) {
+        self.handle(Method::Get, pattern.to_string(), Arc::new(handler));
+    }
+
+    pub fn post<H: Handler + Send + Sync + 'static>(&mut self, pattern: &str, handler: H) {
+        self.handle(Method::Post, pattern.to_string(), Arc::new(handler));
+    }
+
+    pub fn put<H: Handler + Send + Sync + 'static>(&mut self, pattern: &str, handler: H) {
+        self.handle(Method::Put, pattern.to_string(), Arc::new(handler));
+    }
+
+    pub fn delete<H: Handler + Send + Sync + 'static>(&mut self, pattern: &str, handler: H) {
+        self.handle(Method::Delete, pattern.to_string(), Arc::new(handler));
+    }
+
+    /// Register `handler` for every method not given its own route.
+    pub fn any<H: Handler + Send + Sync + 'static>(&mut self, pattern: &str, handler: H) {
+        insert_entry(
+            &mut self.any,
+            Entry::new(pattern.to_string(), Arc::new(handler)),
+        );
+    }
+
+    /// Every registered route as its method and pattern, sorted by
+    /// pattern. Routes registered with `any` have no method.
+    pub fn routes(&self) -> Vec<(Option<Method>, &str)> {
+        let mut routes: Vec<(Option<Method>, &str)> = self
+            .routes
+            .iter()
+            .flat_map(|(m, entries)| entries.iter().map(move |e| (Some(*m), e.path.as_str())))
+            .chain(self.any.iter().map(|e| (None, e.path.as_str())))
+            .collect();
+        routes.sort_by_key(|(m, path)| (*path, m.map_or("", |m| m.as_str())));
+        routes
+    }
+
+    /// `HEAD` requests fall back to the `GET` routes.
+    fn handler(&self, r: &Request) -> Option<(Arc<dyn Handler + Send + Sync>, Params)> {
+        let path = r.target_path();
+        let route = |method| {
+            self.routes
+                .get(&method)
+                .and_then(|entries| find_entry(entries, path))
+        };
+        route(r.method)
+            .or_else(|| match r.method {
+                Method::Head => route(Method::Get),
+                _ => None,
+            })
+            .or_else(|| find_entry(&self.any, path))
+            .map(|(e, params)| (e.handler.clone(), params))
+    }
+
+    /// Methods with a route matching `path`, for the `Allow` header.
+    fn allowed_methods(&self, path: &str) -> Vec<Method> {
+        let mut methods: Vec<Method> = self
+            .routes
+            .iter()
+            .filter(|(_, entries)| find_entry(entries, path).is_some())
+            .map(|(m, _)| *m)
+            .collect();
+        if methods.contains(&Method::Get) && !methods.contains(&Method::Head) {
+            methods.push(Method::Head);
+        }
+        if !methods.is_empty() && !methods.contains(&Method::Options) {
+            methods.push(Method::Options);
         }
+        methods.sort_by_key(|m| m.as_str());
+        methods
     }
 }
 
 pub struct Server {
     pool: ThreadPool,
-    addr: String,
-    handler: Arc<(dyn HandlerServeMux + Send + Sync)>,
+    listen: Vec<Listen>,
+    shutdown_timeout: Duration,
+    pub(crate) read_timeout: Option<Duration>,
+    handler: Arc<dyn HandlerServeMux + Send + Sync>,
+    pub(crate) stats: Arc<Stats>,
+    metrics_path: Option<String>,
+    pub(crate) logger: Option<Arc<dyn RequestLogger + Send + Sync>>,
+    pub(crate) state: Arc<Extensions>,
+    pub(crate) http2: bool,
+    pub(crate) max_body_size: Option<u64>,
+    pub(crate) buffer_bodies: bool,
+    #[cfg(feature = "tls")]
+    pub(crate) tls: Option<TlsConfig>,
 }
 
 pub type StreamBuffer = [u8; 1024];
 
 impl Server {
-    pub fn new(size: usize, addr: String, handler: Arc<dyn HandlerServeMux + Send + Sync>) -> Self {
-        let pool = ThreadPool::new(size).unwrap();
-        Server {
+    /// A server with `size` worker threads accepting connections on `addr`,
+    /// e.g. `"127.0.0.1:8080"`, `"[::]:8080"` or `"unix:/run/app.sock"`.
+    /// Fails with `PoolCreationError` if `size` is zero.
+    pub fn new<L: Into<Listen>>(
+        size: usize,
+        addr: L,
+        handler: Arc<dyn HandlerServeMux + Send + Sync>,
+    ) -> Result<Self, ServerError> {
+        let pool = ThreadPool::new(size).map_err(|_| ServerError::PoolCreationError)?;
+        let stats = Arc::new(Stats::new(pool.counters(), size));
+        Ok(Server {
             pool,
             handler,
-            addr,
+            listen: vec![addr.into()],
+            shutdown_timeout: Duration::from_secs(5),
+            read_timeout: None,
+            stats,
+            metrics_path: None,
+            logger: None,
+            state: Arc::new(Extensions::new()),
+            http2: true,
+            max_body_size: None,
+            buffer_bodies: false,
+            #[cfg(feature = "tls")]
+            tls: None,
+        })
+    }
+
+    /// Live statistics of this server and its thread pool.
+    pub fn stats(&self) -> Arc<Stats> {
+        self.stats.clone()
+    }
+
+    /// Make `value` available to every handler through `Request::state`.
+    pub fn set_state<T: Send + Sync + 'static>(&mut self, value: T) {
+        Arc::make_mut(&mut self.state).insert(value);
+    }
+
+    /// Log every served request through `logger`.
+    pub fn set_logger(&mut self, logger: Arc<dyn RequestLogger + Send + Sync>) {
+        self.logger = Some(logger);
+    }
+
+    /// Serve Prometheus metrics at `path` ahead of the registered handler.
+    pub fn set_metrics_path(&mut self, path: &str) {
+        self.metrics_path = Some(path.to_string());
+    }
+
+    /// Refuse requests with a body over `bytes` with `413 Payload Too
+    /// Large`, without reading the body. A body without a length that
+    /// grows past the limit fails to read instead.
+    pub fn set_max_body_size(&mut self, bytes: u64) {
+        self.max_body_size = Some(bytes);
+    }
+
+    /// Read every request body into `Request::body` before the handler
+    /// runs, off by default. Otherwise handlers read it as it arrives
+    /// through `Request::body_reader`, or buffer it when they need it with
+    /// `Request::body_bytes`.
+    pub fn set_buffer_bodies(&mut self, enabled: bool) {
+        self.buffer_bodies = enabled;
+    }
+
+    /// Whether to serve HTTP/2 next to HTTP/1.1, on by default. Cleartext
+    /// clients get it with prior knowledge or `Upgrade: h2c`, TLS clients
+    /// by negotiating `h2` with ALPN.
+    pub fn set_http2(&mut self, enabled: bool) {
+        self.http2 = enabled;
+        #[cfg(feature = "tls")]
+        {
+            self.tls = self.tls.take().map(|tls| tls.with_http2(enabled));
         }
     }
 
+    /// Accept connections on `addr` as well.
+    pub fn add_listener<L: Into<Listen>>(&mut self, addr: L) {
+        self.listen.push(addr.into());
+    }
+
+    /// Serve every TCP connection over TLS; Unix sockets stay cleartext.
+    #[cfg(feature = "tls")]
+    pub fn set_tls(&mut self, tls: TlsConfig) {
+        self.tls = Some(tls.with_http2(self.http2));
+    }
+
+    /// How long `ServerHandle::shutdown` waits for open connections to
+    /// finish, 5 seconds by default.
+    pub fn set_shutdown_timeout(&mut self, timeout: Duration) {
+        self.shutdown_timeout = timeout;
+    }
+
+    /// Close HTTP/1.1 connections whose client sends nothing for
+    /// `timeout` while the server waits for the request or its body; no
+    /// limit by default.
+    pub fn set_read_timeout(&mut self, timeout: Option<Duration>) {
+        self.read_timeout = timeout;
+    }
+
+    /// Bind every listener and serve connections from all of them until
+    /// the process ends. Fails with `BindError` if any cannot be bound.
     pub fn listen_and_serve(self) -> Result<(), ServerError> {
-        let mut addr: &str = &self.addr;
+        self.start()?.join();
+        Ok(())
+    }
 
-        if addr == "" {
-            addr = "127.0.0.1:8080";
+    /// Bind every listener and serve from background threads, returning
+    /// right away with a handle to learn the bound addresses, e.g. of a
+    /// listener asked for port 0, and to shut the server down. Fails with
+    /// `BindError` if any listener cannot be bound.
+    pub fn start(mut self) -> Result<ServerHandle, ServerError> {
+        let listeners = self
+            .listen
+            .drain(..)
+            .map(|listen| Listener::bind(listen).map(Arc::new))
+            .collect::<io::Result<Vec<_>>>()
+            .map_err(|_| ServerError::BindError)?;
+        let server = Arc::new(self);
+        let stop = Arc::new(AtomicBool::new(false));
+        let threads = listeners
+            .iter()
+            .map(|listener| {
+                let (server, stop) = (server.clone(), stop.clone());
+                let listener = listener.clone();
+                thread::spawn(move || accept(&server, &listener, &stop))
+            })
+            .collect();
+        Ok(ServerHandle {
+            server: Some(server),
+            listeners,
+            stop,
+            threads,
+        })
+    }
+}
+
+/// Hand the connections of `listener` to the pool until `stop` is set.
+fn accept(server: &Arc<Server>, listener: &Listener, stop: &AtomicBool) {
+    while !stop.load(Ordering::SeqCst) {
+        match listener.accept() {
+            Ok(_) if stop.load(Ordering::SeqCst) => break,
+            Ok(stream) => {
+                let c = Conn::new(server.clone(), stream);
+                server.pool.execute(move || {
+                    let _ = c.serve();
+                });
+            }
+            // e.g. out of file descriptors; let some close
+            Err(_) => thread::sleep(Duration::from_millis(10)),
         }
-        let listener = TcpListener::bind(addr).unwrap();
-        self.serve(listener)
+    }
+}
+
+/// A server running in the background, from `Server::start`.
+///
+/// Dropping the handle shuts the server down like `shutdown`.
+pub struct ServerHandle {
+    server: Option<Arc<Server>>,
+    listeners: Vec<Arc<Listener>>,
+    stop: Arc<AtomicBool>,
+    threads: Vec<thread::JoinHandle<()>>,
+}
+
+impl ServerHandle {
+    /// The address of the first TCP listener, with the port the system
+    /// picked if it was bound to port 0.
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.local_addrs().into_iter().next()
+    }
+
+    /// The addresses of every TCP listener, in the order they were added.
+    pub fn local_addrs(&self) -> Vec<SocketAddr> {
+        self.listeners
+            .iter()
+            .filter_map(|l| l.local_addr())
+            .collect()
+    }
+
+    /// Live statistics of the server.
+    pub fn stats(&self) -> Arc<Stats> {
+        self.server.as_ref().unwrap().stats()
+    }
+
+    /// Stop accepting connections and wait for the open ones to finish,
+    /// up to the server's shutdown timeout. Unix socket files are removed.
+    pub fn shutdown(mut self) {
+        self.stop();
     }
 
-    /// ## warning
-    /// after calling this method, self will moved
-    fn serve(self, listener: TcpListener) -> Result<(), ServerError> {
-        let srvarc = Arc::new(self);
-        for stream in listener.incoming() {
-            let stream = stream.unwrap();
-            let c = Conn::new(srvarc.clone(), stream);
-            srvarc.pool.execute(move || {
-                c.serve();
-            });
+    fn stop(&mut self) {
+        let server = match self.server.take() {
+            Some(server) => server,
+            None => return,
+        };
+        self.stop.store(true, Ordering::SeqCst);
+        for listener in &self.listeners {
+            listener.wake();
+        }
+        for thread in self.threads.drain(..) {
+            let _ = thread.join();
+        }
+        self.listeners.clear();
+
+        let deadline = Instant::now() + server.shutdown_timeout;
+        loop {
+            let stats = server.stats.snapshot();
+            if stats.open_connections == 0 && stats.pool.queued == 0 {
+                break;
+            }
+            if Instant::now() > deadline {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    /// Serve until the process ends: the accept threads do not end on
+    /// their own.
+    pub fn join(mut self) {
+        for thread in self.threads.drain(..) {
+            let _ = thread.join();
         }
-        Ok(())
+    }
+}
+
+impl Drop for ServerHandle {
+    fn drop(&mut self) {
+        self.stop();
     }
 }
 
@@ -158,7 +565,40 @@ impl ServeHandler {
         rw: &mut dyn ResponseWriter,
         req: &Request,
     ) -> Result<(), ServerError> {
-        self.server.handler.serve_http(rw, req)
+        if self.server.metrics_path.as_deref() == Some(req.target_path()) {
+            return MetricsHandler::new(self.server.stats()).serve_http(rw, req);
+        }
+        let panic = match recover::catch(|| self.server.handler.serve_http(rw, req)) {
+            Ok(result) => return result,
+            Err(panic) => panic,
+        };
+        self.server.stats.handler_panicked();
+        let mut report = format!(
+            "panic serving \"{} {} {}\"",
+            req.method.as_str(),
+            req.path,
+            req.version
+        );
+        if let Some(addr) = req.remote_addr {
+            report.push_str(&format!(" from {}", addr));
+        }
+        if let Some(location) = &panic.location {
+            report.push_str(&format!(" at {}", location));
+        }
+        report.push_str(&format!(": {}", panic.message));
+        if let Some(backtrace) = &panic.backtrace {
+            report.push_str(&format!("\n{}", backtrace));
+        }
+        eprintln!("{}", report);
+        // with part of the response out, all that is left is to hang up
+        if rw.is_sent() {
+            return Err(ServerError::HandlerPanic);
+        }
+        // nothing the handler set on its way to the panic goes out with
+        // the error
+        rw.clear();
+        write_error(rw, req, StatusCode::InternalServerError);
+        Ok(())
     }
 }
 
@@ -167,25 +607,9 @@ impl Handler for NotFoundHandler {
     fn serve_http(
         &self,
         writer: &mut dyn ResponseWriter,
-        _req: &Request,
+        req: &Request,
     ) -> Result<(), ServerError> {
-        println!("not found");
-        let mut headers: Header = HashMap::new();
-        headers.insert("x-my-headers".to_string(), "hello world".to_string());
-        writer.header(headers);
-
-        let mut file = File::open("404.html").unwrap();
-        let mut not_found_html = String::new();
-        file.read_to_string(&mut not_found_html).unwrap();
-
-        writer.write(ResponseBody::BytesBody(not_found_html.as_bytes().to_vec()));
-        writer.write_header(404);
-        writer.send();
+        write_error(writer, req, StatusCode::NotFound);
         Ok(())
     }
 }
-impl NotFoundHandler {
-    const fn new() -> Self {
-        NotFoundHandler {}
-    }
-}
@@ -0,0 +1,439 @@
+//! Server-side sessions.
+//!
+//! `SessionHandler` wraps a handler, loads the session named by the session
+//! cookie from a `SessionStore` and makes it available as a `Session` (an
+//! extractor, or `Request::extension::<Session>()`). Changes are saved and
+//! the cookie is set right before the response is sent.
+use crate::cookie::{Cookie, SameSite, SET_COOKIE};
+use crate::error::ServerError;
+use crate::extract::{FromRequest, Rejection};
+use crate::message::{percent_decode, percent_encode, Request, Response, ResponseWriter};
+use crate::server::Handler;
+use crate::status_code::StatusCode;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::process;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// A stored session.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct SessionRecord {
+    pub data: HashMap<String, String>,
+    pub created: SystemTime,
+    pub last_access: SystemTime,
+}
+
+/// Backend keeping sessions between requests.
+///
+/// `expires` is when the session stops being valid; stores must not return
+/// records past it and may drop them.
+pub trait SessionStore {
+    fn load(&self, id: &str) -> io::Result<Option<SessionRecord>>;
+    fn save(&self, id: &str, record: &SessionRecord, expires: SystemTime) -> io::Result<()>;
+    fn destroy(&self, id: &str) -> io::Result<()>;
+}
+
+/// Keeps sessions in process memory; they are lost on restart.
+#[derive(Default)]
+pub struct MemoryStore {
+    sessions: Mutex<HashMap<String, (SessionRecord, SystemTime)>>,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        MemoryStore {
+            sessions: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Number of sessions currently held, including expired ones not yet
+    /// dropped.
+    pub fn len(&self) -> usize {
+        self.sessions.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl SessionStore for MemoryStore {
+    fn load(&self, id: &str) -> io::Result<Option<SessionRecord>> {
+        let sessions = self.sessions.lock().unwrap();
+        Ok(sessions
+            .get(id)
+            .filter(|(_, expires)| *expires > SystemTime::now())
+            .map(|(record, _)| record.clone()))
+    }
+
+    fn save(&self, id: &str, record: &SessionRecord, expires: SystemTime) -> io::Result<()> {
+        let mut sessions = self.sessions.lock().unwrap();
+        let now = SystemTime::now();
+        sessions.retain(|_, (_, e)| *e > now);
+        sessions.insert(id.to_string(), (record.clone(), expires));
+        Ok(())
+    }
+
+    fn destroy(&self, id: &str) -> io::Result<()> {
+        self.sessions.lock().unwrap().remove(id);
+        Ok(())
+    }
+}
+
+/// Keeps one file per session in a directory.
+pub struct FileStore {
+    dir: PathBuf,
+}
+
+impl FileStore {
+    /// Use `dir`, creating it if needed.
+    pub fn new<P: Into<PathBuf>>(dir: P) -> io::Result<Self> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)?;
+        Ok(FileStore { dir })
+    }
+
+    fn path(&self, id: &str) -> io::Result<PathBuf> {
+        if id.is_empty() || !id.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "invalid session id",
+            ));
+        }
+        Ok(self.dir.join(id))
+    }
+
+    /// Remove the files of every expired session.
+    pub fn purge_expired(&self) -> io::Result<()> {
+        for entry in fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            // a save in progress
+            if path.extension().is_some_and(|e| e == "tmp") {
+                continue;
+            }
+            if let Ok(content) = fs::read_to_string(&path) {
+                if decode_file(&content).is_none_or(|(_, expires)| expires <= SystemTime::now()) {
+                    let _ = fs::remove_file(path);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn secs(t: SystemTime) -> u64 {
+    t.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs())
+}
+
+fn encode_file(record: &SessionRecord, expires: SystemTime) -> String {
+    let mut out = format!(
+        "created {}\nlast_access {}\nexpires {}\n",
+        secs(record.created),
+        secs(record.last_access),
+        secs(expires)
+    );
+    for (k, v) in &record.data {
+        out.push_str(&format!("{}={}\n", percent_encode(k), percent_encode(v)));
+    }
+    out
+}
+
+fn decode_file(content: &str) -> Option<(SessionRecord, SystemTime)> {
+    let mut lines = content.lines();
+    let mut time = |label: &str| {
+        let line = lines.next()?;
+        let value = line.strip_prefix(label)?.trim().parse().ok()?;
+        Some(UNIX_EPOCH + Duration::from_secs(value))
+    };
+    let created = time("created")?;
+    let last_access = time("last_access")?;
+    let expires = time("expires")?;
+    let data = lines
+        .filter_map(|l| l.split_once('='))
+        .map(|(k, v)| (percent_decode(k), percent_decode(v)))
+        .collect();
+    Some((
+        SessionRecord {
+            data,
+            created,
+            last_access,
+        },
+        expires,
+    ))
+}
+
+impl SessionStore for FileStore {
+    fn load(&self, id: &str) -> io::Result<Option<SessionRecord>> {
+        let content = match fs::read_to_string(self.path(id)?) {
+            Ok(c) => c,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        Ok(decode_file(&content)
+            .filter(|(_, expires)| *expires > SystemTime::now())
+            .map(|(record, _)| record))
+    }
+
+    fn save(&self, id: &str, record: &SessionRecord, expires: SystemTime) -> io::Result<()> {
+        // a temporary file of its own, so concurrent saves of one session
+        // each rename a whole record into place
+        static SAVES: AtomicU64 = AtomicU64::new(0);
+        let path = self.path(id)?;
+        let seq = SAVES.fetch_add(1, Ordering::Relaxed);
+        let tmp = self
+            .dir
+            .join(format!("{}.{}-{}.tmp", id, process::id(), seq));
+        let written = fs::write(&tmp, encode_file(record, expires));
+        match written.and_then(|()| fs::rename(&tmp, path)) {
+            Ok(()) => Ok(()),
+            Err(e) => {
+                let _ = fs::remove_file(&tmp);
+                Err(e)
+            }
+        }
+    }
+
+    fn destroy(&self, id: &str) -> io::Result<()> {
+        match fs::remove_file(self.path(id)?) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// A new random session id.
+pub fn generate_id() -> String {
+    let mut bytes = [0; 32];
+    getrandom::getrandom(&mut bytes).expect("system random source");
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+struct SessionState {
+    id: Option<String>,
+    data: HashMap<String, String>,
+    created: SystemTime,
+    /// Drop the stored session at `id` when finishing.
+    rotate: bool,
+}
+
+/// The session of the current request. Clones share the same session.
+#[derive(Clone)]
+pub struct Session {
+    state: Arc<Mutex<SessionState>>,
+}
+
+impl Session {
+    fn new(id: Option<String>, record: Option<SessionRecord>) -> Self {
+        let (data, created) = match record {
+            Some(r) => (r.data, r.created),
+            None => (HashMap::new(), SystemTime::now()),
+        };
+        Session {
+            state: Arc::new(Mutex::new(SessionState {
+                id,
+                data,
+                created,
+                rotate: false,
+            })),
+        }
+    }
+
+    /// The id of a stored session, `None` for a session not saved yet.
+    pub fn id(&self) -> Option<String> {
+        self.state.lock().unwrap().id.clone()
+    }
+
+    pub fn get(&self, key: &str) -> Option<String> {
+        self.state.lock().unwrap().data.get(key).cloned()
+    }
+
+    pub fn insert(&self, key: &str, value: &str) {
+        let mut state = self.state.lock().unwrap();
+        state.data.insert(key.to_string(), value.to_string());
+    }
+
+    pub fn remove(&self, key: &str) {
+        self.state.lock().unwrap().data.remove(key);
+    }
+
+    pub fn clear(&self) {
+        self.state.lock().unwrap().data.clear();
+    }
+
+    /// Move the session to a new id, e.g. after login, so an id known
+    /// before authentication becomes useless.
+    pub fn rotate_id(&self) {
+        self.state.lock().unwrap().rotate = true;
+    }
+
+    /// Delete the session from the store and the client. Values inserted
+    /// afterwards start a new session.
+    pub fn destroy(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.data.clear();
+        state.created = SystemTime::now();
+        state.rotate = true;
+    }
+}
+
+impl FromRequest for Session {
+    fn from_request(req: &Request) -> Result<Self, Rejection> {
+        req.extension::<Session>().cloned().ok_or_else(|| {
+            Rejection::new(
+                StatusCode::InternalServerError,
+                "no session, the handler is not wrapped in a SessionHandler",
+            )
+        })
+    }
+}
+
+#[derive(Clone)]
+struct SessionConfig {
+    cookie_name: String,
+    idle_timeout: Duration,
+    absolute_timeout: Duration,
+    cookie: Cookie,
+}
+
+/// Middleware providing a `Session` to the handler it wraps.
+///
+/// By default the cookie is `session_id` with `Path=/`, `HttpOnly` and
+/// `SameSite=Lax`, sessions expire after 30 idle minutes and at most 24
+/// hours after creation.
+pub struct SessionHandler {
+    store: Arc<dyn SessionStore + Send + Sync>,
+    inner: Arc<dyn Handler + Send + Sync>,
+    config: SessionConfig,
+}
+
+impl SessionHandler {
+    pub fn new<H: Handler + Send + Sync + 'static>(
+        store: Arc<dyn SessionStore + Send + Sync>,
+        inner: H,
+    ) -> Self {
+        SessionHandler {
+            store,
+            inner: Arc::new(inner),
+            config: SessionConfig {
+                cookie_name: "session_id".to_string(),
+                idle_timeout: Duration::from_secs(30 * 60),
+                absolute_timeout: Duration::from_secs(24 * 60 * 60),
+                cookie: Cookie::new("", "")
+                    .path("/")
+                    .http_only(true)
+                    .same_site(SameSite::Lax),
+            },
+        }
+    }
+
+    pub fn cookie_name(mut self, name: &str) -> Self {
+        self.config.cookie_name = name.to_string();
+        self
+    }
+
+    /// Expire sessions not used for `timeout`.
+    pub fn idle_timeout(mut self, timeout: Duration) -> Self {
+        self.config.idle_timeout = timeout;
+        self
+    }
+
+    /// Expire sessions `timeout` after their creation, however active.
+    pub fn absolute_timeout(mut self, timeout: Duration) -> Self {
+        self.config.absolute_timeout = timeout;
+        self
+    }
+
+    /// Attributes of the session cookie; its name and value are ignored.
+    pub fn cookie(mut self, template: Cookie) -> Self {
+        self.config.cookie = template;
+        self
+    }
+
+    fn load(&self, id: &str) -> Option<SessionRecord> {
+        let record = self.store.load(id).ok()??;
+        let now = SystemTime::now();
+        let idle = now.duration_since(record.last_access).unwrap_or_default();
+        let age = now.duration_since(record.created).unwrap_or_default();
+        if idle > self.config.idle_timeout || age > self.config.absolute_timeout {
+            let _ = self.store.destroy(id);
+            return None;
+        }
+        Some(record)
+    }
+}
+
+fn session_cookie(config: &SessionConfig, value: &str) -> Cookie {
+    let mut c = config.cookie.clone();
+    c.set_name(&config.cookie_name);
+    c.set_value(value);
+    c
+}
+
+/// Save or drop `session` and set the cookie on `res` accordingly.
+fn finish(
+    store: &dyn SessionStore,
+    config: &SessionConfig,
+    session: &Session,
+    sent_id: Option<&str>,
+    res: &mut Response,
+) {
+    let mut state = session.state.lock().unwrap();
+    if state.rotate {
+        if let Some(id) = state.id.take() {
+            let _ = store.destroy(&id);
+        }
+    }
+    if state.data.is_empty() && state.id.is_none() {
+        if sent_id.is_some() {
+            let removal = session_cookie(config, "")
+                .max_age(Duration::from_secs(0))
+                .expires(UNIX_EPOCH);
+            res.headers.append(SET_COOKIE, &removal.to_string());
+        }
+        return;
+    }
+
+    let id = state.id.get_or_insert_with(generate_id).clone();
+    let now = SystemTime::now();
+    let expires = std::cmp::min(
+        now + config.idle_timeout,
+        state.created + config.absolute_timeout,
+    );
+    let record = SessionRecord {
+        data: state.data.clone(),
+        created: state.created,
+        last_access: now,
+    };
+    if store.save(&id, &record, expires).is_ok() && sent_id != Some(id.as_str()) {
+        let cookie = session_cookie(config, &id);
+        res.headers.append(SET_COOKIE, &cookie.to_string());
+    }
+}
+
+impl Handler for SessionHandler {
+    fn serve_http(
+        &self,
+        writer: &mut dyn ResponseWriter,
+        req: &Request,
+    ) -> Result<(), ServerError> {
+        let sent_id = req.cookie(&self.config.cookie_name);
+        let record = sent_id.as_deref().and_then(|id| self.load(id));
+        let id = record.as_ref().and(sent_id.clone());
+        let session = Session::new(id, record);
+
+        let store = self.store.clone();
+        let config = self.config.clone();
+        let handle = session.clone();
+        writer.on_send(Box::new(move |res| {
+            finish(&*store, &config, &handle, sent_id.as_deref(), res)
+        }));
+
+        let mut req = req.clone();
+        req.extensions.insert(session);
+        self.inner.serve_http(writer, &req)
+    }
+}
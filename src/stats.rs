@@ -0,0 +1,304 @@
+use crate::error::ServerError;
+use crate::header::{ContentType, HttpHeader};
+use crate::message::{Request, ResponseBody, ResponseWriter};
+use crate::server::Handler;
+use crate::status_code::StatusCode;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Upper bounds (in seconds) of the request latency histogram buckets.
+pub const LATENCY_BUCKETS: [f64; 11] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+/// Live counters shared between a `ThreadPool` and its workers.
+#[derive(Default, Debug)]
+pub(crate) struct PoolCounters {
+    pub(crate) active: AtomicUsize,
+    pub(crate) queued: AtomicUsize,
+    pub(crate) completed: AtomicU64,
+    pub(crate) panicked: AtomicU64,
+}
+
+impl PoolCounters {
+    pub(crate) fn snapshot(&self, workers: usize) -> PoolStats {
+        let active = self.active.load(Ordering::Relaxed);
+        PoolStats {
+            workers,
+            active,
+            idle: workers.saturating_sub(active),
+            queued: self.queued.load(Ordering::Relaxed),
+            completed: self.completed.load(Ordering::Relaxed),
+            panicked: self.panicked.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Point-in-time view of a `ThreadPool`.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct PoolStats {
+    pub workers: usize,
+    pub active: usize,
+    pub idle: usize,
+    pub queued: usize,
+    /// Jobs that returned.
+    pub completed: u64,
+    /// Jobs that panicked, not counted in `completed`.
+    pub panicked: u64,
+}
+
+/// Live statistics of a `Server` and the pool it runs on.
+///
+/// Obtained with `Server::stats` and safe to keep around after the server
+/// has been moved into `listen_and_serve`.
+#[derive(Debug)]
+pub struct Stats {
+    pool: Arc<PoolCounters>,
+    workers: usize,
+    open_connections: AtomicUsize,
+    connections: AtomicU64,
+    handler_panics: AtomicU64,
+    status_classes: [AtomicU64; 5],
+    bytes_in: AtomicU64,
+    bytes_out: AtomicU64,
+    latency_buckets: [AtomicU64; LATENCY_BUCKETS.len()],
+    latency_count: AtomicU64,
+    latency_sum_micros: AtomicU64,
+}
+
+impl Stats {
+    pub(crate) fn new(pool: Arc<PoolCounters>, workers: usize) -> Self {
+        Stats {
+            pool,
+            workers,
+            open_connections: AtomicUsize::new(0),
+            connections: AtomicU64::new(0),
+            handler_panics: AtomicU64::new(0),
+            status_classes: Default::default(),
+            bytes_in: AtomicU64::new(0),
+            bytes_out: AtomicU64::new(0),
+            latency_buckets: Default::default(),
+            latency_count: AtomicU64::new(0),
+            latency_sum_micros: AtomicU64::new(0),
+        }
+    }
+
+    pub(crate) fn connection_opened(&self) {
+        self.open_connections.fetch_add(1, Ordering::Relaxed);
+        self.connections.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn connection_closed(&self) {
+        self.open_connections.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn add_bytes_in(&self, n: u64) {
+        self.bytes_in.fetch_add(n, Ordering::Relaxed);
+    }
+
+    pub(crate) fn add_bytes_out(&self, n: u64) {
+        self.bytes_out.fetch_add(n, Ordering::Relaxed);
+    }
+
+    pub(crate) fn handler_panicked(&self) {
+        self.handler_panics.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record a finished request with its final status and latency.
+    pub(crate) fn record_request(&self, status: StatusCode, elapsed: Duration) {
+        let class = (status.as_num() / 100).clamp(1, 5) - 1;
+        self.status_classes[class].fetch_add(1, Ordering::Relaxed);
+
+        let secs = elapsed.as_secs_f64();
+        for (bucket, le) in self.latency_buckets.iter().zip(LATENCY_BUCKETS.iter()) {
+            if secs <= *le {
+                bucket.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        self.latency_count.fetch_add(1, Ordering::Relaxed);
+        self.latency_sum_micros
+            .fetch_add(elapsed.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    /// Take a snapshot of every counter.
+    pub fn snapshot(&self) -> StatsSnapshot {
+        let load = |c: &AtomicU64| c.load(Ordering::Relaxed);
+        let mut requests = [0; 5];
+        for (r, c) in requests.iter_mut().zip(self.status_classes.iter()) {
+            *r = load(c);
+        }
+        let mut buckets = [0; LATENCY_BUCKETS.len()];
+        for (b, c) in buckets.iter_mut().zip(self.latency_buckets.iter()) {
+            *b = load(c);
+        }
+        StatsSnapshot {
+            pool: self.pool.snapshot(self.workers),
+            open_connections: self.open_connections.load(Ordering::Relaxed),
+            connections: load(&self.connections),
+            handler_panics: load(&self.handler_panics),
+            requests,
+            bytes_in: load(&self.bytes_in),
+            bytes_out: load(&self.bytes_out),
+            latency_buckets: buckets,
+            latency_count: load(&self.latency_count),
+            latency_sum: Duration::from_micros(load(&self.latency_sum_micros)),
+        }
+    }
+}
+
+/// Point-in-time view of a `Stats`.
+#[derive(Clone, PartialEq, Debug)]
+pub struct StatsSnapshot {
+    pub pool: PoolStats,
+    pub open_connections: usize,
+    pub connections: u64,
+    /// Requests whose handler panicked.
+    pub handler_panics: u64,
+    /// Finished requests per status class, `requests[0]` is 1xx and `requests[4]` is 5xx.
+    pub requests: [u64; 5],
+    pub bytes_in: u64,
+    pub bytes_out: u64,
+    /// Cumulative counts matching `LATENCY_BUCKETS`.
+    pub latency_buckets: [u64; LATENCY_BUCKETS.len()],
+    pub latency_count: u64,
+    pub latency_sum: Duration,
+}
+
+impl StatsSnapshot {
+    /// Render the snapshot in the Prometheus text exposition format.
+    pub fn to_prometheus(&self) -> String {
+        let mut out = String::new();
+        let mut metric = |name: &str, kind: &str, help: &str, samples: &[(String, String)]| {
+            let _ = writeln!(out, "# HELP rust_server_{} {}", name, help);
+            let _ = writeln!(out, "# TYPE rust_server_{} {}", name, kind);
+            for (suffix, value) in samples {
+                let _ = writeln!(out, "rust_server_{}{} {}", name, suffix, value);
+            }
+        };
+        let plain = |v: String| vec![(String::new(), v)];
+
+        metric(
+            "workers",
+            "gauge",
+            "Worker threads in the pool by state.",
+            &[
+                (
+                    "{state=\"active\"}".to_string(),
+                    self.pool.active.to_string(),
+                ),
+                ("{state=\"idle\"}".to_string(), self.pool.idle.to_string()),
+            ],
+        );
+        metric(
+            "queue_depth",
+            "gauge",
+            "Jobs waiting for a free worker.",
+            &plain(self.pool.queued.to_string()),
+        );
+        metric(
+            "jobs_completed_total",
+            "counter",
+            "Jobs run to completion by the pool.",
+            &plain(self.pool.completed.to_string()),
+        );
+        metric(
+            "job_panics_total",
+            "counter",
+            "Jobs that panicked.",
+            &plain(self.pool.panicked.to_string()),
+        );
+        metric(
+            "open_connections",
+            "gauge",
+            "Connections currently being served.",
+            &plain(self.open_connections.to_string()),
+        );
+        metric(
+            "connections_total",
+            "counter",
+            "Connections accepted.",
+            &plain(self.connections.to_string()),
+        );
+        metric(
+            "handler_panics_total",
+            "counter",
+            "Requests whose handler panicked.",
+            &plain(self.handler_panics.to_string()),
+        );
+        let requests: Vec<_> = self
+            .requests
+            .iter()
+            .enumerate()
+            .map(|(i, n)| (format!("{{class=\"{}xx\"}}", i + 1), n.to_string()))
+            .collect();
+        metric(
+            "requests_total",
+            "counter",
+            "Requests served by status class.",
+            &requests,
+        );
+        metric(
+            "received_bytes_total",
+            "counter",
+            "Bytes read from clients.",
+            &plain(self.bytes_in.to_string()),
+        );
+        metric(
+            "sent_bytes_total",
+            "counter",
+            "Bytes written to clients.",
+            &plain(self.bytes_out.to_string()),
+        );
+        let mut latency: Vec<_> = LATENCY_BUCKETS
+            .iter()
+            .zip(self.latency_buckets.iter())
+            .map(|(le, n)| (format!("_bucket{{le=\"{}\"}}", le), n.to_string()))
+            .collect();
+        latency.push((
+            "_bucket{le=\"+Inf\"}".to_string(),
+            self.latency_count.to_string(),
+        ));
+        latency.push((
+            "_sum".to_string(),
+            self.latency_sum.as_secs_f64().to_string(),
+        ));
+        latency.push(("_count".to_string(), self.latency_count.to_string()));
+        metric(
+            "request_duration_seconds",
+            "histogram",
+            "Time spent handling a request and writing its response.",
+            &latency,
+        );
+        out
+    }
+}
+
+/// Serve `Stats` in the Prometheus text format.
+pub struct MetricsHandler {
+    stats: Arc<Stats>,
+}
+
+impl MetricsHandler {
+    pub fn new(stats: Arc<Stats>) -> Self {
+        MetricsHandler { stats }
+    }
+}
+
+impl Handler for MetricsHandler {
+    fn serve_http(
+        &self,
+        writer: &mut dyn ResponseWriter,
+        _req: &Request,
+    ) -> Result<(), ServerError> {
+        let body = self.stats.snapshot().to_prometheus();
+        writer.set_header(
+            HttpHeader::ContentType.as_str(),
+            &format!("{}; version=0.0.4", ContentType::TextPlain.as_str()),
+        );
+        writer.write(ResponseBody::BytesBody(body.into_bytes()));
+        writer.status(StatusCode::Ok);
+        Ok(())
+    }
+}
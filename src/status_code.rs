@@ -1,4 +1,4 @@
-use self::StatusCode::{Accepted, BadRequest, Continue, Created, Found, NotFound};
+use self::StatusCode::*;
 use std::fmt;
 use std::str::FromStr;
 use uncased;
@@ -6,64 +6,168 @@ use uncased;
 #[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
 pub enum StatusCode {
     Continue,
+    SwitchingProtocols,
     Ok,
     Created,
     Accepted,
+    NoContent,
+    MovedPermanently,
     Found,
+    SeeOther,
+    NotModified,
+    TemporaryRedirect,
+    PermanentRedirect,
     BadRequest,
+    Unauthorized,
+    Forbidden,
     NotFound,
+    MethodNotAllowed,
+    NotAcceptable,
+    RequestTimeout,
+    Conflict,
+    Gone,
+    LengthRequired,
+    PreconditionFailed,
+    PayloadTooLarge,
+    UnsupportedMediaType,
+    ExpectationFailed,
+    UnprocessableEntity,
+    TooManyRequests,
+    InternalServerError,
+    NotImplemented,
+    BadGateway,
+    ServiceUnavailable,
+    GatewayTimeout,
+    /// A code without a variant of its own, e.g. relayed from an upstream.
+    Other(u16),
 }
 
+const ALL: [StatusCode; 33] = [
+    Continue,
+    SwitchingProtocols,
+    StatusCode::Ok,
+    Created,
+    Accepted,
+    NoContent,
+    MovedPermanently,
+    Found,
+    SeeOther,
+    NotModified,
+    TemporaryRedirect,
+    PermanentRedirect,
+    BadRequest,
+    Unauthorized,
+    Forbidden,
+    NotFound,
+    MethodNotAllowed,
+    NotAcceptable,
+    RequestTimeout,
+    Conflict,
+    Gone,
+    LengthRequired,
+    PreconditionFailed,
+    PayloadTooLarge,
+    UnsupportedMediaType,
+    ExpectationFailed,
+    UnprocessableEntity,
+    TooManyRequests,
+    InternalServerError,
+    NotImplemented,
+    BadGateway,
+    ServiceUnavailable,
+    GatewayTimeout,
+];
+
 impl StatusCode {
     pub fn as_str(&self) -> &'static str {
         match self {
             Continue => "Continue",
+            SwitchingProtocols => "Switching Protocols",
             StatusCode::Ok => "Ok",
             Created => "Created",
             Accepted => "Accepted",
+            NoContent => "No Content",
+            MovedPermanently => "Moved Permanently",
             Found => "Found",
+            SeeOther => "See Other",
+            NotModified => "Not Modified",
+            TemporaryRedirect => "Temporary Redirect",
+            PermanentRedirect => "Permanent Redirect",
             BadRequest => "Bad Request",
+            Unauthorized => "Unauthorized",
+            Forbidden => "Forbidden",
             NotFound => "Not Found",
+            MethodNotAllowed => "Method Not Allowed",
+            NotAcceptable => "Not Acceptable",
+            RequestTimeout => "Request Timeout",
+            Conflict => "Conflict",
+            Gone => "Gone",
+            LengthRequired => "Length Required",
+            PreconditionFailed => "Precondition Failed",
+            PayloadTooLarge => "Payload Too Large",
+            UnsupportedMediaType => "Unsupported Media Type",
+            ExpectationFailed => "Expectation Failed",
+            UnprocessableEntity => "Unprocessable Entity",
+            TooManyRequests => "Too Many Requests",
+            InternalServerError => "Internal Server Error",
+            NotImplemented => "Not Implemented",
+            BadGateway => "Bad Gateway",
+            ServiceUnavailable => "Service Unavailable",
+            GatewayTimeout => "Gateway Timeout",
+            Other(_) => "",
         }
     }
     pub fn as_num(&self) -> usize {
         match self {
             Continue => 100,
+            SwitchingProtocols => 101,
             StatusCode::Ok => 200,
             Created => 201,
             Accepted => 202,
+            NoContent => 204,
+            MovedPermanently => 301,
             Found => 302,
+            SeeOther => 303,
+            NotModified => 304,
+            TemporaryRedirect => 307,
+            PermanentRedirect => 308,
             BadRequest => 400,
+            Unauthorized => 401,
+            Forbidden => 403,
             NotFound => 404,
+            MethodNotAllowed => 405,
+            NotAcceptable => 406,
+            RequestTimeout => 408,
+            Conflict => 409,
+            Gone => 410,
+            LengthRequired => 411,
+            PreconditionFailed => 412,
+            PayloadTooLarge => 413,
+            UnsupportedMediaType => 415,
+            ExpectationFailed => 417,
+            UnprocessableEntity => 422,
+            TooManyRequests => 429,
+            InternalServerError => 500,
+            NotImplemented => 501,
+            BadGateway => 502,
+            ServiceUnavailable => 503,
+            GatewayTimeout => 504,
+            Other(code) => *code as usize,
         }
     }
+    #[allow(clippy::result_unit_err)]
     pub fn from_num(code: usize) -> Result<Self, ()> {
-        match code {
-            x if x == Continue.as_num() => Ok(Continue),
-            x if x == StatusCode::Ok.as_num() => Ok(StatusCode::Ok),
-            x if x == Created.as_num() => Ok(Created),
-            x if x == Accepted.as_num() => Ok(Accepted),
-            x if x == Found.as_num() => Ok(Found),
-            x if x == BadRequest.as_num() => Ok(BadRequest),
-            x if x == NotFound.as_num() => Ok(NotFound),
-            _ => Err(()),
-        }
+        ALL.iter().find(|x| x.as_num() == code).copied().ok_or(())
     }
 }
 
 impl FromStr for StatusCode {
     type Err = ();
     fn from_str(s: &str) -> Result<StatusCode, ()> {
-        match s {
-            x if uncased::eq(x, Continue.as_str()) => Ok(Continue),
-            x if uncased::eq(x, StatusCode::Ok.as_str()) => Ok(StatusCode::Ok),
-            x if uncased::eq(x, Created.as_str()) => Ok(Created),
-            x if uncased::eq(x, Accepted.as_str()) => Ok(Accepted),
-            x if uncased::eq(x, Found.as_str()) => Ok(Found),
-            x if uncased::eq(x, BadRequest.as_str()) => Ok(BadRequest),
-            x if uncased::eq(x, NotFound.as_str()) => Ok(NotFound),
-            _ => Err(()),
-        }
+        ALL.iter()
+            .find(|x| uncased::eq(s, x.as_str()))
+            .copied()
+            .ok_or(())
     }
 }
 
@@ -0,0 +1,275 @@
+//! Running handlers without a server.
+//!
+//! `TestClient` builds requests, passes them to a handler, usually a whole
+//! `DefaultServeMux`, and captures the response in memory. No socket or
+//! thread is involved, so tests can run in parallel and inspect exactly
+//! what the handler produced.
+//!
+//! ```ignore
+//! let client = TestClient::new(Arc::new(mux));
+//! client
+//!     .post("/users")
+//!     .json(r#"{"name":"ferris"}"#)
+//!     .send()
+//!     .assert_status(StatusCode::Created)
+//!     .assert_header("Location", "/users/1");
+//! ```
+use crate::cookie::COOKIE;
+use crate::error::ServerError;
+use crate::extensions::Extensions;
+use crate::header::HttpHeader;
+use crate::message::{BufferedWriter, Request, Response};
+use crate::method::Method;
+use crate::server::Handler;
+use crate::status_code::StatusCode;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::ops::Deref;
+use std::sync::Arc;
+
+/// Sends requests to a handler in memory.
+pub struct TestClient {
+    handler: Arc<dyn Handler + Send + Sync>,
+    state: Arc<Extensions>,
+    remote_addr: Option<SocketAddr>,
+}
+
+impl TestClient {
+    /// A client for `handler`. Requests come from `127.0.0.1` unless told
+    /// otherwise.
+    pub fn new(handler: Arc<dyn Handler + Send + Sync>) -> Self {
+        TestClient {
+            handler,
+            state: Arc::new(Extensions::new()),
+            remote_addr: Some((Ipv4Addr::LOCALHOST, 0).into()),
+        }
+    }
+
+    /// Make `value` available through `Request::state`, like
+    /// `Server::set_state`.
+    pub fn set_state<T: Send + Sync + 'static>(&mut self, value: T) {
+        Arc::make_mut(&mut self.state).insert(value);
+    }
+
+    /// The address requests come from by default; `None` as for requests
+    /// over a Unix socket.
+    pub fn set_remote_addr(&mut self, addr: Option<SocketAddr>) {
+        self.remote_addr = addr;
+    }
+
+    /// A request with `method` for `target`, a path with an optional query.
+    pub fn request(&self, method: Method, target: &str) -> TestRequest<'_> {
+        let mut req = Request::new();
+        req.method = method;
+        req.path = target.to_string();
+        req.remote_addr = self.remote_addr;
+        req.app_state = self.state.clone();
+        TestRequest {
+            client: self,
+            req,
+            body: None,
+        }
+    }
+
+    pub fn get(&self, target: &str) -> TestRequest<'_> {
+        self.request(Method::Get, target)
+    }
+
+    pub fn head(&self, target: &str) -> TestRequest<'_> {
+        self.request(Method::Head, target)
+    }
+
+    pub fn post(&self, target: &str) -> TestRequest<'_> {
+        self.request(Method::Post, target)
+    }
+
+    pub fn put(&self, target: &str) -> TestRequest<'_> {
+        self.request(Method::Put, target)
+    }
+
+    pub fn patch(&self, target: &str) -> TestRequest<'_> {
+        self.request(Method::Patch, target)
+    }
+
+    pub fn delete(&self, target: &str) -> TestRequest<'_> {
+        self.request(Method::Delete, target)
+    }
+
+    pub fn options(&self, target: &str) -> TestRequest<'_> {
+        self.request(Method::Options, target)
+    }
+}
+
+/// A request being built by a `TestClient`.
+pub struct TestRequest<'a> {
+    client: &'a TestClient,
+    req: Request,
+    body: Option<Vec<u8>>,
+}
+
+impl<'a> TestRequest<'a> {
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.req.insert_header(name, value);
+        self
+    }
+
+    /// Add a cookie to the `Cookie` header.
+    pub fn cookie(mut self, name: &str, value: &str) -> Self {
+        let cookie = match self.req.header(COOKIE) {
+            Some(cookies) => format!("{}; {}={}", cookies, name, value),
+            None => format!("{}={}", name, value),
+        };
+        self.req.insert_header(COOKIE, &cookie);
+        self
+    }
+
+    /// Send `body`, with a `Content-Length` unless a length or coding was
+    /// set as a header.
+    pub fn body<B: Into<Vec<u8>>>(mut self, body: B) -> Self {
+        self.body = Some(body.into());
+        self
+    }
+
+    /// Send `body` as `application/json`.
+    pub fn json(self, body: &str) -> Self {
+        self.header(HttpHeader::ContentType.as_str(), "application/json")
+            .body(body)
+    }
+
+    /// Send `pairs` as an `application/x-www-form-urlencoded` body.
+    pub fn form(self, pairs: &[(&str, &str)]) -> Self {
+        let body: Vec<String> = pairs
+            .iter()
+            .map(|(k, v)| {
+                format!(
+                    "{}={}",
+                    crate::message::percent_encode(k),
+                    crate::message::percent_encode(v)
+                )
+            })
+            .collect();
+        self.header(
+            HttpHeader::ContentType.as_str(),
+            "application/x-www-form-urlencoded",
+        )
+        .body(body.join("&"))
+    }
+
+    pub fn remote_addr(mut self, addr: SocketAddr) -> Self {
+        self.req.remote_addr = Some(addr);
+        self
+    }
+
+    /// Send the request as if over TLS.
+    pub fn secure(mut self) -> Self {
+        self.req.secure = true;
+        self
+    }
+
+    /// Run the handler and return what it responded. A response the
+    /// handler did not send is sent for it, as by the server.
+    pub fn send(self) -> TestResponse {
+        let TestRequest {
+            client,
+            mut req,
+            body,
+        } = self;
+        if let Some(body) = body {
+            let framed = req.header(HttpHeader::ContentLength.as_str()).is_some()
+                || req.header("Transfer-Encoding").is_some();
+            if !framed {
+                req.insert_header(HttpHeader::ContentLength.as_str(), &body.len().to_string());
+            }
+            req.set_body(body);
+        }
+        let mut writer = BufferedWriter::new();
+        let result = client.handler.serve_http(&mut writer, &req);
+        let mut res = writer.finish();
+        if req.method == Method::Head {
+            res.content_length = res.body_len() as u64;
+            res.body = None;
+        }
+        TestResponse { res, result }
+    }
+}
+
+/// The response to a `TestRequest`, with assertions that panic with the
+/// response in the message. It derefs to the `Response`.
+#[derive(Debug)]
+pub struct TestResponse {
+    res: Response,
+    result: Result<(), ServerError>,
+}
+
+impl TestResponse {
+    /// What the handler returned.
+    pub fn result(&self) -> &Result<(), ServerError> {
+        &self.result
+    }
+
+    pub fn into_response(self) -> Response {
+        self.res
+    }
+
+    #[track_caller]
+    pub fn assert_status(&self, status: StatusCode) -> &Self {
+        assert_eq!(
+            self.res.status_code, status,
+            "unexpected status: {:?}",
+            self.res
+        );
+        self
+    }
+
+    /// Assert that header `name` has `value`.
+    #[track_caller]
+    pub fn assert_header(&self, name: &str, value: &str) -> &Self {
+        assert_eq!(
+            self.res.header(name),
+            Some(value),
+            "unexpected {} header: {:?}",
+            name,
+            self.res
+        );
+        self
+    }
+
+    #[track_caller]
+    pub fn assert_no_header(&self, name: &str) -> &Self {
+        assert_eq!(
+            self.res.header(name),
+            None,
+            "unexpected {} header: {:?}",
+            name,
+            self.res
+        );
+        self
+    }
+
+    #[track_caller]
+    pub fn assert_body(&self, body: &str) -> &Self {
+        assert_eq!(self.res.text(), body, "unexpected body");
+        self
+    }
+
+    #[track_caller]
+    pub fn assert_body_contains(&self, part: &str) -> &Self {
+        let text = self.res.text();
+        assert!(text.contains(part), "{:?} not in body {:?}", part, text);
+        self
+    }
+
+    /// Assert that the handler returned an error.
+    #[track_caller]
+    pub fn assert_err(&self) -> &Self {
+        assert!(self.result.is_err(), "handler succeeded: {:?}", self.res);
+        self
+    }
+}
+
+impl Deref for TestResponse {
+    type Target = Response;
+
+    fn deref(&self) -> &Response {
+        &self.res
+    }
+}
@@ -0,0 +1,170 @@
+//! TLS on rustls.
+//!
+//! `TlsConfig` holds the certificate chain and private key given to
+//! `Server::set_tls`. The server offers `h2` and `http/1.1` with ALPN, or
+//! only `http/1.1` when HTTP/2 is disabled.
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::{ServerConfig, ServerConnection};
+use std::fs;
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// How long a client may take to complete the handshake.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+fn invalid(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// Certificate and key for serving TLS.
+#[derive(Clone)]
+pub struct TlsConfig {
+    config: Arc<ServerConfig>,
+}
+
+impl TlsConfig {
+    /// From a PEM certificate chain, leaf first, and a PEM private key
+    /// (PKCS#8, PKCS#1 or SEC1).
+    pub fn from_pem(cert_chain: &[u8], key: &[u8]) -> io::Result<Self> {
+        let certs: Vec<CertificateDer<'static>> =
+            rustls_pemfile::certs(&mut &cert_chain[..]).collect::<Result<_, _>>()?;
+        if certs.is_empty() {
+            return Err(invalid("no certificate found".to_string()));
+        }
+        let key: PrivateKeyDer<'static> = rustls_pemfile::private_key(&mut &key[..])?
+            .ok_or_else(|| invalid("no private key found".to_string()))?;
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let config = ServerConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()
+            .map_err(|e| invalid(e.to_string()))?
+            .with_no_client_auth()
+            .with_single_cert(certs, key)
+            .map_err(|e| invalid(e.to_string()))?;
+        Ok(TlsConfig {
+            config: Arc::new(config),
+        })
+    }
+
+    pub fn from_pem_files<P: AsRef<Path>, Q: AsRef<Path>>(
+        cert_chain: P,
+        key: Q,
+    ) -> io::Result<Self> {
+        TlsConfig::from_pem(&fs::read(cert_chain)?, &fs::read(key)?)
+    }
+
+    /// This configuration offering `h2` with ALPN or not.
+    pub(crate) fn with_http2(&self, http2: bool) -> Self {
+        let mut config = (*self.config).clone();
+        config.alpn_protocols = if http2 {
+            vec![b"h2".to_vec(), b"http/1.1".to_vec()]
+        } else {
+            vec![b"http/1.1".to_vec()]
+        };
+        TlsConfig {
+            config: Arc::new(config),
+        }
+    }
+
+    /// Run the server side of the handshake on `tcp`.
+    pub(crate) fn accept(&self, tcp: TcpStream) -> io::Result<TlsStream> {
+        let mut conn =
+            ServerConnection::new(self.config.clone()).map_err(|e| invalid(e.to_string()))?;
+        tcp.set_read_timeout(Some(HANDSHAKE_TIMEOUT))?;
+        while conn.is_handshaking() {
+            conn.complete_io(&mut &tcp)?;
+        }
+        tcp.set_read_timeout(None)?;
+        Ok(TlsStream {
+            inner: Arc::new(Inner {
+                conn: Mutex::new(conn),
+                tcp,
+            }),
+        })
+    }
+}
+
+struct Inner {
+    conn: Mutex<ServerConnection>,
+    tcp: TcpStream,
+}
+
+impl Inner {
+    fn flush_tls(&self, conn: &mut ServerConnection) -> io::Result<()> {
+        while conn.wants_write() {
+            conn.write_tls(&mut &self.tcp)?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Inner {
+    fn drop(&mut self) {
+        if let Ok(mut conn) = self.conn.lock() {
+            conn.send_close_notify();
+            let _ = self.flush_tls(&mut conn);
+        }
+    }
+}
+
+/// A server-side TLS connection. Clones share the session, so one thread
+/// can read while another writes; reads never hold the session lock while
+/// waiting for the network.
+#[derive(Clone)]
+pub(crate) struct TlsStream {
+    inner: Arc<Inner>,
+}
+
+impl TlsStream {
+    pub(crate) fn tcp(&self) -> &TcpStream {
+        &self.inner.tcp
+    }
+
+    pub(crate) fn alpn_protocol(&self) -> Option<Vec<u8>> {
+        let conn = self.inner.conn.lock().unwrap();
+        conn.alpn_protocol().map(|p| p.to_vec())
+    }
+}
+
+impl Read for TlsStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut raw = [0; 16 * 1024];
+        loop {
+            {
+                let mut conn = self.inner.conn.lock().unwrap();
+                match conn.reader().read(buf) {
+                    Err(e) if e.kind() == io::ErrorKind::WouldBlock => {}
+                    result => return result,
+                }
+                self.inner.flush_tls(&mut conn)?;
+            }
+            let n = (&self.inner.tcp).read(&mut raw)?;
+            if n == 0 {
+                return Ok(0);
+            }
+            let mut conn = self.inner.conn.lock().unwrap();
+            let mut rest = &raw[..n];
+            while !rest.is_empty() {
+                conn.read_tls(&mut rest)?;
+                conn.process_new_packets()
+                    .map_err(|e| invalid(e.to_string()))?;
+            }
+        }
+    }
+}
+
+impl Write for TlsStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut conn = self.inner.conn.lock().unwrap();
+        let n = conn.writer().write(buf)?;
+        self.inner.flush_tls(&mut conn)?;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let mut conn = self.inner.conn.lock().unwrap();
+        self.inner.flush_tls(&mut conn)
+    }
+}
@@ -0,0 +1,146 @@
+//! The byte stream under a client connection.
+use crate::http2::frame::PREFACE;
+#[cfg(feature = "tls")]
+use crate::tls::TlsStream;
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpStream};
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+use std::thread;
+use std::time::{Duration, Instant};
+
+pub(crate) enum Transport {
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(UnixStream),
+    #[cfg(feature = "tls")]
+    Tls(TlsStream),
+}
+
+impl Transport {
+    /// The client's address; an error for Unix sockets, which have none.
+    pub(crate) fn peer_addr(&self) -> io::Result<SocketAddr> {
+        match self {
+            Transport::Tcp(s) => s.peer_addr(),
+            #[cfg(unix)]
+            Transport::Unix(_) => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "no address for a Unix socket peer",
+            )),
+            #[cfg(feature = "tls")]
+            Transport::Tls(s) => s.tcp().peer_addr(),
+        }
+    }
+
+    /// Whether the connection is encrypted.
+    pub(crate) fn is_secure(&self) -> bool {
+        match self {
+            #[cfg(feature = "tls")]
+            Transport::Tls(_) => true,
+            _ => false,
+        }
+    }
+
+    pub(crate) fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        match self {
+            Transport::Tcp(s) => s.set_read_timeout(timeout),
+            #[cfg(unix)]
+            Transport::Unix(s) => s.set_read_timeout(timeout),
+            #[cfg(feature = "tls")]
+            Transport::Tls(s) => s.tcp().set_read_timeout(timeout),
+        }
+    }
+
+    /// Whether the client speaks HTTP/2: it negotiated `h2` with ALPN or,
+    /// without TLS, opened with the connection preface. Nothing is consumed,
+    /// which Unix sockets cannot do, so their clients need `Upgrade: h2c`.
+    pub(crate) fn is_http2(&self) -> bool {
+        match self {
+            Transport::Tcp(s) => starts_with_preface(s),
+            #[cfg(unix)]
+            Transport::Unix(_) => false,
+            #[cfg(feature = "tls")]
+            Transport::Tls(s) => s.alpn_protocol().as_deref() == Some(&b"h2"[..]),
+        }
+    }
+
+    pub(crate) fn try_clone(&self) -> io::Result<Transport> {
+        match self {
+            Transport::Tcp(s) => s.try_clone().map(Transport::Tcp),
+            #[cfg(unix)]
+            Transport::Unix(s) => s.try_clone().map(Transport::Unix),
+            #[cfg(feature = "tls")]
+            Transport::Tls(s) => Ok(Transport::Tls(s.clone())),
+        }
+    }
+
+    /// Separate halves for a reading and a writing thread.
+    pub(crate) fn split(self) -> io::Result<(Box<dyn Read + Send>, Box<dyn Write + Send>)> {
+        match self {
+            Transport::Tcp(s) => Ok((Box::new(s.try_clone()?), Box::new(s))),
+            #[cfg(unix)]
+            Transport::Unix(s) => Ok((Box::new(s.try_clone()?), Box::new(s))),
+            #[cfg(feature = "tls")]
+            Transport::Tls(s) => Ok((Box::new(s.clone()), Box::new(s))),
+        }
+    }
+}
+
+/// Peek until the bytes read either diverge from the preface or are
+/// enough to tell it apart from an HTTP/1 request line.
+fn starts_with_preface(s: &TcpStream) -> bool {
+    // "PRI" is no HTTP/1 method; anything shorter could still be "PUT"
+    const DISTINCT: usize = 3;
+    let deadline = Instant::now() + Duration::from_secs(1);
+    let mut buf = [0; DISTINCT];
+    loop {
+        let n = match s.peek(&mut buf) {
+            Ok(n) => n,
+            Err(_) => return false,
+        };
+        if n == 0 || buf[..n] != PREFACE[..n] {
+            return false;
+        }
+        if n == DISTINCT {
+            return true;
+        }
+        if Instant::now() > deadline {
+            return false;
+        }
+        thread::sleep(Duration::from_millis(1));
+    }
+}
+
+impl Read for Transport {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Transport::Tcp(s) => s.read(buf),
+            #[cfg(unix)]
+            Transport::Unix(s) => s.read(buf),
+            #[cfg(feature = "tls")]
+            Transport::Tls(s) => s.read(buf),
+        }
+    }
+}
+
+impl Write for Transport {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Transport::Tcp(s) => s.write(buf),
+            #[cfg(unix)]
+            Transport::Unix(s) => s.write(buf),
+            #[cfg(feature = "tls")]
+            Transport::Tls(s) => s.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Transport::Tcp(s) => s.flush(),
+            #[cfg(unix)]
+            Transport::Unix(s) => s.flush(),
+            #[cfg(feature = "tls")]
+            Transport::Tls(s) => s.flush(),
+        }
+    }
+}
@@ -1,3 +1,6 @@
+use crate::stats::{PoolCounters, PoolStats};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::Ordering;
 use std::sync::{mpsc, Arc, Mutex};
 use std::thread;
 
@@ -12,12 +15,11 @@ impl<F: FnOnce()> FnBox for F {
 }
 
 struct Worker {
-    id: u64,
     thread: Option<thread::JoinHandle<()>>,
 }
 
 impl Worker {
-    fn new(id: u64, receiver: Arc<Mutex<mpsc::Receiver<Message>>>) -> Worker {
+    fn new(receiver: Arc<Mutex<mpsc::Receiver<Message>>>, counters: Arc<PoolCounters>) -> Worker {
         let thread = thread::spawn(move || loop {
             let message = receiver
                 .lock()
@@ -32,17 +34,21 @@ impl Worker {
 
             match message {
                 Message::NewJob(job) => {
-                    println!("Worker {} got a job; executing.", id);
-                    job.call_box();
-                }
-                Message::Terminate => {
-                    println!("Worker {} was told to terminate", id);
-                    break;
+                    counters.queued.fetch_sub(1, Ordering::Relaxed);
+                    counters.active.fetch_add(1, Ordering::Relaxed);
+                    // a panicking job must not take the worker down with it
+                    let finished = panic::catch_unwind(AssertUnwindSafe(|| job.call_box()));
+                    counters.active.fetch_sub(1, Ordering::Relaxed);
+                    let counter = match finished {
+                        Ok(()) => &counters.completed,
+                        Err(_) => &counters.panicked,
+                    };
+                    counter.fetch_add(1, Ordering::Relaxed);
                 }
+                Message::Terminate => break,
             }
         });
         Worker {
-            id,
             thread: Some(thread),
         }
     }
@@ -61,6 +67,7 @@ enum Message {
 pub struct ThreadPool {
     workers: Vec<Worker>,
     sender: Arc<Mutex<mpsc::Sender<Message>>>,
+    counters: Arc<PoolCounters>,
 }
 
 impl ThreadPool {
@@ -72,29 +79,42 @@ impl ThreadPool {
     ///
     /// The `new` function will panic if the size is zero
     pub fn new(size: usize) -> Result<ThreadPool, PoolCreationErr> {
-        if size <= 0 {
+        if size == 0 {
             return Err(PoolCreationErr);
         }
 
         let (sender, receiver) = mpsc::channel();
         let receiver = Arc::new(Mutex::new(receiver));
 
+        let counters = Arc::new(PoolCounters::default());
+
         let mut workers = Vec::with_capacity(size);
-        for i in 0..size {
-            workers.push(Worker::new(i as u64, Arc::clone(&receiver)));
+        for _ in 0..size {
+            workers.push(Worker::new(Arc::clone(&receiver), Arc::clone(&counters)));
         }
 
         Ok(ThreadPool {
             workers,
             sender: Arc::new(Mutex::new(sender)),
+            counters,
         })
     }
 
+    /// Take a snapshot of the pool's live statistics.
+    pub fn stats(&self) -> PoolStats {
+        self.counters.snapshot(self.workers.len())
+    }
+
+    pub(crate) fn counters(&self) -> Arc<PoolCounters> {
+        Arc::clone(&self.counters)
+    }
+
     pub fn execute<F>(&self, f: F)
     where
         F: FnOnce() + Send + 'static,
     {
         let job = Box::new(f);
+        self.counters.queued.fetch_add(1, Ordering::Relaxed);
         self.sender
             .lock()
             .unwrap()
@@ -105,8 +125,6 @@ impl ThreadPool {
 
 impl Drop for ThreadPool {
     fn drop(&mut self) {
-        println!("Setting terminate message to all workers.");
-
         for _ in &mut self.workers {
             self.sender
                 .lock()
@@ -115,13 +133,13 @@ impl Drop for ThreadPool {
                 .unwrap();
         }
 
-        println!("Shutting down all workers.");
-
         for worker in &mut self.workers {
-            println!("Shutting down worker {}", worker.id);
-
             if let Some(thread) = worker.thread.take() {
-                thread.join().unwrap();
+                // the pool can go away with the last job of a server shut
+                // down while connections were still open
+                if thread.thread().id() != thread::current().id() {
+                    thread.join().unwrap();
+                }
             }
         }
     }
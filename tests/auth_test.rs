@@ -0,0 +1,154 @@
+#![cfg(feature = "auth")]
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use rust_server::auth::{BasicAuth, BearerAuth, Htpasswd, JwtVerifier, Principal, TokenValidator};
+use rust_server::client::Client;
+use rust_server::error::ServerError;
+use rust_server::extract::ExtractHandler;
+use rust_server::message::ResponseWriter;
+use rust_server::server::{DefaultServeMux, Server};
+use serde_json::json;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const SECRET: &[u8] = b"an HS256 secret of reasonable length";
+
+fn basic(user: &str, password: &str) -> String {
+    let credentials = format!("{}:{}", user, password);
+    format!("Basic {}", STANDARD.encode(credentials))
+}
+
+fn whoami(p: Principal, w: &mut dyn ResponseWriter) -> Result<(), ServerError> {
+    w.text(&p.name);
+    Ok(())
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+#[test]
+fn basic_and_bearer() {
+    let htpasswd = format!(
+        "# users\nalice:{}\nbob:{{SHA}}W6ph5Mm5Pz8GgiULbPgzG37mj9g=\n",
+        bcrypt::hash("wonderland", 4).unwrap()
+    );
+    let users = Htpasswd::parse(&htpasswd).unwrap();
+    assert_eq!(users.len(), 2);
+
+    let mut m = DefaultServeMux::new();
+    m.get(
+        "/basic",
+        BasicAuth::new("admin area", users, ExtractHandler::new(whoami)),
+    );
+    m.get(
+        "/bearer",
+        BearerAuth::new(
+            "api",
+            JwtVerifier::hs256(SECRET),
+            ExtractHandler::new(whoami),
+        ),
+    );
+    let s = Server::new(2, "127.0.0.1:0", Arc::new(m)).unwrap();
+    let server = s.start().unwrap();
+    let addr = server.local_addr().unwrap();
+
+    let client = Client::new();
+    let url = |p: &str| format!("http://{}{}", addr, p);
+
+    let res = client.get(&url("/basic")).send().unwrap();
+    assert_eq!(res.status(), 401);
+    assert_eq!(
+        res.header("www-authenticate").unwrap(),
+        "Basic realm=\"admin area\", charset=\"UTF-8\""
+    );
+    let res = client
+        .get(&url("/basic"))
+        .header("Authorization", &basic("alice", "wonderland"))
+        .send()
+        .unwrap();
+    assert_eq!(res.status(), 200);
+    assert_eq!(res.text(), "alice");
+    let res = client
+        .get(&url("/basic"))
+        .header("Authorization", &basic("bob", "password"))
+        .send()
+        .unwrap();
+    assert_eq!(res.text(), "bob");
+    let res = client
+        .get(&url("/basic"))
+        .header("Authorization", &basic("alice", "password"))
+        .send()
+        .unwrap();
+    assert_eq!(res.status(), 401);
+
+    let res = client.get(&url("/bearer")).send().unwrap();
+    assert_eq!(res.status(), 401);
+    assert_eq!(
+        res.header("www-authenticate").unwrap(),
+        "Bearer realm=\"api\""
+    );
+
+    let token = JwtVerifier::hs256(SECRET).sign(&json!({"sub": "carol", "exp": now() + 60}));
+    let res = client
+        .get(&url("/bearer"))
+        .bearer_auth(&token)
+        .send()
+        .unwrap();
+    assert_eq!(res.status(), 200);
+    assert_eq!(res.text(), "carol");
+
+    let forged = JwtVerifier::hs256(b"another secret").sign(&json!({"sub": "carol"}));
+    let res = client
+        .get(&url("/bearer"))
+        .bearer_auth(&forged)
+        .send()
+        .unwrap();
+    assert_eq!(res.status(), 401);
+    assert_eq!(
+        res.header("www-authenticate").unwrap(),
+        "Bearer realm=\"api\", error=\"invalid_token\", error_description=\"bad signature\""
+    );
+}
+
+#[test]
+fn jwt_claims() {
+    let verifier = JwtVerifier::hs256(SECRET).issuer("me").audience("you");
+    let ok = verifier.sign(&json!({"sub": "x", "iss": "me", "aud": ["them", "you"]}));
+    let principal = verifier.validate(&ok).unwrap();
+    assert_eq!(principal.claims["iss"], "me");
+
+    let expired = verifier.sign(&json!({"iss": "me", "aud": "you", "exp": now() - 10}));
+    assert_eq!(
+        verifier.validate(&expired).unwrap_err().message(),
+        "token expired"
+    );
+    let lenient = JwtVerifier::hs256(SECRET).leeway(Duration::from_secs(30));
+    assert!(lenient.validate(&expired).is_ok());
+
+    let early = verifier.sign(&json!({"iss": "me", "aud": "you", "nbf": now() + 60}));
+    assert!(verifier.validate(&early).is_err());
+    let stranger = verifier.sign(&json!({"iss": "someone", "aud": "you"}));
+    assert_eq!(
+        verifier.validate(&stranger).unwrap_err().message(),
+        "wrong issuer"
+    );
+
+    // alg: none must never be accepted
+    let unsigned = format!(
+        "{}.{}.",
+        "eyJhbGciOiJub25lIn0",
+        ok.split('.').nth(1).unwrap()
+    );
+    assert!(verifier.validate(&unsigned).is_err());
+    assert!(verifier.validate("not a token").is_err());
+}
+
+#[test]
+fn htpasswd_rejects_unknown_hashes() {
+    let err = Htpasswd::parse("alice:$apr1$abc$def\n").err().unwrap();
+    assert!(err.to_string().contains("line 1"));
+}
@@ -0,0 +1,157 @@
+use rust_server::message::RequestBody;
+use rust_server::server::{DefaultServeMux, HandlerFunc, Server};
+use rust_server::status_code::StatusCode;
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+fn send(addr: SocketAddr, request: &[u8]) -> String {
+    let mut stream = TcpStream::connect(addr).unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    stream.write_all(request).unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    response
+}
+
+#[test]
+fn streamed_bodies() {
+    let mut m = DefaultServeMux::new();
+    m.post(
+        "/sum",
+        HandlerFunc::new(|req, w| {
+            // read in small pieces, never holding the whole body
+            let mut body = req.body_reader();
+            let mut buf = [0; 4096];
+            let (mut len, mut sum) = (0u64, 0u64);
+            loop {
+                match body.read(&mut buf) {
+                    Ok(0) => break,
+                    Ok(n) => {
+                        len += n as u64;
+                        sum += buf[..n].iter().map(|&b| u64::from(b)).sum::<u64>();
+                    }
+                    Err(_) => {
+                        w.status(StatusCode::PayloadTooLarge);
+                        return Ok(());
+                    }
+                }
+            }
+            assert!(req.body.is_none());
+            w.text(&format!("{} {}", len, sum));
+            Ok(())
+        }),
+    );
+    m.post(
+        "/echo",
+        HandlerFunc::new(|req, w| {
+            w.text(&String::from_utf8_lossy(req.body_bytes()));
+            Ok(())
+        }),
+    );
+    m.post(
+        "/try",
+        HandlerFunc::new(|req, w| {
+            match req.try_body_bytes() {
+                Ok(body) => w.text(&body.len().to_string()),
+                Err(e) => w.text(&e.to_string()),
+            }
+            Ok(())
+        }),
+    );
+    let mut s = Server::new(2, "127.0.0.1:0", Arc::new(m)).unwrap();
+    s.set_max_body_size(4 << 20);
+    let server = s.start().unwrap();
+    let addr = server.local_addr().unwrap();
+
+    let body: Vec<u8> = (0..3 << 20).map(|i| (i % 251) as u8).collect();
+    let sum: u64 = body.iter().map(|&b| u64::from(b)).sum();
+    let mut request = format!(
+        "POST /sum HTTP/1.1\r\nContent-Length: {}\r\n\r\n",
+        body.len()
+    )
+    .into_bytes();
+    request.extend_from_slice(&body);
+    let response = send(addr, &request);
+    assert!(
+        response.ends_with(&format!("\r\n\r\n{} {}", body.len(), sum)),
+        "{}",
+        response
+    );
+
+    let response = send(
+        addr,
+        b"POST /echo HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n\
+          6\r\nhello \r\n5;ext=1\r\nworld\r\n0\r\nTrailer: x\r\n\r\n",
+    );
+    assert!(response.ends_with("\r\n\r\nhello world"), "{}", response);
+
+    // a chunked body has no length to refuse up front
+    let mut request = b"POST /sum HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n".to_vec();
+    for size in [1 << 20, 1 << 20, 1 << 20, 1 << 20, 100_000] {
+        request.extend_from_slice(format!("{:x}\r\n", size).as_bytes());
+        request.extend_from_slice(&vec![b'x'; size]);
+        request.extend_from_slice(b"\r\n");
+    }
+    request.extend_from_slice(b"0\r\n\r\n");
+    assert!(send(addr, &request).starts_with("HTTP/1.1 413 "));
+
+    // buffering it in the handler reports why it stopped
+    let response = send(
+        addr,
+        b"POST /try HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n\
+          5\r\nhello\r\nzz\r\n",
+    );
+    assert!(response.ends_with("\r\n\r\nbad chunk size"), "{}", response);
+}
+
+#[test]
+fn buffered_bodies() {
+    let calls = Arc::new(AtomicUsize::new(0));
+    let counter = calls.clone();
+    let mut m = DefaultServeMux::new();
+    m.post(
+        "/",
+        HandlerFunc::new(move |req, w| {
+            counter.fetch_add(1, Ordering::SeqCst);
+            match &req.body {
+                Some(RequestBody::StringBody(s)) => w.text(s),
+                _ => w.status(StatusCode::BadRequest),
+            }
+            Ok(())
+        }),
+    );
+    let mut s = Server::new(2, "127.0.0.1:0", Arc::new(m)).unwrap();
+    s.set_buffer_bodies(true);
+    s.set_max_body_size(16);
+    let server = s.start().unwrap();
+    let addr = server.local_addr().unwrap();
+
+    let mut stream = TcpStream::connect(addr).unwrap();
+    stream
+        .write_all(b"POST / HTTP/1.1\r\nContent-Length: 8\r\n\r\nbuffered")
+        .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    assert!(response.ends_with("\r\n\r\nbuffered"), "{}", response);
+    assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+    // bodies that cannot be read whole are answered for the handler
+    let response = send(
+        addr,
+        b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n\
+          10\r\n0123456789abcdef\r\n1\r\nx\r\n0\r\n\r\n",
+    );
+    assert!(response.starts_with("HTTP/1.1 413 "), "{}", response);
+    let response = send(
+        addr,
+        b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n\
+          zz\r\n",
+    );
+    assert!(response.starts_with("HTTP/1.1 400 "), "{}", response);
+    assert_eq!(calls.load(Ordering::SeqCst), 1);
+}
@@ -0,0 +1,143 @@
+use rust_server::cache::{CacheStore, ResponseCache};
+use rust_server::client::Client;
+use rust_server::message::{Request, ResponseWriter};
+use rust_server::server::{DefaultServeMux, Handler, HandlerFunc, Server};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+/// Answers with how often it ran, using `cache_control` when given.
+fn counter(cache_control: &'static str) -> impl Handler + Send + Sync {
+    let n = AtomicUsize::new(0);
+    HandlerFunc::new(move |req: &Request, w: &mut dyn ResponseWriter| {
+        let n = n.fetch_add(1, Ordering::SeqCst) + 1;
+        if !cache_control.is_empty() {
+            w.set_header("Cache-Control", cache_control);
+        }
+        if req.path.starts_with("/vary") {
+            w.set_header("Vary", "Accept-Language");
+        }
+        w.text(&format!(
+            "{} {}",
+            n,
+            req.header("Accept-Language").unwrap_or("")
+        ));
+        Ok(())
+    })
+}
+
+#[test]
+fn response_cache() {
+    let store = Arc::new(CacheStore::default());
+    let small = Arc::new(CacheStore::new(160));
+    let mut m = DefaultServeMux::new();
+    m.get("/fresh", counter("max-age=60"));
+    m.get("/vary", counter("max-age=60"));
+    m.get("/nostore", counter("no-store"));
+    m.get("/plain", counter(""));
+    m.get("/swr", counter("max-age=1, stale-while-revalidate=30"));
+    m.get("/lru/:n", counter("max-age=60"));
+    let mut root = DefaultServeMux::new();
+    root.any("/lru/*n", ResponseCache::new(m).store(small.clone()));
+    // a header already on the writer gives way to the stored one
+    let outer = ResponseCache::new(counter("max-age=60"));
+    root.get(
+        "/outer",
+        HandlerFunc::new(move |req: &Request, w: &mut dyn ResponseWriter| {
+            w.set_header("Cache-Control", "no-store");
+            outer.serve_http(w, req)
+        }),
+    );
+    let mut m = DefaultServeMux::new();
+    m.get("/fresh", counter("max-age=60"));
+    m.get("/vary", counter("max-age=60"));
+    m.get("/nostore", counter("no-store"));
+    m.get("/plain", counter(""));
+    m.get("/swr", counter("max-age=1, stale-while-revalidate=30"));
+    root.any("/*rest", ResponseCache::new(m).store(store.clone()));
+    let s = Server::new(2, "127.0.0.1:0", Arc::new(root)).unwrap();
+    let server = s.start().unwrap();
+    let addr = server.local_addr().unwrap();
+
+    let client = Client::new();
+    let url = |p: &str| format!("http://{}{}", addr, p);
+    let get = |p: &str| {
+        let res = client
+            .get(&url(p))
+            .header("Accept-Language", "en")
+            .send()
+            .unwrap();
+        let state = res.header("x-cache").unwrap().to_string();
+        (state, res.text())
+    };
+
+    assert_eq!(get("/fresh"), ("MISS".to_string(), "1 en".to_string()));
+    let res = client
+        .get(&url("/fresh"))
+        .header("Accept-Language", "en")
+        .send()
+        .unwrap();
+    assert_eq!(res.header("x-cache").unwrap(), "HIT");
+    assert_eq!(res.header("age").unwrap(), "0");
+    assert_eq!(res.header("cache-control").unwrap(), "max-age=60");
+    assert_eq!(res.text(), "1 en");
+    let res = client.head(&url("/fresh")).send().unwrap();
+    assert_eq!(res.header("x-cache").unwrap(), "HIT");
+    // the request target, query included, is the key
+    assert_eq!(get("/fresh?x=1").1, "2 en");
+
+    // Vary selects between stored variants
+    assert_eq!(get("/vary").1, "1 en");
+    let res = client
+        .get(&url("/vary"))
+        .header("Accept-Language", "fr")
+        .send()
+        .unwrap();
+    assert_eq!(res.text(), "2 fr");
+    assert_eq!(get("/vary"), ("HIT".to_string(), "1 en".to_string()));
+
+    assert_eq!(get("/outer").1, "1 en");
+    let res = client.get(&url("/outer")).send().unwrap();
+    assert_eq!(res.header("x-cache").unwrap(), "HIT");
+    let cache_control: Vec<_> = res.headers.get_all("cache-control").collect();
+    assert_eq!(cache_control, ["max-age=60"]);
+
+    assert_eq!(get("/nostore").1, "1 en");
+    assert_eq!(get("/nostore"), ("MISS".to_string(), "2 en".to_string()));
+    assert_eq!(get("/plain").1, "1 en");
+    assert_eq!(get("/plain").1, "2 en");
+
+    // a client asking for a fresh copy refreshes the entry
+    let res = client
+        .get(&url("/fresh"))
+        .header("Accept-Language", "en")
+        .header("Cache-Control", "no-cache")
+        .send()
+        .unwrap();
+    assert_eq!(res.text(), "3 en");
+    assert_eq!(get("/fresh"), ("HIT".to_string(), "3 en".to_string()));
+
+    assert!(store.purge("GET /fresh"));
+    assert!(!store.purge("GET /fresh"));
+    assert_eq!(get("/fresh").1, "4 en");
+    assert_eq!(store.purge_prefix("GET /fresh"), 2);
+    assert_eq!(get("/fresh").0, "MISS");
+
+    assert_eq!(get("/swr").1, "1 en");
+    thread::sleep(Duration::from_millis(1100));
+    assert_eq!(get("/swr"), ("STALE".to_string(), "1 en".to_string()));
+    thread::sleep(Duration::from_millis(200));
+    assert_eq!(get("/swr"), ("HIT".to_string(), "2 en".to_string()));
+
+    // the small store holds about two responses, evicting the least
+    // recently used
+    get("/lru/a");
+    get("/lru/b");
+    assert_eq!(get("/lru/a").0, "HIT");
+    get("/lru/c");
+    assert_eq!(small.len(), 2);
+    assert!(small.size() <= 160);
+    assert_eq!(get("/lru/a").0, "HIT");
+    assert_eq!(get("/lru/b").0, "MISS");
+}
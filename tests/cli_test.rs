@@ -0,0 +1,130 @@
+#![cfg(feature = "cli")]
+use std::fs;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::path::PathBuf;
+use std::process::{Command, Output, Stdio};
+
+fn dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("rust_server_cli_{}_{}", name, std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn main() -> Command {
+    let mut cmd = Command::new(env!("CARGO_BIN_EXE_main"));
+    cmd.env_remove("RUST_SERVER_CONFIG");
+    cmd
+}
+
+fn run(args: &[&str]) -> Output {
+    main().args(args).output().unwrap()
+}
+
+#[test]
+fn check_config() {
+    let dir = dir("check");
+    let good = dir.join("good.toml");
+    fs::write(&good, "threads = 2\nlisten = \"127.0.0.1:0\"").unwrap();
+    let bad = dir.join("bad.toml");
+    fs::write(&bad, "[[static]]\nmount = \"assets\"\nroot = \".\"").unwrap();
+
+    let out = run(&["--config", good.to_str().unwrap(), "check-config"]);
+    assert!(out.status.success());
+    let out = run(&["check-config", "-c", bad.to_str().unwrap()]);
+    assert_eq!(out.status.code(), Some(1));
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(
+        stderr.contains("static[0].mount: must start with `/`"),
+        "{}",
+        stderr
+    );
+    // the flag of earlier versions still works
+    let out = run(&["--config", bad.to_str().unwrap(), "--check-config"]);
+    assert_eq!(out.status.code(), Some(1));
+    let out = main()
+        .args(["check-config"])
+        .env("RUST_SERVER_CONFIG", &good)
+        .env("RUST_SERVER_THREADS", "0")
+        .output()
+        .unwrap();
+    assert_eq!(out.status.code(), Some(1));
+
+    assert!(run(&["--help"]).status.success());
+    assert_eq!(run(&["--bogus"]).status.code(), Some(2));
+    assert_eq!(run(&["serve", "--threads", "0"]).status.code(), Some(2));
+    assert_eq!(
+        run(&["serve", "--tls-cert", "a.pem"]).status.code(),
+        Some(2)
+    );
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn routes() {
+    let dir = dir("routes");
+    let config = dir.join("server.toml");
+    fs::write(
+        &config,
+        "metrics_path = \"/metrics\"\n\
+         [[static]]\nmount = \"/assets\"\nroot = \".\"\n\
+         [[proxy]]\nmount = \"/api\"\nupstreams = \"127.0.0.1:9000\"",
+    )
+    .unwrap();
+
+    let out = run(&["routes", "--example", "--config", config.to_str().unwrap()]);
+    assert!(out.status.success());
+    let stdout = String::from_utf8(out.stdout).unwrap();
+    let lines: Vec<Vec<&str>> = stdout
+        .lines()
+        .map(|l| l.split_whitespace().collect())
+        .collect();
+    assert_eq!(lines[0], ["GET", "/metrics", "metrics"]);
+    assert!(lines.contains(&vec!["GET", "/hello"]), "{}", stdout);
+    assert!(lines.contains(&vec!["*", "/api/*path", "proxy", "to", "127.0.0.1:9000"]));
+    let assets = lines.iter().find(|l| l[1] == "/assets/*path").unwrap();
+    assert_eq!(assets[..4], ["GET", "/assets/*path", "files", "in"]);
+
+    // without --example only the config's routes are served
+    let out = run(&["routes"]);
+    assert!(out.status.success());
+    assert!(out.stdout.is_empty());
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn serve_root() {
+    let dir = dir("serve");
+    fs::write(dir.join("index.html"), "<h1>hi</h1>").unwrap();
+    let mut child = main()
+        .args(["serve", "--addr", "127.0.0.1:0", "--threads", "2", "--root"])
+        .arg(&dir)
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    // the bound address is printed once the server listens
+    let mut line = String::new();
+    BufReader::new(child.stdout.take().unwrap())
+        .read_line(&mut line)
+        .unwrap();
+    let addr = line
+        .trim_end()
+        .strip_prefix("listening on ")
+        .expect("server did not start")
+        .to_string();
+    let mut stream = TcpStream::connect(&addr).unwrap();
+    write!(stream, "GET / HTTP/1.1\r\nConnection: close\r\n\r\n").unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 200 "), "{}", response);
+    assert!(response.ends_with("<h1>hi</h1>"));
+
+    // a second server cannot bind the same address
+    let out = run(&["serve", "--addr", &addr]);
+    assert_eq!(out.status.code(), Some(3));
+
+    child.kill().unwrap();
+    child.wait().unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+}
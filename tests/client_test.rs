@@ -0,0 +1,236 @@
+use rust_server::client::{Client, ClientError};
+use rust_server::server::{DefaultServeMux, HandlerFunc, Server};
+use rust_server::status_code::StatusCode;
+use std::io::{BufRead, BufReader, Cursor, Read, Write};
+use std::net::{SocketAddr, TcpListener};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+/// A keep-alive server answering `/slow` late and everything else with a
+/// chunked body echoing the request line; returns its address and the
+/// accepted connection count.
+fn raw_server() -> (SocketAddr, Arc<AtomicUsize>) {
+    let accepted = Arc::new(AtomicUsize::new(0));
+    let counter = accepted.clone();
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            counter.fetch_add(1, Ordering::SeqCst);
+            let stream = stream.unwrap();
+            thread::spawn(move || {
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut writer = stream;
+                loop {
+                    let mut request_line = String::new();
+                    if reader.read_line(&mut request_line).unwrap_or(0) == 0 {
+                        return;
+                    }
+                    let mut line = String::new();
+                    while reader.read_line(&mut line).unwrap_or(0) > 2 {
+                        line.clear();
+                    }
+                    if request_line.contains("/slow") {
+                        thread::sleep(Duration::from_millis(500));
+                    }
+                    let echo = request_line.trim_end();
+                    let head = "HTTP/1.1 100 Continue\r\n\r\n\
+                                HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n";
+                    let body = format!(
+                        "{:x};ext=1\r\n{}\r\n0\r\nX-Trailer: t\r\n\r\n",
+                        echo.len(),
+                        echo
+                    );
+                    let _ = writer.write_all(head.as_bytes());
+                    let _ = writer.write_all(body.as_bytes());
+                }
+            });
+        }
+    });
+    (addr, accepted)
+}
+
+#[test]
+fn chunked_keep_alive_and_timeouts() {
+    let (addr, accepted) = raw_server();
+
+    let client = Client::new();
+    for i in 0..3 {
+        let res = client
+            .get(&format!("http://{}/n/{}?q=1", addr, i))
+            .send()
+            .unwrap();
+        assert_eq!(res.status(), 200);
+        assert_eq!(res.text(), format!("GET /n/{}?q=1 HTTP/1.1", i));
+    }
+    assert_eq!(accepted.load(Ordering::SeqCst), 1);
+
+    // a body dropped before the end is not pooled
+    let (_, mut body) = client
+        .get(&format!("http://{}/partial", addr))
+        .send_streamed()
+        .unwrap();
+    let mut first = [0; 3];
+    body.read_exact(&mut first).unwrap();
+    assert_eq!(&first, b"GET");
+    drop(body);
+    client.get(&format!("http://{}/", addr)).send().unwrap();
+    assert_eq!(accepted.load(Ordering::SeqCst), 2);
+
+    let impatient = Client::new().timeout(Duration::from_millis(100));
+    let err = impatient
+        .get(&format!("http://{}/slow", addr))
+        .send()
+        .unwrap_err();
+    assert!(err.is_timeout(), "{}", err);
+
+    match Client::new().get("https://example.com/").send() {
+        Err(ClientError::InvalidUrl(_)) => {}
+        other => panic!("{:?}", other.map(|r| r.status())),
+    }
+    let closed = TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap();
+    match Client::new().get(&format!("http://{}/", closed)).send() {
+        Err(ClientError::Connect(_)) => {}
+        other => panic!("{:?}", other.map(|r| r.status())),
+    }
+}
+
+#[test]
+fn redirects_and_bodies() {
+    let mut m = DefaultServeMux::new();
+    m.post(
+        "/submit",
+        HandlerFunc::new(|_, w| {
+            w.redirect(StatusCode::SeeOther, "done");
+            Ok(())
+        }),
+    );
+    m.post(
+        "/keep",
+        HandlerFunc::new(|_, w| {
+            w.redirect(StatusCode::TemporaryRedirect, "/echo");
+            Ok(())
+        }),
+    );
+    m.any(
+        "/echo",
+        HandlerFunc::new(|req, w| {
+            let auth = req.header("Authorization").unwrap_or("-");
+            let body = String::from_utf8_lossy(req.body_bytes());
+            w.text(&format!("{} {} {}", req.method, auth, body));
+            Ok(())
+        }),
+    );
+    m.get(
+        "/done",
+        HandlerFunc::new(|req, w| {
+            w.text(&format!("{} done", req.method));
+            Ok(())
+        }),
+    );
+    m.get(
+        "/loop",
+        HandlerFunc::new(|_, w| {
+            w.redirect(StatusCode::Found, "/loop");
+            Ok(())
+        }),
+    );
+    m.get(
+        "/stream",
+        HandlerFunc::new(|_, w| {
+            w.write_stream(Box::new(Cursor::new(b"chunked stream".to_vec())), None);
+            Ok(())
+        }),
+    );
+    let server = Server::new(2, "127.0.0.1:0", Arc::new(m))
+        .unwrap()
+        .start()
+        .unwrap();
+
+    let client = Client::new();
+    let url = |p: &str| format!("http://{}{}", server.local_addr().unwrap(), p);
+
+    let res = client.post(&url("/submit")).body("x=1").send().unwrap();
+    assert_eq!(res.status(), 200);
+    assert_eq!(res.text(), "GET done");
+
+    let res = client
+        .post(&url("/keep"))
+        .bearer_auth("token")
+        .body("payload")
+        .send()
+        .unwrap();
+    assert_eq!(res.text(), "POST Bearer token payload");
+
+    match client.get(&url("/loop")).send() {
+        Err(ClientError::TooManyRedirects) => {}
+        other => panic!("{:?}", other.map(|r| r.status())),
+    }
+    let res = Client::new()
+        .max_redirects(0)
+        .get(&url("/loop"))
+        .send()
+        .unwrap();
+    assert_eq!(res.status(), 302);
+    assert_eq!(res.header("Location"), Some("/loop"));
+
+    let res = client.get(&url("/stream")).send().unwrap();
+    assert_eq!(res.header("transfer-encoding"), Some("chunked"));
+    assert_eq!(res.text(), "chunked stream");
+
+    let res = client.head(&url("/echo")).send().unwrap();
+    assert_eq!(res.status(), 200);
+    assert_eq!(res.text(), "");
+}
+
+#[test]
+fn post_not_replayed_after_sending() {
+    // answers a GET, then reads a POST and closes without answering
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let posts = Arc::new(AtomicUsize::new(0));
+    let counter = posts.clone();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let stream = stream.unwrap();
+            let counter = counter.clone();
+            thread::spawn(move || {
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut writer = stream;
+                loop {
+                    let mut request_line = String::new();
+                    if reader.read_line(&mut request_line).unwrap_or(0) == 0 {
+                        return;
+                    }
+                    let mut line = String::new();
+                    let mut length = 0;
+                    while reader.read_line(&mut line).unwrap_or(0) > 2 {
+                        if let Some(v) = line.strip_prefix("Content-Length: ") {
+                            length = v.trim().parse().unwrap();
+                        }
+                        line.clear();
+                    }
+                    reader.read_exact(&mut vec![0; length]).unwrap();
+                    if request_line.starts_with("POST") {
+                        counter.fetch_add(1, Ordering::SeqCst);
+                        return;
+                    }
+                    let _ = writer.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok");
+                }
+            });
+        }
+    });
+
+    let client = Client::new();
+    let url = format!("http://{}/", addr);
+    assert_eq!(client.get(&url).send().unwrap().text(), "ok");
+    let err = client.post(&url).body("once").send().unwrap_err();
+    assert!(matches!(err, ClientError::Io(_)), "{:?}", err);
+    thread::sleep(Duration::from_millis(100));
+    assert_eq!(posts.load(Ordering::SeqCst), 1);
+}
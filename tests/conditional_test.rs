@@ -0,0 +1,137 @@
+use rust_server::client::Client;
+use rust_server::conditional::{
+    check_preconditions, http_date, CacheControl, ConditionalHandler, ETag,
+};
+use rust_server::message::Request;
+use rust_server::method::Method;
+use rust_server::server::{DefaultServeMux, HandlerFunc, Server};
+use rust_server::status_code::StatusCode;
+use std::sync::Arc;
+use std::time::{Duration, UNIX_EPOCH};
+
+#[test]
+fn validators() {
+    let etag = ETag::from_body(b"hello");
+    assert_eq!(etag, ETag::from_body(b"hello"));
+    assert_ne!(etag, ETag::from_body(b"hello!"));
+    assert!(!etag.is_weak());
+    assert!(ETag::weak_from_body(b"hello").weak_eq(&etag));
+    assert!(!ETag::weak_from_body(b"hello").strong_eq(&etag));
+
+    let parsed = ETag::parse("W/\"abc\"").unwrap();
+    assert!(parsed.is_weak());
+    assert_eq!(parsed.tag(), "abc");
+    assert_eq!(parsed.to_string(), "W/\"abc\"");
+    assert_eq!(ETag::parse("abc"), None);
+
+    // entity tags may contain commas
+    let mut req = Request::new();
+    req.method = Method::Get;
+    req.headers
+        .insert("If-None-Match".to_string(), r#""a,b", "c""#.to_string());
+    let matches = |tag: &str| check_preconditions(&req, Some(&ETag::strong(tag)), None);
+    assert_eq!(matches("a,b"), Some(StatusCode::NotModified));
+    assert_eq!(matches("c"), Some(StatusCode::NotModified));
+    assert_eq!(matches("a"), None);
+
+    let cc = CacheControl::new()
+        .public()
+        .max_age(Duration::from_secs(3600))
+        .stale_while_revalidate(Duration::from_secs(60));
+    assert_eq!(
+        cc.to_string(),
+        "public, max-age=3600, stale-while-revalidate=60"
+    );
+    assert_eq!(CacheControl::new().no_store().to_string(), "no-store");
+}
+
+#[test]
+fn conditional_requests() {
+    let modified = UNIX_EPOCH + Duration::from_secs(784_111_777);
+    let mut m = DefaultServeMux::new();
+    m.get(
+        "/doc",
+        HandlerFunc::new(move |_, w| {
+            w.set_last_modified(modified);
+            w.set_cache_control(&CacheControl::new().no_cache());
+            w.text("document");
+            Ok(())
+        }),
+    );
+    m.put(
+        "/doc",
+        HandlerFunc::new(move |req, w| {
+            let current = ETag::from_body(b"document");
+            match check_preconditions(req, Some(&current), Some(modified)) {
+                Some(status) => w.status(status),
+                None => w.no_content(),
+            }
+            Ok(())
+        }),
+    );
+    let mut root = DefaultServeMux::new();
+    root.any("/*rest", ConditionalHandler::new(m));
+    let s = Server::new(2, "127.0.0.1:0", Arc::new(root)).unwrap();
+    let server = s.start().unwrap();
+    let addr = server.local_addr().unwrap();
+
+    let client = Client::new();
+    let url = format!("http://{}/doc", addr);
+    let get = |name: &str, value: &str| client.get(&url).header(name, value).send().unwrap();
+
+    let res = client.get(&url).send().unwrap();
+    let etag = res.header("etag").unwrap().to_string();
+    assert_eq!(etag, ETag::from_body(b"document").to_string());
+    assert_eq!(
+        res.header("last-modified").unwrap(),
+        "Sun, 06 Nov 1994 08:49:37 GMT"
+    );
+    assert_eq!(http_date(modified), "Sun, 06 Nov 1994 08:49:37 GMT");
+
+    let res = get("If-None-Match", &format!("\"x\", W/{}", etag));
+    assert_eq!(res.status(), 304);
+    assert_eq!(res.header("etag").unwrap(), etag.as_str());
+    assert_eq!(res.header("cache-control").unwrap(), "no-cache");
+    assert_eq!(res.text(), "");
+    assert_eq!(get("If-None-Match", "\"other\"").status(), 200);
+    assert_eq!(get("If-None-Match", "*").status(), 304);
+
+    assert_eq!(get("If-Match", &etag).status(), 200);
+    let res = get("If-Match", "\"other\"");
+    assert_eq!(res.status(), 412);
+    assert!(res.header("etag").is_none());
+
+    for date in &[
+        "Sun, 06 Nov 1994 08:49:37 GMT",
+        "Sunday, 06-Nov-94 08:49:37 GMT",
+        "Sun Nov  6 08:49:37 1994",
+    ] {
+        assert_eq!(get("If-Modified-Since", date).status(), 304, "{}", date);
+    }
+    assert_eq!(
+        get("If-Modified-Since", "Sat, 05 Nov 1994 08:49:37 GMT").status(),
+        200
+    );
+    assert_eq!(get("If-Modified-Since", "garbage").status(), 200);
+    // If-None-Match takes precedence over If-Modified-Since
+    let res = client
+        .get(&url)
+        .header("If-None-Match", "\"other\"")
+        .header("If-Modified-Since", "Sun, 06 Nov 1994 08:49:37 GMT")
+        .send()
+        .unwrap();
+    assert_eq!(res.status(), 200);
+    assert_eq!(
+        get("If-Unmodified-Since", "Sat, 05 Nov 1994 08:49:37 GMT").status(),
+        412
+    );
+
+    let put = |name: &str, value: &str| client.put(&url).header(name, value).send().unwrap();
+    assert_eq!(put("If-Match", &etag).status(), 204);
+    assert_eq!(put("If-Match", "\"stale\"").status(), 412);
+    assert_eq!(put("If-None-Match", "*").status(), 412);
+    assert_eq!(
+        check_preconditions(&Default::default(), None, None),
+        None::<StatusCode>
+    );
+}
@@ -0,0 +1,227 @@
+#![cfg(feature = "config")]
+use rust_server::config::Config;
+use rust_server::log::LogFormat;
+use rust_server::proxy::Balance;
+use rust_server::server::{DefaultServeMux, HandlerFunc, Server};
+use std::fs;
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+fn dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("rust_server_{}_{}", name, std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn get(addr: SocketAddr, path: &str) -> String {
+    let mut stream = TcpStream::connect(addr).unwrap();
+    write!(stream, "GET {} HTTP/1.1\r\n\r\n", path).unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    response
+}
+
+fn err(text: &str) -> String {
+    match Config::parse(text).and_then(|c| c.validate()) {
+        Ok(()) => panic!("accepted {:?}", text),
+        Err(e) => e.to_string(),
+    }
+}
+
+#[test]
+fn parse_and_validate() {
+    let config = Config::parse(
+        r#"
+        threads = 2
+        listen = "127.0.0.1:0"
+
+        [timeouts]
+        read = "1500ms"
+        shutdown = 2
+
+        [limits]
+        max_body_size = "2MiB"
+
+        [log]
+        format = "json"
+        max_age = "1d"
+
+        [[proxy]]
+        mount = "/api"
+        upstreams = ["http://127.0.0.1:9000/v1", "[::1]:9001"]
+        balance = "least_connections"
+        "#,
+    )
+    .unwrap();
+    assert_eq!(config.threads, 2);
+    assert_eq!(config.listen, vec!["127.0.0.1:0"]);
+    assert_eq!(config.timeouts.read, Some(Duration::from_millis(1500)));
+    assert_eq!(config.timeouts.shutdown, Duration::from_secs(2));
+    assert_eq!(config.limits.max_body_size, Some(2 << 20));
+    assert_eq!(config.log.format, Some(LogFormat::Json));
+    assert_eq!(config.log.max_age, Some(Duration::from_secs(86400)));
+    assert_eq!(config.proxy[0].balance, Balance::LeastConnections);
+    assert!(config.proxy[0].strip_prefix);
+    config.validate().unwrap();
+    assert_eq!(Config::default().listen, vec!["127.0.0.1:7878"]);
+
+    // errors name the key at fault
+    assert!(err("threds = 4").starts_with("threds: unknown field `threds`"));
+    assert!(err("threads = 0").starts_with("threads: "));
+    assert!(err("[limits]\nmax_body_size = \"lots\"").starts_with("limits.max_body_size: "));
+    assert!(err("[log]\nformat = \"xml\"").starts_with("log.format: unknown variant `xml`"));
+    assert!(err("listen = [\"127.0.0.1:0\", \"nowhere\"]").starts_with("listen[1]: "));
+    assert!(err("[[static]]\nmount = \"/\"\nroot = \"/no/such/dir\"")
+        .starts_with("static[0].root: is not a directory"));
+    let proxies = "[[proxy]]\nmount = \"/a\"\nupstreams = \"a:1\"\n\
+                   [[proxy]]\nmount = \"/b\"\nupstreams = [\"b:1\", \"https://b\"]";
+    assert!(err(proxies).starts_with("proxy[1].upstreams[1]: https"));
+    let twice = "[[proxy]]\nmount = \"/a\"\nupstreams = \"a:1\"\n\
+                 [[proxy]]\nmount = \"/a/\"\nupstreams = \"b:1\"";
+    assert_eq!(err(twice), "proxy[1].mount: is already mounted by proxy[0]");
+    assert!(err("[error_pages]\n200 = \"404.html\"").starts_with("error_pages.200: "));
+    assert!(err("[error_pages]\n404 = \"missing.html\"").starts_with("error_pages.404: "));
+    assert!(err("threads = 2\nthreads = 3").starts_with("line 2: "));
+    #[cfg(not(feature = "tls"))]
+    assert!(err("[tls]\ncert = \"a\"\nkey = \"b\"").starts_with("tls: "));
+    #[cfg(feature = "tls")]
+    assert!(err("[tls]\ncert = \"a\"\nkey = \"b\"").starts_with("tls.cert: "));
+}
+
+#[test]
+fn environment_overrides() {
+    let vars = |pairs: &[(&str, &str)]| -> Vec<(String, String)> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    };
+    let config = Config::parse_with_env(
+        "threads = 2\n[limits]\nbuffer_bodies = true",
+        vars(&[
+            ("RUST_SERVER_THREADS", "16"),
+            ("RUST_SERVER_LISTEN", "[::]:8080"),
+            ("RUST_SERVER_TIMEOUTS__READ", "10s"),
+            ("RUST_SERVER_LIMITS__MAX_BODY_SIZE", "1024"),
+            ("RUST_SERVER_ERROR_PAGES__404", "404.html"),
+            ("RUST_SERVER_CONFIG", "ignored.toml"),
+            ("HOME", "/root"),
+        ]),
+    )
+    .unwrap();
+    assert_eq!(config.threads, 16);
+    assert_eq!(config.listen, vec!["[::]:8080"]);
+    assert_eq!(config.timeouts.read, Some(Duration::from_secs(10)));
+    assert_eq!(config.limits.max_body_size, Some(1024));
+    assert!(config.limits.buffer_bodies);
+    assert_eq!(config.error_pages["404"], PathBuf::from("404.html"));
+
+    let e = Config::parse_with_env("", vars(&[("RUST_SERVER_THREADS", "many")])).unwrap_err();
+    assert_eq!(e.key(), Some("threads"));
+    assert_eq!(e.env(), Some("RUST_SERVER_THREADS"));
+    let e = Config::parse_with_env("", vars(&[("RUST_SERVER_THREADS", "0")]))
+        .unwrap()
+        .validate()
+        .unwrap_err();
+    assert_eq!(
+        e.to_string(),
+        "threads: must be at least 1 (set by RUST_SERVER_THREADS)"
+    );
+    let e = Config::parse_with_env("", vars(&[("RUST_SERVER_THREADS__MAX", "1")])).unwrap_err();
+    assert_eq!(e.key(), Some("threads"));
+}
+
+#[test]
+fn server_from_config() {
+    let upstream = {
+        let mut m = DefaultServeMux::new();
+        m.get(
+            "/v1/*rest",
+            HandlerFunc::new(|req, w| {
+                w.text(&format!("upstream {}", req.path));
+                Ok(())
+            }),
+        );
+        Server::new(2, "127.0.0.1:0", Arc::new(m))
+            .unwrap()
+            .start()
+            .unwrap()
+    };
+    let dir = dir("config");
+    fs::create_dir_all(dir.join("public")).unwrap();
+    fs::write(dir.join("public/style.css"), "body {}").unwrap();
+    fs::write(dir.join("missing.html"), "<p>nothing here</p>").unwrap();
+    let path = dir.join("server.toml");
+    fs::write(
+        &path,
+        format!(
+            r#"
+            threads = 4
+            listen = "127.0.0.1:0"
+            metrics_path = "/metrics"
+
+            [timeouts]
+            read = "300ms"
+
+            [log]
+            format = "common"
+            file = "access.log"
+
+            [[static]]
+            mount = "/assets"
+            root = "public"
+
+            [[proxy]]
+            mount = "/api"
+            upstreams = "http://{}/v1"
+
+            [error_pages]
+            404 = "missing.html"
+            "#,
+            upstream.local_addr().unwrap()
+        ),
+    )
+    .unwrap();
+
+    let config = Config::load(&path).unwrap();
+    let mut m = DefaultServeMux::new();
+    m.get(
+        "/",
+        HandlerFunc::new(|_, w| {
+            w.text("home");
+            Ok(())
+        }),
+    );
+    let server = config.server(m).unwrap().start().unwrap();
+    let addr = server.local_addr().unwrap();
+
+    assert!(get(addr, "/").ends_with("\r\n\r\nhome"));
+    let css = get(addr, "/assets/style.css");
+    assert!(css.contains("Content-Type: text/css"), "{}", css);
+    assert!(css.ends_with("\r\n\r\nbody {}"));
+    assert!(get(addr, "/api/users?id=1").ends_with("\r\n\r\nupstream /v1/users?id=1"));
+    let missing = get(addr, "/assets/nope.css");
+    assert!(missing.starts_with("HTTP/1.1 404 "));
+    assert!(missing.contains("Content-Type: text/html"));
+    assert!(missing.ends_with("<p>nothing here</p>"));
+    assert!(get(addr, "/metrics").contains("rust_server_requests_total"));
+
+    // a silent client is let go after the read timeout
+    let start = Instant::now();
+    let mut idle = TcpStream::connect(addr).unwrap();
+    let mut buf = Vec::new();
+    let _ = idle.read_to_end(&mut buf);
+    assert!(start.elapsed() < Duration::from_secs(3));
+
+    server.shutdown();
+    let log = fs::read_to_string(dir.join("access.log")).unwrap();
+    assert!(
+        log.contains("\"GET /assets/style.css HTTP/1.1\" 200 7"),
+        "{}",
+        log
+    );
+    fs::remove_dir_all(&dir).unwrap();
+}
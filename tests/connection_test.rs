@@ -0,0 +1,44 @@
+use rust_server::server::{DefaultServeMux, HandlerFunc, Server};
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::sync::Arc;
+
+fn get(addr: SocketAddr, path: &str) -> String {
+    let mut stream = TcpStream::connect(addr).unwrap();
+    write!(stream, "GET {} HTTP/1.1\r\nHost: {}\r\n\r\n", path, addr).unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    response
+}
+
+#[test]
+fn connection_close() {
+    let mut m = DefaultServeMux::new();
+    m.get(
+        "/plain",
+        HandlerFunc::new(|_req, w| {
+            w.text("plain");
+            Ok(())
+        }),
+    );
+    m.get(
+        "/upgrade",
+        HandlerFunc::new(|_req, w| {
+            w.set_header("Connection", "upgrade");
+            w.text("upgrade");
+            Ok(())
+        }),
+    );
+    let s = Server::new(1, "127.0.0.1:0", Arc::new(m)).unwrap();
+    let server = s.start().unwrap();
+    let addr = server.local_addr().unwrap();
+
+    // the connection is closed after one response, and the client told so
+    let res = get(addr, "/plain");
+    assert!(res.contains("\r\nConnection: close\r\n"), "{}", res);
+    assert!(res.ends_with("\r\n\r\nplain"));
+    // a handler's own Connection header is left alone
+    let res = get(addr, "/upgrade");
+    assert_eq!(res.matches("Connection:").count(), 1, "{}", res);
+    assert!(res.contains("\r\nConnection: upgrade\r\n"));
+}
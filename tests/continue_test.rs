@@ -0,0 +1,89 @@
+use rust_server::server::{DefaultServeMux, HandlerFunc, Server};
+use rust_server::status_code::StatusCode;
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Read up to and including the blank line ending a response head.
+fn read_head(stream: &mut TcpStream) -> String {
+    let mut head = Vec::new();
+    let mut byte = [0];
+    while !head.ends_with(b"\r\n\r\n") {
+        stream.read_exact(&mut byte).unwrap();
+        head.push(byte[0]);
+    }
+    String::from_utf8(head).unwrap()
+}
+
+fn send_head(addr: SocketAddr, head: &str) -> TcpStream {
+    let mut stream = TcpStream::connect(addr).unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    stream.write_all(head.as_bytes()).unwrap();
+    stream
+}
+
+#[test]
+fn expect_continue() {
+    let mut m = DefaultServeMux::new();
+    m.post(
+        "/upload",
+        HandlerFunc::new(|req, w| {
+            if req.header("Authorization").is_none() {
+                w.status(StatusCode::Unauthorized);
+                return Ok(());
+            }
+            let body = String::from_utf8_lossy(req.body_bytes()).into_owned();
+            w.text(&format!("got {}", body));
+            Ok(())
+        }),
+    );
+    let mut s = Server::new(2, "127.0.0.1:0", Arc::new(m)).unwrap();
+    s.set_max_body_size(1024);
+    let server = s.start().unwrap();
+    let addr = server.local_addr().unwrap();
+
+    // the body is asked for once the handler reads it
+    let mut stream = send_head(
+        addr,
+        "POST /upload HTTP/1.1\r\nAuthorization: yes\r\n\
+         Expect: 100-continue\r\nContent-Length: 5\r\n\r\n",
+    );
+    assert_eq!(read_head(&mut stream), "HTTP/1.1 100 Continue\r\n\r\n");
+    stream.write_all(b"hello").unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 200 "), "{}", response);
+    assert!(response.ends_with("got hello"));
+
+    // a handler refusing from the headers never gets the body
+    let mut stream = send_head(
+        addr,
+        "POST /upload HTTP/1.1\r\nExpect: 100-Continue\r\nContent-Length: 5\r\n\r\n",
+    );
+    assert!(read_head(&mut stream).starts_with("HTTP/1.1 401 "));
+
+    let mut stream = send_head(
+        addr,
+        "POST /upload HTTP/1.1\r\nExpect: something-else\r\nContent-Length: 5\r\n\r\n",
+    );
+    assert!(read_head(&mut stream).starts_with("HTTP/1.1 417 "));
+
+    let mut stream = send_head(
+        addr,
+        "POST /upload HTTP/1.1\r\nAuthorization: yes\r\n\
+         Expect: 100-continue\r\nContent-Length: 4096\r\n\r\n",
+    );
+    assert!(read_head(&mut stream).starts_with("HTTP/1.1 413 "));
+
+    // without an expectation bodies are read as before
+    let mut stream = send_head(
+        addr,
+        "POST /upload HTTP/1.1\r\nAuthorization: yes\r\nContent-Length: 5\r\n\r\nworld",
+    );
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    assert!(response.ends_with("got world"), "{}", response);
+}
@@ -0,0 +1,109 @@
+use rust_server::cookie::{parse_cookies, Cookie, CookieJar, SameSite};
+use rust_server::message::Request;
+use std::time::{Duration, UNIX_EPOCH};
+
+fn request(cookie: &str) -> Request {
+    let mut req = Request::new();
+    req.headers.insert("cookie".to_string(), cookie.to_string());
+    req
+}
+
+#[test]
+fn parse_cookie_header() {
+    assert_eq!(
+        parse_cookies("a=1; b=\"two\";bad; c=x=y"),
+        vec![
+            ("a".to_string(), "1".to_string()),
+            ("b".to_string(), "two".to_string()),
+            ("c".to_string(), "x=y".to_string()),
+        ]
+    );
+    assert_eq!(request("a=1; b=2").cookie("b"), Some("2".to_string()));
+}
+
+#[test]
+fn set_cookie_attributes() {
+    let c = Cookie::new("__Host-id", "v")
+        .expires(UNIX_EPOCH + Duration::from_secs(784_111_777))
+        .domain("example.com")
+        .secure(true)
+        .same_site(SameSite::None)
+        .partitioned(true);
+    assert_eq!(
+        c.to_string(),
+        "__Host-id=v; Expires=Sun, 06 Nov 1994 08:49:37 GMT; Domain=example.com; Secure; SameSite=None; Partitioned"
+    );
+    assert_eq!(
+        Cookie::removal("a").to_string(),
+        "a=; Expires=Thu, 01 Jan 1970 00:00:00 GMT; Max-Age=0"
+    );
+}
+
+#[test]
+fn cookies_cannot_inject_attributes() {
+    let c = Cookie::new("id", "x; Domain=evil.com; Max-Age=99999");
+    assert_eq!(
+        c.to_string(),
+        "id=x%3B%20Domain=evil.com%3B%20Max-Age=99999"
+    );
+    let c = Cookie::new("a b;", "1\r\nSet-Cookie: evil=1,\"%")
+        .domain("example.com; Secure")
+        .path("/\r\nx");
+    let header = c.to_string();
+    assert_eq!(
+        header,
+        "a%20b%3B=1%0D%0ASet-Cookie:%20evil=1%2C%22%25; Domain=example.com%3B%20Secure; Path=/%0D%0Ax"
+    );
+    // and the client sends back what was set
+    let (name, value) = header.split_once("; ").unwrap().0.split_once('=').unwrap();
+    let cookie = format!("{}={}", name, value);
+    assert_eq!(
+        parse_cookies(&cookie),
+        vec![(
+            "a b;".to_string(),
+            "1\r\nSet-Cookie: evil=1,\"%".to_string()
+        )]
+    );
+}
+
+#[test]
+fn jar_tracks_changes() {
+    let mut jar = CookieJar::from_request(&request("a=1; b=2"));
+    jar.add(Cookie::new("c", "3"));
+    jar.remove("a");
+    assert_eq!(jar.get("a"), None);
+    assert_eq!(jar.get("b"), Some("2"));
+    assert_eq!(jar.get("c"), Some("3"));
+    assert_eq!(jar.delta().len(), 2);
+}
+
+#[cfg(feature = "secure-cookies")]
+#[test]
+fn signed_and_private() {
+    use rust_server::cookie::Key;
+
+    let key = Key::derive_from(b"a very secret server key of enough length");
+    let mut jar = CookieJar::new();
+    jar.signed(&key).add(Cookie::new("user", "alice"));
+    jar.private(&key).add(Cookie::new("token", "s3cret"));
+
+    let sent: Vec<String> = jar
+        .delta()
+        .iter()
+        .map(|c| format!("{}={}", c.name(), c.value()))
+        .collect();
+    assert!(sent[0].ends_with("alice"));
+    assert!(!sent[1].contains("s3cret"));
+
+    let mut back = CookieJar::from_request(&request(&sent.join("; ")));
+    assert_eq!(back.signed(&key).get("user"), Some("alice".to_string()));
+    assert_eq!(back.private(&key).get("token"), Some("s3cret".to_string()));
+
+    let other = Key::derive_from(b"another secret server key of enough length");
+    assert_eq!(back.signed(&other).get("user"), None);
+    assert_eq!(back.private(&other).get("token"), None);
+
+    let tampered = sent[0].replace("alice", "admin");
+    let mut back = CookieJar::from_request(&request(&tampered));
+    assert_eq!(back.signed(&key).get("user"), None);
+}
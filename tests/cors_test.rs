@@ -0,0 +1,134 @@
+use rust_server::client::Client;
+use rust_server::cors::Cors;
+use rust_server::method::Method;
+use rust_server::server::{DefaultServeMux, HandlerFunc, Server};
+use std::sync::Arc;
+use std::time::Duration;
+
+fn api(prefix: &str) -> DefaultServeMux {
+    let mut m = DefaultServeMux::new();
+    m.get(
+        &format!("{}/items", prefix),
+        HandlerFunc::new(|_, w| {
+            w.set_header("X-Total", "3");
+            w.json("[1,2,3]");
+            Ok(())
+        }),
+    );
+    m.delete(
+        &format!("{}/items/:id", prefix),
+        HandlerFunc::new(|_, w| {
+            w.no_content();
+            Ok(())
+        }),
+    );
+    m
+}
+
+#[test]
+fn cors() {
+    let cors = Cors::new(api("/api"))
+        .allow_origin("https://app.example.com")
+        .allow_origin("https://*.preview.example.com")
+        .allow_origin_fn(|o| o.ends_with(".localhost:3000"))
+        .allow_methods(&[Method::Get, Method::Delete])
+        .allow_headers(&["X-Token"])
+        .expose_headers(&["X-Total"])
+        .allow_credentials(true)
+        .max_age(Duration::from_secs(600));
+    let mut root = DefaultServeMux::new();
+    root.any("/api/*rest", cors);
+    root.any("/plain/*rest", api("/plain"));
+    let s = Server::new(2, "127.0.0.1:0", Arc::new(root)).unwrap();
+    let server = s.start().unwrap();
+    let addr = server.local_addr().unwrap();
+
+    let client = Client::new();
+    let url = |p: &str| format!("http://{}{}", addr, p);
+    let preflight = |origin: &str, method: &str, headers: &str| {
+        client
+            .request(Method::Options, &url("/api/items/1"))
+            .header("Origin", origin)
+            .header("Access-Control-Request-Method", method)
+            .header("Access-Control-Request-Headers", headers)
+            .send()
+            .unwrap()
+    };
+
+    let res = preflight("https://app.example.com", "DELETE", "x-token");
+    assert_eq!(res.status(), 204);
+    let h = |name| res.header(name).unwrap();
+    assert_eq!(h("access-control-allow-origin"), "https://app.example.com");
+    assert_eq!(h("access-control-allow-methods"), "GET, DELETE");
+    assert_eq!(h("access-control-allow-headers"), "x-token");
+    assert_eq!(h("access-control-allow-credentials"), "true");
+    assert_eq!(h("access-control-max-age"), "600");
+    assert!(res.headers.get_all("vary").any(|v| v == "Origin"));
+
+    let res = preflight("https://pr-12.preview.example.com", "GET", "");
+    assert_eq!(res.status(), 204);
+    let res = preflight("http://dev.localhost:3000", "GET", "");
+    assert_eq!(res.status(), 204);
+    assert_eq!(preflight("https://evil.com", "GET", "").status(), 403);
+    assert_eq!(
+        preflight("https://app.example.com", "PUT", "").status(),
+        403
+    );
+    assert_eq!(
+        preflight("https://app.example.com", "GET", "X-Other").status(),
+        403
+    );
+
+    let res = client
+        .get(&url("/api/items"))
+        .header("Origin", "https://app.example.com")
+        .send()
+        .unwrap();
+    let h = |name| res.header(name).unwrap();
+    assert_eq!(h("access-control-allow-origin"), "https://app.example.com");
+    assert_eq!(h("access-control-expose-headers"), "X-Total");
+    assert_eq!(h("vary"), "Origin");
+    assert_eq!(res.text(), "[1,2,3]");
+
+    // the answer depends on Origin even when there is none
+    let res = client.get(&url("/api/items")).send().unwrap();
+    assert!(res.header("access-control-allow-origin").is_none());
+    assert_eq!(res.header("vary").unwrap(), "Origin");
+
+    let res = client
+        .get(&url("/api/items"))
+        .header("Origin", "https://evil.com")
+        .send()
+        .unwrap();
+    assert_eq!(res.status(), 200);
+    assert!(res.header("access-control-allow-origin").is_none());
+
+    // without the middleware the mux answers OPTIONS and HEAD itself
+    let res = client
+        .request(Method::Options, &url("/plain/items"))
+        .send()
+        .unwrap();
+    assert_eq!(res.status(), 204);
+    assert_eq!(res.header("allow").unwrap(), "GET, HEAD, OPTIONS");
+    let res = client.head(&url("/plain/items")).send().unwrap();
+    assert_eq!(res.status(), 200);
+    assert_eq!(res.header("content-length").unwrap(), "7");
+    assert_eq!(res.text(), "");
+}
+
+#[test]
+fn any_origin() {
+    let mut root = DefaultServeMux::new();
+    root.any("/*rest", Cors::new(api("")).allow_origin("*"));
+    let s = Server::new(2, "127.0.0.1:0", Arc::new(root)).unwrap();
+    let server = s.start().unwrap();
+    let addr = server.local_addr().unwrap();
+
+    let res = Client::new()
+        .get(&format!("http://{}/items", addr))
+        .header("Origin", "https://anywhere.org")
+        .send()
+        .unwrap();
+    assert_eq!(res.header("access-control-allow-origin").unwrap(), "*");
+    assert!(res.header("vary").is_none());
+}
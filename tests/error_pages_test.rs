@@ -0,0 +1,157 @@
+use rust_server::error_pages::{prefers_json, ErrorPages};
+use rust_server::message::Request;
+use rust_server::server::{DefaultServeMux, HandlerFunc};
+use rust_server::status_code::StatusCode;
+use rust_server::testing::TestClient;
+use std::fs;
+use std::sync::Arc;
+
+#[test]
+fn not_found_handler() {
+    let client = TestClient::new(Arc::new(DefaultServeMux::new()));
+    client
+        .get("/nope")
+        .send()
+        .assert_status(StatusCode::NotFound)
+        .assert_header("Content-Type", "text/html; charset=utf-8")
+        .assert_body_contains("<h1>404 Not Found</h1>");
+    client
+        .get("/nope")
+        .header("Accept", "application/json")
+        .send()
+        .assert_status(StatusCode::NotFound)
+        .assert_body(r#"{"error":{"message":"Not Found","status":404}}"#);
+
+    let mut m = DefaultServeMux::new();
+    m.set_not_found(HandlerFunc::new(|req, w| {
+        w.status(StatusCode::NotFound);
+        w.text(&format!("no {}", req.target_path()));
+        Ok(())
+    }));
+    let client = TestClient::new(Arc::new(m));
+    client
+        .get("/nope?x=1")
+        .send()
+        .assert_status(StatusCode::NotFound)
+        .assert_body("no /nope");
+}
+
+#[test]
+fn error_pages() {
+    let page = std::env::temp_dir().join(format!("rust_server_page_{}.html", std::process::id()));
+    fs::write(&page, "<p>forbidden</p>").unwrap();
+    let mut m = DefaultServeMux::new();
+    m.get(
+        "/secret",
+        HandlerFunc::new(|_, w| {
+            w.status(StatusCode::Forbidden);
+            w.text("no");
+            Ok(())
+        }),
+    );
+    m.get(
+        "/teapot",
+        HandlerFunc::new(|_, w| {
+            w.status(StatusCode::ServiceUnavailable);
+            Ok(())
+        }),
+    );
+    m.set_error_pages(
+        ErrorPages::new()
+            .file(StatusCode::Forbidden, &page)
+            .template(
+                StatusCode::NotFound,
+                "<h1>{status} {reason}</h1><p>{method} {path}</p>",
+            )
+            .handler(
+                StatusCode::ServiceUnavailable,
+                HandlerFunc::new(|req, w| {
+                    if prefers_json(req) {
+                        w.json("{\"retry\":true}");
+                    } else {
+                        w.text("try again later");
+                    }
+                    Ok(())
+                }),
+            ),
+    );
+    let client = TestClient::new(Arc::new(m));
+
+    client
+        .get("/secret")
+        .send()
+        .assert_status(StatusCode::Forbidden)
+        .assert_header("Content-Type", "text/html; charset=utf-8")
+        .assert_header("Vary", "Accept")
+        .assert_body("<p>forbidden</p>");
+    // the file is read once
+    fs::remove_file(&page).unwrap();
+    client.get("/secret").send().assert_body("<p>forbidden</p>");
+    client
+        .get("/secret")
+        .header("Accept", "application/json, text/html;q=0.5")
+        .send()
+        .assert_status(StatusCode::Forbidden)
+        .assert_header("Content-Type", "application/json")
+        .assert_body(r#"{"error":{"message":"Forbidden","status":403}}"#);
+
+    client
+        .get("/a<b>")
+        .send()
+        .assert_status(StatusCode::NotFound)
+        .assert_body("<h1>404 Not Found</h1><p>GET /a&lt;b&gt;</p>");
+
+    client
+        .get("/teapot")
+        .send()
+        .assert_status(StatusCode::ServiceUnavailable)
+        .assert_header("Content-Type", "text/plain; charset=utf-8")
+        .assert_body("try again later");
+    client
+        .get("/teapot")
+        .header("Accept", "application/problem+json")
+        .send()
+        .assert_body("{\"retry\":true}");
+}
+
+#[test]
+fn negotiation() {
+    let accepts = |accept: &str| {
+        let mut req = Request::new();
+        req.headers.insert("accept".to_string(), accept.to_string());
+        prefers_json(&req)
+    };
+    assert!(!prefers_json(&Request::new()));
+    assert!(accepts("application/json"));
+    assert!(accepts("application/json, */*;q=0.1"));
+    assert!(accepts("application/vnd.api+json"));
+    assert!(!accepts("*/*"));
+    assert!(!accepts(
+        "text/html,application/xhtml+xml,application/xml;q=0.9,*/*;q=0.8"
+    ));
+    assert!(!accepts("application/json;q=0.5, text/html"));
+    assert!(!accepts("text/plain"));
+}
+
+#[cfg(feature = "json")]
+#[test]
+fn same_json_errors_as_handlers() {
+    let mut m = DefaultServeMux::new();
+    m.get(
+        "/gone",
+        HandlerFunc::new(|_, w| {
+            rust_server::json::write_error(w, StatusCode::NotFound, "Not Found");
+            Ok(())
+        }),
+    );
+    let client = TestClient::new(Arc::new(m));
+    let body = |path: &str| {
+        let res = client
+            .get(path)
+            .header("Accept", "application/json")
+            .send()
+            .into_response();
+        res.text()
+    };
+    assert_eq!(body("/nope"), body("/gone"));
+}
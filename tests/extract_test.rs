@@ -0,0 +1,162 @@
+use rust_server::client::Client;
+use rust_server::extract::{Cookies, ExtractHandler, Path, TypedHeader, UserAgent};
+use rust_server::server::{DefaultServeMux, Server, ServerHandle};
+use std::sync::Arc;
+
+fn start(m: DefaultServeMux) -> ServerHandle {
+    Server::new(2, "127.0.0.1:0", Arc::new(m))
+        .unwrap()
+        .start()
+        .unwrap()
+}
+
+#[test]
+fn path_header_and_cookies() {
+    let mut m = DefaultServeMux::new();
+    m.get(
+        "/users/:id/posts/:post",
+        ExtractHandler::new(|Path((id, post)): Path<(u32, String)>, w| {
+            w.text(&format!("{} {}", id, post));
+            Ok(())
+        }),
+    );
+    m.get(
+        "/users/new",
+        ExtractHandler::new(|_: (), w| {
+            w.text("static wins");
+            Ok(())
+        }),
+    );
+    m.get(
+        "/users/:id",
+        ExtractHandler::new(
+            |(Path(id), TypedHeader(UserAgent(ua)), cookies): (
+                Path<u32>,
+                TypedHeader<UserAgent>,
+                Cookies,
+            ),
+             w| {
+                w.text(&format!("{} {} {:?}", id, ua, cookies.get("session")));
+                Ok(())
+            },
+        ),
+    );
+    m.get(
+        "/files/*rest",
+        ExtractHandler::new(|Path(rest): Path<String>, w| {
+            w.text(&rest);
+            Ok(())
+        }),
+    );
+    let server = start(m);
+
+    let url = |p: &str| format!("http://{}{}", server.local_addr().unwrap(), p);
+    let client = Client::new();
+    let get = |p: &str| {
+        client
+            .get(&url(p))
+            .header("User-Agent", "test")
+            .send()
+            .unwrap()
+    };
+
+    assert_eq!(get("/users/7/posts/hello%20world").text(), "7 hello world");
+    assert_eq!(get("/users/new").text(), "static wins");
+    let res = client
+        .get(&url("/users/3?x=1"))
+        .header("User-Agent", "ua")
+        .header("Cookie", "a=b; session=xyz")
+        .send()
+        .unwrap();
+    assert_eq!(res.text(), "3 ua Some(\"xyz\")");
+    assert_eq!(get("/files/a/b/c.txt").text(), "a/b/c.txt");
+
+    let res = get("/users/abc");
+    assert_eq!(res.status(), 400);
+    assert_eq!(
+        res.text(),
+        "invalid path parameter `id`: invalid digit found in string"
+    );
+
+    let res = client.get(&url("/users/3")).send().unwrap();
+    assert_eq!(res.status(), 400);
+}
+
+#[cfg(all(feature = "form", feature = "json"))]
+#[test]
+fn query_form_and_json() {
+    use rust_server::extract::{Form, Json, Query};
+    use serde::Deserialize;
+
+    #[derive(Deserialize)]
+    struct Page {
+        page: u32,
+    }
+    #[derive(Deserialize)]
+    struct Login {
+        user: String,
+    }
+
+    let mut m = DefaultServeMux::new();
+    m.get(
+        "/list",
+        ExtractHandler::new(|Query(q): Query<Page>, w| {
+            w.text(&q.page.to_string());
+            Ok(())
+        }),
+    );
+    m.post(
+        "/form",
+        ExtractHandler::new(|Form(f): Form<Login>, w| {
+            w.text(&f.user);
+            Ok(())
+        }),
+    );
+    m.post(
+        "/json",
+        ExtractHandler::new(|Json(j): Json<Login>, w| {
+            w.text(&j.user);
+            Ok(())
+        }),
+    );
+    let server = start(m);
+
+    let url = |p: &str| format!("http://{}{}", server.local_addr().unwrap(), p);
+    let client = Client::new();
+    let post = |p: &str, ct: &str, body: &str| {
+        client
+            .post(&url(p))
+            .header("Content-Type", ct)
+            .body(body.to_string())
+            .send()
+            .unwrap()
+    };
+
+    assert_eq!(client.get(&url("/list?page=2")).send().unwrap().text(), "2");
+    assert_eq!(
+        client.get(&url("/list?page=x")).send().unwrap().status(),
+        400
+    );
+
+    let form = "application/x-www-form-urlencoded";
+    assert_eq!(post("/form", form, "user=a+b%21").text(), "a b!");
+    assert_eq!(post("/form", form, "name=x").status(), 400);
+    assert_eq!(post("/form", "text/plain", "user=x").status(), 415);
+
+    let json = "application/json";
+    assert_eq!(post("/json", json, r#"{"user":"bob"}"#).text(), "bob");
+    // rejected the same way as by JsonHandler
+    let res = post("/json", json, r#"{"user":1}"#);
+    assert_eq!(res.status(), 400);
+    assert_eq!(res.header("content-type").unwrap(), "application/json");
+    assert!(res
+        .text()
+        .starts_with(r#"{"error":{"message":"invalid JSON body: invalid type"#));
+    assert_eq!(post("/json", json, r#"{"user""#).status(), 400);
+    let res = post("/json", "text/plain", "{}");
+    assert_eq!(res.status(), 415);
+    assert_eq!(
+        res.text(),
+        r#"{"error":{"message":"expected Content-Type: application/json","status":415}}"#
+    );
+}
@@ -0,0 +1,49 @@
+use rust_server::files::FileServer;
+use rust_server::status_code::StatusCode;
+use rust_server::testing::TestClient;
+use std::fs;
+use std::sync::Arc;
+
+#[test]
+fn file_server() {
+    let root = std::env::temp_dir().join(format!("rust_server_files_{}", std::process::id()));
+    fs::create_dir_all(root.join("docs")).unwrap();
+    fs::write(root.join("docs/index.html"), "<h1>docs</h1>").unwrap();
+    fs::write(root.join("app.js"), "let x = 1;").unwrap();
+    fs::write(root.join(".secret"), "hidden").unwrap();
+    let client = TestClient::new(Arc::new(FileServer::new(&root).strip_prefix("/assets")));
+
+    let res = client.get("/assets/app.js").send();
+    res.assert_status(StatusCode::Ok)
+        .assert_header("Content-Type", "text/javascript; charset=utf-8")
+        .assert_body("let x = 1;");
+    let etag = res.header("ETag").unwrap().to_string();
+    assert!(res.header("Last-Modified").is_some());
+    client
+        .get("/assets/app.js")
+        .header("If-None-Match", &etag)
+        .send()
+        .assert_status(StatusCode::NotModified)
+        .assert_body("");
+
+    client
+        .get("/assets/docs/")
+        .send()
+        .assert_header("Content-Type", "text/html; charset=utf-8")
+        .assert_body("<h1>docs</h1>");
+    for path in [
+        "/assets/missing.css",
+        "/assets/.secret",
+        "/assets/../Cargo.toml",
+        "/assets/docs/%2e%2e/app.js",
+        "/assetsapp.js",
+    ] {
+        client.get(path).send().assert_status(StatusCode::NotFound);
+    }
+    client
+        .post("/assets/app.js")
+        .send()
+        .assert_status(StatusCode::MethodNotAllowed)
+        .assert_header("Allow", "GET, HEAD");
+    fs::remove_dir_all(&root).unwrap();
+}
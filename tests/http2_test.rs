@@ -0,0 +1,501 @@
+use rust_server::client;
+use rust_server::http2::frame::{
+    ErrorCode, Frame, FrameError, DEFAULT_MAX_FRAME_SIZE, PREFACE, SETTINGS_INITIAL_WINDOW_SIZE,
+};
+use rust_server::http2::hpack::{Decoder, Encoder};
+use rust_server::server::{DefaultServeMux, HandlerFunc, Server, ServerHandle};
+use std::io::{ErrorKind, Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+fn serve() -> ServerHandle {
+    let mut m = DefaultServeMux::new();
+    m.get(
+        "/slow",
+        HandlerFunc::new(|_, w| {
+            thread::sleep(Duration::from_millis(300));
+            w.text("slow");
+            Ok(())
+        }),
+    );
+    m.get(
+        "/fast",
+        HandlerFunc::new(|_, w| {
+            w.text("fast");
+            Ok(())
+        }),
+    );
+    m.get(
+        "/big",
+        HandlerFunc::new(|_, w| {
+            w.text(&"x".repeat(50_000));
+            Ok(())
+        }),
+    );
+    m.post(
+        "/echo",
+        HandlerFunc::new(|req, w| {
+            let body = String::from_utf8_lossy(req.body_bytes());
+            let host = req.header("Host").unwrap_or("-");
+            w.text(&format!("{} {} {}", req.version, host, body));
+            Ok(())
+        }),
+    );
+    m.post(
+        "/length",
+        HandlerFunc::new(|req, w| {
+            let len = std::io::copy(&mut req.body_reader(), &mut std::io::sink()).unwrap();
+            w.text(&len.to_string());
+            Ok(())
+        }),
+    );
+    m.post(
+        "/ignore",
+        HandlerFunc::new(|_, w| {
+            thread::sleep(Duration::from_secs(1));
+            w.text("ignored");
+            Ok(())
+        }),
+    );
+    Server::new(2, "127.0.0.1:0", Arc::new(m))
+        .unwrap()
+        .start()
+        .unwrap()
+}
+
+/// A bare HTTP/2 client speaking frames.
+struct H2 {
+    stream: TcpStream,
+    encoder: Encoder,
+    decoder: Decoder,
+}
+
+struct Reply {
+    status: String,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+impl H2 {
+    fn connect(addr: SocketAddr, settings: Vec<(u16, u32)>) -> H2 {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream.write_all(PREFACE).unwrap();
+        H2::start(stream, settings)
+    }
+
+    fn start(stream: TcpStream, settings: Vec<(u16, u32)>) -> H2 {
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let mut h2 = H2 {
+            stream,
+            encoder: Encoder::new(),
+            decoder: Decoder::new(),
+        };
+        h2.send(Frame::Settings {
+            ack: false,
+            params: settings,
+        });
+        h2
+    }
+
+    fn send(&mut self, frame: Frame) {
+        frame.write(&mut self.stream).unwrap();
+    }
+
+    fn request(&mut self, stream_id: u32, method: &str, path: &str, end_stream: bool) {
+        let mut block = Vec::new();
+        self.encoder.encode(
+            vec![
+                (":method", method),
+                (":scheme", "http"),
+                (":path", path),
+                (":authority", "example.com"),
+            ],
+            &mut block,
+        );
+        self.send(Frame::Headers {
+            stream_id,
+            end_stream,
+            end_headers: true,
+            block,
+        });
+    }
+
+    fn read(&mut self) -> Result<Frame, FrameError> {
+        Frame::read(&mut self.stream, DEFAULT_MAX_FRAME_SIZE)
+    }
+
+    /// Read frames until one of the streams in `replies` ends; the id of
+    /// that stream.
+    fn read_until_end(&mut self, replies: &mut [(u32, Reply)]) -> u32 {
+        loop {
+            let (stream_id, end_stream) = match self.read().unwrap() {
+                Frame::Headers {
+                    stream_id,
+                    end_stream,
+                    block,
+                    ..
+                } => {
+                    let fields = self.decoder.decode(&block).unwrap();
+                    let reply = &mut replies.iter_mut().find(|r| r.0 == stream_id).unwrap().1;
+                    for (name, value) in fields {
+                        match name.as_str() {
+                            ":status" => reply.status = value,
+                            _ => reply.headers.push((name, value)),
+                        }
+                    }
+                    (stream_id, end_stream)
+                }
+                Frame::Data {
+                    stream_id,
+                    end_stream,
+                    data,
+                    ..
+                } => {
+                    let reply = &mut replies.iter_mut().find(|r| r.0 == stream_id).unwrap().1;
+                    reply.body.extend_from_slice(&data);
+                    (stream_id, end_stream)
+                }
+                _ => continue,
+            };
+            if end_stream {
+                return stream_id;
+            }
+        }
+    }
+
+    fn response(&mut self, stream_id: u32) -> Reply {
+        let mut replies = [(stream_id, Reply::new())];
+        self.read_until_end(&mut replies);
+        let [(_, reply)] = replies;
+        reply
+    }
+}
+
+impl Reply {
+    fn new() -> Reply {
+        Reply {
+            status: String::new(),
+            headers: Vec::new(),
+            body: Vec::new(),
+        }
+    }
+
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+#[test]
+fn hpack_decodes_rfc_example() {
+    // RFC 7541 C.4.1, a request with Huffman-coded literals
+    let block = [
+        0x82, 0x86, 0x84, 0x41, 0x8c, 0xf1, 0xe3, 0xc2, 0xe5, 0xf2, 0x3a, 0x6b, 0xa0, 0xab, 0x90,
+        0xf4, 0xff,
+    ];
+    let fields = Decoder::new().decode(&block).unwrap();
+    let expected = [
+        (":method", "GET"),
+        (":scheme", "http"),
+        (":path", "/"),
+        (":authority", "www.example.com"),
+    ];
+    let fields: Vec<(&str, &str)> = fields
+        .iter()
+        .map(|(n, v)| (n.as_str(), v.as_str()))
+        .collect();
+    assert_eq!(fields, expected);
+
+    let mut block = Vec::new();
+    let headers = vec![
+        (":status", "200"),
+        ("x-custom", "a value long enough to be worth coding"),
+    ];
+    Encoder::new().encode(headers.clone(), &mut block);
+    let decoded = Decoder::new().decode(&block).unwrap();
+    let decoded: Vec<(&str, &str)> = decoded
+        .iter()
+        .map(|(n, v)| (n.as_str(), v.as_str()))
+        .collect();
+    assert_eq!(decoded, headers);
+}
+
+#[test]
+fn multiplexed_streams() {
+    let server = serve();
+    let addr = server.local_addr().unwrap();
+
+    let mut h2 = H2::connect(addr, Vec::new());
+    h2.request(1, "GET", "/slow", true);
+    h2.request(3, "GET", "/fast", true);
+    h2.request(5, "POST", "/echo", false);
+    for (part, end_stream) in [("hello ", false), ("world", true)] {
+        h2.send(Frame::Data {
+            stream_id: 5,
+            end_stream,
+            data: part.as_bytes().to_vec(),
+            flow_len: part.len() as u32,
+        });
+    }
+
+    let mut replies = [(1, Reply::new()), (3, Reply::new()), (5, Reply::new())];
+    let mut order = Vec::new();
+    for _ in 0..3 {
+        order.push(h2.read_until_end(&mut replies));
+    }
+    assert_eq!(order.last(), Some(&1), "the slow stream held up others");
+    let [(_, slow), (_, fast), (_, echo)] = replies;
+    assert_eq!(slow.status, "200");
+    assert_eq!(slow.body, b"slow");
+    assert_eq!(fast.body, b"fast");
+    assert_eq!(fast.header("content-length"), Some("4"));
+    assert_eq!(echo.body, b"HTTP/2.0 example.com hello world");
+
+    // HEAD gets the headers only, and HTTP/1.1 still works on the port
+    h2.request(7, "HEAD", "/fast", true);
+    let head = h2.response(7);
+    assert_eq!(head.status, "200");
+    assert!(head.body.is_empty());
+    let res = client::get(&format!("http://{}/fast", addr)).unwrap();
+    assert_eq!(res.text(), "fast");
+}
+
+#[test]
+fn flow_control() {
+    let server = serve();
+    let addr = server.local_addr().unwrap();
+
+    let mut h2 = H2::connect(addr, vec![(SETTINGS_INITIAL_WINDOW_SIZE, 100)]);
+    h2.request(1, "GET", "/big", true);
+    let mut received = 0;
+    while received < 100 {
+        if let Frame::Data { data, .. } = h2.read().unwrap() {
+            received += data.len();
+        }
+    }
+    assert_eq!(received, 100);
+
+    // nothing more until the window opens
+    h2.stream
+        .set_read_timeout(Some(Duration::from_millis(200)))
+        .unwrap();
+    loop {
+        match h2.read() {
+            Ok(Frame::Data { .. }) => panic!("data beyond the window"),
+            Ok(_) => {}
+            Err(FrameError::Io(e)) => {
+                assert!(matches!(
+                    e.kind(),
+                    ErrorKind::WouldBlock | ErrorKind::TimedOut
+                ));
+                break;
+            }
+            Err(e) => panic!("{}", e),
+        }
+    }
+    h2.stream
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    for stream_id in [0, 1] {
+        h2.send(Frame::WindowUpdate {
+            stream_id,
+            increment: 100_000,
+        });
+    }
+    let rest = h2.response(1);
+    assert_eq!(received + rest.body.len(), 50_000);
+}
+
+#[test]
+fn receive_flow_control() {
+    let server = serve();
+    let addr = server.local_addr().unwrap();
+    let send = |h2: &mut H2, stream_id: u32, len: usize| {
+        for chunk in vec![b'x'; len].chunks(16_384) {
+            h2.send(Frame::Data {
+                stream_id,
+                end_stream: false,
+                data: chunk.to_vec(),
+                flow_len: chunk.len() as u32,
+            });
+        }
+    };
+
+    // one byte past the window of a stream whose handler does not read
+    let mut h2 = H2::connect(addr, Vec::new());
+    h2.request(1, "POST", "/ignore", false);
+    send(&mut h2, 1, 1 << 20);
+    send(&mut h2, 1, 1);
+    loop {
+        match h2.read().unwrap() {
+            Frame::RstStream { stream_id, code } => {
+                assert_eq!(stream_id, 1);
+                assert_eq!(code, ErrorCode::FlowControlError);
+                break;
+            }
+            Frame::GoAway { .. } => panic!("connection closed"),
+            _ => {}
+        }
+    }
+
+    // streams each within their window, together one byte past the
+    // connection's four times that
+    let mut h2 = H2::connect(addr, Vec::new());
+    for stream_id in [1, 3, 5, 7] {
+        h2.request(stream_id, "POST", "/ignore", false);
+        send(&mut h2, stream_id, 1 << 20);
+    }
+    h2.request(9, "POST", "/ignore", false);
+    send(&mut h2, 9, 1);
+    loop {
+        match h2.read().unwrap() {
+            Frame::GoAway { code, .. } => {
+                assert_eq!(code, ErrorCode::FlowControlError);
+                break;
+            }
+            Frame::RstStream { .. } => panic!("stream reset"),
+            _ => {}
+        }
+    }
+}
+
+#[test]
+fn ping_and_protocol_errors() {
+    let server = serve();
+    let addr = server.local_addr().unwrap();
+
+    let mut h2 = H2::connect(addr, Vec::new());
+    h2.send(Frame::Ping {
+        ack: false,
+        data: *b"12345678",
+    });
+    loop {
+        match h2.read().unwrap() {
+            Frame::Ping { ack: true, data } => {
+                assert_eq!(&data, b"12345678");
+                break;
+            }
+            Frame::Ping { ack: false, .. } => panic!("ping not acknowledged"),
+            _ => {}
+        }
+    }
+
+    h2.request(1, "GET", "/fast", true);
+    assert_eq!(h2.response(1).body, b"fast");
+    // clients only open odd streams
+    h2.request(2, "GET", "/fast", true);
+    loop {
+        match h2.read().unwrap() {
+            Frame::GoAway {
+                last_stream_id,
+                code,
+                ..
+            } => {
+                assert_eq!(last_stream_id, 1);
+                assert_eq!(code, ErrorCode::ProtocolError);
+                break;
+            }
+            Frame::Headers { .. } | Frame::Data { .. } => panic!("stream 2 was served"),
+            _ => {}
+        }
+    }
+    let mut rest = Vec::new();
+    let _ = h2.stream.read_to_end(&mut rest);
+    assert!(rest.is_empty());
+}
+
+#[test]
+fn h2c_upgrade() {
+    let server = serve();
+    let addr = server.local_addr().unwrap();
+
+    let mut stream = TcpStream::connect(addr).unwrap();
+    stream
+        .write_all(
+            b"POST /echo HTTP/1.1\r\nHost: upgraded\r\nContent-Length: 4\r\n\
+              Connection: Upgrade, HTTP2-Settings\r\nUpgrade: h2c\r\n\
+              HTTP2-Settings: AAMAAABkAAQCAAAAAAIAAAAA\r\n\r\nbody",
+        )
+        .unwrap();
+    let mut head = Vec::new();
+    let mut byte = [0];
+    while !head.ends_with(b"\r\n\r\n") {
+        stream.read_exact(&mut byte).unwrap();
+        head.push(byte[0]);
+    }
+    let head = String::from_utf8(head).unwrap();
+    assert!(head.starts_with("HTTP/1.1 101 "), "{}", head);
+    assert!(head.contains("Upgrade: h2c"));
+
+    stream.write_all(PREFACE).unwrap();
+    let mut h2 = H2::start(stream, Vec::new());
+    let reply = h2.response(1);
+    assert_eq!(reply.status, "200");
+    // the request itself came as HTTP/1.1
+    assert_eq!(reply.body, b"HTTP/1.1 upgraded body");
+
+    h2.request(3, "GET", "/fast", true);
+    assert_eq!(h2.response(3).body, b"fast");
+}
+
+#[test]
+fn streamed_request_body() {
+    let server = serve();
+    let addr = server.local_addr().unwrap();
+
+    let mut h2 = H2::connect(addr, Vec::new());
+    let (mut conn_window, mut stream_window) = (0, 0);
+    for _ in 0..2 {
+        match h2.read().unwrap() {
+            Frame::Settings { params, .. } => {
+                let (_, window) = params
+                    .iter()
+                    .find(|(id, _)| *id == SETTINGS_INITIAL_WINDOW_SIZE)
+                    .unwrap();
+                stream_window = i64::from(*window);
+            }
+            Frame::WindowUpdate { increment, .. } => conn_window = 65_535 + i64::from(increment),
+            frame => panic!("{:?}", frame),
+        }
+    }
+
+    // three times the stream window, which only opens as the handler reads
+    let total = 3 * stream_window;
+    let mut left = total;
+    h2.request(1, "POST", "/length", false);
+    while left > 0 {
+        let n = left.min(16_384).min(conn_window).min(stream_window);
+        if n == 0 {
+            match h2.read().unwrap() {
+                Frame::WindowUpdate {
+                    stream_id: 0,
+                    increment,
+                } => conn_window += i64::from(increment),
+                Frame::WindowUpdate {
+                    stream_id: 1,
+                    increment,
+                } => stream_window += i64::from(increment),
+                _ => {}
+            }
+            continue;
+        }
+        h2.send(Frame::Data {
+            stream_id: 1,
+            end_stream: n == left,
+            data: vec![b'x'; n as usize],
+            flow_len: n as u32,
+        });
+        left -= n;
+        conn_window -= n;
+        stream_window -= n;
+    }
+    let reply = h2.response(1);
+    assert_eq!(reply.status, "200");
+    assert_eq!(reply.body, total.to_string().as_bytes());
+}
@@ -0,0 +1,127 @@
+#![cfg(feature = "json")]
+use rust_server::client::Client;
+use rust_server::extract::{ExtractHandler, Json};
+use rust_server::json::{write_json, JsonHandler};
+use rust_server::server::{DefaultServeMux, Server};
+use serde::{Deserialize, Serialize};
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::sync::Arc;
+
+#[derive(Deserialize)]
+struct NewUser {
+    name: String,
+    age: u32,
+}
+
+#[derive(Serialize)]
+struct User {
+    id: u32,
+    name: String,
+    age: u32,
+}
+
+#[test]
+fn json_round_trip() {
+    let mut m = DefaultServeMux::new();
+    m.post(
+        "/users",
+        JsonHandler::new(|_req, body: NewUser, w| {
+            write_json(
+                w,
+                &User {
+                    id: 1,
+                    name: body.name,
+                    age: body.age,
+                },
+            );
+            Ok(())
+        }),
+    );
+    let s = Server::new(2, "127.0.0.1:0", Arc::new(m)).unwrap();
+    let server = s.start().unwrap();
+    let addr = server.local_addr().unwrap();
+
+    let url = format!("http://{}/users", addr);
+    let client = Client::new();
+    let post = |ct: &str, body: &str| {
+        client
+            .post(&url)
+            .header("Content-Type", ct)
+            .body(body.to_string())
+            .send()
+            .unwrap()
+    };
+
+    let res = post(
+        "application/json; charset=utf-8",
+        r#"{"name":"alice","age":30}"#,
+    );
+    assert_eq!(res.status(), 200);
+    assert_eq!(res.header("content-type").unwrap(), "application/json");
+    assert_eq!(res.text(), r#"{"id":1,"name":"alice","age":30}"#);
+
+    let res = post("application/json", r#"{"name":"alice"}"#);
+    assert_eq!(res.status(), 400);
+    assert!(res
+        .text()
+        .starts_with(r#"{"error":{"message":"invalid JSON body: missing field `age`"#));
+
+    let res = post("text/plain", "hello");
+    assert_eq!(res.status(), 415);
+    assert_eq!(
+        res.text(),
+        r#"{"error":{"message":"expected Content-Type: application/json","status":415}}"#
+    );
+}
+
+#[test]
+fn unreadable_bodies() {
+    let mut m = DefaultServeMux::new();
+    m.post(
+        "/handler",
+        JsonHandler::new(|_req, body: NewUser, w| {
+            w.text(&body.name);
+            Ok(())
+        }),
+    );
+    m.post(
+        "/extract",
+        ExtractHandler::new(|Json(body): Json<NewUser>, w| {
+            w.text(&body.name);
+            Ok(())
+        }),
+    );
+    let mut s = Server::new(2, "127.0.0.1:0", Arc::new(m)).unwrap();
+    s.set_buffer_bodies(false);
+    s.set_max_body_size(16);
+    let server = s.start().unwrap();
+    let addr = server.local_addr().unwrap();
+
+    // a chunked body has no length to refuse before the handler reads it
+    let post = |path: &str, chunks: &str| {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        write!(
+            stream,
+            "POST {} HTTP/1.1\r\nContent-Type: application/json\r\n\
+             Transfer-Encoding: chunked\r\n\r\n{}",
+            path, chunks
+        )
+        .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    };
+    let oversized = "1c\r\n{\"name\":\"alice\",\"age\":30000}\r\n0\r\n\r\n";
+    let malformed = "zz\r\n";
+    for path in &["/handler", "/extract"] {
+        let res = post(path, oversized);
+        assert!(res.starts_with("HTTP/1.1 413 "), "{}", res);
+        assert!(res.ends_with(
+            r#"{"error":{"message":"cannot read body: request body too large","status":413}}"#
+        ));
+        let res = post(path, malformed);
+        assert!(res.starts_with("HTTP/1.1 400 "), "{}", res);
+        assert!(res.contains(r#"{"error":{"message":"cannot read body: "#));
+    }
+}
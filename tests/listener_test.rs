@@ -0,0 +1,136 @@
+use rust_server::error::ServerError;
+use rust_server::listener::Listen;
+use rust_server::server::{DefaultServeMux, HandlerFunc, Server};
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+fn mux() -> Arc<DefaultServeMux> {
+    let mut m = DefaultServeMux::new();
+    m.get(
+        "/",
+        HandlerFunc::new(|req, w| {
+            let remote = req
+                .remote_addr
+                .map_or("-".to_string(), |a| a.ip().to_string());
+            w.text(&remote);
+            Ok(())
+        }),
+    );
+    Arc::new(m)
+}
+
+fn get<S: Read + Write>(mut stream: S) -> String {
+    stream.write_all(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    response.split("\r\n\r\n").nth(1).unwrap().to_string()
+}
+
+#[test]
+fn several_listeners() {
+    let bound = TcpListener::bind("127.0.0.1:0").unwrap();
+    let bound_addr = bound.local_addr().unwrap();
+    let mut s = Server::new(2, "[::]:0", mux()).unwrap();
+    s.add_listener(bound);
+    #[cfg(unix)]
+    let path = std::env::temp_dir().join(format!("rust_server_{}.sock", std::process::id()));
+    #[cfg(unix)]
+    {
+        // left behind by a server that did not shut down
+        drop(std::os::unix::net::UnixListener::bind(&path).unwrap());
+        s.add_listener(Listen::Unix {
+            path: path.clone(),
+            mode: Some(0o600),
+        });
+    }
+    let server = s.start().unwrap();
+    let port = server.local_addr().unwrap().port();
+
+    // the IPv6 wildcard takes IPv4 clients too
+    assert_eq!(get(TcpStream::connect(("::1", port)).unwrap()), "::1");
+    assert_eq!(
+        get(TcpStream::connect(("127.0.0.1", port)).unwrap()),
+        "::ffff:127.0.0.1"
+    );
+    assert_eq!(get(TcpStream::connect(bound_addr).unwrap()), "127.0.0.1");
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        let stream = std::os::unix::net::UnixStream::connect(&path).unwrap();
+        assert_eq!(get(stream), "-");
+
+        // a socket in use is not taken over
+        let s = Server::new(1, Listen::unix(&path), mux()).unwrap();
+        assert_eq!(s.listen_and_serve(), Err(ServerError::BindError));
+        assert!(path.exists());
+        assert!(matches!(
+            Listen::from("unix:/run/app.sock"),
+            Listen::Unix { mode: None, .. }
+        ));
+    }
+
+    let s = Server::new(1, format!("127.0.0.1:{}", port), mux()).unwrap();
+    assert_eq!(s.listen_and_serve(), Err(ServerError::BindError));
+}
+
+#[test]
+fn background_servers() {
+    let mut m = DefaultServeMux::new();
+    m.get(
+        "/slow",
+        HandlerFunc::new(|_, w| {
+            thread::sleep(Duration::from_millis(300));
+            w.text("done");
+            Ok(())
+        }),
+    );
+    let m = Arc::new(m);
+    // any number of servers on ports of their own, without waiting
+    let first = Server::new(2, "127.0.0.1:0", m.clone())
+        .unwrap()
+        .start()
+        .unwrap();
+    let second = Server::new(2, "127.0.0.1:0", mux())
+        .unwrap()
+        .start()
+        .unwrap();
+    let addr = first.local_addr().unwrap();
+    assert_ne!(addr.port(), 0);
+    assert_ne!(addr, second.local_addr().unwrap());
+    assert_eq!(
+        get(TcpStream::connect(second.local_addr().unwrap()).unwrap()),
+        "127.0.0.1"
+    );
+
+    // shutting down lets the request in flight finish
+    let slow = thread::spawn(move || {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream.write_all(b"GET /slow HTTP/1.1\r\n\r\n").unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    });
+    thread::sleep(Duration::from_millis(100));
+    first.shutdown();
+    assert!(slow.join().unwrap().ends_with("\r\n\r\ndone"));
+    assert!(TcpStream::connect(addr).is_err());
+
+    #[cfg(unix)]
+    {
+        let path = std::env::temp_dir().join(format!("rust_server_bg_{}.sock", std::process::id()));
+        let handle = Server::new(1, Listen::unix(&path), mux())
+            .unwrap()
+            .start()
+            .unwrap();
+        assert_eq!(handle.local_addr(), None);
+        assert!(path.exists());
+        drop(handle);
+        assert!(!path.exists());
+    }
+}
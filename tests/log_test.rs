@@ -0,0 +1,78 @@
+use rust_server::log::{LogEntry, LogFormat, RotatingFile};
+use rust_server::method::Method;
+use rust_server::status_code::StatusCode;
+use std::fs;
+use std::io::Write;
+use std::time::{Duration, UNIX_EPOCH};
+
+fn entry() -> LogEntry {
+    LogEntry {
+        time: UNIX_EPOCH + Duration::from_secs(971_186_136),
+        remote_addr: Some("127.0.0.1:50000".parse().unwrap()),
+        method: Method::Get,
+        target: "/apache_pb.gif".to_string(),
+        version: "HTTP/1.1".to_string(),
+        status: StatusCode::Ok,
+        bytes: 2326,
+        duration: Duration::from_millis(12),
+        user_agent: Some("Mozilla/4.08 \"quoted\"".to_string()),
+        referer: Some("http://www.example.com/start.html".to_string()),
+    }
+}
+
+#[test]
+fn common_and_combined() {
+    let e = entry();
+    assert_eq!(
+        LogFormat::Common.format(&e),
+        "127.0.0.1 - - [10/Oct/2000:13:55:36 +0000] \"GET /apache_pb.gif HTTP/1.1\" 200 2326"
+    );
+    assert!(LogFormat::Combined
+        .format(&e)
+        .ends_with("2326 \"http://www.example.com/start.html\" \"Mozilla/4.08 \\\"quoted\\\"\""));
+
+    // client-supplied fields cannot close their quotes or start a new line
+    let mut forged = entry();
+    forged.target = "/a\" 200 1\n10.0.0.1 - - [x] \"GET /b".to_string();
+    forged.referer = Some("c:\\dir\t".to_string());
+    let line = LogFormat::Combined.format(&forged);
+    assert!(!line.contains('\n'));
+    assert!(line.contains("\"GET /a\\\" 200 1\\x0a10.0.0.1 - - [x] \\\"GET /b HTTP/1.1\" 200"));
+    assert!(line.contains("\"c:\\\\dir\\x09\""));
+}
+
+#[test]
+fn json() {
+    let line = LogFormat::Json.format(&entry());
+    assert!(line.starts_with("{\"time\":\"2000-10-10T13:55:36Z\",\"remote_addr\":\"127.0.0.1\""));
+    assert!(line.contains("\"status\":200,\"bytes\":2326"));
+    assert!(line.contains("\"user_agent\":\"Mozilla/4.08 \\\"quoted\\\"\""));
+}
+
+#[test]
+fn rotate_by_size() {
+    let dir = std::env::temp_dir().join(format!("rust_server_log_{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("access.log");
+
+    let mut f = RotatingFile::open(&path).unwrap().max_size(10);
+    f.write_all(b"12345678\n").unwrap();
+    f.write_all(b"abcdefgh\n").unwrap();
+
+    assert_eq!(fs::read_to_string(&path).unwrap(), "abcdefgh\n");
+    assert_eq!(fs::read_dir(&dir).unwrap().count(), 2);
+
+    // rotations within the same second keep every archive
+    f.write_all(b"ijklmnop\n").unwrap();
+    f.write_all(b"qrstuvwx\n").unwrap();
+    assert_eq!(fs::read_dir(&dir).unwrap().count(), 4);
+    let mut archived = String::new();
+    for entry in fs::read_dir(&dir).unwrap() {
+        archived.push_str(&fs::read_to_string(entry.unwrap().path()).unwrap());
+    }
+    for line in ["12345678", "abcdefgh", "ijklmnop", "qrstuvwx"] {
+        assert!(archived.contains(line), "{}", archived);
+    }
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -1,39 +1,33 @@
+use rust_server::client;
+use rust_server::message::Request;
 use rust_server::method::Method;
-use rust_server::request::Request;
 use std::fs::File;
 use std::io::prelude::*;
 use std::io::Read;
-use std::net::{TcpListener, TcpStream};
+use std::net::{SocketAddr, TcpListener, TcpStream};
 use std::thread;
-use std::time::Duration;
-
-const HOST: &str = "127.0.0.1";
-const PORT: &str = "7878";
-const ADDR: &str = "127.0.0.1:7878";
 
 #[test]
 fn parse_header() {
-    let listener = TcpListener::bind(ADDR).unwrap();
-    thread::spawn(|| {
-        thread::sleep(Duration::from_secs(1));
-        // send_request();
-    });
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    thread::spawn(move || send_request(addr));
     for stream in listener.incoming().take(1) {
-        let stream = stream.unwrap();
+        let mut stream = stream.unwrap();
         let mut m = Request::new();
-        m.parse(&stream).unwrap();
+        m.parse(&mut stream).unwrap();
         index(stream).unwrap();
         println!("{:?}", m);
-        // assert_eq!(m.method, Method::Get);
+        assert_eq!(m.method, Method::Get);
         assert_eq!(m.path, "/");
         assert_eq!(m.version, "HTTP/1.1");
     }
 }
 
-fn send_request() {
+fn send_request(addr: SocketAddr) {
     println!("start to sending task");
-    let add = format!("http://{}", ADDR);
-    let _ = reqwest::blocking::get(&add).unwrap();
+    let add = format!("http://{}", addr);
+    let _ = client::get(&add).unwrap();
     println!("end");
 }
 
@@ -48,7 +42,7 @@ fn index(mut stream: TcpStream) -> Result<(), String> {
 
     let response = format!("{}{}", status_line, contents);
 
-    stream.write(response.as_bytes()).unwrap();
+    stream.write_all(response.as_bytes()).unwrap();
     stream.flush().unwrap();
     Ok(())
 }
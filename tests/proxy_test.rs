@@ -0,0 +1,370 @@
+use rust_server::client::{self, Client};
+use rust_server::message::{Request, ResponseWriter};
+use rust_server::proxy::{Balance, ReverseProxy, UpstreamError};
+use rust_server::server::{DefaultServeMux, Handler, HandlerFunc, Server, ServerHandle};
+use rust_server::testing::TestClient;
+use std::io::{BufRead, BufReader, Cursor, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// An upstream answering every request with its name and what it received.
+fn upstream(name: &'static str, delay: Duration) -> ServerHandle {
+    let mut m = DefaultServeMux::new();
+    m.get(
+        "/v1/stream",
+        HandlerFunc::new(|_, w| {
+            w.set_header("Content-Type", "text/plain");
+            w.write_stream(Box::new(Cursor::new(b"streamed body".to_vec())), None);
+            Ok(())
+        }),
+    );
+    m.any(
+        "/*rest",
+        HandlerFunc::new(move |req: &Request, w: &mut dyn ResponseWriter| {
+            thread::sleep(delay);
+            let mut seen = vec![
+                format!("name={}", name),
+                format!("request={} {}", req.method, req.path),
+                format!("body={}", String::from_utf8_lossy(req.body_bytes())),
+            ];
+            for h in &[
+                "Host",
+                "X-Forwarded-For",
+                "X-Forwarded-Proto",
+                "X-Forwarded-Host",
+                "Forwarded",
+                "X-Secret",
+                "Keep-Alive",
+                "X-Custom",
+                "Content-Length",
+                "Transfer-Encoding",
+            ] {
+                if let Some(v) = req.header(h) {
+                    seen.push(format!("{}={}", h.to_lowercase(), v));
+                }
+            }
+            w.set_header("X-Upstream", name);
+            w.set_header("Keep-Alive", "timeout=5");
+            w.text(&seen.join("\n"));
+            Ok(())
+        }),
+    );
+    Server::new(4, "127.0.0.1:0", Arc::new(m))
+        .unwrap()
+        .start()
+        .unwrap()
+}
+
+/// An upstream keeping connections alive; returns its address and how many
+/// connections it accepted.
+fn keep_alive_upstream() -> (SocketAddr, Arc<AtomicUsize>) {
+    let accepted = Arc::new(AtomicUsize::new(0));
+    let counter = accepted.clone();
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            counter.fetch_add(1, Ordering::SeqCst);
+            let stream = stream.unwrap();
+            thread::spawn(move || {
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut writer = stream;
+                loop {
+                    let mut line = String::new();
+                    loop {
+                        line.clear();
+                        if reader.read_line(&mut line).unwrap_or(0) == 0 {
+                            return;
+                        }
+                        if line == "\r\n" {
+                            break;
+                        }
+                    }
+                    let body = "5\r\nhello\r\n6\r\n world\r\n0\r\n\r\n";
+                    let head = "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n";
+                    writer.write_all(head.as_bytes()).unwrap();
+                    writer.write_all(body.as_bytes()).unwrap();
+                }
+            });
+        }
+    });
+    (addr, accepted)
+}
+
+fn field(body: &str, name: &str) -> Option<String> {
+    body.lines()
+        .find_map(|l| l.strip_prefix(&format!("{}=", name)).map(String::from))
+}
+
+#[test]
+fn reverse_proxy() {
+    let upstreams = [
+        upstream("a", Duration::from_millis(0)),
+        upstream("b", Duration::from_millis(0)),
+        upstream("slow", Duration::from_millis(800)),
+    ];
+    let [a, b, slow] = upstreams
+        .each_ref()
+        .map(|u| u.local_addr().unwrap().to_string());
+    let (ka, accepted) = keep_alive_upstream();
+    // nothing listens here
+    let dead = TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap();
+
+    let balanced = Arc::new(
+        ReverseProxy::with_upstreams(&[
+            format!("http://{}", a).as_str(),
+            &format!("http://{}", b),
+            &format!("http://{}", dead),
+        ])
+        .unwrap()
+        .strip_prefix("/lb")
+        .passive_health(1, Duration::from_secs(60)),
+    );
+    let mut m = DefaultServeMux::new();
+    m.any(
+        "/api/*rest",
+        ReverseProxy::new(&format!("http://{}/v1", a))
+            .unwrap()
+            .strip_prefix("/api"),
+    );
+    let lb = balanced.clone();
+    m.any(
+        "/lb/*rest",
+        HandlerFunc::new(move |req: &Request, w: &mut dyn ResponseWriter| lb.serve_http(w, req)),
+    );
+    m.any(
+        "/least/*rest",
+        ReverseProxy::with_upstreams(&[slow.as_str(), &b])
+            .unwrap()
+            .balance(Balance::LeastConnections),
+    );
+    m.any(
+        "/ka/*rest",
+        ReverseProxy::new(&format!("http://{}", ka)).unwrap(),
+    );
+    m.any(
+        "/dead/*rest",
+        ReverseProxy::new(&format!("http://{}", dead)).unwrap(),
+    );
+    let server = Server::new(8, "127.0.0.1:0", Arc::new(m))
+        .unwrap()
+        .start()
+        .unwrap();
+    let addr = server.local_addr().unwrap();
+
+    let client = Client::new();
+    let url = |p: &str| format!("http://{}{}", addr, p);
+
+    let res = client
+        .post(&url("/api/items?x=1"))
+        .header("X-Forwarded-For", "10.0.0.1")
+        .header("Connection", "X-Secret")
+        .header("X-Secret", "hop")
+        .header("X-Custom", "kept")
+        .body("payload")
+        .send()
+        .unwrap();
+    assert_eq!(res.status(), 200);
+    assert_eq!(res.header("x-upstream").unwrap(), "a");
+    assert!(res.header("keep-alive").is_none());
+    let body = res.text();
+    assert_eq!(field(&body, "request").unwrap(), "POST /v1/items?x=1");
+    assert_eq!(field(&body, "body").unwrap(), "payload");
+    assert_eq!(field(&body, "host").unwrap(), a);
+    assert_eq!(
+        field(&body, "x-forwarded-for").unwrap(),
+        "10.0.0.1, 127.0.0.1"
+    );
+    assert_eq!(field(&body, "x-forwarded-proto").unwrap(), "http");
+    assert_eq!(field(&body, "x-forwarded-host").unwrap(), addr.to_string());
+    assert_eq!(
+        field(&body, "forwarded").unwrap(),
+        format!("for=127.0.0.1;host=\"{}\";proto=http", addr)
+    );
+    assert_eq!(field(&body, "x-custom").unwrap(), "kept");
+    assert_eq!(field(&body, "x-secret"), None);
+    assert_eq!(field(&body, "content-length").unwrap(), "7");
+
+    // the scheme comes from the connection, the host is quoted
+    let tls = TestClient::new(Arc::new(ReverseProxy::new(&a).unwrap()));
+    let res = tls
+        .get("/tls")
+        .header("Host", "a\"b\\c")
+        .secure()
+        .send()
+        .into_response();
+    let body = String::from_utf8_lossy(res.body_bytes()).into_owned();
+    assert_eq!(field(&body, "x-forwarded-proto").unwrap(), "https");
+    assert_eq!(
+        field(&body, "forwarded").unwrap(),
+        "for=127.0.0.1;host=\"a\\\"b\\\\c\";proto=https"
+    );
+
+    // a chunked request body is streamed on chunked
+    let mut stream = TcpStream::connect(addr).unwrap();
+    stream
+        .write_all(
+            b"POST /api/upload HTTP/1.1\r\nHost: x\r\nTransfer-Encoding: chunked\r\n\r\n\
+              5\r\nhello\r\n6\r\n world\r\n0\r\n\r\n",
+        )
+        .unwrap();
+    let mut raw = String::new();
+    stream.read_to_string(&mut raw).unwrap();
+    let body = raw.split("\r\n\r\n").nth(1).unwrap();
+    assert_eq!(field(body, "body").unwrap(), "hello world");
+    assert_eq!(field(body, "transfer-encoding").unwrap(), "chunked");
+    assert_eq!(field(body, "content-length"), None);
+
+    // chunked upstream bodies are streamed through
+    let res = client.get(&url("/api/stream")).send().unwrap();
+    assert!(res.header("content-length").is_none());
+    assert_eq!(res.text(), "streamed body");
+    let res = client::get(&url("/ka/chunked")).unwrap();
+    assert_eq!(res.text(), "hello world");
+    for _ in 0..3 {
+        let res = client::get(&url("/ka/again")).unwrap();
+        assert_eq!(res.text(), "hello world");
+    }
+    assert_eq!(accepted.load(Ordering::SeqCst), 1);
+
+    // round robin, skipping the dead upstream once it failed
+    let mut names = Vec::new();
+    for _ in 0..6 {
+        let res = client.get(&url("/lb/who")).send().unwrap();
+        assert_eq!(res.status(), 200);
+        let body = res.text();
+        assert_eq!(field(&body, "request").unwrap(), "GET /who");
+        names.push(field(&body, "name").unwrap());
+    }
+    assert!(names.windows(2).all(|w| w[0] != w[1]), "{:?}", names);
+    assert_eq!(balanced.healthy_upstreams(), vec![a.clone(), b.clone()]);
+
+    let res = client.get(&url("/dead/x")).send().unwrap();
+    assert_eq!(res.status(), 502);
+
+    // least connections sends new requests away from the busy upstream
+    let slow = thread::spawn(move || {
+        client::get(&format!("http://{}/least/1", addr))
+            .unwrap()
+            .text()
+    });
+    thread::sleep(Duration::from_millis(200));
+    let start = Instant::now();
+    for _ in 0..2 {
+        let body = client.get(&url("/least/2")).send().unwrap().text();
+        assert_eq!(field(&body, "name").unwrap(), "b");
+    }
+    assert!(start.elapsed() < Duration::from_millis(600));
+    assert_eq!(field(&slow.join().unwrap(), "name").unwrap(), "slow");
+}
+
+#[test]
+fn failover_with_bodies() {
+    let server = upstream("live", Duration::from_millis(0));
+    let live = server.local_addr().unwrap().to_string();
+    // nothing listens here
+    let dead = TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap();
+    let dead = format!("http://{}", dead);
+    // reads a request up to its body, then hangs up
+    let closer = TcpListener::bind("127.0.0.1:0").unwrap();
+    let closing = format!("http://{}", closer.local_addr().unwrap());
+    thread::spawn(move || {
+        for stream in closer.incoming() {
+            let mut stream = stream.unwrap();
+            let mut seen = Vec::new();
+            let mut buf = [0; 1024];
+            while !String::from_utf8_lossy(&seen).contains("hello") {
+                match stream.read(&mut buf) {
+                    Ok(0) | Err(_) => break,
+                    Ok(n) => seen.extend_from_slice(&buf[..n]),
+                }
+            }
+        }
+    });
+
+    // a fresh proxy per request, so the first upstream is tried first
+    let proxy = |first: &str| {
+        ReverseProxy::with_upstreams(&[first, live.as_str()])
+            .unwrap()
+            .passive_health(1, Duration::from_secs(60))
+    };
+    let mut m = DefaultServeMux::new();
+    m.any("/post", proxy(&dead));
+    m.any("/chunked", proxy(&dead));
+    let closed = Arc::new(proxy(&closing));
+    let handler = closed.clone();
+    m.any(
+        "/closed",
+        HandlerFunc::new(move |req: &Request, w: &mut dyn ResponseWriter| {
+            handler.serve_http(w, req)
+        }),
+    );
+    let front = Server::new(4, "127.0.0.1:0", Arc::new(m))
+        .unwrap()
+        .start()
+        .unwrap();
+    let addr = front.local_addr().unwrap();
+
+    // a body not sent yet goes whole to the next upstream
+    let res = Client::new()
+        .post(&format!("http://{}/post", addr))
+        .body("hello world")
+        .send()
+        .unwrap();
+    assert_eq!(res.status(), 200);
+    let body = res.text();
+    assert_eq!(field(&body, "name").unwrap(), "live");
+    assert_eq!(field(&body, "body").unwrap(), "hello world");
+
+    let mut stream = TcpStream::connect(addr).unwrap();
+    stream
+        .write_all(
+            b"PUT /chunked HTTP/1.1\r\nHost: x\r\nTransfer-Encoding: chunked\r\n\r\n\
+              5\r\nhello\r\n6\r\n world\r\n0\r\n\r\n",
+        )
+        .unwrap();
+    let mut raw = String::new();
+    stream.read_to_string(&mut raw).unwrap();
+    assert!(raw.starts_with("HTTP/1.1 200 "), "{}", raw);
+    let body = raw.split("\r\n\r\n").nth(1).unwrap();
+    assert_eq!(field(body, "name").unwrap(), "live");
+    assert_eq!(field(body, "body").unwrap(), "hello world");
+
+    // once some of it was sent, even an idempotent request is not retried,
+    // and the next upstream is not blamed
+    let res = Client::new()
+        .put(&format!("http://{}/closed", addr))
+        .body("hello world")
+        .send()
+        .unwrap();
+    assert_eq!(res.status(), 502);
+    assert_eq!(closed.healthy_upstreams(), vec![live]);
+}
+
+#[test]
+fn invalid_upstreams() {
+    let err = |urls: &[&str]| ReverseProxy::with_upstreams(urls).err().unwrap();
+    assert_eq!(err(&[]), UpstreamError::NoUpstreams);
+    assert_eq!(
+        err(&["https://a.example"]),
+        UpstreamError::Https("https://a.example".to_string())
+    );
+    assert_eq!(
+        err(&["http:///v1"]),
+        UpstreamError::InvalidUrl("http:///v1".to_string())
+    );
+    assert_eq!(
+        err(&["a:1", "b:http"]),
+        UpstreamError::InvalidPort("b:http".to_string())
+    );
+    assert!(ReverseProxy::new("[::1]:8080/v1").is_ok());
+}
@@ -0,0 +1,235 @@
+use rust_server::client::Client;
+use rust_server::cookie::Cookie;
+use rust_server::error::ServerError;
+use rust_server::message::{percent_decode, Request, ResponseBody, ResponseWriter};
+use rust_server::server::{DefaultServeMux, HandlerFunc, Server};
+use rust_server::status_code::StatusCode;
+use std::io::{Cursor, Read, Write};
+use std::net::TcpStream;
+use std::sync::Arc;
+
+fn reply(writer: &mut dyn ResponseWriter, code: usize, body: &str) -> Result<(), ServerError> {
+    writer.write(ResponseBody::BytesBody(body.as_bytes().to_vec()));
+    writer.write_header(code);
+    writer.send();
+    Ok(())
+}
+
+#[test]
+fn closure_routes() {
+    let mut m = DefaultServeMux::new();
+    m.get("/item", HandlerFunc::new(|_req, w| reply(w, 200, "get")));
+    m.post(
+        "/item",
+        |req: &Request, w: &mut dyn ResponseWriter| match &req.body {
+            Some(rust_server::message::RequestBody::StringBody(b)) => reply(w, 201, b),
+            _ => reply(w, 400, ""),
+        },
+    );
+    m.any(
+        "/any",
+        HandlerFunc::new(|req, w| reply(w, 200, req.method.as_str())),
+    );
+
+    let mut s = Server::new(2, "127.0.0.1:0", Arc::new(m)).unwrap();
+    s.set_buffer_bodies(true);
+    let server = s.start().unwrap();
+    let addr = server.local_addr().unwrap();
+
+    let url = |p: &str| format!("http://{}{}", addr, p);
+    let client = Client::new();
+
+    let res = client.get(&url("/item")).send().unwrap();
+    assert_eq!(res.status(), 200);
+    assert_eq!(res.text(), "get");
+
+    let res = client.post(&url("/item")).body("posted").send().unwrap();
+    assert_eq!(res.status(), 201);
+    assert_eq!(res.text(), "posted");
+
+    let res = client.put(&url("/any")).send().unwrap();
+    assert_eq!(res.text(), "PUT");
+
+    let res = client.delete(&url("/item")).send().unwrap();
+    assert_eq!(res.status(), 404);
+}
+
+#[test]
+fn response_helpers() {
+    let mut m = DefaultServeMux::new();
+    m.get(
+        "/text",
+        HandlerFunc::new(|_req, w| {
+            w.text("hi");
+            w.append_header("X-Multi", "a");
+            w.append_header("X-Multi", "b");
+            w.status(StatusCode::Created);
+            Ok(())
+        }),
+    );
+    m.get(
+        "/redirect",
+        HandlerFunc::new(|_req, w| {
+            w.redirect(StatusCode::SeeOther, "/text");
+            Ok(())
+        }),
+    );
+    m.get(
+        "/twice",
+        HandlerFunc::new(|_req, w| {
+            w.text("first");
+            w.send();
+            assert!(w.is_sent());
+            w.text("second");
+            w.send();
+            Ok(())
+        }),
+    );
+    m.get(
+        "/empty",
+        HandlerFunc::new(|_req, w| {
+            w.no_content();
+            Ok(())
+        }),
+    );
+
+    let s = Server::new(2, "127.0.0.1:0", Arc::new(m)).unwrap();
+    let server = s.start().unwrap();
+    let addr = server.local_addr().unwrap();
+
+    let url = |p: &str| format!("http://{}{}", addr, p);
+    let client = Client::new().max_redirects(0);
+
+    let res = client.get(&url("/text")).send().unwrap();
+    assert_eq!(res.status(), 201);
+    assert_eq!(
+        res.header("content-type").unwrap(),
+        "text/plain; charset=utf-8"
+    );
+    let multi: Vec<_> = res.headers.get_all("x-multi").collect();
+    assert_eq!(multi.len(), 2);
+    assert_eq!(res.text(), "hi");
+
+    let res = client.get(&url("/redirect")).send().unwrap();
+    assert_eq!(res.status(), 303);
+    assert_eq!(res.header("location").unwrap(), "/text");
+
+    let res = client.get(&url("/twice")).send().unwrap();
+    assert_eq!(res.text(), "first");
+
+    let res = client.get(&url("/empty")).send().unwrap();
+    assert_eq!(res.status(), 204);
+}
+
+#[test]
+fn handler_panics() {
+    let mut m = DefaultServeMux::new();
+    m.get(
+        "/boom",
+        HandlerFunc::new(|_, w| {
+            w.set_header("X-Partial", "1");
+            panic!("boom");
+        }),
+    );
+    m.get(
+        "/login",
+        HandlerFunc::new(|_, w| {
+            w.set_cookie(&Cookie::new("session", "abc"));
+            w.redirect(StatusCode::SeeOther, "/home");
+            w.set_header("Cache-Control", "public, max-age=600");
+            w.write_stream(Box::new(Cursor::new(b"streamed".to_vec())), Some(8));
+            panic!("after the cookie");
+        }),
+    );
+    m.get(
+        "/late",
+        HandlerFunc::new(|_, w| {
+            w.text("partial");
+            w.send();
+            panic!("too late");
+        }),
+    );
+    m.get(
+        "/ok",
+        HandlerFunc::new(|_, w| {
+            w.text("ok");
+            Ok(())
+        }),
+    );
+    let s = Server::new(1, "127.0.0.1:0", Arc::new(m)).unwrap();
+    let stats = s.stats();
+    let server = s.start().unwrap();
+    let url = |p: &str| format!("http://{}{}", server.local_addr().unwrap(), p);
+    let client = Client::new();
+
+    let res = client.get(&url("/boom")).send().unwrap();
+    assert_eq!(res.status(), 500);
+    assert!(res.header("x-partial").is_none());
+    assert!(res.text().contains("500 Internal Server Error"));
+    let res = client
+        .get(&url("/boom"))
+        .header("Accept", "application/json")
+        .send()
+        .unwrap();
+    assert_eq!(res.header("content-type").unwrap(), "application/json");
+    assert_eq!(
+        res.text(),
+        r#"{"error":{"message":"Internal Server Error","status":500}}"#
+    );
+    // what the handler set before panicking is not sent
+    let res = client.get(&url("/login")).send().unwrap();
+    assert_eq!(res.status(), 500);
+    for name in ["set-cookie", "location", "cache-control"] {
+        assert!(res.header(name).is_none(), "{}", name);
+    }
+    assert!(res.text().contains("500 Internal Server Error"));
+    // the response was already out, so the connection is dropped after it
+    let _ = client.get(&url("/late")).send();
+    // and the single worker is still there to serve
+    assert_eq!(client.get(&url("/ok")).send().unwrap().text(), "ok");
+
+    let snapshot = stats.snapshot();
+    assert_eq!(snapshot.handler_panics, 4);
+    assert_eq!(snapshot.pool.panicked, 0);
+    server.shutdown();
+}
+
+#[test]
+fn header_values_cannot_split_the_response() {
+    let mut m = DefaultServeMux::new();
+    m.get(
+        "/go",
+        HandlerFunc::new(|req, w| {
+            let to = percent_decode(req.query().unwrap_or("").trim_start_matches("to="));
+            w.redirect(StatusCode::Found, &to);
+            Ok(())
+        }),
+    );
+    let server = Server::new(1, "127.0.0.1:0", Arc::new(m))
+        .unwrap()
+        .start()
+        .unwrap();
+    let mut stream = TcpStream::connect(server.local_addr().unwrap()).unwrap();
+    write!(
+        stream,
+        "GET /go?to=/home%0D%0ASet-Cookie:%20evil=1%00 HTTP/1.1\r\n\r\n"
+    )
+    .unwrap();
+    let mut res = String::new();
+    stream.read_to_string(&mut res).unwrap();
+
+    assert!(res.starts_with("HTTP/1.1 302 "), "{}", res);
+    assert!(
+        res.contains("\r\nLocation: /homeSet-Cookie: evil=1\r\n"),
+        "{}",
+        res
+    );
+    assert!(!res.contains("\r\nSet-Cookie"));
+    server.shutdown();
+}
+
+#[test]
+fn needs_a_worker() {
+    let s = Server::new(0, "127.0.0.1:0", Arc::new(DefaultServeMux::new()));
+    assert_eq!(s.err(), Some(ServerError::PoolCreationError));
+}
@@ -0,0 +1,161 @@
+use rust_server::client::{Client, RequestBuilder};
+use rust_server::extract::ExtractHandler;
+use rust_server::message::Response;
+use rust_server::server::{DefaultServeMux, Server};
+use rust_server::session::{
+    FileStore, MemoryStore, Session, SessionHandler, SessionRecord, SessionStore,
+};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+fn session_cookie(res: &Response) -> Option<String> {
+    res.headers
+        .get_all("set-cookie")
+        .find(|v| v.starts_with("session_id="))
+        .map(String::from)
+}
+
+#[test]
+fn session_lifecycle() {
+    let store = Arc::new(MemoryStore::new());
+    let mut m = DefaultServeMux::new();
+    m.get(
+        "/visit",
+        ExtractHandler::new(|s: Session, w| {
+            let n: u32 = s.get("count").map_or(0, |c| c.parse().unwrap()) + 1;
+            s.insert("count", &n.to_string());
+            w.text(&n.to_string());
+            Ok(())
+        }),
+    );
+    m.post(
+        "/login",
+        ExtractHandler::new(|s: Session, w| {
+            s.insert("user", "alice");
+            s.rotate_id();
+            w.no_content();
+            Ok(())
+        }),
+    );
+    m.post(
+        "/logout",
+        ExtractHandler::new(|s: Session, w| {
+            s.destroy();
+            w.no_content();
+            Ok(())
+        }),
+    );
+    let app = SessionHandler::new(store.clone(), m).idle_timeout(Duration::from_millis(500));
+    let mut app_mux = DefaultServeMux::new();
+    app_mux.any("/*path", app);
+    let s = Server::new(2, "127.0.0.1:0", Arc::new(app_mux)).unwrap();
+    let server = s.start().unwrap();
+    let addr = server.local_addr().unwrap();
+
+    let url = |p: &str| format!("http://{}{}", addr, p);
+    let client = Client::new();
+    let send = |req: RequestBuilder, cookie: &str| req.header("Cookie", cookie).send().unwrap();
+
+    let res = client.get(&url("/visit")).send().unwrap();
+    let first = session_cookie(&res).unwrap();
+    assert!(first.contains("HttpOnly") && first.contains("SameSite=Lax"));
+    assert_eq!(res.text(), "1");
+    let id = first.split(';').next().unwrap().to_string();
+
+    let res = send(client.get(&url("/visit")), &id);
+    assert_eq!(session_cookie(&res), None);
+    assert_eq!(res.text(), "2");
+
+    let res = send(client.post(&url("/login")), &id);
+    let rotated = session_cookie(&res).unwrap();
+    let new_id = rotated.split(';').next().unwrap().to_string();
+    assert_ne!(new_id, id);
+    assert_eq!(store.len(), 1);
+
+    let res = send(client.get(&url("/visit")), &new_id);
+    assert_eq!(res.text(), "3");
+    let res = send(client.get(&url("/visit")), &id);
+    assert!(session_cookie(&res).is_some());
+    assert_eq!(res.text(), "1");
+
+    let res = send(client.post(&url("/logout")), &new_id);
+    assert!(session_cookie(&res).unwrap().contains("Max-Age=0"));
+    let res = send(client.get(&url("/visit")), &new_id);
+    assert_eq!(res.text(), "1");
+
+    let res = client.get(&url("/visit")).send().unwrap();
+    let idle = session_cookie(&res).unwrap();
+    let idle = idle.split(';').next().unwrap();
+    thread::sleep(Duration::from_millis(700));
+    assert_eq!(send(client.get(&url("/visit")), idle).text(), "1");
+}
+
+#[test]
+fn file_store_round_trip() {
+    let dir = std::env::temp_dir().join(format!("rust_server_sessions_{}", std::process::id()));
+    let store = FileStore::new(&dir).unwrap();
+    let mut data = HashMap::new();
+    data.insert("user name".to_string(), "a=b\nc".to_string());
+    let now = SystemTime::now();
+    let record = SessionRecord {
+        data,
+        created: now,
+        last_access: now,
+    };
+
+    store
+        .save("abcdef", &record, now + Duration::from_secs(60))
+        .unwrap();
+    let loaded = store.load("abcdef").unwrap().unwrap();
+    assert_eq!(loaded.data, record.data);
+    assert!(store.load("../etc").is_err());
+
+    store
+        .save("012345", &record, now - Duration::from_secs(1))
+        .unwrap();
+    assert_eq!(store.load("012345").unwrap(), None);
+    store.purge_expired().unwrap();
+    assert!(!dir.join("012345").exists());
+
+    store.destroy("abcdef").unwrap();
+    assert_eq!(store.load("abcdef").unwrap(), None);
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn file_store_concurrent_saves() {
+    let dir = std::env::temp_dir().join(format!("rust_server_saves_{}", std::process::id()));
+    let store = Arc::new(FileStore::new(&dir).unwrap());
+    let now = SystemTime::now();
+    let threads: Vec<_> = (0..8)
+        .map(|i| {
+            let store = store.clone();
+            std::thread::spawn(move || {
+                let mut data = HashMap::new();
+                data.insert("writer".to_string(), i.to_string().repeat(1000));
+                let record = SessionRecord {
+                    data,
+                    created: now,
+                    last_access: now,
+                };
+                for _ in 0..20 {
+                    store
+                        .save("abcdef", &record, now + Duration::from_secs(60))
+                        .unwrap();
+                }
+            })
+        })
+        .collect();
+    for t in threads {
+        t.join().unwrap();
+    }
+    // one writer's record, whole
+    let loaded = store.load("abcdef").unwrap().unwrap();
+    let value = &loaded.data["writer"];
+    assert_eq!(value.len(), 1000);
+    assert!(value.chars().all(|c| c == value.chars().next().unwrap()));
+    assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+    std::fs::remove_dir_all(dir).unwrap();
+}
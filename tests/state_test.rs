@@ -0,0 +1,57 @@
+use rust_server::client;
+use rust_server::extract::{Extension, ExtractHandler, State};
+use rust_server::message::{Request, ResponseWriter};
+use rust_server::server::{DefaultServeMux, Handler, HandlerFunc, Server};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+struct Counter(AtomicUsize);
+struct Greeting(&'static str);
+struct User(String);
+
+#[test]
+fn state_and_extensions() {
+    let mut m = DefaultServeMux::new();
+    m.get(
+        "/count",
+        ExtractHandler::new(|State(c): State<Counter>, w| {
+            let n = c.0.fetch_add(1, Ordering::SeqCst) + 1;
+            w.text(&n.to_string());
+            Ok(())
+        }),
+    );
+    m.get(
+        "/greet",
+        HandlerFunc::new(|req, w| {
+            w.text(req.state::<Greeting>().unwrap().0);
+            Ok(())
+        }),
+    );
+
+    let inner = ExtractHandler::new(|Extension(user): Extension<User>, w| {
+        w.text(&user.0);
+        Ok(())
+    });
+    m.get("/me", move |req: &Request, w: &mut dyn ResponseWriter| {
+        let mut req = req.clone();
+        req.extensions.insert(User("alice".to_string()));
+        inner.serve_http(w, &req)
+    });
+    m.set_state(Greeting("from mux"));
+
+    let mut s = Server::new(2, "127.0.0.1:0", Arc::new(m)).unwrap();
+    s.set_state(Counter(AtomicUsize::new(0)));
+    s.set_state(Greeting("from server"));
+    let server = s.start().unwrap();
+    let addr = server.local_addr().unwrap();
+
+    let get = |p: &str| {
+        client::get(&format!("http://{}{}", addr, p))
+            .unwrap()
+            .text()
+    };
+    assert_eq!(get("/count"), "1");
+    assert_eq!(get("/count"), "2");
+    assert_eq!(get("/greet"), "from mux");
+    assert_eq!(get("/me"), "alice");
+}
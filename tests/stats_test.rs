@@ -0,0 +1,57 @@
+use rust_server::client;
+use rust_server::server::{DefaultServeMux, Server};
+use rust_server::worker::ThreadPool;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+#[test]
+fn metrics_endpoint() {
+    let mut s = Server::new(2, "127.0.0.1:0", Arc::new(DefaultServeMux::new())).unwrap();
+    s.set_metrics_path("/metrics");
+    let stats = s.stats();
+    let server = s.start().unwrap();
+    let addr = server.local_addr().unwrap();
+
+    let res = client::get(&format!("http://{}/missing", addr)).unwrap();
+    assert_eq!(res.status(), 404);
+    // the request is counted once the response is written
+    thread::sleep(Duration::from_millis(50));
+
+    let body = client::get(&format!("http://{}/metrics", addr))
+        .unwrap()
+        .text();
+    assert!(body.contains("# TYPE rust_server_requests_total counter"));
+    assert!(body.contains("rust_server_requests_total{class=\"4xx\"} 1"));
+    assert!(body.contains("rust_server_workers{state=\"active\"} 1"));
+    // a query string does not hide the metrics path
+    let res = client::get(&format!("http://{}/metrics?x=1", addr)).unwrap();
+    assert_eq!(res.status(), 200);
+    assert!(res
+        .text()
+        .contains("# TYPE rust_server_requests_total counter"));
+
+    let snapshot = stats.snapshot();
+    assert_eq!(snapshot.pool.workers, 2);
+    assert!(snapshot.connections >= 2);
+    assert!(snapshot.bytes_in > 0);
+    assert!(snapshot.bytes_out > 0);
+    assert_eq!(snapshot.requests[3], 1);
+}
+
+#[test]
+fn panicked_jobs_are_not_completed() {
+    let pool = ThreadPool::new(1).unwrap();
+    pool.execute(|| panic!("job failed"));
+    pool.execute(|| {});
+    for _ in 0..100 {
+        let stats = pool.stats();
+        if stats.completed + stats.panicked == 2 {
+            break;
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+    let stats = pool.stats();
+    assert_eq!(stats.completed, 1);
+    assert_eq!(stats.panicked, 1);
+}
@@ -0,0 +1,132 @@
+use rust_server::cors::Cors;
+use rust_server::error::ServerError;
+use rust_server::message::RequestBody;
+use rust_server::server::{DefaultServeMux, HandlerFunc};
+use rust_server::status_code::StatusCode;
+use rust_server::testing::TestClient;
+use std::io::Read;
+use std::sync::Arc;
+
+struct Greeting(&'static str);
+
+fn mux() -> DefaultServeMux {
+    let mut m = DefaultServeMux::new();
+    m.get(
+        "/hello/:name",
+        HandlerFunc::new(|req, w| {
+            let greeting = req.state::<Greeting>().map_or("hi", |g| g.0);
+            let from = req
+                .remote_addr
+                .map_or("-".to_string(), |a| a.ip().to_string());
+            w.set_header("X-From", &from);
+            w.text(&format!("{} {}", greeting, req.param("name").unwrap()));
+            Ok(())
+        }),
+    );
+    m.post(
+        "/users",
+        HandlerFunc::new(|req, w| {
+            let name = match &req.body {
+                Some(RequestBody::StringBody(s)) => s.clone(),
+                _ => {
+                    w.status(StatusCode::BadRequest);
+                    return Ok(());
+                }
+            };
+            let session = req.cookie("session").unwrap_or_default();
+            w.status(StatusCode::Created);
+            w.set_header("Location", "/users/1");
+            w.json(&format!(
+                "{{\"user\":{},\"session\":\"{}\"}}",
+                name, session
+            ));
+            Ok(())
+        }),
+    );
+    m.post(
+        "/stream",
+        HandlerFunc::new(|req, w| {
+            let mut body = String::new();
+            req.body_reader().read_to_string(&mut body).unwrap();
+            w.write_stream(Box::new(std::io::Cursor::new(body.into_bytes())), None);
+            Ok(())
+        }),
+    );
+    m.get(
+        "/fail",
+        HandlerFunc::new(|_, _| Err(ServerError::ParseError)),
+    );
+    m
+}
+
+#[test]
+fn mux_in_memory() {
+    let mut client = TestClient::new(Arc::new(mux()));
+    client
+        .get("/hello/ferris")
+        .send()
+        .assert_status(StatusCode::Ok)
+        .assert_header("X-From", "127.0.0.1")
+        .assert_body("hi ferris");
+
+    client.set_state(Greeting("hello"));
+    let res = client
+        .get("/hello/ferris?x=1")
+        .remote_addr("10.0.0.1:4000".parse().unwrap())
+        .send();
+    res.assert_header("X-From", "10.0.0.1");
+    assert_eq!(res.text(), "hello ferris");
+
+    client
+        .post("/users")
+        .cookie("theme", "dark")
+        .cookie("session", "abc")
+        .json("{\"name\":\"ferris\"}")
+        .send()
+        .assert_status(StatusCode::Created)
+        .assert_header("Location", "/users/1")
+        .assert_header("Content-Type", "application/json")
+        .assert_body("{\"user\":{\"name\":\"ferris\"},\"session\":\"abc\"}");
+
+    client
+        .post("/stream")
+        .body("streamed")
+        .send()
+        .assert_body("streamed");
+
+    // HEAD is routed to GET and has no body
+    let res = client.head("/hello/ferris").send();
+    res.assert_status(StatusCode::Ok).assert_body("");
+    assert_eq!(res.content_length, 12);
+
+    client
+        .options("/users")
+        .send()
+        .assert_status(StatusCode::NoContent)
+        .assert_header("Allow", "OPTIONS, POST");
+    client
+        .get("/nowhere")
+        .send()
+        .assert_status(StatusCode::NotFound);
+    let res = client.get("/fail").send();
+    res.assert_err();
+    assert_eq!(res.result(), &Err(ServerError::ParseError));
+}
+
+#[test]
+fn middleware_in_memory() {
+    let cors = Cors::new(mux()).allow_origin("https://app.example");
+    let client = TestClient::new(Arc::new(cors));
+    client
+        .get("/hello/ferris")
+        .header("Origin", "https://app.example")
+        .send()
+        .assert_header("Access-Control-Allow-Origin", "https://app.example")
+        .assert_body("hi ferris");
+    client
+        .get("/hello/ferris")
+        .header("Origin", "https://evil.example")
+        .send()
+        .assert_no_header("Access-Control-Allow-Origin")
+        .assert_body_contains("ferris");
+}
@@ -0,0 +1,92 @@
+#![cfg(feature = "tls")]
+use rust_server::http2::frame::{Frame, DEFAULT_MAX_FRAME_SIZE, PREFACE};
+use rust_server::http2::hpack::Encoder;
+use rust_server::server::{DefaultServeMux, HandlerFunc, Server};
+use rust_server::tls::TlsConfig;
+use rustls::pki_types::ServerName;
+use rustls::{ClientConfig, ClientConnection, RootCertStore, StreamOwned};
+use std::convert::TryFrom;
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::sync::Arc;
+use std::time::Duration;
+
+fn connect(addr: SocketAddr, alpn: &[&[u8]]) -> StreamOwned<ClientConnection, TcpStream> {
+    let mut roots = RootCertStore::empty();
+    let ca = include_bytes!("tls/ca.pem");
+    for cert in rustls_pemfile::certs(&mut &ca[..]) {
+        roots.add(cert.unwrap()).unwrap();
+    }
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let mut config = ClientConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    config.alpn_protocols = alpn.iter().map(|p| p.to_vec()).collect();
+    let name = ServerName::try_from("localhost").unwrap();
+    let conn = ClientConnection::new(Arc::new(config), name).unwrap();
+    let tcp = TcpStream::connect(addr).unwrap();
+    tcp.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    StreamOwned::new(conn, tcp)
+}
+
+#[test]
+fn tls_with_alpn() {
+    let mut m = DefaultServeMux::new();
+    m.get(
+        "/version",
+        HandlerFunc::new(|req, w| {
+            w.text(&req.version);
+            Ok(())
+        }),
+    );
+    let mut s = Server::new(2, "127.0.0.1:0", Arc::new(m)).unwrap();
+    let tls = TlsConfig::from_pem_files("tests/tls/cert.pem", "tests/tls/key.pem").unwrap();
+    s.set_tls(tls);
+    let server = s.start().unwrap();
+    let addr = server.local_addr().unwrap();
+
+    let mut tls = connect(addr, &[b"http/1.1"]);
+    tls.write_all(b"GET /version HTTP/1.1\r\nHost: localhost\r\n\r\n")
+        .unwrap();
+    let mut response = String::new();
+    let _ = tls.read_to_string(&mut response);
+    assert!(response.starts_with("HTTP/1.1 200 "), "{}", response);
+    assert!(response.ends_with("\r\n\r\nHTTP/1.1"));
+
+    let mut tls = connect(addr, &[b"h2", b"http/1.1"]);
+    let mut block = Vec::new();
+    Encoder::new().encode(
+        vec![
+            (":method", "GET"),
+            (":scheme", "https"),
+            (":path", "/version"),
+            (":authority", "localhost"),
+        ],
+        &mut block,
+    );
+    let mut out = PREFACE.to_vec();
+    let settings = Frame::Settings {
+        ack: false,
+        params: Vec::new(),
+    };
+    settings.encode(&mut out);
+    let headers = Frame::Headers {
+        stream_id: 1,
+        end_stream: true,
+        end_headers: true,
+        block,
+    };
+    headers.encode(&mut out);
+    tls.write_all(&out).unwrap();
+    assert_eq!(tls.conn.alpn_protocol(), Some(&b"h2"[..]));
+    loop {
+        if let Frame::Data { data, .. } = Frame::read(&mut tls, DEFAULT_MAX_FRAME_SIZE).unwrap() {
+            assert_eq!(data, b"HTTP/2.0");
+            break;
+        }
+    }
+
+    assert!(TlsConfig::from_pem(b"not a certificate", b"").is_err());
+}